@@ -73,24 +73,18 @@ impl Game {
 
     fn on_draw(&mut self, _: &events::Draw) {
         let mut renderer = self.ctx.get_mut::<BatchRenderer>();
-        renderer.draw_quad(&graphics::Quad {
-            center: IVec2::new(0, 0),
-            size: IVec2::new(200, 200),
-            color: Srgba::from_format(palette::named::WHITE.with_alpha(1.0)),
-            texture: Some(self.texture.clone())
-        }, -1);
-        renderer.draw_quad(&graphics::Quad {
-            center: IVec2::new(500, 0),
-            size: IVec2::new(200, 200),
-            color: Srgba::from_format(palette::named::RED.with_alpha(1.0)),
-            texture: None
-        }, 0);
-        renderer.draw_quad(&graphics::Quad {
-            center: IVec2::new(0, 0),
-            size: IVec2::new(100, 100),
-            color: Srgba::from_format(palette::named::WHITE.with_alpha(1.0)),
-            texture: None
-        }, 1);
+        renderer.draw_quad(&graphics::Quad::builder()
+            .size(IVec2::new(200, 200))
+            .texture(self.texture.clone())
+            .build(), -1);
+        renderer.draw_quad(&graphics::Quad::builder()
+            .center(IVec2::new(500, 0))
+            .size(IVec2::new(200, 200))
+            .color(Srgba::from_format(palette::named::RED.with_alpha(1.0)))
+            .build(), 0);
+        renderer.draw_quad(&graphics::Quad::builder()
+            .size(IVec2::new(100, 100))
+            .build(), 1);
     }
 }
 impl GeeseSystem for Game {
@@ -111,14 +105,17 @@ impl GeeseSystem for Game {
         info!("Game created");
         
         let mut input = ctx.get_mut::<InputSystem>();
-        input.add_action("cam_left", InputActionTrigger::new_key(KeyCode::ArrowLeft, ModifiersState::empty()));
-        input.add_action("cam_right", InputActionTrigger::new_key(KeyCode::ArrowRight, ModifiersState::empty()));
-        input.add_action("cam_up", InputActionTrigger::new_key(KeyCode::ArrowUp, ModifiersState::empty()));
-        input.add_action("cam_down", InputActionTrigger::new_key(KeyCode::ArrowDown, ModifiersState::empty()));
+        input.add_actions([
+            ("cam_left", InputActionTrigger::key(KeyCode::ArrowLeft)),
+            ("cam_right", InputActionTrigger::key(KeyCode::ArrowRight)),
+            ("cam_up", InputActionTrigger::key(KeyCode::ArrowUp)),
+            ("cam_down", InputActionTrigger::key(KeyCode::ArrowDown)),
+        ]);
         drop(input);
 
         let mut asset_sys = ctx.get_mut::<AssetSystem>();
-        let texture = asset_sys.load::<TextureAsset>("assets/cat2.jpg", true);
+        asset_sys.load_manifest("assets/manifest.ron").expect("Could not load the asset manifest");
+        let texture = asset_sys.get_handle_by_name::<TextureAsset>("cat").unwrap();
         drop(asset_sys);
 
         Self {
@@ -0,0 +1,287 @@
+use anyhow::{bail, Context};
+use wgpu::{AstcBlock, AstcChannel, Extent3d, TextureFormat};
+
+
+/// A block compressed image read from a DDS or KTX2 container, not decompressed
+pub(crate) struct CompressedImage {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    /// The data of each mip level, starting with the full size
+    pub mip_levels: Vec<Vec<u8>>
+}
+impl CompressedImage {
+    /// Parses the container, detected by its magic bytes
+    pub(crate) fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let image = if data.starts_with(DDS_MAGIC) {
+            Self::parse_dds(data)?
+        } else if data.starts_with(KTX2_MAGIC) {
+            Self::parse_ktx2(data)?
+        } else {
+            bail!("Neither a DDS nor a KTX2 file");
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+
+    /// Returns true if the path has an extension of a container that parse understands
+    pub(crate) fn is_compressed_path(path: &std::path::Path) -> bool {
+        path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("dds") || ext.eq_ignore_ascii_case("ktx2"))
+    }
+
+
+    fn parse_dds(data: &[u8]) -> anyhow::Result<Self> {
+        // The magic is followed by a 124 byte header, which may be followed by a 20 byte DX10 header
+        let height = read_u32(data, 12)?;
+        let width = read_u32(data, 16)?;
+        let mip_count = read_u32(data, 28)?.max(1);
+        let four_cc = data.get(84..88).context("DDS header is too short")?;
+        // Legacy DDS files have no color space flag. DXT1/3/5 mostly hold colors (like albedo maps), so they are
+        // read as sRGB, for linear data like normal maps, save the file with a DX10 header and a UNORM DXGI format
+        let (format, data_offset) = match four_cc {
+            b"DXT1" => (TextureFormat::Bc1RgbaUnormSrgb, 128),
+            b"DXT3" => (TextureFormat::Bc2RgbaUnormSrgb, 128),
+            b"DXT5" => (TextureFormat::Bc3RgbaUnormSrgb, 128),
+            b"ATI1" | b"BC4U" => (TextureFormat::Bc4RUnorm, 128),
+            b"ATI2" | b"BC5U" => (TextureFormat::Bc5RgUnorm, 128),
+            b"DX10" => {
+                let dxgi_format = read_u32(data, 128)?;
+                (dxgi_to_format(dxgi_format).with_context(|| format!("Unsupported DXGI format {}", dxgi_format))?, 148)
+            },
+            _ => bail!("Unsupported DDS pixel format {:?}", String::from_utf8_lossy(four_cc))
+        };
+
+        // The mip levels are stored right after each other
+        let mut offset = data_offset;
+        let mut mip_levels = vec![];
+        for level in 0..mip_count {
+            let size = mip_level_byte_size(format, width, height, level);
+            let level_data = data.get(offset..offset + size).with_context(|| format!("DDS data of mip level {} is truncated", level))?;
+            mip_levels.push(level_data.to_vec());
+            offset += size;
+        }
+        Ok(Self { format, width, height, mip_levels })
+    }
+
+
+    fn parse_ktx2(data: &[u8]) -> anyhow::Result<Self> {
+        let vk_format = read_u32(data, 12)?;
+        let width = read_u32(data, 20)?;
+        let height = read_u32(data, 24)?;
+        let depth = read_u32(data, 28)?;
+        let layer_count = read_u32(data, 32)?;
+        let face_count = read_u32(data, 36)?;
+        let level_count = read_u32(data, 40)?.max(1);
+        let supercompression = read_u32(data, 44)?;
+        if supercompression != 0 {
+            bail!("Supercompressed KTX2 files (scheme {}) are not supported", supercompression);
+        };
+        if depth > 1 || layer_count > 1 || face_count != 1 {
+            bail!("Only 2D KTX2 textures are supported (depth {}, layers {}, faces {})", depth, layer_count, face_count);
+        };
+        let format = vk_to_format(vk_format).with_context(|| format!("Unsupported Vulkan format {}", vk_format))?;
+
+        // The level index (offset, length and uncompressed length per level) starts after the 80 byte header
+        let mut mip_levels = vec![];
+        for level in 0..level_count as usize {
+            let entry = 80 + level * 24;
+            let offset = read_u64(data, entry)? as usize;
+            let length = read_u64(data, entry + 8)? as usize;
+            let level_data = data.get(offset..offset + length).with_context(|| format!("KTX2 data of mip level {} is truncated", level))?;
+            mip_levels.push(level_data.to_vec());
+        }
+        Ok(Self { format, width, height, mip_levels })
+    }
+
+
+    /// Makes sure the size and the amount of data match what the GPU expects
+    fn validate(&self) -> anyhow::Result<()> {
+        let (block_width, block_height) = self.format.block_dimensions();
+        if self.width == 0 || self.height == 0 || self.width % block_width != 0 || self.height % block_height != 0 {
+            bail!("The size {}x{} is not a multiple of the {}x{} blocks of {:?}", self.width, self.height, block_width, block_height, self.format);
+        };
+        for (level, data) in self.mip_levels.iter().enumerate() {
+            let expected = mip_level_byte_size(self.format, self.width, self.height, level as u32);
+            if data.len() != expected {
+                bail!("Mip level {} has {} bytes, expected {}", level, data.len(), expected);
+            };
+        }
+        Ok(())
+    }
+
+
+    pub(crate) fn extent(&self) -> Extent3d {
+        Extent3d { width: self.width, height: self.height, depth_or_array_layers: 1 }
+    }
+}
+
+
+const DDS_MAGIC: &[u8] = b"DDS ";
+const KTX2_MAGIC: &[u8] = &[0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+
+/// Number of blocks along each axis of the mip level (partial blocks at the edges count as whole blocks)
+pub(crate) fn mip_level_blocks(format: TextureFormat, width: u32, height: u32, level: u32) -> (u32, u32) {
+    let (block_width, block_height) = format.block_dimensions();
+    let width = (width >> level).max(1);
+    let height = (height >> level).max(1);
+    (width.div_ceil(block_width), height.div_ceil(block_height))
+}
+
+
+fn mip_level_byte_size(format: TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (blocks_x, blocks_y) = mip_level_blocks(format, width, height, level);
+    let block_size = format.block_copy_size(None).unwrap_or(16);
+    (blocks_x * blocks_y * block_size) as usize
+}
+
+
+fn read_u32(data: &[u8], offset: usize) -> anyhow::Result<u32> {
+    let bytes = data.get(offset..offset + 4).context("Header is too short")?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> anyhow::Result<u64> {
+    let bytes = data.get(offset..offset + 8).context("Header is too short")?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+
+/// See the DXGI_FORMAT enum
+fn dxgi_to_format(dxgi_format: u32) -> Option<TextureFormat> {
+    Some(match dxgi_format {
+        71 => TextureFormat::Bc1RgbaUnorm,
+        72 => TextureFormat::Bc1RgbaUnormSrgb,
+        74 => TextureFormat::Bc2RgbaUnorm,
+        75 => TextureFormat::Bc2RgbaUnormSrgb,
+        77 => TextureFormat::Bc3RgbaUnorm,
+        78 => TextureFormat::Bc3RgbaUnormSrgb,
+        80 => TextureFormat::Bc4RUnorm,
+        81 => TextureFormat::Bc4RSnorm,
+        83 => TextureFormat::Bc5RgUnorm,
+        84 => TextureFormat::Bc5RgSnorm,
+        95 => TextureFormat::Bc6hRgbUfloat,
+        96 => TextureFormat::Bc6hRgbFloat,
+        98 => TextureFormat::Bc7RgbaUnorm,
+        99 => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None
+    })
+}
+
+
+/// See the VkFormat enum, only the block compressed formats are handled
+fn vk_to_format(vk_format: u32) -> Option<TextureFormat> {
+    const ASTC_BLOCKS: [AstcBlock; 14] = [
+        AstcBlock::B4x4, AstcBlock::B5x4, AstcBlock::B5x5, AstcBlock::B6x5, AstcBlock::B6x6, AstcBlock::B8x5, AstcBlock::B8x6,
+        AstcBlock::B8x8, AstcBlock::B10x5, AstcBlock::B10x6, AstcBlock::B10x8, AstcBlock::B10x10, AstcBlock::B12x10, AstcBlock::B12x12
+    ];
+    Some(match vk_format {
+        131 | 133 => TextureFormat::Bc1RgbaUnorm,
+        132 | 134 => TextureFormat::Bc1RgbaUnormSrgb,
+        135 => TextureFormat::Bc2RgbaUnorm,
+        136 => TextureFormat::Bc2RgbaUnormSrgb,
+        137 => TextureFormat::Bc3RgbaUnorm,
+        138 => TextureFormat::Bc3RgbaUnormSrgb,
+        139 => TextureFormat::Bc4RUnorm,
+        140 => TextureFormat::Bc4RSnorm,
+        141 => TextureFormat::Bc5RgUnorm,
+        142 => TextureFormat::Bc5RgSnorm,
+        143 => TextureFormat::Bc6hRgbUfloat,
+        144 => TextureFormat::Bc6hRgbFloat,
+        145 => TextureFormat::Bc7RgbaUnorm,
+        146 => TextureFormat::Bc7RgbaUnormSrgb,
+        147 => TextureFormat::Etc2Rgb8Unorm,
+        148 => TextureFormat::Etc2Rgb8UnormSrgb,
+        149 => TextureFormat::Etc2Rgb8A1Unorm,
+        150 => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        151 => TextureFormat::Etc2Rgba8Unorm,
+        152 => TextureFormat::Etc2Rgba8UnormSrgb,
+        153 => TextureFormat::EacR11Unorm,
+        154 => TextureFormat::EacR11Snorm,
+        155 => TextureFormat::EacRg11Unorm,
+        156 => TextureFormat::EacRg11Snorm,
+        // Every block size comes as UNORM followed by SRGB
+        157..=184 => TextureFormat::Astc {
+            block: ASTC_BLOCKS[(vk_format - 157) as usize / 2],
+            channel: match (vk_format - 157) % 2 {
+                0 => AstcChannel::Unorm,
+                _ => AstcChannel::UnormSrgb
+            }
+        },
+        _ => return None
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills each mip level with its index, so slicing errors show up in the data
+    fn level_data(sizes: &[usize]) -> Vec<Vec<u8>> {
+        sizes.iter().enumerate().map(|(level, size)| vec![level as u8; *size]).collect()
+    }
+
+    fn dds(four_cc: &[u8; 4], width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0; 128];
+        data[0..4].copy_from_slice(DDS_MAGIC);
+        data[4..8].copy_from_slice(&124u32.to_le_bytes());
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[28..32].copy_from_slice(&(levels.len() as u32).to_le_bytes());
+        data[84..88].copy_from_slice(four_cc);
+        levels.iter().for_each(|level| data.extend_from_slice(level));
+        data
+    }
+
+    /// The levels are stored smallest first, like most KTX2 writers do
+    fn ktx2(vk_format: u32, width: u32, height: u32, levels: &[Vec<u8>]) -> Vec<u8> {
+        let header = [vk_format, 1, width, height, 0, 0, 1, levels.len() as u32, 0];
+        let mut data = KTX2_MAGIC.to_vec();
+        header.iter().for_each(|value| data.extend_from_slice(&value.to_le_bytes()));
+        data.resize(80 + levels.len() * 24, 0);
+        for (index, level) in levels.iter().enumerate().rev() {
+            let entry = 80 + index * 24;
+            let (offset, length) = (data.len() as u64, level.len() as u64);
+            data[entry..entry + 8].copy_from_slice(&offset.to_le_bytes());
+            data[entry + 8..entry + 16].copy_from_slice(&length.to_le_bytes());
+            data[entry + 16..entry + 24].copy_from_slice(&length.to_le_bytes());
+            data.extend_from_slice(level);
+        }
+        data
+    }
+
+
+    #[test]
+    fn parses_bc1_dds() {
+        // 8x8 is 2x2 blocks of 8 bytes, the 4x4 level is one block
+        let levels = level_data(&[32, 8]);
+        let image = CompressedImage::parse(&dds(b"DXT1", 8, 8, &levels)).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnormSrgb);
+        assert_eq!(image.extent(), Extent3d { width: 8, height: 8, depth_or_array_layers: 1 });
+        assert_eq!(image.mip_levels, levels);
+    }
+
+    #[test]
+    fn parses_bc1_ktx2() {
+        // 8x4 has 2x1 blocks, then 4x2 and 2x1 still take a whole block each
+        let levels = level_data(&[16, 8, 8]);
+        let image = CompressedImage::parse(&ktx2(131, 8, 4, &levels)).unwrap();
+        assert_eq!(image.format, TextureFormat::Bc1RgbaUnorm);
+        assert_eq!(image.extent(), Extent3d { width: 8, height: 4, depth_or_array_layers: 1 });
+        assert_eq!(image.mip_levels, levels);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        // Truncated mip level
+        assert!(CompressedImage::parse(&dds(b"DXT1", 8, 8, &level_data(&[32, 4]))).is_err());
+        // Not a multiple of the block size
+        assert!(CompressedImage::parse(&dds(b"DXT1", 6, 8, &level_data(&[32]))).is_err());
+        assert!(CompressedImage::parse(&dds(b"RGBA", 8, 8, &level_data(&[32]))).is_err());
+        assert!(CompressedImage::parse(&ktx2(131, 8, 8, &level_data(&[31]))).is_err());
+        assert!(CompressedImage::parse(b"\x89PNG").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{any::Any, path::Path};
+use std::{any::Any, path::Path, sync::atomic::{AtomicU64, Ordering}};
 use geese::GeeseContextHandle;
 
 use super::{Asset, AssetSystem};
@@ -9,15 +9,29 @@ use super::{Asset, AssetSystem};
 pub(super) trait AssetHolder {
     fn as_any(&self) -> &dyn Any;
     fn update_from_path(&mut self, ctx: &GeeseContextHandle<AssetSystem>, path: &Path);
+    /// Replaces the asset with value, returns false if value is not of the asset's type
+    fn replace(&mut self, value: Box<dyn Any>) -> bool;
+    /// Approximate memory used by the asset (or its placeholder while evicted), in bytes
+    fn memory_size(&self) -> u64;
+    /// Stores the value of the access counter of the AssetSystem, used for LRU eviction
+    fn touch(&self, access: u64);
+    fn last_access(&self) -> u64;
+    /// Replaces the asset with its placeholder, returns false if the asset type has none
+    fn evict(&mut self, ctx: &GeeseContextHandle<AssetSystem>) -> bool;
+    fn is_evicted(&self) -> bool;
 }
 
 pub(super) struct TypedAssetHolder<T: Asset> {
-    value: T
+    value: T,
+    last_access: AtomicU64,
+    evicted: bool
 }
 impl<T: Asset> TypedAssetHolder<T> {
     pub fn new(value: T) -> Self {
         Self {
-            value
+            value,
+            last_access: AtomicU64::new(0),
+            evicted: false
         }
     }
 }
@@ -28,5 +42,44 @@ impl<T: Asset> AssetHolder for TypedAssetHolder<T> {
     
     fn update_from_path(&mut self, ctx: &GeeseContextHandle<AssetSystem>, path: &Path) {
         self.value = T::from_path(ctx, path);
+        self.evicted = false;
     }
-}
\ No newline at end of file
+
+    fn replace(&mut self, value: Box<dyn Any>) -> bool {
+        match value.downcast::<T>() {
+            Ok(value) => {
+                self.value = *value;
+                self.evicted = false;
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    fn memory_size(&self) -> u64 {
+        self.value.memory_size()
+    }
+
+    fn touch(&self, access: u64) {
+        self.last_access.store(access, Ordering::Relaxed);
+    }
+
+    fn last_access(&self) -> u64 {
+        self.last_access.load(Ordering::Relaxed)
+    }
+
+    fn evict(&mut self, ctx: &GeeseContextHandle<AssetSystem>) -> bool {
+        match T::placeholder(ctx) {
+            Some(placeholder) => {
+                self.value = placeholder;
+                self.evicted = true;
+                true
+            },
+            None => false
+        }
+    }
+
+    fn is_evicted(&self) -> bool {
+        self.evicted
+    }
+}
@@ -0,0 +1,135 @@
+use std::{any::Any, collections::VecDeque, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread::JoinHandle};
+
+use geese::GeeseContextHandle;
+use log::{debug, error};
+
+use super::AssetSystem;
+
+
+/// Turns the decoded data into the asset on the main thread (for example uploading it to the GPU)
+pub(super) type FinishLoad = Box<dyn FnOnce(&GeeseContextHandle<AssetSystem>) -> anyhow::Result<Box<dyn Any>> + Send>;
+/// Decodes an asset on a worker thread
+pub(super) type LoadJob = Box<dyn FnOnce() -> anyhow::Result<FinishLoad> + Send>;
+
+
+/// A fixed number of worker threads decoding assets for `AssetSystem::load_async`.
+/// Only a limited number of jobs is handed to the workers at once, the rest waits on the main thread,
+/// so loading hundreds of assets neither spawns hundreds of threads nor holds all of their data in memory.
+pub(super) struct LoaderPool {
+    num_threads: usize,
+    /// Started on the first submit
+    workers: Vec<JoinHandle<()>>,
+    job_sender: Option<Sender<(u64, LoadJob)>>,
+    job_receiver: Arc<Mutex<Receiver<(u64, LoadJob)>>>,
+    result_sender: Sender<(u64, anyhow::Result<FinishLoad>)>,
+    result_receiver: Receiver<(u64, anyhow::Result<FinishLoad>)>,
+    /// Jobs that wait until the workers have capacity, with their ticket
+    queued: VecDeque<(u64, LoadJob)>,
+    in_flight: usize
+}
+impl LoaderPool {
+    /// At most this many threads are used, even on machines with more cores
+    const MAX_THREADS: usize = 4;
+    /// How many jobs each worker may have waiting for it
+    const JOBS_PER_THREAD: usize = 2;
+
+
+    pub fn new() -> Self {
+        let num_threads = std::thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(2)
+            .clamp(1, Self::MAX_THREADS);
+        let (job_sender, job_receiver) = channel();
+        let (result_sender, result_receiver) = channel();
+        Self {
+            num_threads,
+            workers: vec![],
+            job_sender: Some(job_sender),
+            job_receiver: Arc::new(Mutex::new(job_receiver)),
+            result_sender,
+            result_receiver,
+            queued: VecDeque::new(),
+            in_flight: 0
+        }
+    }
+
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+    }
+
+    /// Jobs that are queued or being decoded
+    pub fn pending(&self) -> usize {
+        self.queued.len() + self.in_flight
+    }
+
+
+    pub fn submit(&mut self, ticket: u64, job: LoadJob) {
+        if self.workers.is_empty() {
+            self.start_workers();
+        };
+        self.queued.push_back((ticket, job));
+        self.dispatch();
+    }
+
+
+    /// Returns the results of all jobs that finished since the last call, by ticket
+    pub fn poll(&mut self) -> Vec<(u64, anyhow::Result<FinishLoad>)> {
+        let finished: Vec<_> = self.result_receiver.try_iter().collect();
+        self.in_flight -= finished.len();
+        self.dispatch();
+        finished
+    }
+
+
+    /// Hands queued jobs to the workers until they are at capacity
+    fn dispatch(&mut self) {
+        let Some(sender) = &self.job_sender else {
+            return;
+        };
+        while self.in_flight < self.num_threads * Self::JOBS_PER_THREAD {
+            let Some(job) = self.queued.pop_front() else {
+                break;
+            };
+            if sender.send(job).is_err() {
+                error!("The asset loader threads have stopped");
+                return;
+            };
+            self.in_flight += 1;
+        }
+    }
+
+
+    fn start_workers(&mut self) {
+        for i in 0..self.num_threads {
+            let jobs = self.job_receiver.clone();
+            let results = self.result_sender.clone();
+            let worker = std::thread::Builder::new()
+                .name(format!("Asset loader {}", i))
+                .spawn(move || loop {
+                    // The lock is released before decoding, so the other workers can take jobs meanwhile
+                    let job = jobs.lock().unwrap().recv();
+                    let Ok((ticket, job)) = job else {
+                        break;
+                    };
+                    if results.send((ticket, job())).is_err() {
+                        break;
+                    };
+                });
+            match worker {
+                Ok(worker) => self.workers.push(worker),
+                Err(e) => error!("Could not start an asset loader thread: {}", e)
+            };
+        }
+        debug!("Started {} asset loader threads", self.workers.len());
+    }
+}
+impl Drop for LoaderPool {
+    fn drop(&mut self) {
+        // Closing the channel makes the workers stop once they finished their current job
+        self.job_sender = None;
+        self.workers.drain(..).for_each(|worker| {
+            let _ = worker.join();
+        });
+    }
+}
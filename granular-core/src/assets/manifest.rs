@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+use rustc_hash::FxHashMap as HashMap;
+use serde::Deserialize;
+
+
+/// Which asset type a manifest entry should be loaded as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ManifestAssetKind {
+    Texture,
+    Shader
+}
+
+
+/// A single asset inside of an AssetManifest
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the base path of the AssetSystem
+    pub path: PathBuf,
+    pub kind: ManifestAssetKind,
+    #[serde(default)]
+    pub hot_reload: bool
+}
+
+
+/// Maps logical names to asset files. Stored as RON, for example:
+/// ```ron
+/// {
+///     "cat": (path: "assets/cat.jpg", kind: Texture, hot_reload: true),
+///     "batch_shader": (path: "shaders/batch_renderer.wgsl", kind: Shader),
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct AssetManifest {
+    pub entries: HashMap<String, ManifestEntry>
+}
+impl AssetManifest {
+    pub fn from_ron(source: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(source)?)
+    }
+
+
+    /// Loads every entry with load and returns the results by name. Stops at the first entry
+    /// that cannot be loaded and returns its error.
+    pub fn load_entries<H>(&self, mut load: impl FnMut(&ManifestEntry) -> anyhow::Result<H>) -> anyhow::Result<HashMap<String, H>> {
+        let mut loaded = HashMap::default();
+        for (name, entry) in self.entries.iter() {
+            let handle = load(entry).with_context(|| format!("Could not load '{}' from '{}'", name, entry.path.display()))?;
+            loaded.insert(name.clone(), handle);
+        }
+        Ok(loaded)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries() {
+        let manifest = AssetManifest::from_ron(r#"{
+            "cat": (path: "assets/cat.jpg", kind: Texture, hot_reload: true),
+            "batch_shader": (path: "shaders/batch_renderer.wgsl", kind: Shader),
+        }"#).unwrap();
+
+        assert_eq!(manifest.entries.len(), 2);
+        let cat = &manifest.entries["cat"];
+        assert_eq!(cat.path, PathBuf::from("assets/cat.jpg"));
+        assert_eq!(cat.kind, ManifestAssetKind::Texture);
+        assert!(cat.hot_reload);
+        let shader = &manifest.entries["batch_shader"];
+        assert_eq!(shader.kind, ManifestAssetKind::Shader);
+        assert!(!shader.hot_reload);
+    }
+
+    #[test]
+    fn resolves_entries_by_name() {
+        let manifest = AssetManifest::from_ron(r#"{
+            "player": (path: "assets/player.png", kind: Texture),
+            "tiles": (path: "assets/tiles.png", kind: Texture, hot_reload: true),
+            "batch_shader": (path: "shaders/batch_renderer.wgsl", kind: Shader),
+        }"#).unwrap();
+
+        let loaded = manifest.load_entries(|entry| Ok((entry.kind, entry.path.clone()))).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded["player"], (ManifestAssetKind::Texture, PathBuf::from("assets/player.png")));
+        assert_eq!(loaded["tiles"], (ManifestAssetKind::Texture, PathBuf::from("assets/tiles.png")));
+        assert_eq!(loaded["batch_shader"], (ManifestAssetKind::Shader, PathBuf::from("shaders/batch_renderer.wgsl")));
+    }
+
+    #[test]
+    fn load_error_names_the_entry() {
+        let manifest = AssetManifest::from_ron(r#"{
+            "player": (path: "assets/player.png", kind: Texture),
+            "missing": (path: "assets/missing.png", kind: Texture),
+        }"#).unwrap();
+
+        let result = manifest.load_entries(|entry| match entry.path.ends_with("missing.png") {
+            true => Err(anyhow::anyhow!("File not found")),
+            false => Ok(())
+        });
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("'missing'"), "{}", error);
+        assert!(error.contains("File not found"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_unknown_kind() {
+        assert!(AssetManifest::from_ron(r#"{ "song": (path: "assets/song.ogg", kind: Audio) }"#).is_err());
+    }
+}
@@ -1,29 +1,68 @@
-use std::{marker::PhantomData, path::{Path, PathBuf}, sync::Arc};
+use std::{any::TypeId, marker::PhantomData, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}};
+use anyhow::Context;
 use log::{debug, info, warn};
-use rustc_hash::FxHashMap as HashMap;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use geese::*;
 
 mod holder;
 use holder::{TypedAssetHolder, AssetHolder};
+mod loader;
+use loader::{FinishLoad, LoaderPool};
 
 use crate::{filewatcher::FileWatcher, graphics::GraphicsSystem};
 
 
 mod texture_asset;
-pub use texture_asset::TextureAsset;
+pub use texture_asset::{DecodedTexture, TextureAsset};
+mod compressed_texture;
 mod shader_asset;
 pub use shader_asset::ShaderAsset;
+mod manifest;
+pub use manifest::{AssetManifest, ManifestAssetKind, ManifestEntry};
 
 
 pub mod events {
     pub struct AssetReload {
         pub asset_id: u64
     }
+
+    /// Raised on the main thread when an asset requested with `AssetSystem::load_async` has been loaded
+    /// and replaced its placeholder. Assets that failed to load only get logged.
+    pub struct AssetLoaded {
+        pub asset_id: u64
+    }
 }
 
 
 pub trait Asset: 'static {
     fn from_path(ctx: &GeeseContextHandle<AssetSystem>, path: &Path) -> Self;
+
+    /// Like from_path, but returns an error instead of panicking if the file cannot be loaded
+    fn try_from_path(ctx: &GeeseContextHandle<AssetSystem>, path: &Path) -> anyhow::Result<Self> where Self: Sized {
+        Ok(Self::from_path(ctx, path))
+    }
+
+    /// Approximate memory used by the asset in bytes, counted against the memory budget of the AssetSystem
+    fn memory_size(&self) -> u64 {
+        0
+    }
+
+    /// A cheap stand-in that is used while the asset is evicted, assets without one are never evicted
+    fn placeholder(_ctx: &GeeseContextHandle<AssetSystem>) -> Option<Self> where Self: Sized {
+        None
+    }
+}
+
+/// An asset which can be decoded on a worker thread, see `AssetSystem::load_async`
+pub trait AsyncAsset: Asset {
+    /// The result of decoding, handed back to the main thread
+    type Decoded: Send + 'static;
+
+    /// Reads and decodes the file, runs on a worker thread without access to any system
+    fn decode(path: &Path) -> anyhow::Result<Self::Decoded>;
+
+    /// Creates the asset from the decoded data on the main thread (for example uploading it to the GPU)
+    fn from_decoded(ctx: &GeeseContextHandle<AssetSystem>, decoded: Self::Decoded) -> anyhow::Result<Self> where Self: Sized;
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -59,16 +98,84 @@ impl<T: Asset> Clone for AssetHandle<T> {
 }
 
 
+/// An AssetHandle which does not know its asset type at compile time,
+/// use `typed` to turn it back into an AssetHandle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnyAssetHandle {
+    id: Arc<u64>,
+    type_id: TypeId
+}
+impl AnyAssetHandle {
+    pub fn id(&self) -> &Arc<u64> {
+        &self.id
+    }
+
+    /// Returns true if this handle points to an asset of type T
+    pub fn is<T: Asset>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    /// Returns the typed handle, or None if the asset is not of type T
+    pub fn typed<T: Asset>(&self) -> Option<AssetHandle<T>> {
+        if self.is::<T>() {
+            Some(AssetHandle::new(self.id.clone()))
+        } else {
+            None
+        }
+    }
+}
+impl<T: Asset> From<AssetHandle<T>> for AnyAssetHandle {
+    fn from(handle: AssetHandle<T>) -> Self {
+        Self {
+            id: handle.id,
+            type_id: TypeId::of::<T>()
+        }
+    }
+}
+
+
 
 pub struct AssetSystem {
     ctx: GeeseContextHandle<Self>,
     assets: HashMap<Arc<u64>, Box<dyn AssetHolder>>,
+    /// The id of the next asset, ids are never reused, so a stale id can not point to a newer asset
+    next_id: u64,
     path_to_id: HashMap<PathBuf, u64>,
+    /// Names given to assets by a manifest
+    name_to_id: HashMap<String, u64>,
     base_path: PathBuf,
+    /// Maximum memory (in bytes) of all loaded assets before evictable ones get evicted
+    memory_budget: Option<u64>,
+    /// Assets that may be evicted when over budget
+    evictable: HashSet<u64>,
+    /// Incremented on every `get`, its value gets stored in the accessed asset to find the least recently used ones
+    access_counter: AtomicU64,
+    /// Value of access_counter when the budget was last enforced
+    last_budget_check: u64,
+    /// Evicted assets that were accessed and need to be loaded again
+    pending_reloads: Mutex<HashSet<u64>>,
+    /// The assets each asset (the key) depends on, see add_reload_dependency
+    reload_dependencies: HashMap<u64, Vec<u64>>,
+    /// Assets that were loaded with hot reloading enabled
+    watched: HashSet<u64>,
+    /// Raster sizes of the textures loaded with load_svg, so they keep their size when loaded again
+    svg_sizes: HashMap<u64, (u32, u32)>,
+    /// Decodes the assets of load_async
+    loader: LoaderPool,
+    /// Assets that are being loaded by the loader, with the ticket of their job
+    async_loads: HashMap<u64, u64>,
+    next_load_ticket: u64,
 }
 impl AssetSystem {
+    /// Returns the asset behind handle. If the asset has been evicted (see `set_memory_budget`),
+    /// its placeholder is returned and the asset gets loaded again before the next frame is drawn.
     pub fn get<T: Asset>(&self, handle: &AssetHandle<T>) -> &T {
-        self.assets.get(handle.id()).unwrap().as_any().downcast_ref().expect("Invalid type given as generic")
+        let holder = self.assets.get(handle.id()).unwrap();
+        holder.touch(self.access_counter.fetch_add(1, Ordering::Relaxed));
+        if holder.is_evicted() {
+            self.pending_reloads.lock().unwrap().insert(**handle.id());
+        };
+        holder.as_any().downcast_ref().expect("Invalid type given as generic")
     }
 
 
@@ -84,45 +191,406 @@ impl AssetSystem {
     pub fn load<T: Asset>(&mut self, path: impl TryInto<PathBuf>, hot_reload: bool) -> AssetHandle<T> {
         let path = self.add_basepath(path);
 
-        let id = self.assets.len() as u64;
         // If this is a new asset, create it and return a new handle,
-        if !self.assets.contains_key(&id) {
-            self.assets.insert(Arc::new(id), Box::new(TypedAssetHolder::new(T::from_path(&self.ctx, &path))));
-            let arc = self.assets.get_key_value(&(self.assets.len() as u64 - 1)).unwrap().0;
-            self.path_to_id.insert(path.clone(), id);
-            
-            if hot_reload {
-                let mut filewatcher = self.ctx.get_mut::<FileWatcher>();
-                filewatcher.watch(path, true);
-            };
-
-            AssetHandle::new(arc.clone())
+        if !self.path_to_id.contains_key(&path) {
+            let asset = T::from_path(&self.ctx, &path);
+            self.insert_asset(path, asset, hot_reload)
         } else { // else, clone the existing handle
             self.get_handle(path)
         }
     }
 
 
+    /// Like load, but returns an error instead of panicking if the file cannot be loaded
+    pub fn try_load<T: Asset>(&mut self, path: impl TryInto<PathBuf>, hot_reload: bool) -> anyhow::Result<AssetHandle<T>> {
+        let path = self.add_basepath(path);
+        if self.path_to_id.contains_key(&path) {
+            return Ok(self.get_handle(path));
+        };
+        let asset = T::try_from_path(&self.ctx, &path)?;
+        Ok(self.insert_asset(path, asset, hot_reload))
+    }
+
+
+    /// Loads an SVG file as a texture rasterized at size (width, height), see `TextureAsset::from_svg`.
+    /// Hot reloading, eviction and device resets rasterize it again at the same size.
+    pub fn load_svg(&mut self, path: impl TryInto<PathBuf>, size: (u32, u32), hot_reload: bool) -> anyhow::Result<AssetHandle<TextureAsset>> {
+        let path = self.add_basepath(path);
+        if let Some(id) = self.path_to_id.get(&path) {
+            if self.svg_sizes.get(id) != Some(&size) {
+                warn!("load_svg: '{}' is already loaded, the size {:?} is ignored", path.display(), size);
+            };
+            return Ok(self.get_handle(path));
+        };
+        let asset = TextureAsset::from_svg(&self.ctx, &path, size)?;
+        let handle = self.insert_asset(path, asset, hot_reload);
+        self.svg_sizes.insert(**handle.id(), size);
+        Ok(handle)
+    }
+
+
+    /// Loads the asset on a pool of worker threads (at most `loader_threads` of them) and returns its handle right away.
+    /// Until it has been loaded, the handle points to the placeholder of the asset type (for textures a
+    /// transparent pixel), so T needs one. Once done, `events::AssetLoaded` and `events::AssetReload` are raised
+    /// on the main thread. Returns an error if T has no placeholder, if the asset is already loaded its handle is returned.
+    pub fn load_async<T: AsyncAsset>(&mut self, path: impl TryInto<PathBuf>, hot_reload: bool) -> anyhow::Result<AssetHandle<T>> {
+        let path = self.add_basepath(path);
+        if self.path_to_id.contains_key(&path) {
+            return Ok(self.get_handle(path));
+        };
+        let placeholder = T::placeholder(&self.ctx)
+            .with_context(|| format!("Cannot load '{}' asynchronously, the asset type has no placeholder", path.display()))?;
+        let handle = self.insert_asset(path.clone(), placeholder, hot_reload);
+
+        let ticket = self.next_load_ticket;
+        self.next_load_ticket += 1;
+        self.async_loads.insert(**handle.id(), ticket);
+        self.loader.submit(ticket, Box::new(move || {
+            let decoded = T::decode(&path)
+                .with_context(|| format!("Could not load '{}'", path.display()))?;
+            let finish: FinishLoad = Box::new(move |ctx| {
+                T::from_decoded(ctx, decoded).map(|asset| Box::new(asset) as Box<dyn std::any::Any>)
+            });
+            Ok(finish)
+        }));
+        Ok(handle)
+    }
+
+
+    /// The number of assets requested with load_async that have not finished loading yet, for example for a loading bar
+    pub fn loading_count(&self) -> usize {
+        self.async_loads.len()
+    }
+
+    /// The number of worker threads used by load_async
+    pub fn loader_threads(&self) -> usize {
+        self.loader.num_threads()
+    }
+
+
+    /// Puts the assets that were decoded by the loader in place of their placeholders
+    fn finish_async_loads(&mut self, _: &crate::events::phase::PreUpdate) {
+        for (ticket, result) in self.loader.poll() {
+            // The asset could have been dropped while loading
+            let Some(id) = self.async_loads.iter().find(|(_, load_ticket)| **load_ticket == ticket).map(|(id, _)| *id) else {
+                continue;
+            };
+            self.async_loads.remove(&id);
+            let asset = result.and_then(|finish| finish(&self.ctx));
+            let asset = match asset {
+                Ok(asset) => asset,
+                Err(e) => {
+                    warn!("{:#}", e);
+                    continue;
+                }
+            };
+            let Some(holder) = self.assets.get_mut(&id) else {
+                continue;
+            };
+            if holder.replace(asset) {
+                self.ctx.raise_event(events::AssetReload{asset_id: id});
+                self.ctx.raise_event(events::AssetLoaded{asset_id: id});
+            };
+        }
+    }
+
+
+    /// Adds an asset that was created in memory instead of being loaded from a file.
+    /// Since it has no path, it is never hot-reloaded, evicted or recreated after a device reset.
+    pub fn add<T: Asset>(&mut self, asset: T) -> AssetHandle<T> {
+        let id = self.next_id();
+        let arc = Arc::new(id);
+        self.assets.insert(arc.clone(), Box::new(TypedAssetHolder::new(asset)));
+        AssetHandle::new(arc)
+    }
+
+
+    /// Loads every file inside of dir (not recursively) whose name matches pattern and returns the handles
+    /// together with their paths (relative to the base path), sorted by path. The pattern can contain `*`
+    /// (any number of characters) and `?` (a single character), for example `*.png`.
+    /// Files that cannot be loaded are skipped and logged.
+    pub fn load_dir<T: Asset>(&mut self, dir: impl TryInto<PathBuf>, pattern: &str, hot_reload: bool) -> Vec<(PathBuf, AssetHandle<T>)> {
+        let dir: PathBuf = dir.try_into().ok().expect("Invalid directory given to load_dir");
+        let full_dir = self.add_basepath(dir.clone());
+        let entries = match std::fs::read_dir(&full_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("load_dir: Could not read directory '{}': {}", full_dir.display(), e);
+                return vec![];
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .filter(|entry| Self::matches_pattern(&entry.file_name().to_string_lossy(), pattern))
+            .map(|entry| dir.join(entry.file_name()))
+            .collect();
+        paths.sort();
+
+        let mut handles = vec![];
+        for path in paths {
+            match self.try_load::<T>(path.clone(), hot_reload) {
+                Ok(handle) => handles.push((path, handle)),
+                Err(e) => warn!("load_dir: Skipping '{}': {:#}", path.display(), e)
+            };
+        }
+        debug!("Loaded {} assets from '{}' matching '{}'", handles.len(), full_dir.display(), pattern);
+        handles
+    }
+
+
+    /// Matches a file name against a pattern with the wildcards `*` and `?`
+    fn matches_pattern(name: &str, pattern: &str) -> bool {
+        let name: Vec<char> = name.chars().collect();
+        let pattern: Vec<char> = pattern.chars().collect();
+        let (mut n, mut p) = (0, 0);
+        // Where the last `*` was and which character of name it was matched up to
+        let mut star: Option<(usize, usize)> = None;
+        while n < name.len() {
+            if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+                n += 1;
+                p += 1;
+            } else if p < pattern.len() && pattern[p] == '*' {
+                star = Some((p, n));
+                p += 1;
+            } else if let Some((star_p, star_n)) = star {
+                // Let the star swallow one more character
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            } else {
+                return false;
+            };
+        }
+        pattern[p..].iter().all(|c| *c == '*')
+    }
+
+
+    /// Returns a new asset id
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+
+    /// Stores a freshly loaded asset under a new id and returns a handle to it
+    fn insert_asset<T: Asset>(&mut self, path: PathBuf, asset: T, hot_reload: bool) -> AssetHandle<T> {
+        let id = self.next_id();
+        let arc = Arc::new(id);
+        self.assets.insert(arc.clone(), Box::new(TypedAssetHolder::new(asset)));
+        self.path_to_id.insert(path.clone(), id);
+
+        if hot_reload {
+            let mut filewatcher = self.ctx.get_mut::<FileWatcher>();
+            filewatcher.watch(path, true);
+            self.watched.insert(id);
+        };
+
+        AssetHandle::new(arc)
+    }
+
+
+    /// Loads all assets listed in a RON manifest (see `AssetManifest`) and returns their handles by name.
+    /// The names can later be resolved using `get_handle_by_name`. Returns an error if any of the assets cannot be loaded.
+    pub fn load_manifest(&mut self, path: impl TryInto<PathBuf>) -> anyhow::Result<HashMap<String, AnyAssetHandle>> {
+        let path = self.add_basepath(path);
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read asset manifest '{}'", path.display()))?;
+        let manifest = AssetManifest::from_ron(&source)
+            .with_context(|| format!("Could not parse asset manifest '{}'", path.display()))?;
+
+        let handles = manifest.load_entries(|entry| -> anyhow::Result<AnyAssetHandle> {
+            Ok(match entry.kind {
+                ManifestAssetKind::Texture => self.try_load::<TextureAsset>(entry.path.clone(), entry.hot_reload)?.into(),
+                ManifestAssetKind::Shader => self.try_load::<ShaderAsset>(entry.path.clone(), entry.hot_reload)?.into()
+            })
+        }).with_context(|| format!("Could not load asset manifest '{}'", path.display()))?;
+        handles.iter().for_each(|(name, handle)| {
+            self.name_to_id.insert(name.clone(), **handle.id());
+        });
+        info!("Loaded {} assets from manifest '{}'", handles.len(), path.display());
+        Ok(handles)
+    }
+
+
+    /// Returns the handle of an asset that was named in a manifest. Returns None if there is no
+    /// such name, the asset has been dropped or it is not of type T.
+    pub fn get_handle_by_name<T: Asset>(&self, name: &str) -> Option<AssetHandle<T>> {
+        let id = self.name_to_id.get(name)?;
+        let (arc, asset) = self.assets.get_key_value(id)?;
+        if !asset.as_any().is::<T>() {
+            warn!("get_handle_by_name: Asset '{}' is not of the requested type", name);
+            return None;
+        };
+        Some(AssetHandle::new(arc.clone()))
+    }
+
+
+    /// Declares that asset depends on dependency (for example a shader on a shared include): Whenever dependency
+    /// gets hot-reloaded, asset is reloaded as well, and when both change at the same time, dependency is always reloaded first.
+    pub fn add_reload_dependency<A: Asset, B: Asset>(&mut self, asset: &AssetHandle<A>, dependency: &AssetHandle<B>) {
+        let (asset_id, dependency_id) = (**asset.id(), **dependency.id());
+        if asset_id == dependency_id {
+            warn!("add_reload_dependency: An asset cannot depend on itself");
+            return;
+        };
+        let dependencies = self.reload_dependencies.entry(asset_id).or_default();
+        if !dependencies.contains(&dependency_id) {
+            dependencies.push(dependency_id);
+        };
+    }
+
+
+    /// Returns the assets to reload when the assets with these ids changed: the assets themselves and everything that
+    /// (indirectly) depends on them, ordered so that dependencies come before their dependents and otherwise by load order
+    fn reload_order(&self, changed: impl IntoIterator<Item = u64>) -> Vec<u64> {
+        reload_order(&self.reload_dependencies, changed)
+    }
+
+
     fn reload(&mut self, event: &crate::filewatcher::events::FilesChanged) {
-        for path in event.paths.iter() {
-            let id = self.path_to_id.get(path);
-            if let Some(id) = id {
-                let asset = self.assets.get_mut(id);
-                if let Some(asset) = asset {
-                    if !Path::exists(path) {
-                        warn!("Tried reloading file from: '{}' but it doesn't exist!", path.display());
-                        continue;
-                    }
-                    asset.update_from_path(&self.ctx, path);
-                    info!("Reloading asset at {}", path.display());
-                    self.ctx.raise_event(events::AssetReload{asset_id: *id})
+        let changed: Vec<u64> = event.paths.iter().filter_map(|path| self.path_to_id.get(path).copied()).collect();
+        for id in self.reload_order(changed) {
+            let Some(path) = self.path_to_id.iter().find(|(_, asset_id)| **asset_id == id).map(|(path, _)| path.clone()) else {
+                continue;
+            };
+            if let Some(asset) = self.assets.get_mut(&id) {
+                if !Path::exists(&path) {
+                    warn!("Tried reloading file from: '{}' but it doesn't exist!", path.display());
+                    continue;
                 }
+                Self::update_asset(&self.ctx, &self.svg_sizes, id, asset.as_mut(), &path);
+                info!("Reloading asset at {}", path.display());
+                self.ctx.raise_event(events::AssetReload{asset_id: id})
             };
-            
         }
     }
 
 
+    /// Sets the maximum amount of memory (in bytes) that loaded assets may use, None disables the budget.
+    ///
+    /// Every `get` marks the asset as used. Once per frame (in `PreRender`), if the assets use more memory than
+    /// the budget, the least recently used assets that are marked with `set_evictable` get replaced by a placeholder
+    /// (for textures a transparent 1x1 pixel), until the usage is below the budget again. Assets that have been
+    /// accessed since the last check are never evicted.
+    ///
+    /// Handles to evicted assets stay valid: The next `get` returns the placeholder and queues the asset, which is then
+    /// loaded again from its path (synchronously) before the following frame. An `AssetReload` event is raised
+    /// both when an asset gets evicted and when it is loaded again.
+    pub fn set_memory_budget(&mut self, budget: Option<u64>) {
+        self.memory_budget = budget;
+    }
+
+    pub fn memory_budget(&self) -> Option<u64> {
+        self.memory_budget
+    }
+
+    /// Marks an asset as safe to evict when over the memory budget, the users of the asset need to cope
+    /// with it showing a placeholder for a frame after it was evicted
+    pub fn set_evictable<T: Asset>(&mut self, handle: &AssetHandle<T>, evictable: bool) {
+        match evictable {
+            true => self.evictable.insert(**handle.id()),
+            false => self.evictable.remove(&**handle.id())
+        };
+    }
+
+    pub fn is_evicted<T: Asset>(&self, handle: &AssetHandle<T>) -> bool {
+        self.assets.get(handle.id()).is_some_and(|holder| holder.is_evicted())
+    }
+
+    /// Returns true if the asset was loaded with hot reloading enabled, so its file is being watched
+    pub fn is_watched<T: Asset>(&self, handle: &AssetHandle<T>) -> bool {
+        self.watched.contains(&**handle.id())
+    }
+
+    /// Returns true if the asset is still being loaded by load_async, or if it has been evicted and
+    /// was accessed again, so it gets loaded again before the next frame
+    pub fn is_loading<T: Asset>(&self, handle: &AssetHandle<T>) -> bool {
+        self.async_loads.contains_key(&**handle.id()) || self.pending_reloads.lock().unwrap().contains(&**handle.id())
+    }
+
+    /// The approximate memory used by all loaded assets (evicted ones only count their placeholder)
+    pub fn used_memory(&self) -> u64 {
+        self.assets.values().map(|holder| holder.memory_size()).sum()
+    }
+
+
+    /// Loads evicted assets that have been accessed again, then evicts the least recently used ones while over budget
+    fn enforce_memory_budget(&mut self, _: &crate::events::phase::PreRender) {
+        let pending = std::mem::take(&mut *self.pending_reloads.lock().unwrap());
+        for id in pending {
+            let path = self.path_to_id.iter().find(|(_, asset_id)| **asset_id == id).map(|(path, _)| path.clone());
+            let (Some(path), Some(holder)) = (path, self.assets.get_mut(&id)) else {
+                continue;
+            };
+            Self::update_asset(&self.ctx, &self.svg_sizes, id, holder.as_mut(), &path);
+            debug!("Reloaded evicted asset at '{}'", path.display());
+            self.ctx.raise_event(events::AssetReload{asset_id: id});
+        }
+
+        let accessed_before = self.last_budget_check;
+        self.last_budget_check = self.access_counter.load(Ordering::Relaxed);
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+        let mut used = self.used_memory();
+        if used <= budget {
+            return;
+        };
+
+        let mut candidates: Vec<(u64, u64)> = self.assets.iter()
+            .filter(|(id, holder)| {
+                self.evictable.contains(&***id) && !holder.is_evicted() && holder.last_access() < accessed_before
+            })
+            .map(|(id, holder)| (holder.last_access(), **id))
+            .collect();
+        candidates.sort_unstable();
+        for (_, id) in candidates {
+            if used <= budget {
+                break;
+            };
+            let holder = self.assets.get_mut(&id).unwrap();
+            let size = holder.memory_size();
+            if holder.evict(&self.ctx) {
+                used = used - size + holder.memory_size();
+                debug!("Evicted asset {} ({} bytes)", id, size);
+                self.ctx.raise_event(events::AssetReload{asset_id: id});
+            };
+        }
+        if used > budget {
+            debug!("Assets still use {} bytes, which is over the budget of {} bytes", used, budget);
+        };
+    }
+
+
+    /// Loads every asset again from its path, needed after the GPU device has been recreated
+    pub(crate) fn reload_all(&mut self) {
+        for (path, id) in self.path_to_id.iter() {
+            if let Some(asset) = self.assets.get_mut(id) {
+                Self::update_asset(&self.ctx, &self.svg_sizes, *id, asset.as_mut(), path);
+                self.ctx.raise_event(events::AssetReload{asset_id: *id});
+            };
+        }
+        info!("Reloaded all {} assets", self.assets.len());
+    }
+
+
+    /// Loads the asset again from its path, SVGs are rasterized again at their size
+    fn update_asset(ctx: &GeeseContextHandle<Self>, svg_sizes: &HashMap<u64, (u32, u32)>, id: u64, holder: &mut dyn AssetHolder, path: &Path) {
+        let Some(size) = svg_sizes.get(&id) else {
+            holder.update_from_path(ctx, path);
+            return;
+        };
+        match TextureAsset::from_svg(ctx, path, *size) {
+            Ok(texture) => {
+                holder.replace(Box::new(texture));
+            },
+            Err(e) => warn!("Could not load SVG again, keeping the old texture: {:#}", e)
+        };
+    }
+
+
     pub fn add_basepath(&self, to_path: impl TryInto<PathBuf>) -> PathBuf {
         let path: PathBuf = to_path.try_into().ok().expect("Could not add base path");
         self.base_path.join(path)
@@ -145,6 +613,13 @@ impl AssetSystem {
             }
             !should_drop
         });
+        self.name_to_id.retain(|_, id| !removed_usizes.contains(id));
+        self.evictable.retain(|id| !removed_usizes.contains(id));
+        self.watched.retain(|id| !removed_usizes.contains(id));
+        self.svg_sizes.retain(|id, _| !removed_usizes.contains(id));
+        self.async_loads.retain(|id, _| !removed_usizes.contains(id));
+        self.reload_dependencies.retain(|id, _| !removed_usizes.contains(id));
+        self.reload_dependencies.values_mut().for_each(|dependencies| dependencies.retain(|id| !removed_usizes.contains(id)));
     }
 }
 impl GeeseSystem for AssetSystem {
@@ -153,7 +628,9 @@ impl GeeseSystem for AssetSystem {
         .with::<GraphicsSystem>();
     const EVENT_HANDLERS: geese::EventHandlers<Self> = event_handlers()
         .with(Self::reload)
-        .with(Self::drop_unused_assets);
+        .with(Self::drop_unused_assets)
+        .with(Self::enforce_memory_budget)
+        .with(Self::finish_async_loads);
 
 
     fn new(ctx: geese::GeeseContextHandle<Self>) -> Self {
@@ -165,7 +642,93 @@ impl GeeseSystem for AssetSystem {
             ctx,
             base_path,
             assets: HashMap::default(),
-            path_to_id: HashMap::default()
+            next_id: 0,
+            path_to_id: HashMap::default(),
+            name_to_id: HashMap::default(),
+            memory_budget: None,
+            evictable: HashSet::default(),
+            access_counter: AtomicU64::new(0),
+            last_budget_check: 0,
+            pending_reloads: Mutex::new(HashSet::default()),
+            reload_dependencies: HashMap::default(),
+            watched: HashSet::default(),
+            svg_sizes: HashMap::default(),
+            loader: LoaderPool::new(),
+            async_loads: HashMap::default(),
+            next_load_ticket: 0
         }
     }
-}
\ No newline at end of file
+}
+
+
+/// See `AssetSystem::reload_order`, dependencies maps each asset to the assets it depends on
+fn reload_order(dependencies: &HashMap<u64, Vec<u64>>, changed: impl IntoIterator<Item = u64>) -> Vec<u64> {
+    let mut to_reload: HashSet<u64> = changed.into_iter().collect();
+    loop {
+        let dependents: Vec<u64> = dependencies.iter()
+            .filter(|(id, dependencies)| !to_reload.contains(*id) && dependencies.iter().any(|dep| to_reload.contains(dep)))
+            .map(|(id, _)| *id)
+            .collect();
+        if dependents.is_empty() {
+            break;
+        };
+        to_reload.extend(dependents);
+    }
+
+    let mut ids: Vec<u64> = to_reload.iter().copied().collect();
+    ids.sort_unstable();
+    let mut order = vec![];
+    let mut visited = HashSet::default();
+    fn visit(id: u64, to_reload: &HashSet<u64>, dependencies: &HashMap<u64, Vec<u64>>, visited: &mut HashSet<u64>, order: &mut Vec<u64>) {
+        // Also stops at cyclic dependencies
+        if !visited.insert(id) {
+            return;
+        };
+        let mut deps: Vec<u64> = dependencies.get(&id).into_iter().flatten().copied().filter(|dep| to_reload.contains(dep)).collect();
+        deps.sort_unstable();
+        deps.into_iter().for_each(|dep| visit(dep, to_reload, dependencies, visited, order));
+        order.push(id);
+    }
+    ids.into_iter().for_each(|id| visit(id, &to_reload, dependencies, &mut visited, &mut order));
+    order
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_order_puts_dependencies_first() {
+        // 3 (a shader) includes 1, which includes 5; 2 also includes 5; 4 is unrelated
+        let dependencies: HashMap<u64, Vec<u64>> = [(3, vec![1]), (1, vec![5]), (2, vec![5])].into_iter().collect();
+        assert_eq!(reload_order(&dependencies, [5]), vec![5, 1, 2, 3]);
+        assert_eq!(reload_order(&dependencies, [1]), vec![1, 3]);
+        assert_eq!(reload_order(&dependencies, [3, 4]), vec![3, 4]);
+        // A dependency that changed together with its dependent still comes first
+        assert_eq!(reload_order(&dependencies, [3, 5]), vec![5, 1, 2, 3]);
+    }
+
+    #[test]
+    fn reload_order_stops_at_cycles() {
+        let dependencies: HashMap<u64, Vec<u64>> = [(1, vec![2]), (2, vec![1])].into_iter().collect();
+        assert_eq!(reload_order(&dependencies, [1]), vec![2, 1]);
+    }
+
+    #[test]
+    fn matches_glob_patterns() {
+        assert!(AssetSystem::matches_pattern("cat.png", "*.png"));
+        assert!(AssetSystem::matches_pattern(".png", "*.png"));
+        assert!(!AssetSystem::matches_pattern("cat.jpg", "*.png"));
+        assert!(!AssetSystem::matches_pattern("cat.png.bak", "*.png"));
+        assert!(AssetSystem::matches_pattern("tile_01.png", "tile_??.png"));
+        assert!(!AssetSystem::matches_pattern("tile_1.png", "tile_??.png"));
+        // Several stars, where the first one has to give back characters it swallowed
+        assert!(AssetSystem::matches_pattern("ui_button_hover.png", "*_*_*.png"));
+        assert!(!AssetSystem::matches_pattern("ui_button.png", "*_*_*.png"));
+        assert!(AssetSystem::matches_pattern("a_b_c_d.png", "*_d.*"));
+        assert!(AssetSystem::matches_pattern("", "*"));
+        assert!(!AssetSystem::matches_pattern("cat.png", ""));
+    }
+}
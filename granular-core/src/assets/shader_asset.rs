@@ -1,5 +1,6 @@
 use std::{borrow::Cow, path::Path};
 
+use anyhow::Context;
 use log::error;
 use wgpu::{ShaderModule, ShaderModuleDescriptor};
 
@@ -17,12 +18,24 @@ impl ShaderAsset {
     pub fn module(&self) -> &ShaderModule {
         &self.module
     }
-}
-impl Asset for ShaderAsset {
-    fn from_path(ctx: &geese::GeeseContextHandle<super::AssetSystem>, path: &Path) -> Self {
+
+
+    fn from_source(ctx: &geese::GeeseContextHandle<super::AssetSystem>, path: &Path, shader_src: &str) -> Self {
         let graphics_sys = ctx.get::<GraphicsSystem>();
         let device = graphics_sys.device();
 
+        let module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(path.to_str().unwrap()),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(shader_src)),
+        });
+
+        Self {
+            module,
+        }
+    }
+}
+impl Asset for ShaderAsset {
+    fn from_path(ctx: &geese::GeeseContextHandle<super::AssetSystem>, path: &Path) -> Self {
         let shader_contents = std::fs::read_to_string(path);
         let shader_src = match shader_contents {
             Ok(data) => {data},
@@ -31,13 +44,12 @@ impl Asset for ShaderAsset {
                 String::new()
             }
         };
-        let module = device.create_shader_module(ShaderModuleDescriptor {
-            label: Some(path.to_str().unwrap()),
-            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(&shader_src)),
-        });
+        Self::from_source(ctx, path, &shader_src)
+    }
 
-        Self {
-            module,
-        }
+    fn try_from_path(ctx: &geese::GeeseContextHandle<super::AssetSystem>, path: &Path) -> anyhow::Result<Self> {
+        let shader_src = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read shader '{}'", path.display()))?;
+        Ok(Self::from_source(ctx, path, &shader_src))
     }
-}
\ No newline at end of file
+}
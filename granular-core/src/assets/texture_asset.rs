@@ -1,11 +1,24 @@
 #![allow(unused)]
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use anyhow::Context;
+use log::warn;
+use palette::Srgba;
 use wgpu::{Extent3d, Sampler, Texture, TextureView};
 use geese::GeeseContextHandle;
 
 use crate::graphics::{GraphicsSystem, TextureBundle};
-use super::{Asset, AssetSystem};
+use super::{compressed_texture::{self, CompressedImage}, Asset, AssetSystem, AsyncAsset};
+
+
+/// A texture that was decoded on a worker thread, see `AssetSystem::load_async`
+pub struct DecodedTexture(DecodedTextureData);
+
+enum DecodedTextureData {
+    Rgba(image::RgbaImage),
+    /// Falls back to decoding on the main thread when the device does not support the format
+    Compressed(CompressedImage, PathBuf)
+}
 
 
 #[derive(Debug, PartialEq)]
@@ -13,21 +26,156 @@ pub struct TextureAsset {
     texture: TextureBundle
 }
 impl TextureAsset {
+    /// Wraps a texture that was created in code, use AssetSystem::add to get a handle for it
+    pub fn from_texture(texture: TextureBundle) -> Self {
+        Self {
+            texture
+        }
+    }
+
+
     pub fn texture(&self) -> &TextureBundle {
         &self.texture
     }
+
+
+    /// Rasterizes an SVG file to a texture of size (width, height) pixels, stretching the SVG if the aspect
+    /// ratio differs. The result is a normal texture, so zooming in past the raster size still blurs it,
+    /// rasterize at the largest size it gets shown at. Use `AssetSystem::load_svg` to keep the size on hot reload.
+    pub fn from_svg(ctx: &GeeseContextHandle<AssetSystem>, path: &Path, size: (u32, u32)) -> anyhow::Result<Self> {
+        let data = std::fs::read(path)
+            .with_context(|| format!("Could not read SVG '{}'", path.display()))?;
+        let (width, height) = size;
+        let pixels = Self::rasterize_svg(&data, width, height)
+            .with_context(|| format!("Could not rasterize SVG '{}'", path.display()))?;
+
+        let sys = ctx.get::<GraphicsSystem>();
+        Ok(Self {
+            texture: TextureBundle::from_rgba(sys.device(), sys.queue(), width, height, &pixels)
+                .with_context(|| format!("Could not create the texture of SVG '{}'", path.display()))?
+        })
+    }
+
+
+    /// Renders the SVG into straight (not premultiplied) RGBA8 pixels
+    fn rasterize_svg(data: &[u8], width: u32, height: u32) -> anyhow::Result<Vec<u8>> {
+        let tree = resvg::usvg::Tree::from_data(data, &resvg::usvg::Options::default())?;
+        let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)
+            .with_context(|| format!("Invalid raster size {}x{}", width, height))?;
+        let svg_size = tree.size();
+        let transform = resvg::tiny_skia::Transform::from_scale(width as f32 / svg_size.width(), height as f32 / svg_size.height());
+        resvg::render(&tree, transform, &mut pixmap.as_mut());
+        // tiny-skia works with premultiplied alpha, textures are expected to be straight
+        Ok(pixmap.pixels().iter().flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        }).collect())
+    }
+
+
+    /// Uploads a DDS or KTX2 file without decompressing it. Returns None (after logging why) if the file
+    /// cannot be parsed or the device does not support its format, so it can be decoded to RGBA instead.
+    fn try_load_compressed(ctx: &GeeseContextHandle<AssetSystem>, path: &Path) -> Option<Self> {
+        let image = std::fs::read(path)
+            .context("Could not read the file")
+            .and_then(|data| CompressedImage::parse(&data));
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("Could not load '{}' as a compressed texture, decoding it instead: {:#}", path.display(), e);
+                return None;
+            }
+        };
+        Self::upload_compressed(ctx, &image, path)
+    }
+
+
+    /// Returns None if the device does not support the format of the image
+    fn upload_compressed(ctx: &GeeseContextHandle<AssetSystem>, image: &CompressedImage, path: &Path) -> Option<Self> {
+        let sys = ctx.get::<GraphicsSystem>();
+        let required_features = image.format.required_features();
+        if !sys.device().features().contains(required_features) {
+            warn!("The device does not support {:?} (needs {:?}), decoding '{}' instead", image.format, required_features, path.display());
+            return None;
+        };
+        Some(Self {
+            texture: TextureBundle::compressed(sys.device(), sys.queue(), image.format, image.extent(), &image.mip_levels)
+        })
+    }
+
+
+    fn decode_rgba(path: &Path) -> anyhow::Result<image::RgbaImage> {
+        Ok(image::open(path)
+            .with_context(|| format!("Could not load texture '{}'", path.display()))?
+            .to_rgba8())
+    }
+
+
+    fn upload_rgba(ctx: &GeeseContextHandle<AssetSystem>, img: &image::RgbaImage) -> Self {
+        let extent = Extent3d {width: img.width(), height: img.height(), depth_or_array_layers: 1};
+        let sys = ctx.get::<GraphicsSystem>();
+        Self {
+            texture: TextureBundle::default(sys.device(), sys.queue(), extent, img)
+        }
+    }
 }
 impl Asset for TextureAsset {
     fn from_path(ctx: &GeeseContextHandle<AssetSystem>, path: &Path) -> Self {
+        Self::try_from_path(ctx, path).unwrap_or_else(|e| panic!("{:#}", e))
+    }
+
+    /// DDS and KTX2 files are uploaded in their block compressed format when the device supports it,
+    /// everything else (and DXT compressed DDS files otherwise) gets decoded to RGBA
+    fn try_from_path(ctx: &GeeseContextHandle<AssetSystem>, path: &Path) -> anyhow::Result<Self> {
+        if CompressedImage::is_compressed_path(path) {
+            if let Some(texture) = Self::try_load_compressed(ctx, path) {
+                return Ok(texture);
+            };
+        };
+        let img = Self::decode_rgba(path)?;
+        Ok(Self::upload_rgba(ctx, &img))
+    }
+
+    fn memory_size(&self) -> u64 {
+        let texture = self.texture.texture();
+        let block_size = texture.format().block_copy_size(None).unwrap_or(4) as u64;
+        (0..texture.mip_level_count()).map(|level| {
+            let (blocks_x, blocks_y) = compressed_texture::mip_level_blocks(texture.format(), texture.width(), texture.height(), level);
+            blocks_x as u64 * blocks_y as u64 * block_size
+        }).sum()
+    }
+
+    /// A transparent 1x1 pixel
+    fn placeholder(ctx: &GeeseContextHandle<AssetSystem>) -> Option<Self> {
         let sys = ctx.get::<GraphicsSystem>();
-        let device = sys.device();
-        let queue = sys.queue();
+        Some(Self {
+            texture: TextureBundle::solid_color(sys.device(), sys.queue(), Srgba::new(0.0, 0.0, 0.0, 0.0))
+        })
+    }
+}
+impl AsyncAsset for TextureAsset {
+    type Decoded = DecodedTexture;
 
-        let img = image::open(path).unwrap().to_rgba8();
-        let extent = Extent3d {width: img.width(), height: img.height(), depth_or_array_layers: 1};
+    fn decode(path: &Path) -> anyhow::Result<DecodedTexture> {
+        if CompressedImage::is_compressed_path(path) {
+            let image = std::fs::read(path)
+                .context("Could not read the file")
+                .and_then(|data| CompressedImage::parse(&data));
+            match image {
+                Ok(image) => return Ok(DecodedTexture(DecodedTextureData::Compressed(image, path.to_path_buf()))),
+                Err(e) => warn!("Could not load '{}' as a compressed texture, decoding it instead: {:#}", path.display(), e)
+            };
+        };
+        Ok(DecodedTexture(DecodedTextureData::Rgba(Self::decode_rgba(path)?)))
+    }
 
-        Self {
-            texture: TextureBundle::default(device, queue, extent, &img)
+    fn from_decoded(ctx: &GeeseContextHandle<AssetSystem>, decoded: DecodedTexture) -> anyhow::Result<Self> {
+        match decoded.0 {
+            DecodedTextureData::Rgba(img) => Ok(Self::upload_rgba(ctx, &img)),
+            DecodedTextureData::Compressed(image, path) => match Self::upload_compressed(ctx, &image, &path) {
+                Some(texture) => Ok(texture),
+                None => Ok(Self::upload_rgba(ctx, &Self::decode_rgba(&path)?))
+            }
         }
     }
 }
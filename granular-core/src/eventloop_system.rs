@@ -1,38 +1,100 @@
 #![allow(unused)]
 
 use geese::*;
-use winit::event_loop::EventLoop;
+use winit::event_loop::{EventLoop, EventLoopProxy};
+
+
+/// An event that was sent from outside of the event loop using an `EventProxy`.
+/// Raises the wrapped event on the GeeseContext once it arrives.
+pub struct UserEvent(Box<dyn FnOnce(&mut GeeseContext) + Send>);
+impl UserEvent {
+    pub(crate) fn raise(self, ctx: &mut GeeseContext) {
+        (self.0)(ctx);
+    }
+}
+
+
+/// A clonable handle which can be sent to other threads (network, scripting, ...)
+/// to raise events inside of the engine.
+///
+/// Events are queued in the winit event loop and raised on the main thread during
+/// the next event loop iteration (after the update phases of that frame started).
+#[derive(Clone)]
+pub struct EventProxy {
+    proxy: EventLoopProxy<UserEvent>
+}
+impl EventProxy {
+    /// Queues an event to be raised on the GeeseContext.
+    /// Returns false if the event loop has already exited and the event was dropped.
+    pub fn send_event<T: 'static + Send + Sync>(&self, event: T) -> bool {
+        self.proxy.send_event(UserEvent(Box::new(move |ctx: &mut GeeseContext| {
+            ctx.flush().with(event);
+        }))).is_ok()
+    }
+}
 
 
 pub struct EventLoopSystem {
-    event_loop: Option<EventLoop<()>>
+    event_loop: Option<EventLoop<UserEvent>>,
+    proxy: EventLoopProxy<UserEvent>,
+    /// Set by events::RequestExit, the engine exits at the end of the current event loop iteration
+    exit_requested: bool
 }
 impl EventLoopSystem {
-    pub fn get(&self) -> &EventLoop<()> {
+    pub fn get(&self) -> &EventLoop<UserEvent> {
         if self.event_loop.is_none() {
             panic!("Event loop was already taken!");
         };
         self.event_loop.as_ref().unwrap()
     }
 
-    pub fn get_mut(&mut self) -> &mut EventLoop<()> {
+    pub fn get_mut(&mut self) -> &mut EventLoop<UserEvent> {
         if self.event_loop.is_none() {
             panic!("Event loop was already taken!");
         };
         self.event_loop.as_mut().unwrap()
     }
 
-    pub fn take(&mut self) -> EventLoop<()> {
+    pub fn take(&mut self) -> EventLoop<UserEvent> {
         if self.event_loop.is_none() {
             panic!("Event loop was already taken!");
         };
         self.event_loop.take().unwrap()
     }
+
+    /// Creates a new EventProxy, also works after the event loop was taken
+    pub fn event_proxy(&self) -> EventProxy {
+        EventProxy {
+            proxy: self.proxy.clone()
+        }
+    }
+
+
+    /// Makes the engine exit once the current frame is done, same as raising `events::RequestExit`
+    pub fn request_exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    pub fn exit_requested(&self) -> bool {
+        self.exit_requested
+    }
+
+
+    fn on_request_exit(&mut self, _: &crate::events::RequestExit) {
+        self.request_exit();
+    }
 }
 impl GeeseSystem for EventLoopSystem {
+    const EVENT_HANDLERS: EventHandlers<Self> = event_handlers()
+        .with(Self::on_request_exit);
+
     fn new(_ctx: GeeseContextHandle<Self>) -> Self {
+        let event_loop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
+        let proxy = event_loop.create_proxy();
         Self {
-            event_loop: Some(EventLoop::new().unwrap()),
+            event_loop: Some(event_loop),
+            proxy,
+            exit_requested: false
         }
     }
-}
\ No newline at end of file
+}
@@ -32,15 +32,24 @@ impl FileWatcher {
         info!("Watching {}", path.as_ref().display());
     }
 
+    /// Raises a single FilesChanged with all files that were modified since the last poll,
+    /// sorted and without duplicates, so that saving many files at once gets handled in one go
     pub fn poll(&mut self, _event: &crate::events::timing::Tick::<30>) {
-        if let Ok(event) = self.rx.try_recv() {
+        let mut paths = vec![];
+        while let Ok(event) = self.rx.try_recv() {
             match event {
                 Ok(event) => if let notify::EventKind::Modify(_kind) = event.kind {
-                    self.ctx.raise_event(events::FilesChanged::from_event(&event));
+                    paths.extend(event.paths);
                 },
                 Err(e) => error!("Watch error: {:?}", e),
             }
         }
+        if paths.is_empty() {
+            return;
+        };
+        paths.sort();
+        paths.dedup();
+        self.ctx.raise_event(events::FilesChanged { paths });
     }
 }
 impl GeeseSystem for FileWatcher {
@@ -0,0 +1,64 @@
+use geese::{GeeseContextHandle, GeeseSystem};
+use rustc_hash::FxHashMap as HashMap;
+
+
+/// Lightweight, always present view on the engine's frame counter and tick scheduling.
+/// Useful for deterministic behavior keyed to the frame (for example only doing work on even frames).
+pub struct FrameInfo {
+    frame: u64,
+    /// How often each `Tick<N>` was raised, by N
+    ticks: HashMap<u32, u64>,
+    /// How often each `FixedTick<N>` was raised, by N (in ms)
+    fixed_ticks: HashMap<u64, u64>
+}
+impl FrameInfo {
+    /// Index of the current frame, starting at 0
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// How often `events::timing::Tick<frames>` has been raised so far (including the current frame)
+    pub fn tick_count(&self, frames: u32) -> u64 {
+        self.ticks.get(&frames).copied().unwrap_or(0)
+    }
+
+    /// How often `events::timing::FixedTick<millis>` has been raised so far
+    pub fn fixed_tick_count(&self, millis: u64) -> u64 {
+        self.fixed_ticks.get(&millis).copied().unwrap_or(0)
+    }
+
+    /// All frame ticks that were raised at least once, with their count
+    pub fn tick_counts(&self) -> &HashMap<u32, u64> {
+        &self.ticks
+    }
+
+    /// All fixed ticks that were raised at least once, with their count
+    pub fn fixed_tick_counts(&self) -> &HashMap<u64, u64> {
+        &self.fixed_ticks
+    }
+
+
+    /// Called by the engine at the start of every frame
+    pub(crate) fn begin_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    /// Called by the engine right before a `Tick<frames>` gets raised
+    pub(crate) fn count_tick(&mut self, frames: u32) {
+        *self.ticks.entry(frames).or_default() += 1;
+    }
+
+    /// Called by the engine right before a `FixedTick<millis>` gets raised
+    pub(crate) fn count_fixed_tick(&mut self, millis: u64) {
+        *self.fixed_ticks.entry(millis).or_default() += 1;
+    }
+}
+impl GeeseSystem for FrameInfo {
+    fn new(_ctx: GeeseContextHandle<Self>) -> Self {
+        Self {
+            frame: 0,
+            ticks: HashMap::default(),
+            fixed_ticks: HashMap::default()
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use bytemuck_derive::{Pod, Zeroable};
+use palette::Srgba;
+use wgpu::{util::DeviceExt, BindGroup, Buffer, Color, Device, RenderPipeline, TextureFormat};
+
+use crate::{assets::{AssetHandle, TextureAsset}, AssetSystem};
+
+use super::{blit::BlitPipeline, RenderTarget};
+
+
+/// What the frame gets cleared with before anything else is drawn, see `Renderer::set_background`
+#[derive(Debug, Clone)]
+pub enum Background {
+    SolidColor(Srgba),
+    /// A vertical gradient from the top to the bottom of the window
+    Gradient {
+        top: Srgba,
+        bottom: Srgba
+    },
+    /// A texture stretched over the whole window
+    Texture(AssetHandle<TextureAsset>),
+    /// The frame is not cleared, so the contents of the previous frames accumulate
+    None
+}
+impl Default for Background {
+    fn default() -> Self {
+        Self::SolidColor(Srgba::new(0.0, 0.0, 0.0, 1.0))
+    }
+}
+
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct GradientUniform {
+    top: [f32; 4],
+    bottom: [f32; 4]
+}
+
+
+/// Records the pass that draws the Background, the pipelines are created on first use for the format of the frame
+pub(super) struct BackgroundRenderer {
+    gradient: Option<(TextureFormat, RenderPipeline, Buffer, BindGroup)>,
+    blit: Option<BlitPipeline>
+}
+impl BackgroundRenderer {
+    pub fn new() -> Self {
+        Self {
+            gradient: None,
+            blit: None
+        }
+    }
+
+
+    /// Draws the background into the view of target, which has the given format
+    pub fn render(&mut self, background: &Background, target: &mut RenderTarget, format: TextureFormat, asset_sys: &AssetSystem) {
+        match background {
+            Background::SolidColor(color) => {
+                target.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Background pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: target.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(linear_color(*color)),
+                            store: wgpu::StoreOp::Store
+                        }
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None
+                });
+            },
+            Background::Gradient { top, bottom } => {
+                let (_, pipeline, buffer, bind_group) = self.gradient_pipeline(target.device, format);
+                let to_array = |color: Srgba| {
+                    let color = color.into_linear::<f32, f32>();
+                    [color.red, color.green, color.blue, color.alpha]
+                };
+                target.queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[GradientUniform { top: to_array(*top), bottom: to_array(*bottom) }]));
+
+                let mut rpass = target.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Background pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: target.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(Color::BLACK),
+                            store: wgpu::StoreOp::Store
+                        }
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None
+                });
+                rpass.set_pipeline(pipeline);
+                rpass.set_bind_group(0, bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            },
+            Background::Texture(handle) => {
+                if self.blit.as_ref().map(|blit| blit.format()) != Some(format) {
+                    self.blit = Some(BlitPipeline::new(target.device, format, Some(wgpu::BlendState::ALPHA_BLENDING)));
+                };
+                let blit = self.blit.as_ref().unwrap();
+                // While the texture is still loading, this is its placeholder
+                let texture = asset_sys.get(handle).texture();
+                let bind_group = blit.bind_group(target.device, texture.view(), texture.sampler());
+                blit.draw(target.encoder, &bind_group, target.view, wgpu::LoadOp::Clear(Color::BLACK), None);
+            },
+            Background::None => ()
+        };
+    }
+
+
+    fn gradient_pipeline(&mut self, device: &Device, format: TextureFormat) -> &(TextureFormat, RenderPipeline, Buffer, BindGroup) {
+        if self.gradient.as_ref().map(|(gradient_format, ..)| *gradient_format) != Some(format) {
+            self.gradient = Some(Self::create_gradient_pipeline(device, format));
+        };
+        self.gradient.as_ref().unwrap()
+    }
+
+
+    fn create_gradient_pipeline(device: &Device, format: TextureFormat) -> (TextureFormat, RenderPipeline, Buffer, BindGroup) {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Background gradient buffer"),
+            contents: bytemuck::cast_slice(&[GradientUniform { top: [0.0; 4], bottom: [0.0; 4] }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Background gradient bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                }
+            ]
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Background gradient bind group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding()
+                }
+            ]
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Background gradient shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("background_gradient.wgsl").into())
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Background gradient pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Background gradient pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL
+                })],
+                compilation_options: Default::default()
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None
+        });
+        (format, pipeline, buffer, bind_group)
+    }
+}
+
+
+/// Clear colors are linear, like the output of the shaders
+fn linear_color(color: Srgba) -> Color {
+    let color = color.into_linear::<f32, f32>();
+    Color { r: color.red as f64, g: color.green as f64, b: color.blue as f64, a: color.alpha as f64 }
+}
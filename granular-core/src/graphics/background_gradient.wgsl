@@ -0,0 +1,33 @@
+// Fills the whole viewport with a vertical gradient, using a single full-screen triangle
+
+struct Gradient {
+    // Linear colors
+    top: vec4<f32>,
+    bottom: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> gradient: Gradient;
+
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    // (0, 0), (2, 0), (0, 2) in UV space covers the whole viewport
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return mix(gradient.top, gradient.bottom, clamp(in.uv.y, 0.0, 1.0));
+}
@@ -2,6 +2,7 @@
 #![allow(clippy::identity_op)]
 
 use std::collections::BinaryHeap;
+use std::hash::Hasher;
 use std::num::{NonZeroU32, NonZeroU64};
 use std::ops::Range;
 
@@ -15,17 +16,27 @@ use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 use glam::f32::Mat4;
 use palette::Srgba;
-use rustc_hash::FxHashMap as HashMap;
+use rustc_hash::{FxHashMap as HashMap, FxHasher};
 
 use crate::assets::{AssetHandle, AssetSystem, ShaderAsset, TextureAsset};
 
 use super::graphics_system::{GraphicsSystem, Vertex, VERTEX_SIZE};
-use super::{Camera, DynamicBuffer, TextureBundle};
+use super::debug_font::{self, DEBUG_FONT_COLUMNS, DEBUG_FONT_FIRST_CHAR, DEBUG_FONT_GLYPH_SIZE, DEBUG_FONT_NUM_GLYPHS};
+use super::{debug_markers, BitmapFont, Camera, DynamicBuffer, TextRenderer, TextureBundle};
 
 
 
+/// Gets called on every queued quad right before it is batched, see `BatchRenderer::set_quad_preprocessor`
+pub type QuadPreprocessor = Box<dyn FnMut(&mut Quad) + Send + Sync>;
+
+
+/// A texture slot of a batch: the texture (None for the white pixel) and the filter override of its sampler
+type BatchTexture = (Option<AssetHandle<TextureAsset>>, Option<wgpu::FilterMode>);
+
+
 struct Batch {
     helper_idx: usize,
+    /// Only contains the textures, the camera transform is bound separately at draw time
     bind_group: BindGroup,
     num_textures_used: usize,
     vertices_range: Range<u64>,
@@ -42,15 +53,217 @@ pub struct Quad {
     pub size: IVec2,
     /// If there is a texture set, this tints the texture
     pub color: Srgba,
-    pub texture: Option<AssetHandle<TextureAsset>>
+    /// Overrides color with one color per corner (top left, top right, bottom right, bottom left),
+    /// which get interpolated across the quad, for example for gradients
+    pub corner_colors: Option<[Srgba; 4]>,
+    pub texture: Option<AssetHandle<TextureAsset>>,
+    /// Mirrors the texture horizontally
+    pub flip_x: bool,
+    /// Mirrors the texture vertically
+    pub flip_y: bool,
+    /// Gets added to the texture coordinates, the texture wraps around inside of uv_rect so animating
+    /// this makes the texture scroll (for water, conveyor belts, ...)
+    pub uv_offset: Vec2,
+    /// The part of the texture that is shown (min and max UV, top left is 0, 0), for example a tile of an atlas
+    pub uv_rect: (Vec2, Vec2),
+    /// Radius of the corners in world units, 0 gives sharp corners
+    pub corner_radius: f32,
+    /// When greater than 0, only an outline of this thickness is drawn (following the rounded corners)
+    pub outline_thickness: f32,
+    /// Overrides the filtering of the textures sampler, for example Nearest for pixel art and Linear for smooth
+    /// gradients. None uses the sampler of the texture itself.
+    pub filter: Option<wgpu::FilterMode>,
+    /// Orders quads within the same layer, quads with a higher bias are drawn later (on top). 0 by default.
+    pub z_bias: i16,
+    /// Overrides center and size with the positions of the four corners, in the order bottom left, top left,
+    /// top right, bottom right of the texture. This allows rotated quads and (with two equal corners) triangles,
+    /// they need to be convex and listed clockwise (with y pointing up) like that order, otherwise their triangles
+    /// face backwards and get culled with `Face::Back`. Corner radius and outline do not apply to them.
+    pub vertices: Option<[IVec2; 4]>
 }
 impl Quad {
+    /// Returns a QuadBuilder, starting off with the default Quad
+    pub fn builder() -> QuadBuilder {
+        QuadBuilder::default()
+    }
+
     pub(crate) fn get_texture_index(&self) -> u64 {
         match &self.texture {
             None => 0,
             Some(tex_handle) => **tex_handle.id()
         }
     }
+
+
+    /// The positions of the corners in the order of `vertices`
+    fn corner_positions(&self) -> [IVec2; 4] {
+        let (center, size) = (self.center, self.size);
+        self.vertices.unwrap_or([
+            IVec2::new(center.x - size.x, center.y - size.y),
+            IVec2::new(center.x - size.x, center.y + size.y),
+            IVec2::new(center.x + size.x, center.y + size.y),
+            IVec2::new(center.x + size.x, center.y - size.y)
+        ])
+    }
+
+
+    /// The bounding box (min, max) of the quad in world space
+    fn bounds(&self) -> (IVec2, IVec2) {
+        let corners = self.corner_positions();
+        let min = corners.iter().fold(corners[0], |min, corner| min.min(*corner));
+        let max = corners.iter().fold(corners[0], |max, corner| max.max(*corner));
+        (min, max)
+    }
+}
+impl Default for Quad {
+    /// A white, untextured quad of size zero at the origin
+    fn default() -> Self {
+        Self {
+            center: IVec2::ZERO,
+            size: IVec2::ZERO,
+            color: Srgba::new(1.0, 1.0, 1.0, 1.0),
+            corner_colors: None,
+            texture: None,
+            flip_x: false,
+            flip_y: false,
+            uv_offset: Vec2::ZERO,
+            uv_rect: (Vec2::ZERO, Vec2::ONE),
+            corner_radius: 0.0,
+            outline_thickness: 0.0,
+            filter: None,
+            z_bias: 0,
+            vertices: None
+        }
+    }
+}
+
+
+/// Helper to fluently construct a Quad, use `Quad::builder()` to create one
+#[derive(Debug, Clone, Default)]
+pub struct QuadBuilder {
+    quad: Quad
+}
+impl QuadBuilder {
+    pub fn center(mut self, center: IVec2) -> Self {
+        self.quad.center = center;
+        self
+    }
+
+    pub fn size(mut self, size: IVec2) -> Self {
+        self.quad.size = size;
+        self
+    }
+
+    pub fn color(mut self, color: Srgba) -> Self {
+        self.quad.color = color;
+        self
+    }
+
+    /// Colors in the order top left, top right, bottom right, bottom left
+    pub fn corner_colors(mut self, corner_colors: [Srgba; 4]) -> Self {
+        self.quad.corner_colors = Some(corner_colors);
+        self
+    }
+
+    /// Fades from top to bottom
+    pub fn vertical_gradient(self, top: Srgba, bottom: Srgba) -> Self {
+        self.corner_colors([top, top, bottom, bottom])
+    }
+
+    /// Fades from left to right
+    pub fn horizontal_gradient(self, left: Srgba, right: Srgba) -> Self {
+        self.corner_colors([left, right, right, left])
+    }
+
+    pub fn texture(mut self, texture: AssetHandle<TextureAsset>) -> Self {
+        self.quad.texture = Some(texture);
+        self
+    }
+
+    pub fn flip_x(mut self, flip_x: bool) -> Self {
+        self.quad.flip_x = flip_x;
+        self
+    }
+
+    pub fn flip_y(mut self, flip_y: bool) -> Self {
+        self.quad.flip_y = flip_y;
+        self
+    }
+
+    pub fn uv_offset(mut self, uv_offset: Vec2) -> Self {
+        self.quad.uv_offset = uv_offset;
+        self
+    }
+
+    pub fn uv_rect(mut self, min: Vec2, max: Vec2) -> Self {
+        self.quad.uv_rect = (min, max);
+        self
+    }
+
+    pub fn corner_radius(mut self, corner_radius: f32) -> Self {
+        self.quad.corner_radius = corner_radius;
+        self
+    }
+
+    pub fn outline_thickness(mut self, outline_thickness: f32) -> Self {
+        self.quad.outline_thickness = outline_thickness;
+        self
+    }
+
+    pub fn filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.quad.filter = Some(filter);
+        self
+    }
+
+    pub fn z_bias(mut self, z_bias: i16) -> Self {
+        self.quad.z_bias = z_bias;
+        self
+    }
+
+    /// See `Quad::vertices`
+    pub fn vertices(mut self, vertices: [IVec2; 4]) -> Self {
+        self.quad.vertices = Some(vertices);
+        self
+    }
+
+    pub fn build(self) -> Quad {
+        self.quad
+    }
+}
+impl Quad {
+    /// Feeds everything that ends up in the vertices into the hasher
+    fn hash_into(&self, hasher: &mut FxHasher) {
+        hasher.write_i32(self.center.x);
+        hasher.write_i32(self.center.y);
+        hasher.write_i32(self.size.x);
+        hasher.write_i32(self.size.y);
+        let color: [f32; 4] = self.color.into();
+        color.iter().for_each(|c| hasher.write_u32(c.to_bits()));
+        hasher.write_u8(self.corner_colors.is_some() as u8);
+        self.corner_colors.iter().flatten().for_each(|corner| {
+            let color: [f32; 4] = (*corner).into();
+            color.iter().for_each(|c| hasher.write_u32(c.to_bits()));
+        });
+        hasher.write_u64(self.get_texture_index());
+        hasher.write_u8(self.flip_x as u8 | (self.flip_y as u8) << 1);
+        [self.uv_offset, self.uv_rect.0, self.uv_rect.1].iter().for_each(|v| {
+            hasher.write_u32(v.x.to_bits());
+            hasher.write_u32(v.y.to_bits());
+        });
+        hasher.write_u32(self.corner_radius.to_bits());
+        hasher.write_u32(self.outline_thickness.to_bits());
+        hasher.write_u8(match self.filter {
+            None => 0,
+            Some(wgpu::FilterMode::Nearest) => 1,
+            Some(wgpu::FilterMode::Linear) => 2
+        });
+        hasher.write_i16(self.z_bias);
+        hasher.write_u8(self.vertices.is_some() as u8);
+        self.vertices.iter().flatten().for_each(|vertex| {
+            hasher.write_i32(vertex.x);
+            hasher.write_i32(vertex.y);
+        });
+    }
 }
 impl PartialEq for Quad {
     fn eq(&self, other: &Self) -> bool {
@@ -62,7 +275,7 @@ impl Eq for Quad {}
 
 
 /// A simple wrapper that stores a quad and a corresponding layer
-/// for use in the binary heap
+/// for use in the binary heap, ordered by the layer and then the z_bias of the quad
 #[derive(Debug, PartialEq, Eq)]
 struct BatchQuadEntry {
     layer: i32,
@@ -70,12 +283,68 @@ struct BatchQuadEntry {
 }
 impl PartialOrd for BatchQuadEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.layer.cmp(&other.layer))
+        Some(self.cmp(other))
     }
 }
 impl Ord for BatchQuadEntry {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         self.layer.cmp(&other.layer)
+            .then(self.quad.z_bias.cmp(&other.quad.z_bias))
+    }
+}
+
+
+
+/// The look of a bar drawn with `BatchRenderer::draw_bar`, by default it is horizontal, has sharp corners and no border
+#[derive(Debug, Clone, Copy)]
+pub struct BarStyle {
+    /// Radius of the corners in world units, the filled part uses it as well
+    pub corner_radius: f32,
+    /// When greater than 0, an outline of this thickness is drawn around the bar and the filled part stays inside of it
+    pub border_thickness: f32,
+    pub border_color: Srgba,
+    /// Fills from the bottom up instead of from left to right
+    pub vertical: bool
+}
+impl Default for BarStyle {
+    fn default() -> Self {
+        Self {
+            corner_radius: 0.0,
+            border_thickness: 0.0,
+            border_color: Srgba::new(1.0, 1.0, 1.0, 1.0),
+            vertical: false
+        }
+    }
+}
+
+
+/// How layers are mapped to depth values, see `BatchRenderer::set_layer_range`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerDepthMapping {
+    /// Every layer in the range gets the same share of the depth range
+    Linear,
+    /// Layers close to the lower end of the range get more precision, useful when the range is very large
+    Logarithmic
+}
+
+
+
+/// How the fragments of a layer are combined with what has already been drawn
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum AlphaMode {
+    /// Regular alpha blending
+    #[default]
+    Blend,
+    /// No blending, fragments with a lower alpha get discarded (independent of draw order)
+    Cutoff(f32)
+}
+impl AlphaMode {
+    /// Fragments below this alpha get discarded, with 0.0 nothing is discarded
+    fn threshold(&self) -> f32 {
+        match self {
+            AlphaMode::Blend => 0.0,
+            AlphaMode::Cutoff(threshold) => *threshold
+        }
     }
 }
 
@@ -84,12 +353,41 @@ impl Ord for BatchQuadEntry {
 #[derive(Debug)]
 struct BatchHelper {
     num_textures_used: usize,
+    alpha_mode: AlphaMode,
     layout: BindGroupLayout,
     pipeline: RenderPipeline
 }
 
 
 
+/// A camera transform (and optionally a viewport) that batches can be drawn with, see
+/// `BatchRenderer::render_batch_layers_with_view`. Each view has its own uniform buffer,
+/// so the same batches can be drawn multiple times per frame from different cameras.
+pub struct BatchView {
+    transform_buffer: Buffer,
+    bind_group: BindGroup,
+    /// Position and size in pixels, None covers the whole frame
+    viewport: Option<(Vec2, Vec2)>
+}
+impl BatchView {
+    /// Sets the transform from world space to clip space (see `Camera::canvas_transform`)
+    pub fn set_transform(&self, queue: &wgpu::Queue, transform: Mat4) {
+        queue.write_buffer(&self.transform_buffer, 0, bytemuck::cast_slice(&[transform]));
+    }
+
+
+    /// Restricts drawing to the rectangle at pos with size (in pixels), None draws to the whole frame
+    pub fn set_viewport(&mut self, viewport: Option<(Vec2, Vec2)>) {
+        self.viewport = viewport;
+    }
+
+    pub fn viewport(&self) -> Option<(Vec2, Vec2)> {
+        self.viewport
+    }
+}
+
+
+
 /// A simple batch renderer that supports layering of quads
 pub struct BatchRenderer {
     ctx: GeeseContextHandle<Self>,
@@ -97,6 +395,8 @@ pub struct BatchRenderer {
     vertex_buffer: DynamicBuffer<Vertex>,
     index_buffer: Buffer,
     index_format: IndexFormat,
+    /// For how many quads the index buffer contains indices, it grows with the largest batch
+    index_capacity: usize,
     // Links the asset id (1st u64) of a texture to its position in the internal
     // texture array (2nd u64) (and its handle, for easier access)
     texture_slots: HashMap<u64, (u64, AssetHandle<TextureAsset>)>,
@@ -108,37 +408,128 @@ pub struct BatchRenderer {
     batch_helpers: Vec<BatchHelper>,
     
     bind_group: (BindGroup, BindGroupLayout),
+    /// Layout of the bind group (group 1) holding the camera transform
+    camera_layout: BindGroupLayout,
+    /// Binds the transform buffer of the Camera system, used by render_batch_layers
+    camera_bind_group: BindGroup,
 
     render_pipeline: RenderPipeline,
     shader_handle: AssetHandle<ShaderAsset>,
     clear_color: Color,
-
-    white_pixel: TextureBundle
+    /// Layers that use alpha testing instead of alpha blending
+    layer_alpha_modes: HashMap<i32, AlphaMode>,
+    /// False if the device does not support texture binding arrays, then each batch only binds one texture
+    texture_arrays: bool,
+    /// Which faces get culled, quads are always emitted counter-clockwise (with y pointing up)
+    cull_mode: Option<wgpu::Face>,
+    /// Skip quads that lie completely outside of the cameras view
+    frustum_culling: bool,
+    /// Whether the edges of rounded and outlined quads fade out over a pixel instead of being cut off
+    shape_antialiasing: bool,
+    /// Whether batches are drawn with a depth buffer, the depth is computed from the layer
+    depth_test: bool,
+    depth_texture: Option<(Texture, TextureView)>,
+    /// Clears the viewport when render_batch_layers gets a clear color together with a viewport, created lazily
+    clear_rect_pipeline: Option<RenderPipeline>,
+    /// The layers (inclusive) that are mapped onto the depth range, layers outside get clamped
+    layer_range: (i32, i32),
+    layer_depth_mapping: LayerDepthMapping,
+
+    /// Hash over all quads (and their layers) queued this frame, in the order of the draw_quad calls
+    queued_hash: u64,
+    /// queued_hash of the quads the current batches were created from, None if they need to be recreated
+    batched_hash: Option<u64>,
+    /// The batches were recreated and the vertices still need to be uploaded
+    vertices_dirty: bool,
+    /// How often create_batches actually rebuilt the batches
+    rebatch_count: u64,
+    quad_preprocessor: Option<QuadPreprocessor>,
+
+    white_pixel: TextureBundle,
+    /// Samplers used for quads that override the filtering of their texture
+    nearest_sampler: Sampler,
+    linear_sampler: Sampler,
+    /// Built-in 8x8 ASCII font used by draw_debug_text
+    debug_font: BitmapFont
 }
 impl BatchRenderer {
     const MAX_QUAD_COUNT: usize = 1000;
     const MAX_VERTEX_COUNT: usize = BatchRenderer::MAX_QUAD_COUNT * 4;
-    const MAX_INDEX_COUNT: usize = BatchRenderer::MAX_QUAD_COUNT * 6;
+    /// With 16 bit indices, a batch can only address this many quads (65536 vertices)
+    const MAX_QUADS_UINT16: usize = (u16::MAX as usize + 1) / 4;
     const MAX_TEXTURE_COUNT: usize = 15;
+    const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    /// How many translucent quads draw_quad_with_shadow stacks at most for the blur
+    const MAX_SHADOW_STEPS: i32 = 6;
     
     
+    /// Discards the queued quads, the batches are kept in case the next frame queues the same quads
     pub(super) fn end_frame(&mut self) {
-        self.batches.clear();
         self.quads_to_draw.clear();
-        self.vertices_to_draw.clear();
+        self.queued_hash = 0;
+    }
+
+
+    /// Returns true if the queued quads differ from the ones the batches were created from (or a setting
+    /// that affects the batches changed). With frustum culling enabled the batches are always recreated,
+    /// since they depend on the camera. Otherwise a moving camera only updates its uniform.
+    /// A quad preprocessor also forces the batches to be recreated every frame, since its output can change.
+    pub fn needs_rebatch(&self) -> bool {
+        self.frustum_culling || self.quad_preprocessor.is_some() || self.batched_hash != Some(self.queued_hash)
+    }
+
+
+    /// How often the batches have been recreated, for profiling
+    pub fn rebatch_count(&self) -> u64 {
+        self.rebatch_count
     }
 
 
-    /// Handles batching and issuing draw calls accordingly
+    /// Registers a closure that can modify every queued quad right before it gets batched (before frustum culling),
+    /// for effects that apply to everything uniformly like a global tint, a screen shake offset or a color-blind filter.
+    /// It runs once per quad per frame. Per-quad logic that belongs to gameplay should set the quad up in draw_quad instead.
+    /// Replaces the previous preprocessor.
+    pub fn set_quad_preprocessor(&mut self, preprocessor: QuadPreprocessor) {
+        self.quad_preprocessor = Some(preprocessor);
+        self.invalidate_batches();
+    }
+
+    pub fn remove_quad_preprocessor(&mut self) {
+        self.quad_preprocessor = None;
+        self.invalidate_batches();
+    }
+
+
+    /// Forces the batches to be recreated during the next frame
+    fn invalidate_batches(&mut self) {
+        self.batched_hash = None;
+    }
+
+
+    /// Handles batching and issuing draw calls accordingly, does nothing if the batches of the last frame can be reused
     pub(super) fn create_batches(&mut self) {
+        if !self.needs_rebatch() {
+            return;
+        };
+        self.batches.clear();
+        self.vertices_to_draw.clear();
+        self.batched_hash = Some(self.queued_hash);
+        self.vertices_dirty = true;
+        self.rebatch_count += 1;
+
         let cam = self.ctx.get::<Camera>();
-        let shaderglobals = cam.canvas_transform_buffer();
+        // visible_world_rect already is the bounding box of a rotated view, so it needs no extra margin
+        let visible_rect = match self.frustum_culling {
+            true => Some(cam.visible_world_rect()),
+            false => None
+        };
 
-        /// Creates a new Batch object from the given parameters, uses the 1x1 white pixel when a texture is None
+        /// Creates a new Batch object from the given parameters, uses the 1x1 white pixel when a texture is None.
+        /// Slots with a filter override use the matching shared sampler instead of the textures own one.
         /// automatically creates a new bind group for each batch and only a new bindgroup layout/ render pipeline,
         /// when the amount of textures inside the bind group has changed (reuses existing ones if not)
         let mut create_new_batch = 
-        | textures: &Vec<Option<AssetHandle<TextureAsset>>>,
+        | textures: &Vec<BatchTexture>,
           vertices_range: Range<u64>,
           indices_end: u32,
           batch_layer: i32 | {
@@ -147,30 +538,30 @@ impl BatchRenderer {
             let mut samplers = vec![];
             
             // Populate views and samplers with the actual data, using the asset system
-            textures.iter().for_each(|tex| {
-                match tex {
+            textures.iter().for_each(|(tex, filter)| {
+                let texture = match tex {
                     // Use the 1x1 white pixel texture instead
-                    None => {
-                        views.push(self.white_pixel.view());
-                        samplers.push(self.white_pixel.sampler());
-                    },
-                    Some(tex_handle) => {
-                        let asset = asset_sys.get(tex_handle);
-                        views.push(asset.texture().view());
-                        samplers.push(asset.texture().sampler());
-                    }
+                    None => &self.white_pixel,
+                    Some(tex_handle) => asset_sys.get(tex_handle).texture()
                 };
+                views.push(texture.view());
+                samplers.push(match filter {
+                    None => texture.sampler(),
+                    Some(wgpu::FilterMode::Nearest) => &self.nearest_sampler,
+                    Some(wgpu::FilterMode::Linear) => &self.linear_sampler
+                });
             });
 
             // See if another batch has already created a bind group layout with that many textures
-            // use that if possible
+            // (and the same alpha mode) use that if possible
             let num_textures_used = textures.len();
+            let alpha_mode = self.layer_alpha_modes.get(&batch_layer).copied().unwrap_or_default();
             let mut helper_idx = -1;
             let graphics_sys = self.ctx.get::<GraphicsSystem>();
             let device = graphics_sys.device();
             // If an existing helper already has the correct pipeline and BG layout for this batch use it
             self.batch_helpers.iter().enumerate().find(|(h_index, helper)| {
-                if helper.num_textures_used == num_textures_used {
+                if helper.num_textures_used == num_textures_used && helper.alpha_mode == alpha_mode {
                     helper_idx = *h_index as i32;
                     true
                 } else {
@@ -179,19 +570,18 @@ impl BatchRenderer {
             });
             // Otherwise create a new BatchHelper and use that helper
             if helper_idx == -1 {
-                let layout = Self::create_bind_group_layout(device, views.len() as u32, samplers.len() as u32);
                 let shader = asset_sys.get(&self.shader_handle);
-                let color_state = Some(wgpu::ColorTargetState {
-                    format: graphics_sys.surface_config().format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                });
-                let pipeline = Self::create_render_pipeline(device, &layout, shader.module(), color_state);
-                self.batch_helpers.push(BatchHelper {
+                self.batch_helpers.push(Self::create_batch_helper(
+                    device,
+                    shader.module(),
+                    graphics_sys.render_format(),
                     num_textures_used,
-                    layout,
-                    pipeline
-                });
+                    alpha_mode,
+                    self.cull_mode,
+                    self.texture_arrays,
+                    self.depth_test,
+                    self.shape_antialiasing,
+                    &self.camera_layout));
                 helper_idx = self.batch_helpers.len() as i32 - 1;
             };
 
@@ -202,7 +592,7 @@ impl BatchRenderer {
             trace!("    - Num textures: {}", num_textures_used);
             self.batches.push(Batch {
                 helper_idx: helper_idx as usize,
-                bind_group: Self::create_bind_group(device, &self.batch_helpers[helper_idx as usize].layout, shaderglobals, &views, &samplers),
+                bind_group: Self::create_bind_group(device, &self.batch_helpers[helper_idx as usize].layout, &views, &samplers, self.texture_arrays),
                 num_textures_used,
                 vertices_range,
                 indices_end,
@@ -211,9 +601,15 @@ impl BatchRenderer {
         };
 
         let total_quads_to_draw = self.quads_to_draw.len();
+        let max_textures_per_batch = self.max_textures_per_batch();
+        let max_quads_per_batch = match self.index_format {
+            IndexFormat::Uint16 => Self::MAX_QUADS_UINT16,
+            IndexFormat::Uint32 => usize::MAX
+        };
+        let (layer_range, layer_depth_mapping) = (self.layer_range, self.layer_depth_mapping);
 
         let mut last_batch_end_quad_idx: u64 = 0;
-        let mut textures_in_batch: Vec<Option<AssetHandle<TextureAsset>>> = vec![];        
+        let mut textures_in_batch: Vec<BatchTexture> = vec![];        
         let mut previous_layer = 0;
         let mut first_iteration = true;
         let mut num_quads_in_batch = 0;
@@ -225,7 +621,17 @@ impl BatchRenderer {
                 break;
             };
             let entry = current_quad.unwrap().0;
-            let quad = entry.quad; let current_layer = entry.layer;
+            let mut quad = entry.quad; let current_layer = entry.layer;
+            if let Some(preprocessor) = &mut self.quad_preprocessor {
+                preprocessor(&mut quad);
+            };
+            if let Some((view_min, view_max)) = visible_rect {
+                let (quad_min, quad_max) = quad.bounds();
+                let (quad_min, quad_max) = (quad_min.as_vec2(), quad_max.as_vec2());
+                if quad_max.x < view_min.x || quad_min.x > view_max.x || quad_max.y < view_min.y || quad_min.y > view_max.y {
+                    continue;
+                };
+            };
             // Since the quads are ordered by layer, this means that we have now iterated through
             // all quads in this layer and we need to create a batch with the last ones
             if !first_iteration && current_layer != previous_layer {
@@ -236,35 +642,33 @@ impl BatchRenderer {
                 last_batch_end_quad_idx = total_quads_processed;
                 num_quads_in_batch = 0;
             }
+            // The indices of a batch cannot address more vertices than its index format allows
+            if num_quads_in_batch >= max_quads_per_batch {
+                let vertices_range = (last_batch_end_quad_idx * 4)..(total_quads_processed * 4);
+                let indices_end = num_quads_in_batch as u32 * 6;
+                create_new_batch(&textures_in_batch, vertices_range, indices_end, current_layer);
+                textures_in_batch.clear();
+                last_batch_end_quad_idx = total_quads_processed;
+                num_quads_in_batch = 0;
+            };
 
 
-            let quad_pos = quad.center;
-            //info!("Old quad pos: {}   New pos: {}", quad.center, quad_pos);
-            let x = quad_pos.x; let y = quad_pos.y;
             let w = quad.size.x; let h = quad.size.y;
-            let color: [f32; 4] = quad.color.into();
-            
-            let mut texture_in_batch = false;
-            // Custom comparison to see if this quads texture was already in this batches textures
-            for tex in textures_in_batch.iter() {
-                match &quad.texture {
-                    None => {
-                        if tex.is_none() {
-                            texture_in_batch = true;
-                        }
-                    },
-                    Some(quad_tex_handle) => {
-                        if let Some(tex_handle) = tex {
-                            if **tex_handle.id() == **quad_tex_handle.id() {
-                                texture_in_batch = true;
-                            }
-                        };
-                    }
-                }
+            let [pos_bottom_left, pos_top_left, pos_top_right, pos_bottom_right] = quad.corner_positions();
+            let [top_left, top_right, bottom_right, bottom_left]: [[f32; 4]; 4] = match quad.corner_colors {
+                Some(corners) => corners.map(|c| c.into()),
+                None => [quad.color.into(); 4]
             };
+            
+            // See if this quads texture (with the same filter) already has a slot in this batch,
+            // the same texture with a different filter needs its own slot since it uses another sampler
+            let quad_tex_id = quad.texture.as_ref().map(|handle| **handle.id());
+            let existing_slot = textures_in_batch.iter().position(|(tex, filter)| {
+                *filter == quad.filter && tex.as_ref().map(|handle| **handle.id()) == quad_tex_id
+            });
 
             // In case we run out of bind slots, we create a new batch (and therefore new bind group)
-            if textures_in_batch.len() >= Self::MAX_TEXTURE_COUNT && !texture_in_batch {
+            if textures_in_batch.len() >= max_textures_per_batch && existing_slot.is_none() {
                 let vertices_range = (last_batch_end_quad_idx * 4)..(total_quads_processed * 4);
                 let indices_end = num_quads_in_batch as u32 * 6;
                 create_new_batch(&textures_in_batch, vertices_range, indices_end, current_layer);
@@ -273,17 +677,35 @@ impl BatchRenderer {
                 num_quads_in_batch = 0;
             };
 
-            if !texture_in_batch {
-                textures_in_batch.push(quad.texture.clone());
+            let tex_index = match existing_slot {
+                Some(slot) => slot as u64,
+                None => {
+                    textures_in_batch.push((quad.texture.clone(), quad.filter));
+                    textures_in_batch.len() as u64 - 1
+                }
             };
-            let tex_index = textures_in_batch.len() as u64 - 1;
+
+            // Flipping is done by swapping the texture coordinates
+            let (uv_min, uv_max) = quad.uv_rect;
+            let (u_left, u_right) = if quad.flip_x { (uv_max.x, uv_min.x) } else { (uv_min.x, uv_max.x) };
+            let (v_bottom, v_top) = if quad.flip_y { (uv_min.y, uv_max.y) } else { (uv_max.y, uv_min.y) };
+            // The shader wraps the offset coordinates back into the uv rect
+            let uv_offset = quad.uv_offset;
+            let (wrap_min, wrap_max) = if uv_offset != Vec2::ZERO { (uv_min, uv_max) } else { (Vec2::ZERO, Vec2::ZERO) };
+            let depth = Self::layer_to_depth(current_layer, layer_range, layer_depth_mapping);
 
             // Add the vertices of the quad to vertices, respecting size and attributes
             self.vertices_to_draw.reserve(4);
-            self.vertices_to_draw.push(Vertex::new(IVec2::new(x - w, y - h), color, Vec2::new(0.0, 1.0), tex_index));
-            self.vertices_to_draw.push(Vertex::new(IVec2::new(x - w, y + h), color, Vec2::new(0.0, 0.0), tex_index));
-            self.vertices_to_draw.push(Vertex::new(IVec2::new(x + w, y + h), color, Vec2::new(1.0, 0.0), tex_index));
-            self.vertices_to_draw.push(Vertex::new(IVec2::new(x + w, y - h), color, Vec2::new(1.0, 1.0), tex_index));
+            let half_size = Vec2::new(w as f32, h as f32);
+            let (radius, outline) = (quad.corner_radius, quad.outline_thickness);
+            self.vertices_to_draw.push(Vertex::new(pos_bottom_left, bottom_left, Vec2::new(u_left, v_bottom) + uv_offset, tex_index, depth)
+                .with_shape(Vec2::new(-half_size.x, -half_size.y), half_size, radius, outline).with_uv_wrap(wrap_min, wrap_max));
+            self.vertices_to_draw.push(Vertex::new(pos_top_left, top_left, Vec2::new(u_left, v_top) + uv_offset, tex_index, depth)
+                .with_shape(Vec2::new(-half_size.x, half_size.y), half_size, radius, outline).with_uv_wrap(wrap_min, wrap_max));
+            self.vertices_to_draw.push(Vertex::new(pos_top_right, top_right, Vec2::new(u_right, v_top) + uv_offset, tex_index, depth)
+                .with_shape(Vec2::new(half_size.x, half_size.y), half_size, radius, outline).with_uv_wrap(wrap_min, wrap_max));
+            self.vertices_to_draw.push(Vertex::new(pos_bottom_right, bottom_right, Vec2::new(u_right, v_bottom) + uv_offset, tex_index, depth)
+                .with_shape(Vec2::new(half_size.x, -half_size.y), half_size, radius, outline).with_uv_wrap(wrap_min, wrap_max));
 
             first_iteration = false;
             previous_layer = current_layer;
@@ -298,14 +720,141 @@ impl BatchRenderer {
     }
 
 
+    /// Maps the layer into the depth range [0, 1], higher layers get a lower depth (so they are in front)
+    fn layer_to_depth(layer: i32, layer_range: (i32, i32), mapping: LayerDepthMapping) -> f32 {
+        let (min, max) = layer_range;
+        let span = (max as f64 - min as f64).max(1.0);
+        let offset = (layer.clamp(min, max) as f64 - min as f64).max(0.0);
+        let t = match mapping {
+            LayerDepthMapping::Linear => offset / span,
+            LayerDepthMapping::Logarithmic => (1.0 + offset).ln() / (1.0 + span).ln()
+        };
+        (1.0 - t) as f32
+    }
+
+
+    /// How many textures can be bound in a single batch
+    fn max_textures_per_batch(&self) -> usize {
+        match self.texture_arrays {
+            true => Self::MAX_TEXTURE_COUNT,
+            false => 1
+        }
+    }
+
+
     pub(super) fn prepare_to_render(&mut self) {
-        // Write the data from vertices to the vertex buffer
+        // Write the data from vertices to the vertex buffer (only when the batches changed)
         let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
-        self.vertex_buffer.write(&graphics_sys, 0, bytemuck::cast_slice(&self.vertices_to_draw));
+        if self.vertices_dirty {
+            self.vertex_buffer.write(&graphics_sys, 0, bytemuck::cast_slice(&self.vertices_to_draw));
+            self.vertices_dirty = false;
+
+            // Every batch starts indexing at 0, so the indices need to cover the largest batch
+            let largest_batch = self.batches.iter().map(|batch| batch.indices_end as usize / 6).max().unwrap_or(0);
+            if largest_batch > self.index_capacity {
+                self.index_capacity = largest_batch.max(self.index_capacity * 2);
+                self.index_buffer = Self::create_index_buffer(graphics_sys.device(), self.index_capacity, self.index_format);
+            };
+        };
+
+        // The depth texture needs to have the same size as the frame
+        if self.depth_test {
+            if let Some(framedata) = graphics_sys.frame_data_mut() {
+                let size = framedata.0.texture.size();
+                if self.depth_texture.as_ref().map(|(texture, _)| texture.size()) != Some(size) {
+                    let texture = graphics_sys.device().create_texture(&wgpu::TextureDescriptor {
+                        label: Some("BatchRenderer depth texture"),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: Self::DEPTH_FORMAT,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                        view_formats: &[]
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    self.depth_texture = Some((texture, view));
+                };
+            };
+        };
+
+        if self.clear_rect_pipeline.is_none() {
+            self.clear_rect_pipeline = Some(Self::create_clear_rect_pipeline(graphics_sys.device(), graphics_sys.render_format(), self.depth_test));
+        };
     }
 
 
-    pub fn render_batch_layers(&mut self, layer_range: Range<i32>, clear: bool) {
+    /// Clears the depth texture (if depth testing is enabled) without touching the colors of the frame,
+    /// the Renderer does this since it clears the frame with its background instead of the first batch pass
+    pub(super) fn clear_depth(&self) {
+        let (true, Some((_, depth_view))) = (self.depth_test, &self.depth_texture) else {
+            return;
+        };
+        let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
+        let Some(encoder) = graphics_sys.encoder_mut() else {
+            return;
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("BatchRenderer depth clear pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+    }
+
+
+    /// Draws all batches inside of layer_range using the Camera system. With a clear color, the frame gets
+    /// cleared first, otherwise the batches are drawn on top of what is already there. The viewport (position
+    /// and size in pixels) restricts drawing, and clearing, to that rectangle (for example for split screen).
+    pub fn render_batch_layers(&mut self, layer_range: Range<i32>, clear: Option<Srgba>, viewport: Option<(Vec2, Vec2)>) {
+        self.render_batches(layer_range, clear, &self.camera_bind_group, viewport);
+    }
+
+
+    /// The old form of render_batch_layers, where clear means clearing the whole frame to black
+    #[deprecated(note = "use render_batch_layers, which takes a clear color and a viewport")]
+    pub fn render_batch_layers_legacy(&mut self, layer_range: Range<i32>, clear: bool) {
+        let clear = clear.then_some(Srgba::new(0.0, 0.0, 0.0, 1.0));
+        self.render_batch_layers(layer_range, clear, None);
+    }
+
+
+    /// Draws all batches inside of layer_range with the transform and viewport of view instead of
+    /// the Camera system. This can be called multiple times per frame with different views
+    /// (split screen, minimaps, ...) since the batches themselves do not depend on the camera.
+    ///
+    /// Note: Frustum culling still uses the Camera system, so disable it when views show other areas.
+    pub fn render_batch_layers_with_view(&mut self, layer_range: Range<i32>, clear: Option<Srgba>, view: &BatchView) {
+        self.render_batches(layer_range, clear, &view.bind_group, view.viewport);
+    }
+
+
+    /// Creates a new view with an identity transform that covers the whole frame
+    pub fn create_view(&self) -> BatchView {
+        let graphics_sys = self.ctx.get::<GraphicsSystem>();
+        let transform_buffer = graphics_sys.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("BatchView transform buffer"),
+            contents: bytemuck::cast_slice(&[Mat4::IDENTITY]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_camera_bind_group(graphics_sys.device(), &self.camera_layout, &transform_buffer);
+        BatchView {
+            transform_buffer,
+            bind_group,
+            viewport: None
+        }
+    }
+
+
+    fn render_batches(&self, layer_range: Range<i32>, clear: Option<Srgba>, camera_bind_group: &BindGroup, viewport: Option<(Vec2, Vec2)>) {
         let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
         let framedata = graphics_sys.frame_data_mut();
         if framedata.is_none() {
@@ -313,24 +862,72 @@ impl BatchRenderer {
             return;
         };
         let framedata = framedata.unwrap();
-        
+        let frame_size = framedata.0.texture.size();
+
+        // Clear colors are linear, like the output of the shaders
+        let clear_color = clear.map(|color| {
+            let color = color.into_linear::<f32, f32>();
+            Color { r: color.red as f64, g: color.green as f64, b: color.blue as f64, a: color.alpha as f64 }
+        });
+        // LoadOp::Clear always clears the whole frame, a viewport gets cleared by drawing into it instead
+        let clear_frame = clear_color.filter(|_| viewport.is_none());
+        // The scissor rectangle has to lie inside of the frame
+        let scissor = match viewport {
+            Some((pos, size)) => {
+                let min = pos.max(Vec2::ZERO).floor();
+                let max = (pos + size).min(Vec2::new(frame_size.width as f32, frame_size.height as f32)).ceil();
+                if max.x <= min.x || max.y <= min.y {
+                    return;
+                };
+                Some((min.x as u32, min.y as u32, (max.x - min.x) as u32, (max.y - min.y) as u32))
+            },
+            None => None
+        };
+
+        debug_markers::push_group(&mut framedata.2, || {
+            let batch_count = self.batches.iter().filter(|b| layer_range.contains(&b.layer)).count();
+            format!("BatchRenderer layers {:?} ({} batches)", layer_range, batch_count)
+        });
         let mut rpass = framedata.2.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("BatchRenderer render pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: &framedata.1,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: match clear {
-                        true => wgpu::LoadOp::Clear(Color::BLACK),
-                        false => wgpu::LoadOp::Load
+                    load: match clear_frame {
+                        Some(color) => wgpu::LoadOp::Clear(color),
+                        None => wgpu::LoadOp::Load
                     },
                     store: wgpu::StoreOp::Store,
                 },
             })],
-            depth_stencil_attachment: None,
+            depth_stencil_attachment: match (self.depth_test, &self.depth_texture) {
+                (true, Some((_, depth_view))) => Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: match clear_frame {
+                            Some(_) => wgpu::LoadOp::Clear(1.0),
+                            None => wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None
+                }),
+                _ => None
+            },
             timestamp_writes: None,
             occlusion_query_set: None,
         });
+        if let (Some((pos, size)), Some((x, y, width, height))) = (viewport, scissor) {
+            rpass.set_viewport(pos.x, pos.y, size.x, size.y, 0.0, 1.0);
+            rpass.set_scissor_rect(x, y, width, height);
+            if let (Some(color), Some(pipeline)) = (clear_color, &self.clear_rect_pipeline) {
+                rpass.set_pipeline(pipeline);
+                rpass.set_blend_constant(color);
+                rpass.draw(0..3, 0..1);
+            };
+        };
+        rpass.set_bind_group(1, camera_bind_group, &[]);
 
         self.batches.iter().filter(|b| {
             layer_range.contains(&b.layer)
@@ -349,11 +946,64 @@ impl BatchRenderer {
             rpass.set_bind_group(0, &batch.bind_group, &[]);
             rpass.draw_indexed(0..batch.indices_end, 0, 0..1);
         });
+        drop(rpass);
+        debug_markers::pop_group(&mut framedata.2);
+    }
+
+
+    /// Creates the pipelines and bind group layouts for batches with the given numbers of textures
+    /// up front (for example during a loading screen), so drawing does not stutter the first time
+    /// a batch uses that many textures. Also covers the layers that use alpha testing.
+    ///
+    /// Pipelines are recreated when the cull mode changes or the shader reloads, so call this afterwards.
+    pub fn prewarm(&mut self, texture_counts: &[usize]) {
+        let graphics_sys = self.ctx.get::<GraphicsSystem>();
+        let asset_sys = self.ctx.get::<AssetSystem>();
+        let shader = asset_sys.get(&self.shader_handle);
+        let format = graphics_sys.render_format();
+
+        let mut alpha_modes = vec![AlphaMode::Blend];
+        alpha_modes.extend(self.layer_alpha_modes.values().copied());
+
+        for &count in texture_counts {
+            let num_textures_used = count.clamp(1, self.max_textures_per_batch());
+            for &alpha_mode in alpha_modes.iter() {
+                let exists = self.batch_helpers.iter().any(|helper| {
+                    helper.num_textures_used == num_textures_used && helper.alpha_mode == alpha_mode
+                });
+                if !exists {
+                    self.batch_helpers.push(Self::create_batch_helper(
+                        graphics_sys.device(),
+                        shader.module(),
+                        format,
+                        num_textures_used,
+                        alpha_mode,
+                        self.cull_mode,
+                        self.texture_arrays,
+                        self.depth_test,
+                        self.shape_antialiasing,
+                        &self.camera_layout));
+                };
+            }
+        }
+        debug!("Prewarmed batch pipelines, {} in total", self.batch_helpers.len());
+    }
+
+
+    /// Discards all quads that have been queued this frame using draw_quad, pipelines and other resources are kept
+    pub fn clear_queue(&mut self) {
+        self.quads_to_draw.clear();
+        self.queued_hash = 0;
     }
 
 
     /// Records a new quad that needs to be drawn this frame (low performance cost, even though quad gets cloned)
     pub fn draw_quad(&mut self, quad: &Quad, layer: i32) {
+        let mut hasher = FxHasher::default();
+        hasher.write_u64(self.queued_hash);
+        hasher.write_i32(layer);
+        quad.hash_into(&mut hasher);
+        self.queued_hash = hasher.finish();
         self.quads_to_draw.push(std::cmp::Reverse(BatchQuadEntry {
             layer,
             quad: quad.clone()
@@ -361,14 +1011,289 @@ impl BatchRenderer {
     }
 
 
+    /// Draws the quad together with a soft shadow, offset by shadow_offset (in world units, y up) and drawn below
+    /// the quad within the same layer (using its z_bias). blur is how far the shadow fades out past the edges
+    /// of the quad, it is approximated by a few stacked translucent rounded quads. 0 gives a hard shadow.
+    pub fn draw_quad_with_shadow(&mut self, quad: &Quad, shadow_offset: IVec2, shadow_color: Srgba, blur: i32, layer: i32) {
+        let blur = blur.max(0);
+        let steps = blur.clamp(1, Self::MAX_SHADOW_STEPS);
+        // Stacking the steps should give shadow_color in the center: 1 - (1 - a)^steps = alpha
+        let step_alpha = 1.0 - (1.0 - shadow_color.alpha.clamp(0.0, 1.0)).powf(1.0 / steps as f32);
+        for step in 0..steps {
+            let expand = blur * (step + 1) / steps;
+            self.draw_quad(&Quad {
+                center: quad.center + shadow_offset,
+                size: quad.size + IVec2::splat(expand),
+                color: Srgba::new(shadow_color.red, shadow_color.green, shadow_color.blue, step_alpha),
+                corner_radius: quad.corner_radius + expand as f32,
+                z_bias: quad.z_bias.saturating_sub(1),
+                ..Default::default()
+            }, layer);
+        }
+        self.draw_quad(quad, layer);
+    }
+
+
+    /// Draws connected line segments through the points, each thickness wide. The joints between
+    /// the segments are rounded, so the line has no gaps where it bends.
+    pub fn draw_polyline(&mut self, points: &[IVec2], thickness: f32, color: Srgba, layer: i32) {
+        if points.len() < 2 {
+            warn!("draw_polyline: A line needs at least 2 points, got {}", points.len());
+            return;
+        };
+        let half_thickness = thickness / 2.0;
+        for (segment, pair) in points.windows(2).enumerate() {
+            let (start, end) = (pair[0].as_vec2(), pair[1].as_vec2());
+            let Some(direction) = (end - start).try_normalize() else {
+                continue;
+            };
+            // Rotating the direction by 90° lists the corners clockwise (bottom left, top left, top right, bottom right)
+            let normal = direction.perp() * half_thickness;
+            self.draw_quad(&Quad {
+                color,
+                vertices: Some([start - normal, start + normal, end + normal, end - normal].map(|corner| corner.round().as_ivec2())),
+                ..Default::default()
+            }, layer);
+            if segment > 0 {
+                self.draw_quad(&Quad::builder()
+                    .center(pair[0])
+                    .size(IVec2::splat(half_thickness.round() as i32))
+                    .corner_radius(half_thickness)
+                    .color(color)
+                    .build(), layer);
+            };
+        }
+    }
+
+
+    /// Fills the convex polygon spanned by the points (in either winding order). Two triangles of
+    /// the fan are drawn per quad, so the polygon goes through the regular batching.
+    pub fn draw_convex_poly(&mut self, points: &[IVec2], color: Srgba, layer: i32) {
+        if points.len() < 3 {
+            warn!("draw_convex_poly: A polygon needs at least 3 points, got {}", points.len());
+            return;
+        };
+        for vertices in Self::convex_poly_quads(points) {
+            self.draw_quad(&Quad {
+                color,
+                vertices: Some(vertices),
+                ..Default::default()
+            }, layer);
+        }
+    }
+
+
+    /// Splits the convex polygon into the corners of quads (see Quad::vertices) covering its triangle fan
+    fn convex_poly_quads(points: &[IVec2]) -> Vec<[IVec2; 4]> {
+        // The fan has to be counter-clockwise, which means a positive signed area. Reversing all
+        // but the first point keeps the fan starting at the same point for both windings.
+        let doubled_area: i64 = points.iter().zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64)
+            .sum();
+        let mut points = points.to_vec();
+        if doubled_area < 0 {
+            points[1..].reverse();
+        };
+        // The quad corners are clockwise, so (first, c, b, a) covers the counter-clockwise fan triangles
+        // (first, a, b) and (b, c, first), an odd triangle at the end repeats its last corner
+        let first = points[0];
+        let mut quads = vec![];
+        let mut i = 1;
+        while i + 1 < points.len() {
+            let (a, b) = (points[i], points[i + 1]);
+            let c = points.get(i + 2).copied().unwrap_or(b);
+            quads.push([first, c, b, a]);
+            i += 2;
+        }
+        quads
+    }
+
+
+    /// Draws a progress bar (health, loading, ...) with center and size (half the width and height, like Quad)
+    /// in the bg color, filled to fill (0.0 - 1.0) with the fg color. Within the layer, the filled part is drawn
+    /// on top of the background using z_bias 1, the border on top of both with z_bias 2.
+    pub fn draw_bar(&mut self, center: IVec2, size: IVec2, fill: f32, fg: Srgba, bg: Srgba, style: &BarStyle, layer: i32) {
+        let fill = if fill.is_nan() { 0.0 } else { fill.clamp(0.0, 1.0) };
+        self.draw_quad(&Quad {
+            center,
+            size,
+            color: bg,
+            corner_radius: style.corner_radius,
+            ..Default::default()
+        }, layer);
+
+        let border = style.border_thickness.max(0.0);
+        let inner_size = (size - IVec2::splat(border.ceil() as i32)).max(IVec2::ZERO);
+        // Rounding to whole units keeps an empty bar empty and a full bar exactly covering the inside
+        let fill_size = match style.vertical {
+            false => IVec2::new((inner_size.x as f32 * fill).round() as i32, inner_size.y),
+            true => IVec2::new(inner_size.x, (inner_size.y as f32 * fill).round() as i32)
+        };
+        if fill_size.x > 0 && fill_size.y > 0 {
+            // The filled part starts at the left (or bottom) edge, y points up
+            let fill_center = match style.vertical {
+                false => IVec2::new(center.x - inner_size.x + fill_size.x, center.y),
+                true => IVec2::new(center.x, center.y - inner_size.y + fill_size.y)
+            };
+            self.draw_quad(&Quad {
+                center: fill_center,
+                size: fill_size,
+                color: fg,
+                corner_radius: (style.corner_radius - border).max(0.0).min(fill_size.min_element() as f32),
+                z_bias: 1,
+                ..Default::default()
+            }, layer);
+        };
+
+        if border > 0.0 {
+            self.draw_quad(&Quad {
+                center,
+                size,
+                color: style.border_color,
+                corner_radius: style.corner_radius,
+                outline_thickness: border,
+                z_bias: 2,
+                ..Default::default()
+            }, layer);
+        };
+    }
+
+
+    /// Draws text using the built-in 8x8 ASCII font, without needing a font asset. pos is the top left
+    /// corner of the text and each glyph is 8 * scale pixels big. Meant for debug overlays, characters
+    /// outside of printable ASCII are left empty.
+    pub fn draw_debug_text(&mut self, text: &str, pos: IVec2, scale: f32, color: Srgba, layer: i32) {
+        TextRenderer::new(self.debug_font.clone()).draw_text(self, text, pos, DEBUG_FONT_GLYPH_SIZE.y as f32 * scale, color, layer);
+    }
+
+
+    /// Returns the size of the text when drawn with draw_debug_text
+    pub fn measure_debug_text(&self, text: &str, scale: f32) -> Vec2 {
+        TextRenderer::new(self.debug_font.clone()).measure(text, DEBUG_FONT_GLYPH_SIZE.y as f32 * scale)
+    }
+
+
+    /// Makes the layer use alpha testing instead of alpha blending: Fragments with an alpha below
+    /// cutoff get discarded and the rest is drawn opaque, so the draw order within the layer does not matter.
+    /// Pass None to go back to alpha blending.
+    pub fn set_alpha_cutoff(&mut self, layer: i32, cutoff: Option<f32>) {
+        match cutoff {
+            Some(cutoff) => {
+                self.layer_alpha_modes.insert(layer, AlphaMode::Cutoff(cutoff));
+            },
+            None => {
+                self.layer_alpha_modes.remove(&layer);
+            }
+        };
+        self.invalidate_batches();
+    }
+
+
+    /// Sets which faces get culled (None by default). Since all quads are counter-clockwise,
+    /// `Some(Face::Back)` keeps every quad visible. Only call this outside of rendering.
+    pub fn set_cull_mode(&mut self, cull_mode: Option<wgpu::Face>) {
+        if self.cull_mode != cull_mode {
+            self.cull_mode = cull_mode;
+            // The pipelines of the helpers get recreated on demand
+            self.batch_helpers.clear();
+            self.invalidate_batches();
+        };
+    }
+
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        self.cull_mode
+    }
+
+
+    /// Smooths the edges of rounded quads, circles and outlines over one screen pixel (enabled by default),
+    /// when disabled they get a hard, pixel exact edge. Plain quads are not affected. Only call this outside of rendering.
+    pub fn set_shape_antialiasing(&mut self, enabled: bool) {
+        if self.shape_antialiasing != enabled {
+            self.shape_antialiasing = enabled;
+            // The pipelines of the helpers get recreated on demand
+            self.batch_helpers.clear();
+            self.reload_render_pipeline();
+            self.invalidate_batches();
+        };
+    }
+
+    pub fn shape_antialiasing(&self) -> bool {
+        self.shape_antialiasing
+    }
+
+
+    /// Draws the batches with a depth buffer, where the depth of each quad is computed from its layer
+    /// (see set_layer_range). Only call this outside of rendering.
+    pub fn set_depth_test(&mut self, enabled: bool) {
+        if self.depth_test != enabled {
+            self.depth_test = enabled;
+            self.depth_texture = None;
+            // The pipelines of the helpers get recreated on demand
+            self.batch_helpers.clear();
+            self.clear_rect_pipeline = None;
+            self.invalidate_batches();
+        };
+    }
+
+    pub fn depth_test(&self) -> bool {
+        self.depth_test
+    }
+
+
+    /// Sets which layers (both inclusive) get mapped onto the depth range, layers outside of it
+    /// share the depth of the closest end. The smaller the range, the more precision each layer has.
+    /// By default the range is -1000 to 1000, mapped linearly.
+    pub fn set_layer_range(&mut self, min_layer: i32, max_layer: i32, mapping: LayerDepthMapping) {
+        if min_layer >= max_layer {
+            warn!("set_layer_range: min_layer ({}) has to be smaller than max_layer ({})", min_layer, max_layer);
+            return;
+        };
+        self.layer_range = (min_layer, max_layer);
+        self.layer_depth_mapping = mapping;
+        self.invalidate_batches();
+    }
+
+    pub fn layer_range(&self) -> (i32, i32) {
+        self.layer_range
+    }
+
+
+    /// When enabled, quads that lie completely outside of the cameras visible rectangle
+    /// are skipped while batching. Partially visible quads are still drawn.
+    pub fn set_frustum_culling(&mut self, enabled: bool) {
+        if self.frustum_culling != enabled {
+            self.frustum_culling = enabled;
+            // The last batches were created with (or without) culling and would otherwise be reused
+            self.invalidate_batches();
+        };
+    }
+
+    pub fn frustum_culling(&self) -> bool {
+        self.frustum_culling
+    }
+
+
     /// Reloads parts of the renderer depending on what asset changed
     fn on_assetchange(&mut self, event: &crate::assets::events::AssetReload) {
+        // The bind groups of the batches might reference the old texture
+        self.invalidate_batches();
         if event.asset_id == **self.shader_handle.id() {
+            // The batches draw with the pipelines of the helpers, which get recreated on demand
+            self.batch_helpers.clear();
             self.reload_render_pipeline();
         }
     }
 
 
+    /// Recreates all pipelines, for example after the render format changed
+    pub(super) fn recreate_pipelines(&mut self) {
+        self.batch_helpers.clear();
+        self.clear_rect_pipeline = None;
+        self.reload_render_pipeline();
+        self.invalidate_batches();
+    }
+
+
     /// Helper function to set up a new render pipeline using the same shaders
     fn reload_render_pipeline(&mut self) {
         let graphics_sys = self.ctx.get::<GraphicsSystem>();
@@ -377,22 +1302,73 @@ impl BatchRenderer {
         self.render_pipeline = Self::create_render_pipeline(
             graphics_sys.device(),
             &self.bind_group.1,
+            &self.camera_layout,
             shader.module(),
-            Some(graphics_sys.surface_config().format.into()));
+            Some(graphics_sys.render_format().into()),
+            AlphaMode::Blend,
+            self.cull_mode,
+            self.depth_test,
+            self.shape_antialiasing);
+    }
+
+
+    /// Helper function for creating the bind group layout and render pipeline used by
+    /// batches with that many textures and that alpha mode
+    #[allow(clippy::too_many_arguments)]
+    fn create_batch_helper(
+        device: &Device,
+        shader: &ShaderModule,
+        format: wgpu::TextureFormat,
+        num_textures_used: usize,
+        alpha_mode: AlphaMode,
+        cull_mode: Option<wgpu::Face>,
+        texture_arrays: bool,
+        depth_test: bool,
+        shape_antialiasing: bool,
+        camera_layout: &BindGroupLayout
+    ) -> BatchHelper {
+        let layout = Self::create_bind_group_layout(device, num_textures_used as u32, num_textures_used as u32, texture_arrays);
+        let color_state = Some(wgpu::ColorTargetState {
+            format,
+            // The alpha test replaces blending
+            blend: match alpha_mode {
+                AlphaMode::Blend => Some(wgpu::BlendState::ALPHA_BLENDING),
+                AlphaMode::Cutoff(_) => None
+            },
+            write_mask: wgpu::ColorWrites::ALL,
+        });
+        let pipeline = Self::create_render_pipeline(device, &layout, camera_layout, shader, color_state, alpha_mode, cull_mode, depth_test, shape_antialiasing);
+        BatchHelper {
+            num_textures_used,
+            alpha_mode,
+            layout,
+            pipeline
+        }
     }
 
 
     /// Helper function for creating a new render pipeline
+    #[allow(clippy::too_many_arguments)]
     fn create_render_pipeline(
         device: &Device,
         bind_group_layout: &BindGroupLayout,
+        camera_layout: &BindGroupLayout,
         shader: &ShaderModule,
-        color_state: Option<ColorTargetState>
+        color_state: Option<ColorTargetState>,
+        alpha_mode: AlphaMode,
+        cull_mode: Option<wgpu::Face>,
+        depth_test: bool,
+        shape_antialiasing: bool
     ) -> RenderPipeline {
+        // Fragments below this alpha get discarded, with 0.0 nothing is discarded
+        let mut constants = std::collections::HashMap::new();
+        constants.insert(String::from("alpha_threshold"), alpha_mode.threshold() as f64);
+        constants.insert(String::from("shape_antialiasing"), shape_antialiasing as u8 as f64);
+
         // IDEA: Create pipelines with different bind group layouts beforehand
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("main"),
-            bind_group_layouts: &[bind_group_layout],
+            bind_group_layouts: &[bind_group_layout, camera_layout],
             push_constant_ranges: &[],
         });
         device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -403,8 +1379,8 @@ impl BatchRenderer {
                 entry_point: "vert_main",
                 buffers: &[wgpu::VertexBufferLayout {
                     array_stride: VERTEX_SIZE as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex, // position        color       tex_coords     tex_index
-                    attributes: &wgpu::vertex_attr_array![0 => Sint32x2, 1 => Float32x4, 2 => Float32x2, 3 => Sint32],
+                    step_mode: wgpu::VertexStepMode::Vertex, // position        color       tex_coords     tex_index  depth      local_pos      shape          uv_wrap
+                    attributes: &wgpu::vertex_attr_array![0 => Sint32x2, 1 => Float32x4, 2 => Float32x2, 3 => Sint32, 4 => Float32, 5 => Float32x2, 6 => Float32x4, 7 => Float32x4],
                 }],
                 compilation_options: Default::default()
             },
@@ -412,13 +1388,82 @@ impl BatchRenderer {
                 module: shader,
                 entry_point: "uniform_main",
                 targets: &[color_state],
-                compilation_options: Default::default()
+                compilation_options: wgpu::PipelineCompilationOptions {
+                    constants: &constants,
+                    ..Default::default()
+                }
             }),
             primitive: wgpu::PrimitiveState {
+                // Matches the winding of create_indices, since the camera keeps y pointing up
                 front_face: wgpu::FrontFace::Ccw,
+                cull_mode,
                 ..Default::default()
             },
-            depth_stencil: None,
+            // LessEqual, so that quads on the same layer are still drawn in order
+            depth_stencil: match depth_test {
+                true => Some(wgpu::DepthStencilState {
+                    format: Self::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default()
+                }),
+                false => None
+            },
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None
+        })
+    }
+
+
+    /// Creates the pipeline that fills the scissor rectangle with the blend constant (and resets the depth there)
+    fn create_clear_rect_pipeline(device: &Device, format: wgpu::TextureFormat, depth_test: bool) -> RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("BatchRenderer clear rect shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("clear_rect.wgsl").into())
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BatchRenderer clear rect pipeline layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        // Replaces the target with the blend constant, no matter what the shader outputs
+        let replace_with_constant = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::Zero,
+            operation: wgpu::BlendOperation::Add
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("BatchRenderer clear rect pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState { color: replace_with_constant, alpha: replace_with_constant }),
+                    write_mask: wgpu::ColorWrites::ALL
+                })],
+                compilation_options: Default::default()
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: match depth_test {
+                true => Some(wgpu::DepthStencilState {
+                    format: Self::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default()
+                }),
+                false => None
+            },
             multisample: wgpu::MultisampleState::default(),
             multiview: None,
             cache: None
@@ -427,20 +1472,11 @@ impl BatchRenderer {
 
 
     /// Creates a new bind group layout from a number of texture views/ samplers
-    fn create_bind_group_layout(device: &Device, num_views: u32, num_samplers: u32) -> BindGroupLayout {
+    /// (if arrays is false, only a single texture and sampler are bound)
+    fn create_bind_group_layout(device: &Device, num_views: u32, num_samplers: u32, arrays: bool) -> BindGroupLayout {
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("bind group layout"),
             entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(NonZeroU64::new(64).unwrap()),
-                    },
-                    count: None,
-                },
                 // Texture array
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
@@ -450,38 +1486,73 @@ impl BatchRenderer {
                         view_dimension: wgpu::TextureViewDimension::D2,
                         multisampled: false,
                     },
-                    count: NonZeroU32::new(num_views),
+                    count: if arrays { NonZeroU32::new(num_views) } else { None },
                 },
                 // Sampler array
                 wgpu::BindGroupLayoutEntry {
                     binding: 2,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: NonZeroU32::new(num_samplers),
+                    count: if arrays { NonZeroU32::new(num_samplers) } else { None },
                 }
             ],
         })
     }
 
 
+    /// Creates the layout of the bind group containing the camera transform (a single mat4)
+    fn create_camera_bind_group_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("camera bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(NonZeroU64::new(64).unwrap()),
+                    },
+                    count: None,
+                }
+            ],
+        })
+    }
+
+
+    fn create_camera_bind_group(device: &Device, layout: &BindGroupLayout, transform_buffer: &Buffer) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: transform_buffer.as_entire_binding(),
+                }
+            ],
+            layout,
+            label: Some("camera bind group"),
+        })
+    }
+
+
     /// Creates the bind group based on a list of textures
-    fn create_bind_group(device: &wgpu::Device, layout: &BindGroupLayout, shaderglobals: &Buffer, views: &Vec<&TextureView>, samplers: &Vec<&Sampler>) -> BindGroup {
+    /// (if arrays is false, only the first texture and sampler are bound)
+    fn create_bind_group(device: &wgpu::Device, layout: &BindGroupLayout, views: &Vec<&TextureView>, samplers: &Vec<&Sampler>, arrays: bool) -> BindGroup {
         let tex_views = views.as_slice();
         let tex_samplers = samplers.as_slice();
+        let (views_resource, samplers_resource) = match arrays {
+            true => (wgpu::BindingResource::TextureViewArray(tex_views), wgpu::BindingResource::SamplerArray(tex_samplers)),
+            false => (wgpu::BindingResource::TextureView(tex_views[0]), wgpu::BindingResource::Sampler(tex_samplers[0]))
+        };
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: shaderglobals.as_entire_binding(),
-                },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::TextureViewArray(tex_views),
+                    resource: views_resource,
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: wgpu::BindingResource::SamplerArray(tex_samplers),
+                    resource: samplers_resource,
                 }
             ],
             layout,
@@ -492,22 +1563,43 @@ impl BatchRenderer {
     }
 
 
-    /// Creates an array of indices, following the typical quad indexing method (0-1-2, 2-3-0)
-    fn create_indices() -> [u16; BatchRenderer::MAX_INDEX_COUNT] {
-        let mut indices: [u16; BatchRenderer::MAX_INDEX_COUNT] = [0; BatchRenderer::MAX_INDEX_COUNT];
-        let mut offset = 0;
-        (0..BatchRenderer::MAX_INDEX_COUNT).step_by(6).for_each(|i| {
-            indices[i + 0] = 0 + offset;
-            indices[i + 1] = 1 + offset;
-            indices[i + 2] = 2 + offset;
+    /// Creates the indices for num_quads quads (0-3-2, 2-1-0) in the given format. The vertices of a quad are emitted as
+    /// bottom left, top left, top right, bottom right, so with y pointing up (which the camera
+    /// guarantees) both triangles are counter-clockwise and survive back face culling.
+    fn create_indices(num_quads: usize, format: IndexFormat) -> Vec<u8> {
+        let indices = (0..num_quads as u32).flat_map(|quad| {
+            let offset = quad * 4;
+            [0 + offset, 3 + offset, 2 + offset, 2 + offset, 1 + offset, 0 + offset]
+        });
+        match format {
+            IndexFormat::Uint16 => indices.flat_map(|index| (index as u16).to_ne_bytes()).collect(),
+            IndexFormat::Uint32 => indices.flat_map(u32::to_ne_bytes).collect()
+        }
+    }
 
-            indices[i + 3] = 2 + offset;
-            indices[i + 4] = 3 + offset;
-            indices[i + 5] = 0 + offset;
 
-            offset += 4;
-        });
-        indices
+    fn create_index_buffer(device: &Device, num_quads: usize, format: IndexFormat) -> Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: &Self::create_indices(num_quads, format),
+            usage: wgpu::BufferUsages::INDEX,
+        })
+    }
+
+
+    /// Sets the format of the index buffer (Uint32 by default). With Uint16 the indices take half the memory,
+    /// but batches get split after 16384 quads since their vertices could not be addressed otherwise.
+    pub fn set_index_format(&mut self, index_format: IndexFormat) {
+        if index_format == self.index_format {
+            return;
+        };
+        self.index_format = index_format;
+        self.index_buffer = Self::create_index_buffer(self.ctx.get::<GraphicsSystem>().device(), self.index_capacity, index_format);
+        self.invalidate_batches();
+    }
+
+    pub fn index_format(&self) -> IndexFormat {
+        self.index_format
     }
 }
 
@@ -522,8 +1614,13 @@ impl GeeseSystem for BatchRenderer {
 
 
     fn new(mut ctx: geese::GeeseContextHandle<Self>) -> Self {
+        let texture_arrays = ctx.get::<GraphicsSystem>().supports_texture_arrays();
+        let shader_path = match texture_arrays {
+            true => "shaders/batch_renderer.wgsl",
+            false => "shaders/batch_renderer_single.wgsl"
+        };
         let mut asset_sys = ctx.get_mut::<AssetSystem>();
-        let base_shader_handle = asset_sys.load::<ShaderAsset>("shaders/batch_renderer.wgsl", true);
+        let base_shader_handle = asset_sys.load::<ShaderAsset>(shader_path, true);
         // Drop the mutable reference, from now on we only need it immutably
         drop(asset_sys);
 
@@ -534,66 +1631,56 @@ impl GeeseSystem for BatchRenderer {
             &graphics_sys,
             BufferUsages::VERTEX | BufferUsages::COPY_DST,
             BatchRenderer::MAX_VERTEX_COUNT);
-        let indices = BatchRenderer::create_indices();
         let device = graphics_sys.device();
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let index_buffer = Self::create_index_buffer(device, Self::MAX_QUAD_COUNT, IndexFormat::Uint32);
 
         // Set up a white 1x1 texture
         let queue = graphics_sys.queue();
-        let white_pixel = TextureBundle::new(device, queue,
-            "White pixel texture",
-            wgpu::Extent3d::default(),
-            wgpu::TextureDescriptor {
-                size: wgpu::Extent3d::default(),
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                label: Some("White pixel texture descriptor"),
-                view_formats: &[]
-            },
-            &wgpu::TextureViewDescriptor::default(),
-            &wgpu::SamplerDescriptor {
-                label: Some("white pixel sampler"),
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Linear,
-                //mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            },
-            &[255, 255, 255, 255],
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4),
-                rows_per_image: None,
-            }
-        );
+        let white_pixel = TextureBundle::solid_color(device, queue, Srgba::new(1.0, 1.0, 1.0, 1.0));
+        let create_sampler = |filter: wgpu::FilterMode| device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Quad filter override sampler"),
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        let nearest_sampler = create_sampler(wgpu::FilterMode::Nearest);
+        let linear_sampler = create_sampler(wgpu::FilterMode::Linear);
+
+        let debug_font = BitmapFont {
+            texture: ctx.get_mut::<AssetSystem>().add(TextureAsset::from_texture(debug_font::create_debug_font_texture(device, queue))),
+            glyph_size: DEBUG_FONT_GLYPH_SIZE,
+            texture_size: debug_font::debug_font_texture_size(),
+            columns: DEBUG_FONT_COLUMNS,
+            first_char: DEBUG_FONT_FIRST_CHAR,
+            num_glyphs: DEBUG_FONT_NUM_GLYPHS
+        };
         
         let camera = ctx.get::<Camera>();
         let asset_sys = ctx.get::<AssetSystem>();
         let conf = graphics_sys.surface_config();
-        let bind_group_layout = Self::create_bind_group_layout(device, 1, 1);
+        let bind_group_layout = Self::create_bind_group_layout(device, 1, 1, texture_arrays);
         let bind_group = BatchRenderer::create_bind_group(
             device,
             &bind_group_layout,
-            camera.canvas_transform_buffer(),
             &vec![white_pixel.view()],
-            &vec![white_pixel.sampler()]
+            &vec![white_pixel.sampler()],
+            texture_arrays
         );
 
+        let camera_layout = Self::create_camera_bind_group_layout(device);
+        let camera_bind_group = Self::create_camera_bind_group(device, &camera_layout, camera.canvas_transform_buffer());
+
         let base_shader_module = asset_sys.get(&base_shader_handle);
         let render_pipeline = Self::create_render_pipeline(
             device,
             &bind_group_layout,
+            &camera_layout,
             base_shader_module.module(),
-            Some(graphics_sys.surface_config().format.into())
+            Some(graphics_sys.render_format().into()),
+            AlphaMode::Blend,
+            None,
+            false,
+            true
         );
 
         drop(graphics_sys);
@@ -605,7 +1692,8 @@ impl GeeseSystem for BatchRenderer {
 
             vertex_buffer,
             index_buffer,
-            index_format: wgpu::IndexFormat::Uint16,
+            index_format: IndexFormat::Uint32,
+            index_capacity: Self::MAX_QUAD_COUNT,
             texture_slots: HashMap::default(),
 
             quads_to_draw: BinaryHeap::new(),
@@ -614,13 +1702,90 @@ impl GeeseSystem for BatchRenderer {
             vertices_to_draw: Vec::with_capacity(1000),
             
             bind_group: (bind_group, bind_group_layout),
+            camera_layout,
+            camera_bind_group,
 
             render_pipeline,
             clear_color: Color::RED,
             shader_handle: base_shader_handle,
+            layer_alpha_modes: HashMap::default(),
+            texture_arrays,
+            cull_mode: None,
+            frustum_culling: false,
+            shape_antialiasing: true,
+            depth_test: false,
+            depth_texture: None,
+            clear_rect_pipeline: None,
+            layer_range: (-1000, 1000),
+            layer_depth_mapping: LayerDepthMapping::Linear,
+
+            queued_hash: 0,
+            batched_hash: None,
+            vertices_dirty: false,
+            rebatch_count: 0,
+            quad_preprocessor: None,
 
             white_pixel,
+            nearest_sampler,
+            linear_sampler,
+            debug_font,
         }
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twice the signed area of the triangle, positive if it is counter-clockwise (with y pointing up)
+    fn doubled_area(a: IVec2, b: IVec2, c: IVec2) -> i64 {
+        ((b - a).x as i64 * (c - a).y as i64) - ((c - a).x as i64 * (b - a).y as i64)
+    }
+
+
+    #[test]
+    fn indices_above_u16_range() {
+        let num_quads = 20000;
+        let bytes = BatchRenderer::create_indices(num_quads, IndexFormat::Uint32);
+        assert_eq!(bytes.len(), num_quads * 6 * 4);
+        let indices: Vec<u32> = bytes.chunks_exact(4).map(|index| u32::from_ne_bytes(index.try_into().unwrap())).collect();
+        // The last quad starts at vertex 79996, far above what fits into 16 bits
+        let offset = (num_quads as u32 - 1) * 4;
+        assert_eq!(indices[indices.len() - 6..], [offset, offset + 3, offset + 2, offset + 2, offset + 1, offset]);
+        assert!(indices.iter().any(|index| *index > u16::MAX as u32));
+        assert_eq!(indices.iter().max(), Some(&(num_quads as u32 * 4 - 1)));
+
+        let bytes = BatchRenderer::create_indices(2, IndexFormat::Uint16);
+        let indices: Vec<u16> = bytes.chunks_exact(2).map(|index| u16::from_ne_bytes(index.try_into().unwrap())).collect();
+        assert_eq!(indices, [0, 3, 2, 2, 1, 0, 4, 7, 6, 6, 5, 4]);
+    }
+
+    #[test]
+    fn convex_poly_ignores_winding() {
+        let counter_clockwise = [IVec2::new(0, 0), IVec2::new(10, 0), IVec2::new(10, 10), IVec2::new(0, 10)];
+        let clockwise = [IVec2::new(0, 0), IVec2::new(0, 10), IVec2::new(10, 10), IVec2::new(10, 0)];
+        let quads = BatchRenderer::convex_poly_quads(&counter_clockwise);
+        assert_eq!(quads.len(), 1);
+        assert_eq!(quads, BatchRenderer::convex_poly_quads(&clockwise));
+
+        // Every triangle of the index pattern has to face forward
+        let indices = [0, 3, 2, 2, 1, 0];
+        for vertices in quads {
+            for triangle in indices.chunks(3) {
+                let area = doubled_area(vertices[triangle[0]], vertices[triangle[1]], vertices[triangle[2]]);
+                assert!(area > 0, "triangle {:?} of {:?} is not counter-clockwise", triangle, vertices);
+            }
+        }
+    }
+
+    #[test]
+    fn convex_poly_with_odd_triangle() {
+        let pentagon = [IVec2::new(0, 0), IVec2::new(10, 0), IVec2::new(15, 8), IVec2::new(5, 14), IVec2::new(-5, 8)];
+        let quads = BatchRenderer::convex_poly_quads(&pentagon);
+        // 3 fan triangles, the second quad repeats its last corner
+        assert_eq!(quads.len(), 2);
+        assert_eq!(quads[1][1], quads[1][2]);
+    }
+}
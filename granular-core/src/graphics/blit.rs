@@ -0,0 +1,127 @@
+use wgpu::{BindGroup, BindGroupLayout, BlendState, Color, CommandEncoder, Device, LoadOp, RenderPipeline, Sampler, TextureFormat, TextureView};
+
+
+/// A pipeline that draws a texture stretched over the viewport of a render target, used to copy
+/// or scale textures on the GPU (the HDR resolve, the internal resolution of the simulation, ...)
+pub(crate) struct BlitPipeline {
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    format: TextureFormat
+}
+impl BlitPipeline {
+    /// Creates a pipeline writing into targets of that format, blend None replaces the contents of the target
+    pub(crate) fn new(device: &Device, format: TextureFormat, blend: Option<BlendState>) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None
+                }
+            ]
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("blit.wgsl").into())
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[]
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default()
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL
+                })],
+                compilation_options: Default::default()
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None
+        });
+
+        Self {
+            bind_group_layout,
+            pipeline,
+            format
+        }
+    }
+
+
+    /// The format of the targets this pipeline can draw into
+    pub(crate) fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+
+    /// Binds the source texture, the bind group can be reused as long as the view stays alive
+    pub(crate) fn bind_group(&self, device: &Device, source: &TextureView, sampler: &Sampler) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source)
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler)
+                }
+            ]
+        })
+    }
+
+
+    /// Records a pass drawing the source (bound in bind_group) into target. The viewport (x, y, width, height in pixels)
+    /// is the region of the target that gets covered, None covers all of it.
+    pub(crate) fn draw(&self, encoder: &mut CommandEncoder, bind_group: &BindGroup, target: &TextureView, load: LoadOp<Color>, viewport: Option<(f32, f32, f32, f32)>) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store
+                }
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None
+        });
+        if let Some((x, y, width, height)) = viewport {
+            rpass.set_viewport(x, y, width, height, 0.0, 1.0);
+        };
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, bind_group, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
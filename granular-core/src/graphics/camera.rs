@@ -1,9 +1,14 @@
 #![allow(unused)]
-use geese::{dependencies, GeeseContextHandle, GeeseSystem};
+use std::f32::consts::{PI, TAU};
+
+use geese::{dependencies, event_handlers, EventHandlers, GeeseContextHandle, GeeseSystem};
 use glam::{Affine2, IVec2, Mat2, Mat4, Quat, Vec2, Vec3};
 use log::info;
+use serde::{Deserialize, Serialize};
 use wgpu::{util::DeviceExt, Buffer, BufferUsages};
 
+use crate::TimeSystem;
+
 use super::GraphicsSystem;
 
 pub enum ScalingMode {
@@ -12,21 +17,38 @@ pub enum ScalingMode {
 }
 
 
+/// The transform of the camera at one point in time (for example one frame of a replay), see `Camera::snapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraSnapshot {
+    pub position: [i32; 2],
+    pub rotation: f32,
+    pub rotation_target: Option<f32>,
+    pub zoom: f32
+}
+
+
 pub struct Camera {
     ctx: GeeseContextHandle<Self>,
 
     // === General ===
     position: IVec2,
     angle: f32,
+    /// The angle update() rotates toward, None when not animating
+    rotation_target: Option<f32>,
+    /// Radians per second used for rotating toward rotation_target
+    rotation_speed: f32,
     screen_size: Vec2,
     scaling_mode: ScalingMode,
     zoom: f32,
+    /// Whether the view follows render_position instead of position
+    interpolate: bool,
+    /// The position at the end of the last fixed step
+    previous_position: IVec2,
     
     // ortho_proj * view
     canvas_transform: Mat4,
     
     // === Internal projection ===
-    scale: Vec2,
     ortho_proj: Mat4,
     view: Mat4,
     left: f32,
@@ -37,7 +59,10 @@ pub struct Camera {
     far: f32,
 
     // === wgpu ===
-    shader_buffer: Buffer
+    /// Uniform buffer holding canvas_transform, bound by the renderers
+    transform_buffer: Buffer,
+    /// Whether canvas_transform changed since it was last written to transform_buffer
+    transform_dirty: bool
 }
 impl Camera {
     pub fn set_position(&mut self, position: IVec2) {
@@ -54,8 +79,9 @@ impl Camera {
     }
 
 
-    /// Sets the rotation of the camera (in radians)
+    /// Sets the rotation of the camera (in radians), this stops an animation started with set_rotation_target
     pub fn set_rotation(&mut self, rotation: f32) {
+        self.rotation_target = None;
         self.angle = rotation;
         self.recalc_view();
     }
@@ -64,6 +90,106 @@ impl Camera {
     }
 
 
+    /// Smoothly rotates the camera toward the angle (in radians) with the rotation speed, taking the
+    /// shortest way around (from 350° to 10° it rotates by +20°). The rotation is advanced in update.
+    pub fn set_rotation_target(&mut self, target: f32) {
+        self.rotation_target = Some(target);
+    }
+
+    /// The angle the camera is currently rotating toward, None once it has been reached
+    pub fn rotation_target(&self) -> Option<f32> {
+        self.rotation_target
+    }
+
+
+    /// Sets how fast set_rotation_target rotates the camera, in radians per second (PI by default)
+    pub fn set_rotation_speed(&mut self, speed: f32) {
+        self.rotation_speed = speed.abs();
+    }
+    pub fn rotation_speed(&self) -> f32 {
+        self.rotation_speed
+    }
+
+
+    /// Advances the camera animations by dt seconds, called by the engine every frame during PostUpdate
+    pub fn update(&mut self, dt: f32) {
+        if let Some(target) = self.rotation_target {
+            let diff = shortest_angle_diff(self.angle, target);
+            let step = self.rotation_speed * dt;
+            if diff.abs() <= step {
+                self.angle += diff;
+                self.rotation_target = None;
+            } else {
+                self.angle += step * diff.signum();
+            };
+            self.recalc_view();
+        };
+    }
+
+
+    /// Makes the view use render_position, so a camera that is moved during fixed steps (see
+    /// `TimeSystem::fixed_steps`) moves smoothly when there are more frames than fixed steps. Disabled by default.
+    pub fn set_interpolation(&mut self, enabled: bool) {
+        self.interpolate = enabled;
+        self.previous_position = self.position;
+        self.recalc_view();
+    }
+
+    pub fn interpolation(&self) -> bool {
+        self.interpolate
+    }
+
+
+    /// The position between the one at the last fixed step and the current one, using `TimeSystem::interpolation_alpha`
+    pub fn render_position(&self) -> Vec2 {
+        let alpha = self.ctx.get::<TimeSystem>().interpolation_alpha();
+        self.previous_position.as_vec2().lerp(self.position.as_vec2(), alpha)
+    }
+
+
+    /// Remembers the position before the fixed steps of this frame move the camera
+    fn on_pre_update(&mut self, _: &crate::events::phase::PreUpdate) {
+        if self.ctx.get::<TimeSystem>().fixed_steps() > 0 {
+            self.previous_position = self.position;
+        };
+    }
+
+
+    fn on_pre_render(&mut self, _: &crate::events::phase::PreRender) {
+        if self.interpolate {
+            self.recalc_view();
+        };
+    }
+
+
+    fn on_post_update(&mut self, _: &crate::events::phase::PostUpdate) {
+        let dt = self.ctx.get::<TimeSystem>().delta().as_secs_f32();
+        self.update(dt);
+    }
+
+
+    /// Captures the position, rotation (including an ongoing rotation animation) and zoom
+    pub fn snapshot(&self) -> CameraSnapshot {
+        CameraSnapshot {
+            position: self.position.to_array(),
+            rotation: self.angle,
+            rotation_target: self.rotation_target,
+            zoom: self.zoom
+        }
+    }
+
+
+    /// Moves the camera back to the state of a snapshot
+    pub fn restore(&mut self, snapshot: &CameraSnapshot) {
+        self.position = IVec2::from_array(snapshot.position);
+        self.previous_position = self.position;
+        self.angle = snapshot.rotation;
+        self.rotation_target = snapshot.rotation_target;
+        self.zoom = snapshot.zoom;
+        self.recalc_view();
+    }
+
+
     /// A zoom of 1.0 is default, a zoom of 2.0 doubles every pixel
     pub fn set_zoom(&mut self, zoom: f32) {
         self.zoom = zoom;
@@ -74,12 +200,58 @@ impl Camera {
     }
 
 
+    /// Multiplies the zoom by factor, while keeping the world point under focus_screen
+    /// (in window pixels, for example the mouse position) at the same place on the screen
+    pub fn zoom_toward(&mut self, factor: f32, focus_screen: IVec2) {
+        let new_zoom = self.zoom * factor;
+        let offset = zoom_toward_offset(self.ortho_proj, self.view_position(), self.angle, self.zoom, new_zoom, self.screen_size, focus_screen);
+        self.zoom = new_zoom;
+        self.translate(offset.round().as_ivec2());
+    }
+
+
+    /// Converts a position in window pixels (origin top left, y down) into world coordinates
+    pub fn screen_to_world(&self, screen_pos: IVec2) -> Vec2 {
+        screen_to_world(self.canvas_transform, self.screen_size, screen_pos)
+    }
+
+
+    /// Converts a world position into window pixels (origin top left, y down)
+    pub fn world_to_screen(&self, world_pos: Vec2) -> Vec2 {
+        world_to_screen(self.canvas_transform, self.screen_size, world_pos)
+    }
+
+
+    /// Returns the (min, max) corners of the world rectangle that is currently visible.
+    /// When the camera is rotated, this is the bounding box of the rotated view.
+    pub fn visible_world_rect(&self) -> (Vec2, Vec2) {
+        visible_world_rect(self.canvas_transform, self.screen_size)
+    }
+
+
+    /// Chainable version of set_position
+    pub fn with_position(&mut self, position: IVec2) -> &mut Self {
+        self.set_position(position);
+        self
+    }
+
+    /// Chainable version of set_rotation
+    pub fn with_rotation(&mut self, rotation: f32) -> &mut Self {
+        self.set_rotation(rotation);
+        self
+    }
+
+    /// Chainable version of set_zoom
+    pub fn with_zoom(&mut self, zoom: f32) -> &mut Self {
+        self.set_zoom(zoom);
+        self
+    }
+
+
     pub(crate) fn set_screen_size(&mut self, screen_size: (u32, u32)) {
         self.screen_size = Vec2::new(screen_size.0 as f32, screen_size.1 as f32);
         info!("Camera screen size: {}", self.screen_size);
-        
-        self.scale = 1.0 / self.screen_size;
-        
+
         self.recalc_ortho();
         self.recalc_view();
     }
@@ -90,13 +262,19 @@ impl Camera {
     }
 
     
-    pub fn write_canvas_transform_buffer(&self) {
+    /// Uploads canvas_transform to the GPU buffer, if it changed since the last upload
+    pub fn write_canvas_transform_buffer(&mut self) {
+        if !self.transform_dirty {
+            return;
+        };
         let graphics_sys = self.ctx.get::<GraphicsSystem>();
-        graphics_sys.queue().write_buffer(&self.shader_buffer, 0, bytemuck::cast_slice(&[self.canvas_transform]));
+        graphics_sys.queue().write_buffer(&self.transform_buffer, 0, bytemuck::cast_slice(&[self.canvas_transform]));
+        self.transform_dirty = false;
     }
 
+    /// The uniform buffer containing canvas_transform, it is kept up to date by write_canvas_transform_buffer
     pub fn canvas_transform_buffer(&self) -> &Buffer {
-        &self.shader_buffer
+        &self.transform_buffer
     }
 
 
@@ -114,31 +292,44 @@ impl Camera {
             self.far,                 // far
         );
         self.canvas_transform = self.ortho_proj * self.view;
+        self.transform_dirty = true;
+    }
+
+
+    /// The position the view is built from, see set_interpolation
+    fn view_position(&self) -> Vec2 {
+        match self.interpolate {
+            true => self.render_position(),
+            false => self.position.as_vec2()
+        }
     }
 
 
     fn recalc_view(&mut self) {
-        self.view = Mat4::from_scale_rotation_translation(
-            Vec3::new(self.scale.x * self.zoom, self.scale.y * self.zoom, 1.0),
-            Quat::from_rotation_z(self.angle),
-            Vec3::new(-self.position.x as f32 * self.scale.x, -self.position.y as f32 * self.scale.y, 0.0));
+        self.view = view_matrix(self.view_position(), self.zoom, self.angle, self.screen_size);
         self.canvas_transform = self.ortho_proj * self.view;
+        self.transform_dirty = true;
     }
 }
 impl GeeseSystem for Camera {
     const DEPENDENCIES: geese::Dependencies = dependencies()
-        .with::<GraphicsSystem>();
+        .with::<GraphicsSystem>()
+        .with::<TimeSystem>();
+
+    const EVENT_HANDLERS: EventHandlers<Self> = event_handlers()
+        .with(Self::on_pre_update)
+        .with(Self::on_post_update)
+        .with(Self::on_pre_render);
 
     fn new(ctx: geese::GeeseContextHandle<Self>) -> Self {
-        let scale = Vec2::ONE;
         let (left, right, top, bottom, near, far) = (-1.0, 1.0, 1.0, -1.0, -1.0, 1.0);
         let ortho_proj = Mat4::orthographic_rh_gl(left, right, bottom, top, near, far);
         let view = Mat4::IDENTITY;
         let canvas_transform = ortho_proj * view;
 
         let graphics_sys = ctx.get::<GraphicsSystem>();
-        let shader_buffer = graphics_sys.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("SimulationRenderer Shader globals buffer"),
+        let transform_buffer = graphics_sys.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera canvas transform buffer"),
             contents: bytemuck::cast_slice(&[canvas_transform]),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
@@ -149,13 +340,16 @@ impl GeeseSystem for Camera {
 
             position: IVec2::ZERO,
             angle: 0.0,
+            rotation_target: None,
+            rotation_speed: PI,
             screen_size: Vec2::ONE,
             scaling_mode: ScalingMode::Keep,
             zoom: 1.0,
+            interpolate: false,
+            previous_position: IVec2::ZERO,
 
             canvas_transform,
-            
-            scale,
+
             view,
             ortho_proj,
             left,
@@ -165,7 +359,162 @@ impl GeeseSystem for Camera {
             near,
             far,
 
-            shader_buffer
+            transform_buffer,
+            transform_dirty: false
+        }
+    }
+}
+
+
+
+/// The signed difference from one angle to another (in radians) in [-PI, PI), so rotating by it takes the shortest way
+fn shortest_angle_diff(from: f32, to: f32) -> f32 {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
+
+
+/// The view matrix of a camera at position, it maps one world unit to zoom / screen_size in
+/// normalized device coordinates
+fn view_matrix(position: Vec2, zoom: f32, angle: f32, screen_size: Vec2) -> Mat4 {
+    let scale = 1.0 / screen_size;
+    Mat4::from_scale_rotation_translation(
+        (scale * zoom).extend(1.0),
+        Quat::from_rotation_z(angle),
+        (-position * scale).extend(0.0))
+}
+
+
+/// Converts a position in window pixels (origin top left, y down) into world coordinates,
+/// canvas_transform is the projection times the view matrix
+fn screen_to_world(canvas_transform: Mat4, screen_size: Vec2, screen_pos: IVec2) -> Vec2 {
+    let ndc = Vec2::new(
+        screen_pos.x as f32 / screen_size.x * 2.0 - 1.0,
+        1.0 - screen_pos.y as f32 / screen_size.y * 2.0
+    );
+    let world = canvas_transform.inverse() * ndc.extend(0.0).extend(1.0);
+    world.truncate().truncate()
+}
+
+
+/// Converts a world position into window pixels (origin top left, y down), see screen_to_world
+fn world_to_screen(canvas_transform: Mat4, screen_size: Vec2, world_pos: Vec2) -> Vec2 {
+    let ndc = canvas_transform * world_pos.extend(0.0).extend(1.0);
+    Vec2::new(
+        (ndc.x + 1.0) * 0.5 * screen_size.x,
+        (1.0 - ndc.y) * 0.5 * screen_size.y
+    )
+}
+
+
+/// The (min, max) corners of the bounding box of the screen in world coordinates, see screen_to_world
+fn visible_world_rect(canvas_transform: Mat4, screen_size: Vec2) -> (Vec2, Vec2) {
+    let size = screen_size.as_ivec2();
+    let corners = [
+        IVec2::ZERO,
+        IVec2::new(size.x, 0),
+        IVec2::new(0, size.y),
+        size
+    ].map(|corner| screen_to_world(canvas_transform, screen_size, corner));
+    let min = corners.iter().fold(Vec2::INFINITY, |min, corner| min.min(*corner));
+    let max = corners.iter().fold(Vec2::NEG_INFINITY, |max, corner| max.max(*corner));
+    (min, max)
+}
+
+
+/// How far the camera has to move when changing its zoom to new_zoom, so the world point
+/// under focus_screen stays at the same place on the screen
+fn zoom_toward_offset(ortho_proj: Mat4, position: Vec2, angle: f32, zoom: f32, new_zoom: f32, screen_size: Vec2, focus_screen: IVec2) -> Vec2 {
+    let world_under = |zoom: f32| {
+        let canvas_transform = ortho_proj * view_matrix(position, zoom, angle, screen_size);
+        screen_to_world(canvas_transform, screen_size, focus_screen)
+    };
+    // Moving the camera by `d` moves the world point under the cursor by rot^-1 * d / zoom
+    Mat2::from_angle(angle) * (world_under(zoom) - world_under(new_zoom)) * new_zoom
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_4, SQRT_2};
+
+    use super::*;
+
+    /// The projection of a camera with ScalingMode::Keep
+    fn ortho_proj() -> Mat4 {
+        Mat4::orthographic_rh_gl(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0)
+    }
+
+    #[test]
+    fn rotation_takes_the_shortest_way() {
+        let degrees = |from: f32, to: f32| shortest_angle_diff(from.to_radians(), to.to_radians()).to_degrees();
+        assert!((degrees(350.0, 10.0) - 20.0).abs() < 1e-3);
+        assert!((degrees(10.0, 350.0) + 20.0).abs() < 1e-3);
+        assert!((degrees(-90.0, 180.0) + 90.0).abs() < 1e-3);
+        // Just below a full turn to zero rotates forward instead of back around the circle
+        let diff = shortest_angle_diff(TAU - 0.01, 0.0);
+        assert!((diff - 0.01).abs() < 1e-4, "{}", diff);
+        assert!((shortest_angle_diff(0.0, TAU - 0.01) + 0.01).abs() < 1e-4);
+        assert_eq!(shortest_angle_diff(1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn zoom_toward_keeps_the_focus_point() {
+        // Rotating only keeps the point exactly in place on square screens, see zoom_toward_offset
+        let cases = [
+            (Vec2::new(800.0, 600.0), 0.0),
+            (Vec2::new(600.0, 600.0), 0.5)
+        ];
+        for (screen_size, angle) in cases {
+            let (position, focus) = (Vec2::new(30.0, -20.0), IVec2::new(600, 150));
+            let world_under = |position: Vec2, zoom: f32| {
+                screen_to_world(ortho_proj() * view_matrix(position, zoom, angle, screen_size), screen_size, focus)
+            };
+            let before = world_under(position, 1.0);
+            let offset = zoom_toward_offset(ortho_proj(), position, angle, 1.0, 2.0, screen_size, focus);
+            assert_ne!(offset, Vec2::ZERO);
+            let after = world_under(position + offset, 2.0);
+            assert!(before.abs_diff_eq(after, 1e-3), "{} moved to {}", before, after);
+            // Without zooming, the camera does not move
+            assert!(zoom_toward_offset(ortho_proj(), position, angle, 2.0, 2.0, screen_size, focus).abs_diff_eq(Vec2::ZERO, 1e-3));
         }
     }
+
+    #[test]
+    fn visible_world_rect_before_and_after_zoom() {
+        let screen_size = Vec2::new(800.0, 600.0);
+        let rect = |position: Vec2, zoom: f32, angle: f32| {
+            visible_world_rect(ortho_proj() * view_matrix(position, zoom, angle, screen_size), screen_size)
+        };
+        let assert_rect = |(min, max): (Vec2, Vec2), expected_min: Vec2, expected_max: Vec2| {
+            assert!(min.abs_diff_eq(expected_min, 1e-2) && max.abs_diff_eq(expected_max, 1e-2), "{} {}", min, max);
+        };
+        assert_rect(rect(Vec2::ZERO, 1.0, 0.0), Vec2::new(-800.0, -600.0), Vec2::new(800.0, 600.0));
+        assert_rect(rect(Vec2::ZERO, 2.0, 0.0), Vec2::new(-400.0, -300.0), Vec2::new(400.0, 300.0));
+        assert_rect(rect(Vec2::new(100.0, 50.0), 2.0, 0.0), Vec2::new(-350.0, -275.0), Vec2::new(450.0, 325.0));
+        // Rotated by 45°, the corners of the view lie on the axes
+        assert_rect(rect(Vec2::ZERO, 2.0, FRAC_PI_4), Vec2::new(-400.0, -300.0) * SQRT_2, Vec2::new(400.0, 300.0) * SQRT_2);
+    }
+
+    #[test]
+    fn screen_and_world_round_trip() {
+        let screen_size = Vec2::new(800.0, 600.0);
+        let canvas_transform = ortho_proj() * view_matrix(Vec2::new(30.0, -20.0), 1.5, 0.3, screen_size);
+        let world = screen_to_world(canvas_transform, screen_size, IVec2::new(123, 456));
+        assert!(world_to_screen(canvas_transform, screen_size, world).abs_diff_eq(Vec2::new(123.0, 456.0), 1e-2));
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_serde() {
+        let snapshot = CameraSnapshot {
+            position: [-12, 40],
+            rotation: 1.5,
+            rotation_target: Some(-0.25),
+            zoom: 2.0
+        };
+        let ron = ron::to_string(&snapshot).unwrap();
+        assert_eq!(ron::from_str::<CameraSnapshot>(&ron).unwrap(), snapshot);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<CameraSnapshot>(&json).unwrap(), snapshot);
+    }
 }
@@ -0,0 +1,15 @@
+// Fills the scissor rectangle with the blend constant (see BatchRenderer::render_batch_layers),
+// at the far end of the depth range so the depth buffer gets cleared as well
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+}
+
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    // Replaced by the blend constant, the blend state ignores the fragment color
+    return vec4<f32>(1.0);
+}
@@ -0,0 +1,29 @@
+use wgpu::CommandEncoder;
+
+
+/// Opens a debug group, which shows up as a named section in GPU debuggers like RenderDoc or Nsight.
+/// Only recorded with the `debug-markers` feature, label is not even built otherwise.
+pub(crate) fn push_group(encoder: &mut CommandEncoder, label: impl FnOnce() -> String) {
+    #[cfg(feature = "debug-markers")]
+    encoder.push_debug_group(&label());
+    #[cfg(not(feature = "debug-markers"))]
+    let _ = (encoder, label);
+}
+
+/// Closes the debug group opened last by push_group
+pub(crate) fn pop_group(encoder: &mut CommandEncoder) {
+    #[cfg(feature = "debug-markers")]
+    encoder.pop_debug_group();
+    #[cfg(not(feature = "debug-markers"))]
+    let _ = encoder;
+}
+
+
+/// The label of the command encoder of a frame
+pub(crate) fn frame_label(frame: u64) -> String {
+    if cfg!(feature = "debug-markers") {
+        format!("Frame {}", frame)
+    } else {
+        String::from("Command encoder")
+    }
+}
@@ -2,7 +2,7 @@ use std::{marker::PhantomData, mem::{align_of, size_of}, num::NonZeroU64, ops::R
 
 use bytemuck::{cast_slice, cast_slice_mut};
 use bytemuck::{Pod, Zeroable};
-use wgpu::{BindingResource, Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, QueueWriteBufferView};
+use wgpu::{BindingResource, Buffer, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, MapMode, QueueWriteBufferView};
 
 use super::graphics_system::GraphicsSystem;
 
@@ -104,6 +104,38 @@ impl<T: Pod + Zeroable> DynamicBuffer<T> {
         }
     }
 
+    /// Copies the whole buffer into a staging buffer and returns its contents.
+    /// This blocks until the GPU is idle, so it is slow and only meant for debugging and tests.
+    pub fn read_back(&self, gpu: &GraphicsSystem) -> Vec<T> {
+        let size = self.buffer.size();
+        let staging = gpu.device().create_buffer(&BufferDescriptor {
+            label: Some("Dynamic buffer read back"),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+        let mut copy_encoder = gpu.device().create_command_encoder(&CommandEncoderDescriptor { label: Some("Dynamic buffer read back encoder") });
+        copy_encoder.copy_buffer_to_buffer(&self.buffer, 0, &staging, 0, size);
+        gpu.queue().submit(Some(copy_encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        gpu.poll_wait();
+        receiver.recv().expect("Dynamic buffer mapping callback was dropped").expect("Failed to map the dynamic buffer for reading.");
+
+        let data = {
+            let mapped = slice.get_mapped_range();
+            // The buffer size might not be a multiple of T, ignore the remaining bytes
+            let len = mapped.len() / size_of::<T>() * size_of::<T>();
+            bytemuck::pod_collect_to_vec::<u8, T>(&mapped[..len])
+        };
+        staging.unmap();
+        data
+    }
+
     /// Ensures that this buffer can hold at least `size` instances of `T`.
     pub fn reserve_total(&mut self, gpu: &GraphicsSystem, size: usize) {
         self.ensure_raw_size(gpu, (size * size_of::<T>()) as u64);
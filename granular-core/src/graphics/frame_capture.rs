@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use image::RgbaImage;
+use log::*;
+use wgpu::{Buffer, CommandEncoder, Device, Texture, TextureFormat};
+
+
+/// A copy of a frame that has been recorded into a command encoder, but might not be finished yet
+pub(crate) struct FrameReadback {
+    buffer: Buffer,
+    width: u32,
+    height: u32,
+    /// Bytes per row inside of the buffer, wgpu requires them to be aligned to 256 bytes
+    padded_bytes_per_row: u32,
+    format: TextureFormat
+}
+impl FrameReadback {
+    /// Records a copy of the whole texture into a new buffer, returns None if the format is not supported
+    pub(crate) fn record(device: &Device, encoder: &mut CommandEncoder, texture: &Texture) -> Option<Self> {
+        let format = texture.format();
+        if !matches!(format, TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb | TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb) {
+            warn!("FrameReadback: Capturing frames with the format {:?} is not supported", format);
+            return None;
+        };
+        let width = texture.width();
+        let height = texture.height();
+        let unpadded_bytes_per_row = width * 4;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame readback buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height)
+                }
+            },
+            texture.size());
+
+        Some(Self {
+            buffer,
+            width,
+            height,
+            padded_bytes_per_row,
+            format
+        })
+    }
+
+
+    /// Waits until the GPU has finished the copy (the command encoder needs to be submitted first)
+    /// and converts it into an RGBA image. This blocks until the GPU is idle.
+    pub(crate) fn into_image(self, device: &Device) -> Option<RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        if let Err(e) = receiver.recv().ok()? {
+            error!("FrameReadback: Failed to map the readback buffer: {}", e);
+            return None;
+        };
+
+        let unpadded_bytes_per_row = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            data.chunks(self.padded_bytes_per_row as usize).for_each(|row| {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            });
+        }
+        self.buffer.unmap();
+
+        if matches!(self.format, TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb) {
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        };
+        RgbaImage::from_raw(self.width, self.height, pixels)
+    }
+}
+
+
+
+/// Keeps track of an ongoing screenshot sequence, see `Renderer::start_capture`
+pub(crate) struct FrameCapture {
+    path_prefix: PathBuf,
+    frames_left: u32,
+    next_index: u32
+}
+impl FrameCapture {
+    pub(crate) fn new(path_prefix: PathBuf, num_frames: u32) -> Self {
+        Self {
+            path_prefix,
+            frames_left: num_frames,
+            next_index: 0
+        }
+    }
+
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.frames_left == 0
+    }
+
+
+    /// Writes the image as the next numbered PNG ("<prefix>0000.png", "<prefix>0001.png", ...)
+    pub(crate) fn save(&mut self, image: &RgbaImage) {
+        let mut path = self.path_prefix.clone().into_os_string();
+        path.push(format!("{:04}.png", self.next_index));
+        if let Err(e) = image.save(&path) {
+            error!("FrameCapture: Could not save frame to {:?}: {}", path, e);
+        };
+        self.next_index += 1;
+        self.frames_left = self.frames_left.saturating_sub(1);
+    }
+}
@@ -1,12 +1,14 @@
 use geese::*;
-use wgpu::{Adapter, Backends, Instance, InstanceDescriptor, RequestAdapterOptions};
+use wgpu::{Adapter, AdapterInfo, Backends, Instance, InstanceDescriptor, RequestAdapterOptions};
 
 use super::WindowSystem;
 
 
 pub struct GraphicsBackend {
     instance: Instance,
-    adapter: Adapter
+    adapter: Adapter,
+    /// All adapters of the instance, collected once on creation
+    available_adapters: Vec<AdapterInfo>
 }
 impl GraphicsBackend {
     pub fn instance(&self) -> &Instance {
@@ -17,6 +19,16 @@ impl GraphicsBackend {
         &self.adapter
     }
 
+    /// Information about the adapter that is currently in use, always valid once the backend exists
+    pub fn adapter_info(&self) -> AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    /// Information about every adapter that was found when the backend was created
+    pub fn available_adapters(&self) -> &[AdapterInfo] {
+        &self.available_adapters
+    }
+
     pub(super) fn set_adapter(&mut self, adapter: Adapter) {
         self.adapter = adapter;
     }
@@ -26,15 +38,19 @@ impl GeeseSystem for GraphicsBackend {
         .with::<WindowSystem>();
 
     fn new(_ctx: GeeseContextHandle<Self>) -> Self {
+        let backends = Backends::VULKAN;
         let instance = wgpu::Instance::new(InstanceDescriptor {
-            backends: Backends::VULKAN,
+            backends,
             ..Default::default()
         });
+        // Blocks until the adapter is known, so that adapter() can be used right away
         let adapter = pollster::block_on(instance.request_adapter(&RequestAdapterOptions::default())).expect("Cannot request any adapter");
+        let available_adapters = instance.enumerate_adapters(backends).iter().map(|adapter| adapter.get_info()).collect();
 
         Self {
             instance,
             adapter,
+            available_adapters
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,104 @@
+use geese::{GeeseContextHandle, GeeseSystem};
+use log::warn;
+use wgpu::{Adapter, Limits, TextureFormat};
+
+
+/// Picks the swapchain format out of the formats the surface supports, see `GraphicsSettings::set_format_selector`
+pub type FormatSelector = Box<dyn Fn(&[TextureFormat]) -> TextureFormat + Send + Sync>;
+
+
+/// Which limits are requested when creating the device, see `GraphicsSettings::set_device_limits`
+#[derive(Debug, Clone, Default)]
+pub enum DeviceLimits {
+    /// Everything the adapter supports
+    #[default]
+    Adapter,
+    /// The WebGPU defaults
+    Default,
+    /// Limits compatible with GLES 3.1 and D3D11
+    Downlevel,
+    /// Limits compatible with WebGL2
+    DownlevelWebGl2,
+    Custom(Limits)
+}
+
+
+
+/// Settings that are used when the GraphicsSystem gets created. The system is added by the engine right away,
+/// so change the settings before calling `GranularEngine::run` (through `GranularEngine::get_ctx`).
+pub struct GraphicsSettings {
+    ctx: GeeseContextHandle<Self>,
+    device_limits: DeviceLimits,
+    format_selector: Option<FormatSelector>
+}
+impl GraphicsSettings {
+    /// Sets which limits are requested from the adapter (all it supports by default). Lower limits can make
+    /// creating the device faster and help targeting low-spec devices deliberately. Changing the limits
+    /// while the engine is running only takes effect the next time the device is created
+    /// (see `GraphicsSystem::request_device_reset`).
+    pub fn set_device_limits(&mut self, device_limits: DeviceLimits) {
+        self.device_limits = device_limits;
+    }
+
+    pub fn device_limits(&self) -> &DeviceLimits {
+        &self.device_limits
+    }
+
+
+    /// Sets the closure that picks the swapchain format out of the formats supported by the surface (in the order
+    /// of preference of the surface). By default the first sRGB format is used. Pass None to go back to the default.
+    pub fn set_format_selector(&mut self, selector: Option<FormatSelector>) {
+        self.format_selector = selector;
+    }
+
+
+    /// Picks the swapchain format using the format selector, falling back to the first sRGB format
+    /// (or Bgra8UnormSrgb) if there is none or it picked an unsupported format
+    pub(crate) fn select_format(&self, formats: &[TextureFormat]) -> TextureFormat {
+        if let Some(selector) = &self.format_selector {
+            let format = selector(formats);
+            if formats.contains(&format) {
+                return format;
+            };
+            warn!("The format selector picked {:?}, which the surface does not support", format);
+        };
+        formats.iter().copied()
+            .find(|format| format.is_srgb())
+            .unwrap_or(TextureFormat::Bgra8UnormSrgb)
+    }
+
+
+    /// Returns the limits to request from the adapter. The presets use the texture resolution limits of the adapter,
+    /// so that textures can be as big as the swapchain. If the adapter does not support the limits, it falls back to its own.
+    pub(crate) fn resolve_limits(&self, adapter: &Adapter) -> Limits {
+        let supported = adapter.limits();
+        let limits = match &self.device_limits {
+            DeviceLimits::Adapter => return supported,
+            DeviceLimits::Default => Limits::default().using_resolution(supported.clone()),
+            DeviceLimits::Downlevel => Limits::downlevel_defaults().using_resolution(supported.clone()),
+            DeviceLimits::DownlevelWebGl2 => Limits::downlevel_webgl2_defaults().using_resolution(supported.clone()),
+            DeviceLimits::Custom(limits) => limits.clone()
+        };
+        let mut within = true;
+        limits.check_limits_with_fail_fn(&supported, false, |name, requested, allowed| {
+            warn!("Device limit {} of {} is not supported by the adapter (at most {})", name, requested, allowed);
+            within = false;
+        });
+        match within {
+            true => limits,
+            false => {
+                warn!("Requesting the limits of the adapter instead of {:?}", self.device_limits);
+                supported
+            }
+        }
+    }
+}
+impl GeeseSystem for GraphicsSettings {
+    fn new(ctx: GeeseContextHandle<Self>) -> Self {
+        Self {
+            ctx,
+            device_limits: DeviceLimits::default(),
+            format_selector: None
+        }
+    }
+}
@@ -1,16 +1,26 @@
 #![allow(unused)]
 
+use std::{sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, time::{Duration, Instant}};
+
+use anyhow::bail;
 use bytemuck_derive::{Pod, Zeroable};
 use geese::*;
 use glam::{Vec2, IVec2};
 use log::*;
-use wgpu::{Device, Queue, SurfaceConfiguration, Surface, TextureViewDescriptor, CommandEncoderDescriptor, SurfaceTexture, TextureView, CommandEncoder};
-use winit::dpi::PhysicalSize;
+use rustc_hash::FxHashMap as HashMap;
+use wgpu::{Adapter, Device, Queue, Sampler, SurfaceCapabilities, SurfaceConfiguration, Surface, Texture, TextureFormat, TextureViewDescriptor, CommandEncoderDescriptor, SurfaceTexture, TextureView, CommandEncoder};
+use winit::{dpi::PhysicalSize, window::{Window, WindowId}};
+
+use crate::FrameInfo;
 
-use super::{graphics_backend, GraphicsBackend, WindowSystem};
+use super::{blit::BlitPipeline, debug_markers, frame_capture::FrameReadback, graphics_backend, hdr_target::HdrTarget, GraphicsBackend, GraphicsSettings, WindowSystem};
 
 pub type FrameData = Option<(SurfaceTexture, TextureView, CommandEncoder)>;
 pub type FrameDataMut<'a> = Option<&'a mut (wgpu::SurfaceTexture, wgpu::TextureView, wgpu::CommandEncoder)>;
+/// Gets called with every GPU error that is not caught by an error scope (validation errors, out of memory)
+pub type ErrorCallback = Arc<dyn Fn(wgpu::Error) + Send + Sync>;
+/// Replaces the default final pass when HDR is enabled, see `GraphicsSystem::set_final_pass`
+pub type FinalPass = Box<dyn FnMut(&mut FinalPassTarget) + Send + Sync>;
 
 
 #[repr(C)]
@@ -19,94 +29,575 @@ pub(crate) struct Vertex {
     _pos: IVec2,
     _col: [f32; 4],
     _tex_coord: Vec2,
-    _tex_idx: u64,
+    _tex_idx: u32,
+    /// Depth of the layer, only used when depth testing is enabled
+    _depth: f32,
+    /// Position relative to the center of the quad
+    _local_pos: Vec2,
+    /// Half size, corner radius and outline thickness of the quad (see `with_shape`)
+    _shape: [f32; 4],
+    /// Min (xy) and size (zw) of the uv rect the texture coordinates wrap around in, a size of zero disables wrapping
+    _uv_wrap: [f32; 4],
 }
 impl Vertex {
-    pub fn new(pos: IVec2, color: [f32; 4], tex_coord: Vec2, tex_index: u64) -> Self {
+    pub fn new(pos: IVec2, color: [f32; 4], tex_coord: Vec2, tex_index: u64, depth: f32) -> Self {
         Self {
             _pos: pos,
             _col: color,
             _tex_coord: tex_coord,
-            _tex_idx: tex_index,
+            _tex_idx: tex_index as u32,
+            _depth: depth,
+            _local_pos: Vec2::ZERO,
+            _shape: [0.0; 4],
+            _uv_wrap: [0.0; 4],
         }
     }
+
+    /// Sets the data needed for rounded corners and outlines, with a corner radius
+    /// and outline thickness of zero the quad is drawn as a sharp, filled rectangle
+    pub fn with_shape(mut self, local_pos: Vec2, half_size: Vec2, corner_radius: f32, outline_thickness: f32) -> Self {
+        self._local_pos = local_pos;
+        self._shape = [half_size.x, half_size.y, corner_radius, outline_thickness];
+        self
+    }
+
+    /// Makes the texture coordinates wrap around inside of the uv rect (min, max), used for quads with an uv offset
+    pub fn with_uv_wrap(mut self, uv_min: Vec2, uv_max: Vec2) -> Self {
+        let size = uv_max - uv_min;
+        self._uv_wrap = [uv_min.x, uv_min.y, size.x, size.y];
+        self
+    }
 }
 pub const VERTEX_SIZE: usize = std::mem::size_of::<Vertex>();
 
 
 
+/// Statistics about acquiring and presenting frames, see `GraphicsSystem::present_stats`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PresentStats {
+    /// Number of frames that were successfully presented
+    pub frames_presented: u64,
+    /// Number of frames that were dropped because acquiring the surface texture timed out
+    pub timeouts: u64,
+    /// Number of frames that were dropped because the surface was outdated or lost
+    pub outdated: u64,
+    /// Time spent submitting and presenting the last frame
+    pub last_present_time: Duration,
+    /// Time spent submitting and presenting all frames
+    pub total_present_time: Duration
+}
+impl PresentStats {
+    /// Number of frames that could not be presented
+    pub fn dropped_frames(&self) -> u64 {
+        self.timeouts + self.outdated
+    }
+
+    pub fn average_present_time(&self) -> Duration {
+        if self.frames_presented == 0 {
+            Duration::ZERO
+        } else {
+            self.total_present_time / self.frames_presented as u32
+        }
+    }
+}
+
+
+/// The current frame, handed to custom render callbacks (see `Renderer::add_render_callback`)
+pub struct RenderTarget<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub encoder: &'a mut CommandEncoder,
+    /// The view the frame is rendered into (the HDR texture when HDR is enabled), render passes
+    /// should use `LoadOp::Load` to keep what was drawn before
+    pub view: &'a TextureView
+}
+
+
+/// Handed to the final pass, which has to draw the linear HDR frame onto the surface (for example with tonemapping)
+pub struct FinalPassTarget<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub encoder: &'a mut CommandEncoder,
+    /// The rendered frame, its format is `GraphicsSystem::HDR_FORMAT`
+    pub source: &'a TextureView,
+    /// The view of the surface texture
+    pub target: &'a TextureView,
+    pub target_format: TextureFormat
+}
+
+
+/// The surface of a single window
+struct WindowSurface {
+    surface: Surface<'static>,
+    config: SurfaceConfiguration
+}
+
+
 pub struct GraphicsSystem {
     ctx: GeeseContextHandle<Self>,
-    surface_config: SurfaceConfiguration,
+    main_window: WindowId,
+    surfaces: HashMap<WindowId, WindowSurface>,
     frame_data: FrameData,
-    surface: Surface<'static>,
+    /// The window that the current frame (frame_data) gets presented to
+    frame_window: Option<WindowId>,
+    present_stats: PresentStats,
     device: Device,
-    queue: Queue
+    queue: Queue,
+    /// Whether binding arrays of textures (with non-uniform indexing) are supported
+    texture_arrays_supported: bool,
+    /// Whether the surface textures can be copied from (needed for capturing frames)
+    frame_capture_supported: bool,
+    /// Set when the device has been lost, the engine then recreates it (see reset_device)
+    needs_reset: Arc<AtomicBool>,
+    error_callback: Option<ErrorCallback>,
+    /// When set, frames get rendered into its HDR texture and copied onto the surface before presenting
+    hdr: Option<HdrTarget>,
+    /// The view of the surface texture while the frame is being rendered into the HDR texture
+    surface_view: Option<TextureView>,
+    final_pass: Option<FinalPass>,
+    /// Used by blit, one pipeline per destination format (created on first use)
+    blit_pipelines: Mutex<HashMap<TextureFormat, Arc<BlitPipeline>>>,
+    blit_sampler: Sampler
 }
 impl GraphicsSystem {
+    /// Requests a redraw for all windows
     pub fn request_redraw(&self) {
-        self.ctx.get::<WindowSystem>().window_handle().request_redraw();
+        self.ctx.get::<WindowSystem>().windows().iter().for_each(|window| window.request_redraw());
     }
 
 
-    pub fn resize_surface(&mut self, new_size: PhysicalSize<u32>) {
-        self.surface_config.width = new_size.width.max(1);
-        self.surface_config.height = new_size.height.max(1);
-        self.surface.configure(&self.device, &self.surface_config);
+    pub fn resize_surface(&mut self, window_id: WindowId, new_size: PhysicalSize<u32>) {
+        let Some(target) = self.surfaces.get_mut(&window_id) else {
+            warn!("resize_surface: No surface exists for window {:?}", window_id);
+            return;
+        };
+        target.config.width = new_size.width.max(1);
+        target.config.height = new_size.height.max(1);
+        target.surface.configure(&self.device, &target.config);
     }
 
-    pub fn begin_frame(&mut self) {
-        let frame = self.surface.get_current_texture().expect("Failed to acquire next swapchain texture");
-        let view = frame.texture.create_view(&TextureViewDescriptor{..Default::default()});
+
+    /// Creates a surface for an additional window. It uses the format and present mode of the main window
+    /// if the new surface supports them, otherwise ones from its own capabilities.
+    ///
+    /// Note: Without HDR, renderers draw in the format of the main surface, so a window that ends up
+    /// with another format cannot be rendered into by them.
+    pub fn add_surface(&mut self, window: Arc<Window>) {
+        let backend = self.ctx.get::<GraphicsBackend>();
+        let surface = backend.instance().create_surface(window.clone()).unwrap();
+        let capabilities = surface.get_capabilities(backend.adapter());
+        drop(backend);
+
+        let main_config = self.surface_config();
+        let format = match capabilities.formats.contains(&main_config.format) {
+            true => main_config.format,
+            false => {
+                let format = self.ctx.get::<GraphicsSettings>().select_format(&capabilities.formats);
+                warn!("add_surface: The surface of window {:?} does not support {:?}, using {:?}", window.id(), main_config.format, format);
+                format
+            }
+        };
+        // The automatic present modes are supported by every surface, they are never listed in the capabilities
+        let present_mode = match main_config.present_mode {
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync => main_config.present_mode,
+            mode if capabilities.present_modes.contains(&mode) => mode,
+            _ => wgpu::PresentMode::Fifo
+        };
+        let size = window.inner_size();
+        let config = SurfaceConfiguration {
+            usage: main_config.usage & capabilities.usages,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: vec![],
+            desired_maximum_frame_latency: main_config.desired_maximum_frame_latency
+        };
+        surface.configure(&self.device, &config);
+        self.surfaces.insert(window.id(), WindowSurface {
+            surface,
+            config
+        });
+    }
+
+
+    pub fn remove_surface(&mut self, window_id: WindowId) {
+        if window_id == self.main_window {
+            warn!("remove_surface: The surface of the main window cannot be removed");
+            return;
+        };
+        self.surfaces.remove(&window_id);
+    }
+
+
+    /// Acquires the next texture of the windows surface, all rendering goes to
+    /// that window until present_frame is called
+    pub fn begin_frame(&mut self, window_id: WindowId) {
+        if self.frame_data.is_some() {
+            warn!("begin_frame: A frame is already in progress, call present_frame first");
+            return;
+        };
+        let Some(target) = self.surfaces.get(&window_id) else {
+            warn!("begin_frame: No surface exists for window {:?}", window_id);
+            return;
+        };
+        let frame = match target.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Timeout) => {
+                self.present_stats.timeouts += 1;
+                warn!("Acquiring the next swapchain texture timed out, dropping this frame");
+                return;
+            },
+            Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                self.present_stats.outdated += 1;
+                warn!("Swapchain is outdated, reconfiguring and dropping this frame");
+                target.surface.configure(&self.device, &target.config);
+                return;
+            },
+            Err(e) => panic!("Failed to acquire next swapchain texture: {}", e)
+        };
+        let mut view = frame.texture.create_view(&TextureViewDescriptor{..Default::default()});
+        if let Some(hdr) = &mut self.hdr {
+            self.surface_view = Some(view);
+            view = hdr.frame_view(&self.device, frame.texture.size());
+        };
+        let label = debug_markers::frame_label(self.ctx.get::<FrameInfo>().frame());
         let encoder = self.device.create_command_encoder(
             &CommandEncoderDescriptor {
-                label: Some("Command encoder")
+                label: Some(&label)
             });
-        self.frame_data = Some((frame, view, encoder))
+        self.frame_data = Some((frame, view, encoder));
+        self.frame_window = Some(window_id);
     }
 
     pub fn device(&self) -> &Device {
         &self.device
     }
 
+    /// Returns the surface configuration of the main window
     pub fn surface_config(&self) -> &SurfaceConfiguration {
-        &self.surface_config
+        &self.surfaces[&self.main_window].config
+    }
+
+    pub fn surface_config_for(&self, window_id: WindowId) -> Option<&SurfaceConfiguration> {
+        self.surfaces.get(&window_id).map(|target| &target.config)
+    }
+
+
+    /// Returns the formats, present modes and alpha modes the surface of the main window supports with
+    /// the current adapter (queried every call), for example to only offer supported present modes in a settings menu
+    pub fn surface_capabilities(&self) -> SurfaceCapabilities {
+        self.surface_capabilities_for(self.main_window).unwrap_or_default()
+    }
+
+    pub fn surface_capabilities_for(&self, window_id: WindowId) -> Option<SurfaceCapabilities> {
+        let target = self.surfaces.get(&window_id)?;
+        let backend = self.ctx.get::<GraphicsBackend>();
+        Some(target.surface.get_capabilities(backend.adapter()))
+    }
+
+    pub fn main_window(&self) -> WindowId {
+        self.main_window
+    }
+
+    /// The window which the current frame is being rendered to
+    pub fn frame_window(&self) -> Option<WindowId> {
+        self.frame_window
     }
 
     pub fn queue(&self) -> &Queue {
         &self.queue
     }
 
+    /// The format that renderers draw into: HDR_FORMAT when HDR is enabled, otherwise the format of the main surface
+    pub fn render_format(&self) -> TextureFormat {
+        match self.hdr {
+            Some(_) => Self::HDR_FORMAT,
+            None => self.surface_config().format
+        }
+    }
+
+
+    /// Renders frames into a linear HDR texture (HDR_FORMAT) instead of the surface, which then gets
+    /// drawn onto the surface by the final pass. Use `Renderer::set_hdr`, which also recreates the pipelines.
+    pub(crate) fn set_hdr(&mut self, enabled: bool) {
+        if self.frame_data.is_some() {
+            warn!("set_hdr: Changing the render target while a frame is in progress");
+        };
+        self.hdr = match enabled {
+            true => Some(HdrTarget::new(&self.device, self.surface_config().format)),
+            false => None
+        };
+    }
+
+    pub fn hdr_enabled(&self) -> bool {
+        self.hdr.is_some()
+    }
+
+
+    /// Replaces the default final pass (which copies the HDR frame onto the surface, clamping it) with
+    /// a custom one, for example for bloom and tonemapping. Pass None to go back to the default.
+    /// Only used while HDR is enabled.
+    pub fn set_final_pass(&mut self, final_pass: Option<FinalPass>) {
+        self.final_pass = final_pass;
+    }
+
+
+    /// Draws the HDR frame onto the surface, does nothing if HDR is disabled or it already happened this frame
+    fn resolve_hdr(&mut self) {
+        let Some(surface_view) = self.surface_view.take() else {
+            return;
+        };
+        let Some((frame, hdr_view, encoder)) = self.frame_data.as_mut() else {
+            return;
+        };
+        debug_markers::push_group(encoder, || String::from("Final pass"));
+        match (&mut self.final_pass, &self.hdr) {
+            (Some(final_pass), _) => final_pass(&mut FinalPassTarget {
+                device: &self.device,
+                queue: &self.queue,
+                encoder,
+                source: hdr_view,
+                target: &surface_view,
+                target_format: frame.texture.format()
+            }),
+            (None, Some(hdr)) => hdr.resolve(encoder, &surface_view),
+            (None, None) => ()
+        };
+        debug_markers::pop_group(encoder);
+    }
+
+    /// Draws src stretched into dst_rect (x, y, width and height in pixels) of dst on the GPU, keeping the rest
+    /// of dst. The copy is submitted right away. dst needs the RENDER_ATTACHMENT usage and a color format
+    /// that can be rendered to with floats, src needs to be filterable (any non-integer color format).
+    pub fn blit(&self, src: &TextureView, dst: &Texture, dst_rect: (u32, u32, u32, u32)) -> anyhow::Result<()> {
+        let format = dst.format();
+        if !dst.usage().contains(wgpu::TextureUsages::RENDER_ATTACHMENT) {
+            bail!("Cannot blit into a texture without the RENDER_ATTACHMENT usage");
+        };
+        if !matches!(format.sample_type(None, None), Some(wgpu::TextureSampleType::Float { .. })) || format.is_compressed() {
+            bail!("Cannot blit into a texture with the format {:?}, it needs to be a float color format", format);
+        };
+        let (x, y, width, height) = dst_rect;
+        if width == 0 || height == 0 || x + width > dst.width() || y + height > dst.height() {
+            bail!("Blit rectangle {:?} is empty or outside of the destination ({}x{})", dst_rect, dst.width(), dst.height());
+        };
+
+        // Clone the pipeline out of the cache, so the lock is not held while recording
+        let pipeline = self.blit_pipelines.lock().unwrap()
+            .entry(format)
+            .or_insert_with(|| Arc::new(BlitPipeline::new(&self.device, format, None)))
+            .clone();
+        let bind_group = pipeline.bind_group(&self.device, src, &self.blit_sampler);
+        let dst_view = dst.create_view(&TextureViewDescriptor::default());
+        let mut encoder = self.device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Blit encoder")
+        });
+        pipeline.draw(&mut encoder, &bind_group, &dst_view, wgpu::LoadOp::Load, Some((x as f32, y as f32, width as f32, height as f32)));
+        self.queue.submit(Some(encoder.finish()));
+        Ok(())
+    }
+
+
+    /// Returns false if the device does not support texture binding arrays (for example on GL),
+    /// in that case the BatchRenderer only binds a single texture per batch
+    pub fn supports_texture_arrays(&self) -> bool {
+        self.texture_arrays_supported
+    }
+
     pub fn queue_mut(&mut self) -> &mut Queue {
         &mut self.queue
     }
 
-    pub fn present_frame(&mut self) {
+    pub fn present_frame(&mut self, window_id: WindowId) {
         if self.frame_data.is_none() {
             warn!("No frame data present, begin a frame by calling begin_frame()");
             return;
         };
+        if self.frame_window != Some(window_id) {
+            warn!("present_frame: The current frame belongs to {:?}, not {:?}", self.frame_window, window_id);
+            return;
+        };
+        self.frame_window = None;
+        self.resolve_hdr();
         let (frame, _, encoder) = self.frame_data.take().unwrap();
+        let start = Instant::now();
         self.queue.submit(Some(encoder.finish()));
         frame.present();
+        let elapsed = start.elapsed();
+
+        self.present_stats.frames_presented += 1;
+        self.present_stats.last_present_time = elapsed;
+        self.present_stats.total_present_time += elapsed;
+    }
+
+    /// Blocks until the GPU has finished all submitted work, including pending buffer copies and texture uploads.
+    /// This is meant for tests, screenshots and readbacks, calling it every frame stalls the CPU.
+    pub fn poll_wait(&self) {
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+
+    /// Returns statistics about dropped frames and the time spent presenting
+    pub fn present_stats(&self) -> PresentStats {
+        self.present_stats
+    }
+
+    /// Returns true if a frame has been started (and not dropped) and can be rendered to
+    pub fn has_frame(&self) -> bool {
+        self.frame_data.is_some()
+    }
+
+    /// Records a copy of the current frame, which can be read back after present_frame.
+    /// Returns None if there is no frame or the surface does not support being copied from.
+    pub(crate) fn record_frame_readback(&mut self) -> Option<FrameReadback> {
+        if !self.frame_capture_supported {
+            warn!("record_frame_readback: The surface does not support COPY_SRC, frames cannot be captured");
+            return None;
+        };
+        // The surface only contains the frame after the final pass
+        self.resolve_hdr();
+        let (frame, _, encoder) = self.frame_data.as_mut()?;
+        FrameReadback::record(&self.device, encoder, &frame.texture)
+    }
+
+    /// Returns the current frame together with the device and queue, None if no frame was started
+    pub fn render_target(&mut self) -> Option<RenderTarget> {
+        let (_, view, encoder) = self.frame_data.as_mut()?;
+        Some(RenderTarget {
+            device: &self.device,
+            queue: &self.queue,
+            encoder,
+            view
+        })
     }
 
     pub fn frame_data_mut(&mut self) -> FrameDataMut {
         self.frame_data.as_mut()
     }
+
+    /// The command encoder of the current frame, so custom systems can record their own render or
+    /// compute passes, which get submitted together with the frame in present_frame.
+    /// Only returns Some between begin_frame and present_frame (and if the frame was not dropped).
+    pub fn encoder_mut(&mut self) -> Option<&mut CommandEncoder> {
+        self.frame_data.as_mut().map(|(_, _, encoder)| encoder)
+    }
+
+
+    /// Calls callback for every GPU error that is not caught by an error scope, instead of the default
+    /// handler of wgpu (which panics). Use it to log errors or to call request_device_reset.
+    /// The callback is kept when the device gets recreated.
+    pub fn set_error_callback(&mut self, callback: impl Fn(wgpu::Error) + Send + Sync + 'static) {
+        self.error_callback = Some(Arc::new(callback));
+        self.install_device_callbacks();
+    }
+
+
+    /// Returns true if the device has been lost (or a reset was requested), the engine then recreates
+    /// the device before the next frame
+    pub fn needs_reset(&self) -> bool {
+        self.needs_reset.load(Ordering::Relaxed)
+    }
+
+
+    /// Makes the engine recreate the device before the next frame, see needs_reset
+    pub fn request_device_reset(&self) {
+        self.needs_reset.store(true, Ordering::Relaxed);
+    }
+
+
+    /// Recreates the device and queue and reconfigures all surfaces. Everything else that was created with the
+    /// old device is invalid afterwards, the engine takes care of recreating the assets and renderers.
+    pub(crate) fn reset_device(&mut self) {
+        warn!("Recreating the GPU device");
+        self.frame_data = None;
+        self.frame_window = None;
+        self.surface_view = None;
+        let backend = self.ctx.get::<GraphicsBackend>();
+        let limits = self.ctx.get::<GraphicsSettings>().resolve_limits(backend.adapter());
+        let (device, queue) = Self::request_device(backend.adapter(), self.texture_arrays_supported, limits);
+        drop(backend);
+        self.device = device;
+        self.queue = queue;
+        self.surfaces.values().for_each(|target| target.surface.configure(&self.device, &target.config));
+        if self.hdr.is_some() {
+            self.hdr = Some(HdrTarget::new(&self.device, self.surface_config().format));
+        };
+        self.blit_pipelines.lock().unwrap().clear();
+        self.blit_sampler = Self::create_blit_sampler(&self.device);
+        self.needs_reset.store(false, Ordering::Relaxed);
+        self.install_device_callbacks();
+    }
+
+
+    /// Wires the error callback and the device lost handler into the current device
+    fn install_device_callbacks(&self) {
+        let needs_reset = self.needs_reset.clone();
+        self.device.set_device_lost_callback(move |reason, message| {
+            // Dropping the old device in reset_device also reports it as lost
+            if matches!(reason, wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::DeviceInvalid) {
+                error!("The GPU device has been lost ({:?}): {}", reason, message);
+                needs_reset.store(true, Ordering::Relaxed);
+            };
+        });
+        if let Some(callback) = self.error_callback.clone() {
+            self.device.on_uncaptured_error(Box::new(move |error| callback(error)));
+        };
+    }
+
+
+    /// Creates the linear filtering sampler that blit uses to read its source texture
+    fn create_blit_sampler(device: &Device) -> Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        })
+    }
+
+
+    /// Creates the logical device and command queue
+    fn request_device(adapter: &Adapter, texture_arrays_supported: bool, limits: wgpu::Limits) -> (Device, Queue) {
+        pollster::block_on(
+            adapter.request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    // Compressed formats are enabled whenever the adapter has them, see TextureAsset
+                    required_features: match texture_arrays_supported {
+                        true => Self::TEXTURE_ARRAY_FEATURES,
+                        false => wgpu::Features::empty()
+                    } | (adapter.features() & Self::TEXTURE_COMPRESSION_FEATURES),
+                    // See GraphicsSettings::resolve_limits
+                    required_limits: limits,
+                },
+                None,
+            )).expect("Failed to create device")
+    }
+
+    /// Format of the intermediate texture while HDR is enabled, see `Renderer::set_hdr`
+    pub const HDR_FORMAT: TextureFormat = HdrTarget::FORMAT;
+    const TEXTURE_ARRAY_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_BINDING_ARRAY.union(wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING);
+    const TEXTURE_COMPRESSION_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+        .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+        .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
 }
 impl GeeseSystem for GraphicsSystem {
     const DEPENDENCIES: Dependencies = dependencies()
         .with::<WindowSystem>()
+        .with::<GraphicsSettings>()
+        .with::<FrameInfo>()
         .with::<Mut<GraphicsBackend>>();
 
     fn new(mut ctx: GeeseContextHandle<Self>) -> Self {
         let surface;
         let window_size;
+        let main_window;
         {
             let immut_backend = ctx.get::<GraphicsBackend>();
             let window = ctx.get::<WindowSystem>();
             window_size = window.window_handle().inner_size();
+            main_window = window.main_window_id();
             surface = immut_backend.instance().create_surface(window.window_handle()).unwrap();
         }
         {
@@ -121,28 +612,24 @@ impl GeeseSystem for GraphicsSystem {
 
         let backend = ctx.get::<GraphicsBackend>();
         let adapter = backend.adapter();
-        // Create the logical device and command queue
-        let (device, queue) = pollster::block_on(
-            adapter.request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::TEXTURE_BINDING_ARRAY | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
-                    // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-                    required_limits: adapter.limits(),
-                },
-                None,
-            )).expect("Failed to create device");
+        let texture_arrays_supported = adapter.features().contains(Self::TEXTURE_ARRAY_FEATURES);
+        if !texture_arrays_supported {
+            warn!("Adapter '{}' does not support {:?}, falling back to one texture per batch", adapter.get_info().name, Self::TEXTURE_ARRAY_FEATURES);
+        };
+        let limits = ctx.get::<GraphicsSettings>().resolve_limits(adapter);
+        let (device, queue) = Self::request_device(adapter, texture_arrays_supported, limits);
 
         let swapchain_capabilities = surface.get_capabilities(adapter);
-        let swapchain_format = swapchain_capabilities.formats.iter()
-            .find(|format| {
-                format.is_srgb()
-            })
-            .unwrap_or(&wgpu::TextureFormat::Bgra8UnormSrgb);
+        let swapchain_format = ctx.get::<GraphicsSettings>().select_format(&swapchain_capabilities.formats);
         debug!("Swapchain format: {:?}", swapchain_format);
+        // Copying from the surface is needed to capture frames, but not every platform supports it
+        let frame_capture_supported = swapchain_capabilities.usages.contains(wgpu::TextureUsages::COPY_SRC);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: *swapchain_format,
+            usage: match frame_capture_supported {
+                true => wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                false => wgpu::TextureUsages::RENDER_ATTACHMENT
+            },
+            format: swapchain_format,
             width: window_size.width,
             height: window_size.height,
             // Note: Having PresentMode::Fifo (as in the example) caused a Swapchain acquire texture timeout
@@ -157,13 +644,33 @@ impl GeeseSystem for GraphicsSystem {
 
         drop(backend);
 
-        Self {
+        let mut surfaces = HashMap::default();
+        surfaces.insert(main_window, WindowSurface {
+            surface,
+            config
+        });
+
+        let blit_sampler = Self::create_blit_sampler(&device);
+        let graphics_sys = Self {
             ctx,
             device,
             queue,
-            surface,
-            surface_config: config,
-            frame_data: None
-        }
+            main_window,
+            surfaces,
+            frame_data: None,
+            frame_window: None,
+            present_stats: PresentStats::default(),
+            texture_arrays_supported,
+            frame_capture_supported,
+            needs_reset: Arc::new(AtomicBool::new(false)),
+            error_callback: None,
+            hdr: None,
+            surface_view: None,
+            final_pass: None,
+            blit_pipelines: Mutex::new(HashMap::default()),
+            blit_sampler
+        };
+        graphics_sys.install_device_callbacks();
+        graphics_sys
     }
 }
\ No newline at end of file
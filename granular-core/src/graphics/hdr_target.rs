@@ -0,0 +1,58 @@
+use wgpu::{BindGroup, CommandEncoder, Device, Extent3d, Sampler, Texture, TextureFormat, TextureView};
+
+use super::blit::BlitPipeline;
+
+
+/// The linear HDR texture the frame gets rendered into when HDR is enabled (see `Renderer::set_hdr`),
+/// together with the default final pass that copies it onto the surface
+pub(crate) struct HdrTarget {
+    /// Created lazily with the size of the surface
+    texture: Option<(Texture, BindGroup)>,
+    sampler: Sampler,
+    blit: BlitPipeline
+}
+impl HdrTarget {
+    pub(crate) const FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+
+    /// surface_format is the format the default final pass writes to
+    pub(crate) fn new(device: &Device, surface_format: TextureFormat) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("HDR target sampler"),
+            ..Default::default()
+        });
+        Self {
+            texture: None,
+            sampler,
+            blit: BlitPipeline::new(device, surface_format, None)
+        }
+    }
+
+
+    /// Returns a view of the HDR texture for the next frame, recreating the texture if the size changed
+    pub(crate) fn frame_view(&mut self, device: &Device, size: Extent3d) -> TextureView {
+        if self.texture.as_ref().map(|(texture, _)| texture.size()) != Some(size) {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("HDR target texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[]
+            });
+            let bind_group = self.blit.bind_group(device, &texture.create_view(&wgpu::TextureViewDescriptor::default()), &self.sampler);
+            self.texture = Some((texture, bind_group));
+        };
+        self.texture.as_ref().unwrap().0.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+
+    /// The default final pass: draws the HDR texture onto target without any tonemapping
+    pub(crate) fn resolve(&self, encoder: &mut CommandEncoder, target: &TextureView) {
+        if let Some((_, bind_group)) = &self.texture {
+            self.blit.draw(encoder, bind_group, target, wgpu::LoadOp::Clear(wgpu::Color::BLACK), None);
+        };
+    }
+}
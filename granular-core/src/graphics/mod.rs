@@ -1,26 +1,45 @@
 mod graphics_backend;
 pub use graphics_backend::GraphicsBackend;
 
+mod graphics_settings;
+pub use graphics_settings::{DeviceLimits, FormatSelector, GraphicsSettings};
+
 mod graphics_system;
-pub use graphics_system::GraphicsSystem;
+pub use graphics_system::{FinalPass, FinalPassTarget, GraphicsSystem, PresentStats, RenderTarget};
 
 mod texture_bundle;
-pub(crate) use texture_bundle::TextureBundle;
+pub use texture_bundle::TextureBundle;
 
 mod dynamic_buffer;
 pub(crate) use dynamic_buffer::DynamicBuffer;
 
 mod window_system;
-pub use window_system::WindowSystem;
+pub use window_system::{MonitorInfo, WindowSystem};
 
 mod camera;
-pub use camera::Camera;
+pub use camera::{Camera, CameraSnapshot};
 
 mod batchrenderer;
-pub use batchrenderer::{BatchRenderer, Quad};
+pub use batchrenderer::{BarStyle, BatchRenderer, BatchView, LayerDepthMapping, Quad, QuadBuilder, QuadPreprocessor};
+
+mod text_renderer;
+pub use text_renderer::{BitmapFont, TextRenderer};
+
+mod debug_font;
 
 mod simulation_renderer;
 pub use simulation_renderer::SimulationRenderer;
 
+mod frame_capture;
+
+mod blit;
+
+mod background;
+pub use background::Background;
+
+mod debug_markers;
+
+mod hdr_target;
+
 mod renderer;
-pub use renderer::Renderer;
\ No newline at end of file
+pub use renderer::{Renderer, RenderCallback};
\ No newline at end of file
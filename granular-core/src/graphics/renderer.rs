@@ -1,49 +1,151 @@
+use std::path::PathBuf;
+
 use geese::{dependencies, GeeseContextHandle, GeeseSystem, Mut};
 use glam::Mat4;
 use bytemuck_derive::{Zeroable, Pod};
 use wgpu::{util::DeviceExt, Buffer, BufferUsages};
-use winit::dpi::PhysicalSize;
+use log::*;
+use winit::{dpi::PhysicalSize, window::WindowId};
+
+use crate::{AssetSystem, BatchRenderer, Camera};
+
+use super::{background::BackgroundRenderer, debug_markers, frame_capture::FrameCapture, Background, GraphicsSystem, RenderTarget, SimulationRenderer};
+
+
+/// Custom drawing that is recorded into the current frame, see `Renderer::add_render_callback`
+pub type RenderCallback = Box<dyn FnMut(&mut RenderTarget) + Send + Sync>;
 
-use crate::{BatchRenderer, Camera};
+/// A step that gets executed in between two ranges of batch layers
+enum RenderStep {
+    Simulation,
+    Custom(RenderCallback)
+}
 
-use super::{GraphicsSystem, SimulationRenderer};
 
 pub struct Renderer {
     ctx: GeeseContextHandle<Self>,
+    /// The screenshot sequence that is currently being recorded (if any)
+    capture: Option<FrameCapture>,
+    /// Sorted by the layer threshold, the batch layers below a threshold are drawn before its step
+    render_steps: Vec<(i32, RenderStep)>,
+    background: Background,
+    background_renderer: BackgroundRenderer
 }
 impl Renderer {
-    pub fn start_frame(&mut self) {
+    /// Sets what the frame gets cleared with, it is drawn before all layers and render callbacks, so it does
+    /// not depend on the render order. Background::None skips clearing and lets the frames accumulate.
+    pub fn set_background(&mut self, background: Background) {
+        self.background = background;
+    }
+
+    pub fn background(&self) -> &Background {
+        &self.background
+    }
+
+
+    /// Registers a callback that runs after all batch layers below `layer` have been drawn and
+    /// before the layers starting at `layer`. The simulation is drawn the same way at layer 0,
+    /// callbacks registered at 0 run after it. Callbacks with the same layer run in the order they were added.
+    ///
+    /// The frame has already been cleared with the background (see `set_background`), callbacks should
+    /// load (`LoadOp::Load`) the existing contents of the view instead of clearing them.
+    pub fn add_render_callback(&mut self, layer: i32, callback: RenderCallback) {
+        let index = self.render_steps.partition_point(|(threshold, _)| *threshold <= layer);
+        self.render_steps.insert(index, (layer, RenderStep::Custom(callback)));
+    }
+
+
+    /// Removes all custom render callbacks (the simulation stays)
+    pub fn clear_render_callbacks(&mut self) {
+        self.render_steps.retain(|(_, step)| matches!(step, RenderStep::Simulation));
+    }
+
+
+    /// Renders the main window (and all other windows) into a linear HDR texture (`GraphicsSystem::HDR_FORMAT`),
+    /// so colors above 1.0 are kept for post-processing. Before presenting, the final pass draws it onto the
+    /// surface, by default without tonemapping (see `GraphicsSystem::set_final_pass`). Only call this outside of rendering.
+    pub fn set_hdr(&mut self, enabled: bool) {
         let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
-        graphics_sys.begin_frame();
+        if graphics_sys.hdr_enabled() == enabled {
+            return;
+        };
+        graphics_sys.set_hdr(enabled);
+        drop(graphics_sys);
+        self.ctx.get_mut::<BatchRenderer>().recreate_pipelines();
+        self.ctx.get_mut::<SimulationRenderer>().recreate_pipeline();
     }
 
 
-    pub fn end_frame(&mut self) {
+    /// Saves the next num_frames frames of the main window as numbered PNGs
+    /// ("<path_prefix>0000.png", "<path_prefix>0001.png", ...) and then stops automatically.
+    ///
+    /// Note: Reading back a frame waits for the GPU to finish it, so every captured frame causes a small hitch.
+    pub fn start_capture(&mut self, path_prefix: impl Into<PathBuf>, num_frames: u32) {
+        if self.capture.is_some() {
+            warn!("start_capture: A capture is already running, it gets replaced");
+        };
+        self.capture = Some(FrameCapture::new(path_prefix.into(), num_frames));
+    }
+
+
+    /// Returns true while a screenshot sequence is being recorded
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+
+    /// Starts a new frame, rendering to the window with that id
+    pub fn start_frame(&mut self, window_id: WindowId) {
+        let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
+        graphics_sys.begin_frame(window_id);
+    }
+
+
+    /// Presents the frame to the window, the queued quads are only cleared
+    /// after the main window has been rendered
+    pub fn end_frame(&mut self, window_id: WindowId) {
+        let is_main_window;
         {
             let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
-            graphics_sys.present_frame();
+            is_main_window = window_id == graphics_sys.main_window();
+            // The frame might have been dropped in begin_frame
+            if graphics_sys.has_frame() {
+                let readback = match (is_main_window, &self.capture) {
+                    (true, Some(_)) => graphics_sys.record_frame_readback(),
+                    _ => None
+                };
+                graphics_sys.present_frame(window_id);
+
+                if let (Some(readback), Some(capture)) = (readback, self.capture.as_mut()) {
+                    if let Some(image) = readback.into_image(graphics_sys.device()) {
+                        capture.save(&image);
+                    };
+                };
+                if self.capture.as_ref().is_some_and(|capture| capture.is_finished()) {
+                    info!("Frame capture finished");
+                    self.capture = None;
+                };
+            };
         }
-        {
+        if is_main_window {
             let mut batch_renderer = self.ctx.get_mut::<BatchRenderer>();
             batch_renderer.end_frame();
         }
     }
 
 
-    /// Resizes the surface with the new_size
-    pub(crate) fn resize(&mut self, new_size: PhysicalSize<u32>) {
+    /// Resizes the surface of that window with the new_size (the camera follows the main window)
+    pub(crate) fn resize(&mut self, window_id: WindowId, new_size: PhysicalSize<u32>) {
+        let is_main_window;
         {
             let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
-            graphics_sys.resize_surface(new_size);
+            graphics_sys.resize_surface(window_id, new_size);
+            is_main_window = window_id == graphics_sys.main_window();
         }
-        {
+        if is_main_window {
             let mut camera = self.ctx.get_mut::<Camera>();
             camera.set_screen_size((new_size.width, new_size.height));
         }
-        {
-            let mut sim_renderer = self.ctx.get_mut::<SimulationRenderer>();
-            sim_renderer.resize(new_size);
-        }
     }
 
 
@@ -54,23 +156,58 @@ impl Renderer {
     }
 
 
+    /// Renders the game (batches and simulation) into the current frame
     pub fn render(&mut self) {
+        if !self.ctx.get::<GraphicsSystem>().has_frame() {
+            return;
+        };
         {
-            let camera = self.ctx.get::<Camera>();
+            let mut camera = self.ctx.get_mut::<Camera>();
             camera.write_canvas_transform_buffer();
         }
 
         let mut batch_renderer = self.ctx.get_mut::<BatchRenderer>();
         batch_renderer.create_batches();
         batch_renderer.prepare_to_render();
-        batch_renderer.render_batch_layers(i32::MIN..0, true);
         drop(batch_renderer);
+
         {
-            let mut sim_renderer = self.ctx.get_mut::<SimulationRenderer>();
-            sim_renderer.render();
+            let asset_sys = self.ctx.get::<AssetSystem>();
+            let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
+            let format = graphics_sys.render_format();
+            if let Some(mut target) = graphics_sys.render_target() {
+                debug_markers::push_group(target.encoder, || String::from("Background"));
+                self.background_renderer.render(&self.background, &mut target, format, &asset_sys);
+                debug_markers::pop_group(target.encoder);
+            };
+        }
+        self.ctx.get::<BatchRenderer>().clear_depth();
+
+        let mut layers_start = i32::MIN;
+        for (threshold, step) in self.render_steps.iter_mut() {
+            {
+                let mut batch_renderer = self.ctx.get_mut::<BatchRenderer>();
+                batch_renderer.render_batch_layers(layers_start..*threshold, None, None);
+            }
+            layers_start = *threshold;
+
+            match step {
+                RenderStep::Simulation => {
+                    let mut sim_renderer = self.ctx.get_mut::<SimulationRenderer>();
+                    sim_renderer.render();
+                },
+                RenderStep::Custom(callback) => {
+                    let mut graphics_sys = self.ctx.get_mut::<GraphicsSystem>();
+                    if let Some(mut target) = graphics_sys.render_target() {
+                        debug_markers::push_group(target.encoder, || format!("Render callback before layer {}", layers_start));
+                        callback(&mut target);
+                        debug_markers::pop_group(target.encoder);
+                    };
+                }
+            };
         }
         let mut batch_renderer = self.ctx.get_mut::<BatchRenderer>();
-        batch_renderer.render_batch_layers(0..i32::MAX, false);
+        batch_renderer.render_batch_layers(layers_start..i32::MAX, None, None);
     }
 }
 impl GeeseSystem for Renderer {
@@ -78,7 +215,8 @@ impl GeeseSystem for Renderer {
         .with::<Mut<GraphicsSystem>>()
         .with::<Mut<BatchRenderer>>()
         .with::<Mut<SimulationRenderer>>()
-        .with::<Mut<Camera>>();
+        .with::<Mut<Camera>>()
+        .with::<Mut<AssetSystem>>();
 
     fn new(ctx: geese::GeeseContextHandle<Self>) -> Self {
         let camera = ctx.get::<Camera>();
@@ -88,7 +226,11 @@ impl GeeseSystem for Renderer {
         drop(graphics_sys);
         
         Self {
-            ctx
+            ctx,
+            capture: None,
+            render_steps: vec![(0, RenderStep::Simulation)],
+            background: Background::default(),
+            background_renderer: BackgroundRenderer::new()
         }
     }
 }
\ No newline at end of file
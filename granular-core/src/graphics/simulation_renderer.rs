@@ -1,12 +1,12 @@
 use std::num::NonZeroU64;
 
 use geese::{dependencies, event_handlers, EventHandlers, GeeseContextHandle, GeeseSystem, Mut};
+use glam::{Mat4, Vec3};
 use log::warn;
-use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, ColorTargetState, Device, Extent3d, ImageDataLayout, RenderPipeline, SamplerDescriptor, ShaderModule, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor};
-use winit::dpi::PhysicalSize;
+use wgpu::{util::DeviceExt, BindGroup, BindGroupLayout, Buffer, ColorTargetState, Device, Extent3d, FilterMode, ImageDataLayout, RenderPipeline, SamplerDescriptor, ShaderModule, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView, TextureViewDescriptor};
 
-use crate::{assets::{AssetHandle, ShaderAsset}, AssetSystem, Camera, Simulation, GRID_HEIGHT, GRID_WIDTH};
-use super::{GraphicsSystem, TextureBundle};
+use crate::{assets::{AssetHandle, ShaderAsset}, AssetSystem, Camera, Simulation, GRID_HEIGHT, GRID_WIDTH, MAX_PALETTE_SIZE};
+use super::{blit::BlitPipeline, debug_markers, GraphicsSystem, TextureBundle};
 
 
 pub struct SimulationRenderer {
@@ -20,14 +20,128 @@ pub struct SimulationRenderer {
     vertex_size: u64,
     shader_handle: AssetHandle<ShaderAsset>,
 
-    sim_texture: TextureBundle
+    /// One palette index per cell (R8Uint)
+    sim_texture: TextureBundle,
+    /// The linear colors of the palette, indexed by the shader
+    palette_buffer: Buffer,
+    /// The palette version that was last uploaded, None before the first upload
+    uploaded_palette_version: Option<u64>,
+    /// The transform applied to the full-screen triangle, see set_use_camera
+    transform_buffer: Buffer,
+    use_camera: bool,
+
+    /// When set, the simulation is first rendered into a texture of that size, see set_internal_resolution
+    internal_resolution: Option<(u32, u32)>,
+    internal_filter: FilterMode,
+    /// The intermediate texture (with its view and the bind group used to draw it), created lazily
+    internal_target: Option<(Texture, TextureView, BindGroup)>,
+    /// Draws the intermediate texture onto the frame, created lazily with the render format
+    internal_blit: Option<BlitPipeline>
 }
 impl SimulationRenderer {
+    /// When false (the default), the simulation covers the whole screen and ignores the camera.
+    /// When true, the grid is placed in world space (centered on the origin, one cell per unit)
+    /// so it pans and zooms together with the camera.
+    pub fn set_use_camera(&mut self, use_camera: bool) {
+        self.use_camera = use_camera;
+    }
+
+    pub fn use_camera(&self) -> bool {
+        self.use_camera
+    }
+
+
+    /// Renders the simulation into an intermediate texture of that size (in pixels) which then gets
+    /// scaled onto the frame, independently of the grid and window sizes. A resolution lower than
+    /// the window gives a retro look, a higher one supersamples the grid. None (the default) renders
+    /// directly into the frame.
+    pub fn set_internal_resolution(&mut self, resolution: Option<(u32, u32)>) {
+        if let Some((width, height)) = resolution {
+            if width == 0 || height == 0 {
+                warn!("Internal resolution {}x{} is empty, ignoring it", width, height);
+                return;
+            };
+        };
+        self.internal_resolution = resolution;
+    }
+
+    pub fn internal_resolution(&self) -> Option<(u32, u32)> {
+        self.internal_resolution
+    }
+
+
+    /// Sets how the intermediate texture gets filtered when scaled onto the frame (Nearest by default)
+    pub fn set_internal_filter(&mut self, filter: FilterMode) {
+        if self.internal_filter != filter {
+            self.internal_filter = filter;
+            self.internal_target = None;
+        };
+    }
+
+    pub fn internal_filter(&self) -> FilterMode {
+        self.internal_filter
+    }
+
+
+    /// The size of the intermediate texture that was used for the last frame, None if the simulation was rendered directly
+    pub fn internal_texture_size(&self) -> Option<(u32, u32)> {
+        self.internal_target.as_ref().filter(|_| self.internal_resolution.is_some()).map(|(texture, _, _)| (texture.width(), texture.height()))
+    }
+
+
+    /// Returns the transform that maps the triangle (where the grid spans [-1, 1]) into clip space
+    fn grid_transform(&self) -> Mat4 {
+        match self.use_camera {
+            true => {
+                let camera = self.ctx.get::<Camera>();
+                let half_grid_size = Vec3::new(GRID_WIDTH as f32 / 2.0, GRID_HEIGHT as f32 / 2.0, 1.0);
+                camera.canvas_transform() * Mat4::from_scale(half_grid_size)
+            },
+            false => Mat4::IDENTITY
+        }
+    }
+
+
      pub fn render(&mut self) {
         let graphics_sys = self.ctx.get::<GraphicsSystem>();
-        let sim = self.ctx.get::<Simulation>();
-        let d = sim.get_grid_texture_data();
-        graphics_sys.queue().write_texture(self.sim_texture.texture().as_image_copy(), d, self.sim_texture.data_layout(), self.sim_texture.extent());
+        let mut sim = self.ctx.get_mut::<Simulation>();
+        // Only upload the part of the grid that actually changed
+        if let Some((min, max)) = sim.take_dirty_region() {
+            let width = (max.0 - min.0 + 1) as u32;
+            let height = (max.1 - min.1 + 1) as u32;
+            graphics_sys.queue().write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: self.sim_texture.texture(),
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: min.0 as u32, y: min.1 as u32, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                sim.get_grid_texture_data(),
+                ImageDataLayout {
+                    offset: (min.0 + min.1 * GRID_WIDTH) as u64,
+                    bytes_per_row: Some(GRID_WIDTH as u32),
+                    rows_per_image: Some(height),
+                },
+                Extent3d { width, height, depth_or_array_layers: 1 });
+        };
+        let palette_version = sim.get_grid_palette_version();
+        if self.uploaded_palette_version != Some(palette_version) {
+            let colors: Vec<[f32; 4]> = sim.get_grid_palette().iter().map(|color| color.into_linear::<f32, f32>().into()).collect();
+            graphics_sys.queue().write_buffer(&self.palette_buffer, 0, bytemuck::cast_slice(&colors));
+            self.uploaded_palette_version = Some(palette_version);
+        };
+        graphics_sys.queue().write_buffer(&self.transform_buffer, 0, bytemuck::cast_slice(&[self.grid_transform()]));
+        if let Some(resolution) = self.internal_resolution {
+            let format = graphics_sys.render_format();
+            if self.internal_blit.as_ref().map(|blit| blit.format()) != Some(format) {
+                self.internal_blit = Some(BlitPipeline::new(graphics_sys.device(), format, Some(wgpu::BlendState::ALPHA_BLENDING)));
+                self.internal_target = None;
+            };
+            if self.internal_target.as_ref().map(|(texture, _, _)| (texture.width(), texture.height())) != Some(resolution) {
+                let blit = self.internal_blit.as_ref().unwrap();
+                self.internal_target = Some(Self::create_internal_target(graphics_sys.device(), blit, resolution, self.internal_filter));
+            };
+        };
         drop(graphics_sys);
         drop(sim);
 
@@ -39,13 +153,19 @@ impl SimulationRenderer {
         };
         let framedata = framedata.unwrap();
 
+        let internal_target = self.internal_target.as_ref().filter(|_| self.internal_resolution.is_some());
+        let (view, load) = match internal_target {
+            Some((_, view, _)) => (view, wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)),
+            None => (&framedata.1, wgpu::LoadOp::Load)
+        };
+        debug_markers::push_group(&mut framedata.2, || String::from("SimulationRenderer"));
         let mut rpass = framedata.2.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("SimulationRenderer render pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &framedata.1,
+                view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
+                    load,
                     store: wgpu::StoreOp::Store
                 },
             })],
@@ -57,27 +177,49 @@ impl SimulationRenderer {
         rpass.set_bind_group(0, &self.bind_group, &[]);
         rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
         rpass.draw(0..3, 0..1);
+        drop(rpass);
+
+        if let (Some((_, _, bind_group)), Some(blit)) = (internal_target, &self.internal_blit) {
+            blit.draw(&mut framedata.2, bind_group, &framedata.1, wgpu::LoadOp::Load, None);
+        };
+        debug_markers::pop_group(&mut framedata.2);
     }
 
 
-    fn get_vertex_data(window_size: (u32, u32)) -> [[f32; 2]; 3] {
-        let w = window_size.0 as f32;
-        let h = window_size.1 as f32;
-        // Create vertex buffer; array-of-array of position and texture coordinates
-        [
-            // One full-screen triangle
-            // See: https://github.com/parasyte/pixels/issues/180
-            [0.0 - w, 0.0 - h],
-            [3.0 * w, 0.0 - h],
-            [0.0 - w, 3.0 * h],
-        ]
+    /// Creates the intermediate texture used with set_internal_resolution, in the format of the blit pipeline
+    fn create_internal_target(device: &Device, blit: &BlitPipeline, resolution: (u32, u32), filter: FilterMode) -> (Texture, TextureView, BindGroup) {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("SimulationRenderer internal texture"),
+            size: Extent3d { width: resolution.0, height: resolution.1, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: blit.format(),
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[]
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("SimulationRenderer internal sampler"),
+            mag_filter: filter,
+            min_filter: filter,
+            ..Default::default()
+        });
+        let bind_group = blit.bind_group(device, &view, &sampler);
+        (texture, view, bind_group)
     }
 
-    pub(super) fn resize(&mut self, new_size: PhysicalSize<u32>) {
-        let vertex_data = Self::get_vertex_data((new_size.width, new_size.height));
-        let graphics_sys = self.ctx.get::<GraphicsSystem>();
-        graphics_sys.queue().write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertex_data));
-    }
+
+    /// One full-screen triangle in clip space, covering [-1, 1] on both axes
+    /// See: https://github.com/parasyte/pixels/issues/180
+    ///
+    /// The camera is applied through the transform buffer (see set_use_camera), so this never
+    /// needs to be recomputed when the window is resized or the camera zooms.
+    const FULLSCREEN_TRIANGLE: [[f32; 2]; 3] = [
+        [-1.0, -1.0],
+        [3.0, -1.0],
+        [-1.0, 3.0],
+    ];
 
 
     /// Reloads parts of the renderer depending on what asset changed
@@ -88,6 +230,16 @@ impl SimulationRenderer {
     }
 
 
+    /// Recreates the pipeline with the current render format of the GraphicsSystem
+    pub(super) fn recreate_pipeline(&mut self) {
+        let format = self.ctx.get::<GraphicsSystem>().render_format();
+        if let Some(color_target_state) = &mut self.color_target_state {
+            color_target_state.format = format;
+        };
+        self.reload_render_pipeline();
+    }
+
+
     /// Helper function to set up a new render pipeline using the same shaders
     fn reload_render_pipeline(&mut self) {
         let graphics_sys = self.ctx.get::<GraphicsSystem>();
@@ -147,7 +299,7 @@ impl GeeseSystem for SimulationRenderer {
         .with::<Mut<GraphicsSystem>>()
         .with::<Mut<AssetSystem>>()
         .with::<Camera>()
-        .with::<Simulation>();
+        .with::<Mut<Simulation>>();
 
     const EVENT_HANDLERS: EventHandlers<Self> = event_handlers()
         .with(Self::on_assetchange);
@@ -159,18 +311,20 @@ impl GeeseSystem for SimulationRenderer {
         drop(asset_sys);
 
         let graphics_sys = ctx.get::<GraphicsSystem>();
-        let vertex_data = Self::get_vertex_data((graphics_sys.surface_config().width, graphics_sys.surface_config().height));
+        let vertex_data = Self::FULLSCREEN_TRIANGLE;
         let device = graphics_sys.device();
         let vertex_data_slice = bytemuck::cast_slice(&vertex_data);
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("SimulationRenderer vertex buffer"),
             contents: vertex_data_slice,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::VERTEX,
         });
         let vertex_size = (vertex_data_slice.len() / vertex_data.len()) as u64;
 
         let tex_extent = Extent3d {width: GRID_WIDTH as u32, height: GRID_HEIGHT as u32, depth_or_array_layers: 1};
-        let sim_tex_data = [0u8; GRID_WIDTH * GRID_HEIGHT * 4];
+        // Start with the whole grid, render only uploads changes and the renderer can be recreated
+        // after the grid was filled (for example after a device reset)
+        let sim_tex_data = ctx.get::<Simulation>().get_grid_texture_data().to_vec();
         let sim_texture = TextureBundle::new(
             device,
             graphics_sys.queue(),
@@ -182,7 +336,7 @@ impl GeeseSystem for SimulationRenderer {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8UnormSrgb,
+                format: TextureFormat::R8Uint,
                 usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
                 view_formats: &[]
             },
@@ -199,10 +353,16 @@ impl GeeseSystem for SimulationRenderer {
             &sim_tex_data,
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * tex_extent.width),
+                bytes_per_row: Some(tex_extent.width),
                 rows_per_image: Some(tex_extent.height),
             }
         );
+        let palette_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SimulationRenderer palette buffer"),
+            size: (MAX_PALETTE_SIZE * std::mem::size_of::<[f32; 4]>()) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
     
         // Create bind group
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -212,7 +372,7 @@ impl GeeseSystem for SimulationRenderer {
                     binding: 0,
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        sample_type: wgpu::TextureSampleType::Uint,
                         multisampled: false,
                         view_dimension: wgpu::TextureViewDimension::D2,
                     },
@@ -221,7 +381,11 @@ impl GeeseSystem for SimulationRenderer {
                 wgpu::BindGroupLayoutEntry {
                     binding: 1,
                     visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new((MAX_PALETTE_SIZE * std::mem::size_of::<[f32; 4]>()) as u64),
+                    },
                     count: None,
                 },
                 wgpu::BindGroupLayoutEntry {
@@ -236,7 +400,11 @@ impl GeeseSystem for SimulationRenderer {
                 },
             ],
         });
-        let camera = ctx.get::<Camera>();
+        let transform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SimulationRenderer transform buffer"),
+            contents: bytemuck::cast_slice(&[Mat4::IDENTITY]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+        });
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("SimulationRenderer bind group"),
             layout: &bind_group_layout,
@@ -247,11 +415,11 @@ impl GeeseSystem for SimulationRenderer {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sim_texture.sampler()),
+                    resource: palette_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: camera.canvas_transform_buffer().as_entire_binding(),
+                    resource: transform_buffer.as_entire_binding(),
                 },
             ],
         });
@@ -260,7 +428,7 @@ impl GeeseSystem for SimulationRenderer {
         let asset_sys = ctx.get::<AssetSystem>();
         let base_shader_module = asset_sys.get(&shader_handle).module();
         let color_target_state = Some(wgpu::ColorTargetState {
-            format: graphics_sys.surface_config().format,
+            format: graphics_sys.render_format(),
             blend: Some(wgpu::BlendState::ALPHA_BLENDING),
             write_mask: wgpu::ColorWrites::ALL,
         });
@@ -268,7 +436,6 @@ impl GeeseSystem for SimulationRenderer {
 
         drop(asset_sys);
         drop(graphics_sys);
-        drop(camera);
 
         Self {
             ctx,
@@ -281,7 +448,16 @@ impl GeeseSystem for SimulationRenderer {
             vertex_size,
             shader_handle,
 
-            sim_texture
+            sim_texture,
+            palette_buffer,
+            uploaded_palette_version: None,
+            transform_buffer,
+            use_camera: false,
+
+            internal_resolution: None,
+            internal_filter: FilterMode::Nearest,
+            internal_target: None,
+            internal_blit: None
         }
     }
 }
\ No newline at end of file
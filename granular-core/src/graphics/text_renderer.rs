@@ -0,0 +1,171 @@
+use glam::{IVec2, Vec2};
+use palette::Srgba;
+
+use crate::assets::{AssetHandle, TextureAsset};
+
+use super::{BatchRenderer, Quad};
+
+
+/// A monospace font stored as a texture atlas: the glyphs are laid out in a grid of columns, row by row,
+/// starting with first_char and following the order of the unicode code points (for example ASCII from ' ')
+#[derive(Debug, Clone)]
+pub struct BitmapFont {
+    pub texture: AssetHandle<TextureAsset>,
+    /// Size of a single glyph inside of the texture, in pixels
+    pub glyph_size: IVec2,
+    /// Size of the whole texture, in pixels
+    pub texture_size: IVec2,
+    pub columns: u32,
+    pub first_char: char,
+    pub num_glyphs: u32
+}
+impl BitmapFont {
+    /// Returns the UV rectangle of the glyph, None if the font does not contain it
+    fn glyph_uv_rect(&self, c: char) -> Option<(Vec2, Vec2)> {
+        let index = (c as u32).checked_sub(self.first_char as u32)?;
+        if index >= self.num_glyphs || self.columns == 0 {
+            return None;
+        };
+        let min = IVec2::new((index % self.columns) as i32, (index / self.columns) as i32) * self.glyph_size;
+        let max = min + self.glyph_size;
+        let texture_size = self.texture_size.as_vec2();
+        Some((min.as_vec2() / texture_size, max.as_vec2() / texture_size))
+    }
+}
+
+
+
+/// Draws text using a BitmapFont. Text is drawn from the top left corner of its bounding box,
+/// lines are separated by '\n'. Since the font is monospace, every glyph advances by the same width.
+pub struct TextRenderer {
+    font: BitmapFont,
+    /// Additional space between lines, relative to the size
+    line_spacing: f32
+}
+impl TextRenderer {
+    pub fn new(font: BitmapFont) -> Self {
+        Self {
+            font,
+            line_spacing: 0.0
+        }
+    }
+
+
+    pub fn font(&self) -> &BitmapFont {
+        &self.font
+    }
+
+
+    /// Sets the additional space between lines, relative to the size (0.0 by default)
+    pub fn set_line_spacing(&mut self, line_spacing: f32) {
+        self.line_spacing = line_spacing;
+    }
+
+    pub fn line_spacing(&self) -> f32 {
+        self.line_spacing
+    }
+
+
+    /// Queues one quad per glyph, position is the top left corner of the text and size the height of a line
+    pub fn draw_text(&self, renderer: &mut BatchRenderer, text: &str, position: IVec2, size: f32, color: Srgba, layer: i32) {
+        self.layout(text, size, |c, offset, glyph_size| {
+            let Some((uv_min, uv_max)) = self.font.glyph_uv_rect(c) else {
+                return;
+            };
+            // Quads use half sizes and y points up
+            let half_size = glyph_size / 2.0;
+            let center = position.as_vec2() + Vec2::new(offset.x + half_size.x, -(offset.y + half_size.y));
+            renderer.draw_quad(&Quad::builder()
+                .center(center.round().as_ivec2())
+                .size(half_size.round().as_ivec2())
+                .texture(self.font.texture.clone())
+                .uv_rect(uv_min, uv_max)
+                .color(color)
+                .build(), layer);
+        });
+    }
+
+
+    /// Returns the width and height of the bounding box of the text when drawn with draw_text, without drawing it
+    pub fn measure(&self, text: &str, size: f32) -> Vec2 {
+        let mut extent = Vec2::ZERO;
+        self.layout(text, size, |_, offset, glyph_size| {
+            extent = extent.max(offset + glyph_size);
+        });
+        extent
+    }
+
+
+    /// Calls place with every character (including unknown ones and spaces), its offset from the top left
+    /// corner (y pointing down) and its size. Both draw_text and measure use this, so they always agree.
+    fn layout(&self, text: &str, size: f32, mut place: impl FnMut(char, Vec2, Vec2)) {
+        let glyph_size = Vec2::new(size * self.font.glyph_size.x as f32 / self.font.glyph_size.y.max(1) as f32, size);
+        let line_height = size * (1.0 + self.line_spacing);
+        for (line_idx, line) in text.split('\n').enumerate() {
+            for (char_idx, c) in line.chars().enumerate() {
+                place(c, Vec2::new(char_idx as f32 * glyph_size.x, line_idx as f32 * line_height), glyph_size);
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::graphics::debug_font::{self, DEBUG_FONT_COLUMNS, DEBUG_FONT_FIRST_CHAR, DEBUG_FONT_GLYPH_SIZE, DEBUG_FONT_NUM_GLYPHS};
+
+    use super::*;
+
+    /// The built-in debug font (see `BatchRenderer::draw_debug_text`), its texture handle points to no asset
+    fn debug_font() -> BitmapFont {
+        BitmapFont {
+            texture: AssetHandle::new(Arc::new(0)),
+            glyph_size: DEBUG_FONT_GLYPH_SIZE,
+            texture_size: debug_font::debug_font_texture_size(),
+            columns: DEBUG_FONT_COLUMNS,
+            first_char: DEBUG_FONT_FIRST_CHAR,
+            num_glyphs: DEBUG_FONT_NUM_GLYPHS
+        }
+    }
+
+    /// The character, offset and size of every glyph that layout places
+    fn placed_glyphs(text_renderer: &TextRenderer, text: &str, size: f32) -> Vec<(char, Vec2, Vec2)> {
+        let mut glyphs = vec![];
+        text_renderer.layout(text, size, |c, offset, glyph_size| glyphs.push((c, offset, glyph_size)));
+        glyphs
+    }
+
+
+    #[test]
+    fn measure_matches_the_last_glyph() {
+        let mut text_renderer = TextRenderer::new(debug_font());
+        text_renderer.set_line_spacing(0.5);
+        let text = "AB\nCDEF";
+        let (c, offset, glyph_size) = *placed_glyphs(&text_renderer, text, 16.0).last().unwrap();
+        assert_eq!((c, offset), ('F', Vec2::new(48.0, 24.0)));
+        assert_eq!(text_renderer.measure(text, 16.0), offset + glyph_size);
+        assert_eq!(text_renderer.measure(text, 16.0), Vec2::new(64.0, 40.0));
+        assert_eq!(text_renderer.measure("", 16.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn debug_text_places_one_glyph_per_character() {
+        // draw_debug_text at a scale of 2
+        let font = debug_font();
+        let glyphs = placed_glyphs(&TextRenderer::new(font.clone()), "ABC", 16.0);
+        let offsets: Vec<Vec2> = glyphs.iter().map(|(_, offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![Vec2::new(0.0, 0.0), Vec2::new(16.0, 0.0), Vec2::new(32.0, 0.0)]);
+        assert!(glyphs.iter().all(|(_, _, glyph_size)| *glyph_size == Vec2::splat(16.0)));
+
+        // 'A' is the 33rd glyph after the space, in the second column of the third row
+        let texture_size = debug_font::debug_font_texture_size().as_vec2();
+        assert_eq!(font.glyph_uv_rect('A'), Some((Vec2::new(8.0, 16.0) / texture_size, Vec2::new(16.0, 24.0) / texture_size)));
+        let uv_rects: Vec<_> = glyphs.iter().filter_map(|(c, _, _)| font.glyph_uv_rect(*c)).collect();
+        assert_eq!(uv_rects.len(), 3);
+        assert!(uv_rects[0] != uv_rects[1] && uv_rects[1] != uv_rects[2]);
+        assert_eq!(font.glyph_uv_rect('\u{e9}'), None);
+    }
+}
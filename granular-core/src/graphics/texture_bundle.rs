@@ -1,4 +1,6 @@
-use wgpu::{Device, Extent3d, ImageDataLayout, Queue, Sampler, SamplerDescriptor, Texture, TextureDescriptor, TextureView, TextureViewDescriptor};
+use anyhow::bail;
+use palette::Srgba;
+use wgpu::{Device, Extent3d, ImageDataLayout, Queue, Sampler, SamplerDescriptor, Texture, TextureDescriptor, TextureFormat, TextureView, TextureViewDescriptor};
 
 
 
@@ -75,6 +77,80 @@ impl TextureBundle {
         Self::new(device, queue, "New default texture", extent, tex_descriptor, &view_descriptor, &sampler_descriptor, data, data_layout)
     }
 
+    /// Creates a texture from RGBA pixels (8 bits per channel, sRGB), using the default sampler.
+    /// Returns an error if data does not contain exactly width * height pixels.
+    pub fn from_rgba(device: &Device, queue: &Queue, width: u32, height: u32, data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != (width * height * 4) as usize {
+            bail!("Expected {} bytes for a {}x{} RGBA texture, got {}", width * height * 4, width, height, data.len());
+        };
+        let extent = Extent3d {width, height, depth_or_array_layers: 1};
+        Ok(Self::default(device, queue, extent, data))
+    }
+
+
+    /// Creates a texture in a block compressed format (BCn, ETC2, ASTC) from already compressed data, one entry
+    /// per mip level starting with the full size. The device needs the feature the format requires.
+    pub fn compressed(device: &Device, queue: &Queue, format: TextureFormat, extent: Extent3d, mip_levels: &[Vec<u8>]) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("New compressed texture"),
+            size: extent,
+            mip_level_count: mip_levels.len().max(1) as u32,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[]
+        });
+        // Rows are made of blocks, partial blocks at the edges of small mip levels count as whole blocks
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap_or(16);
+        let layout_of = |size: Extent3d| wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(size.width.div_ceil(block_width) * block_size),
+            rows_per_image: Some(size.height.div_ceil(block_height))
+        };
+        for (level, data) in mip_levels.iter().enumerate() {
+            let size = extent.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                layout_of(size),
+                size.physical_size(format),
+            );
+        }
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            extent,
+            texture,
+            data_layout: layout_of(extent),
+            view,
+            sampler
+        }
+    }
+
+
+    /// Creates a 1x1 texture with that color, useful for untextured quads or as a placeholder
+    pub fn solid_color(device: &Device, queue: &Queue, color: Srgba) -> Self {
+        let color: Srgba<u8> = color.into_format();
+        let data: [u8; 4] = color.into();
+        Self::default(device, queue, Extent3d {width: 1, height: 1, depth_or_array_layers: 1}, &data)
+    }
+
     pub fn view(&self) -> &TextureView {
         &self.view
     }
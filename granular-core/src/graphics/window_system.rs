@@ -1,15 +1,53 @@
 use std::sync::Arc;
 
 use geese::*;
-use winit::{event_loop::ActiveEventLoop, window::{Window, WindowAttributes}};
+use log::{error, warn};
+use winit::{dpi::PhysicalSize, event_loop::ActiveEventLoop, monitor::{MonitorHandle, VideoModeHandle}, window::{CursorIcon, CustomCursor, CustomCursorSource, Fullscreen, Window, WindowAttributes, WindowId}};
 
 use crate::EventLoopSystem;
 
 
+/// A monitor and its properties, see `WindowSystem::available_monitors`
+#[derive(Debug, Clone)]
+pub struct MonitorInfo {
+    pub handle: MonitorHandle,
+    pub name: Option<String>,
+    pub size: PhysicalSize<u32>,
+    pub scale_factor: f64,
+    /// The refresh rates of all video modes of the monitor in millihertz, sorted and without duplicates
+    pub refresh_rates_millihertz: Vec<u32>
+}
+impl From<MonitorHandle> for MonitorInfo {
+    fn from(handle: MonitorHandle) -> Self {
+        let mut refresh_rates_millihertz: Vec<u32> = handle.video_modes().map(|mode| mode.refresh_rate_millihertz()).collect();
+        refresh_rates_millihertz.sort_unstable();
+        refresh_rates_millihertz.dedup();
+        Self {
+            name: handle.name(),
+            size: handle.size(),
+            scale_factor: handle.scale_factor(),
+            refresh_rates_millihertz,
+            handle
+        }
+    }
+}
+
+
+
 pub struct WindowSystem {
-    windows: Vec<Arc<Window>>
+    /// All open windows, the first one is the main window
+    windows: Vec<Arc<Window>>,
+    /// Windows that get created at the start of the next frame
+    pending_windows: Vec<WindowAttributes>,
+    /// Custom cursor that gets created (and applied to the main window) at the start of the next frame
+    pending_cursor: Option<CustomCursorSource>
 }
 impl WindowSystem {
+    /// The main window cannot be resized below this, so there always is something sensible to render to
+    pub const DEFAULT_MIN_INNER_SIZE: PhysicalSize<u32> = PhysicalSize::new(160, 120);
+
+
+    /// Returns the main window
     pub fn window_handle(&self) -> Arc<Window> {
         if self.windows.is_empty() {
             panic!("Tried getting a window handle but no windows exist.");
@@ -17,11 +55,117 @@ impl WindowSystem {
         self.windows[0].clone()
     }
 
+    pub fn main_window_id(&self) -> WindowId {
+        self.window_handle().id()
+    }
+
+    /// Returns the window with that id, if it is still open
+    pub fn window(&self, window_id: WindowId) -> Option<Arc<Window>> {
+        self.windows.iter().find(|window| window.id() == window_id).cloned()
+    }
+
+    pub fn windows(&self) -> &[Arc<Window>] {
+        &self.windows
+    }
+
+    /// Returns all monitors that are connected, empty if there are none (headless) or no window exists yet
+    pub fn available_monitors(&self) -> Vec<MonitorInfo> {
+        match self.windows.first() {
+            Some(window) => window.available_monitors().map(MonitorInfo::from).collect(),
+            None => vec![]
+        }
+    }
+
+
+    /// Returns the video modes (resolution, bit depth and refresh rate) supported by the monitor,
+    /// the largest resolutions with the highest refresh rates first
+    pub fn video_modes(&self, monitor: &MonitorInfo) -> Vec<VideoModeHandle> {
+        let mut modes: Vec<VideoModeHandle> = monitor.handle.video_modes().collect();
+        modes.sort_by_key(|mode| std::cmp::Reverse((mode.size().width * mode.size().height, mode.refresh_rate_millihertz(), mode.bit_depth())));
+        modes
+    }
+
+
+    /// Makes the main window fullscreen on that monitor. With a video mode (see `video_modes`) the
+    /// window enters exclusive fullscreen, otherwise it uses borderless fullscreen at the monitors resolution.
+    pub fn set_fullscreen_on(&self, monitor: &MonitorInfo, video_mode: Option<VideoModeHandle>) {
+        let Some(window) = self.windows.first() else {
+            warn!("set_fullscreen_on: There is no window yet");
+            return;
+        };
+        let fullscreen = match video_mode {
+            Some(mode) if mode.monitor() == monitor.handle => Fullscreen::Exclusive(mode),
+            Some(_) => {
+                warn!("set_fullscreen_on: The video mode does not belong to monitor {:?}, using borderless fullscreen", monitor.name);
+                Fullscreen::Borderless(Some(monitor.handle.clone()))
+            },
+            None => Fullscreen::Borderless(Some(monitor.handle.clone()))
+        };
+        window.set_fullscreen(Some(fullscreen));
+    }
+
+
+    /// Leaves fullscreen on the main window
+    pub fn set_windowed(&self) {
+        if let Some(window) = self.windows.first() {
+            window.set_fullscreen(None);
+        };
+    }
+
+
+    /// Sets the size the main window cannot be resized below, None removes the limit.
+    /// `DEFAULT_MIN_INNER_SIZE` is used until this gets called.
+    pub fn set_min_inner_size(&self, size: Option<PhysicalSize<u32>>) {
+        match self.windows.first() {
+            Some(window) => window.set_min_inner_size(size),
+            None => warn!("set_min_inner_size: There is no window yet")
+        };
+    }
+
+    /// Sets the size the main window cannot be resized above, None removes the limit
+    pub fn set_max_inner_size(&self, size: Option<PhysicalSize<u32>>) {
+        match self.windows.first() {
+            Some(window) => window.set_max_inner_size(size),
+            None => warn!("set_max_inner_size: There is no window yet")
+        };
+    }
+
+    /// Requests an additional window (for example for editor tools). It gets created at the start
+    /// of the next frame, after which `events::WindowCreated` is raised with its id.
+    /// Its content can be drawn when handling `events::DrawWindow`.
+    pub fn request_window(&mut self, attributes: WindowAttributes) {
+        self.pending_windows.push(attributes);
+    }
+
+    /// Sets the cursor of the main window to an image (RGBA, 8 bits per channel, rows from top to bottom).
+    /// The hotspot is the pixel that acts as the tip of the cursor. The cursor gets applied at the start
+    /// of the next frame, if the image is invalid the default cursor is used instead.
+    pub fn set_cursor_icon(&mut self, rgba: &[u8], width: u16, height: u16, hotspot: (u16, u16)) {
+        match CustomCursor::from_rgba(rgba, width, height, hotspot.0, hotspot.1) {
+            Ok(source) => self.pending_cursor = Some(source),
+            Err(e) => {
+                warn!("set_cursor_icon: Invalid cursor image ({}), using the default cursor", e);
+                self.pending_cursor = None;
+                self.set_cursor_icon_named(CursorIcon::Default);
+            }
+        };
+    }
+
+
+    /// Sets the cursor of the main window to one of the standard system cursors
+    pub fn set_cursor_icon_named(&mut self, icon: CursorIcon) {
+        self.pending_cursor = None;
+        if let Some(window) = self.windows.first() {
+            window.set_cursor(icon);
+        };
+    }
+
     pub fn init(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = WindowAttributes::default()
             .with_title("Default Granular Window")
             .with_visible(false)
             .with_resizable(true)
+            .with_min_inner_size(Self::DEFAULT_MIN_INNER_SIZE)
             .with_window_level(winit::window::WindowLevel::AlwaysOnTop)
             .with_position(winit::dpi::PhysicalPosition::new(1500, 100));
         let result = event_loop.create_window(window_attributes);
@@ -31,6 +175,42 @@ impl WindowSystem {
             panic!("OS Error while creating a new window: {}", e);
         }
     }
+
+    /// Creates all windows requested with request_window and returns them
+    pub(crate) fn create_pending_windows(&mut self, event_loop: &ActiveEventLoop) -> Vec<Arc<Window>> {
+        let mut created = vec![];
+        for attributes in self.pending_windows.drain(..) {
+            match event_loop.create_window(attributes) {
+                Ok(window) => {
+                    let window = Arc::new(window);
+                    self.windows.push(window.clone());
+                    created.push(window);
+                },
+                Err(e) => error!("OS Error while creating a new window: {}", e)
+            };
+        }
+        created
+    }
+
+    /// Creates the cursor set with set_cursor_icon and applies it to the main window
+    pub(crate) fn create_pending_cursor(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(source) = self.pending_cursor.take() else {
+            return;
+        };
+        let cursor = event_loop.create_custom_cursor(source);
+        if let Some(window) = self.windows.first() {
+            window.set_cursor(cursor);
+        };
+    }
+
+    /// Closes a window, the main window cannot be removed
+    pub(crate) fn remove_window(&mut self, window_id: WindowId) {
+        if self.windows.first().is_some_and(|main| main.id() == window_id) {
+            error!("The main window cannot be removed");
+            return;
+        };
+        self.windows.retain(|window| window.id() != window_id);
+    }
 }
 impl GeeseSystem for WindowSystem {
     const DEPENDENCIES: Dependencies = dependencies()
@@ -38,7 +218,9 @@ impl GeeseSystem for WindowSystem {
     
     fn new(ctx: GeeseContextHandle<Self>) -> Self {
         Self {
-            windows: vec![]
+            windows: vec![],
+            pending_windows: vec![],
+            pending_cursor: None
         }
     }
-}
\ No newline at end of file
+}
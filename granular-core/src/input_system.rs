@@ -1,37 +1,109 @@
 #![allow(unused)]
 
-use geese::{EventQueue, GeeseContextHandle, GeeseSystem};
-use glam::IVec2;
-use winit::{dpi::PhysicalPosition, event::{ElementState, KeyEvent, Modifiers, MouseButton}, keyboard::{KeyCode, ModifiersState, PhysicalKey}};
-use rustc_hash::FxHashMap as HashMap;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use geese::{dependencies, event_handlers, EventQueue, GeeseContextHandle, GeeseSystem, Mut};
+use gilrs::{Axis, Button, Gilrs};
+use serde::{Deserialize, Serialize};
+use glam::{IVec2, Vec2};
+use winit::{dpi::PhysicalPosition, event::{ElementState, KeyEvent, Modifiers, MouseButton, MouseScrollDelta}, keyboard::{KeyCode, ModifiersState, PhysicalKey}};
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use log::*;
 
+use crate::filewatcher::FileWatcher;
+
 
 pub mod events {
     use super::InputAction;
 
     pub struct Input(pub InputAction);
+
+    /// Raised when an action got a new trigger through `InputSystem::start_rebind`
+    pub struct Rebound {
+        pub action: String
+    }
 }
 
 
 /// Helper enum to keep track of multiple ways an action could be triggered
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum InputActionTriggerReason {
     Key(KeyCode),
-    Mouse(MouseButton)
+    Mouse(MouseButton),
+    /// A button of any connected gamepad
+    GamepadButton(Button),
+    /// An axis of any connected gamepad (-1.0 - 1.0) that is past the threshold: with a positive threshold
+    /// the axis needs to be at least that value, with a negative one at most, for example -0.5 for pushing a stick left
+    GamepadAxis {
+        axis: Axis,
+        threshold: f32
+    }
+}
+impl InputActionTriggerReason {
+    /// Whether a gamepad axis trigger is pressed at that value of the axis
+    fn axis_pressed(threshold: f32, value: f32) -> bool {
+        match threshold >= 0.0 {
+            true => value >= threshold,
+            false => value <= threshold
+        }
+    }
+
+    /// How far a gamepad axis is pushed past the threshold (0.0 - 1.0), so the threshold also acts as a dead zone
+    fn axis_strength(threshold: f32, value: f32) -> f32 {
+        if !Self::axis_pressed(threshold, value) {
+            return 0.0;
+        };
+        let (value, threshold) = (value * threshold.signum(), threshold.abs());
+        if threshold >= 1.0 {
+            return 1.0;
+        };
+        ((value - threshold) / (1.0 - threshold)).clamp(0.0, 1.0)
+    }
+}
+
+
+/// The inputs that are currently held down, for checking whether a trigger is still held after a release
+struct HeldInput<'a> {
+    keys: &'a HashSet<KeyCode>,
+    mouse_buttons: &'a HashSet<MouseButton>,
+    gamepad_buttons: &'a HashSet<Button>,
+    gamepad_axes: &'a HashMap<Axis, f32>
+}
+impl HeldInput<'_> {
+    /// Whether all inputs of the trigger are held, the modifiers are only checked when pressing
+    fn is_held(&self, trigger: &InputActionTrigger) -> bool {
+        match trigger.reason {
+            InputActionTriggerReason::Key(key) => self.keys.contains(&key) && trigger.chord.iter().all(|chord_key| self.keys.contains(chord_key)),
+            InputActionTriggerReason::Mouse(button) => self.mouse_buttons.contains(&button),
+            InputActionTriggerReason::GamepadButton(button) => self.gamepad_buttons.contains(&button),
+            InputActionTriggerReason::GamepadAxis { axis, threshold } => {
+                InputActionTriggerReason::axis_pressed(threshold, self.gamepad_axes.get(&axis).copied().unwrap_or(0.0))
+            }
+        }
+    }
 }
 
 
 /// Holds information about what things need to happen in order for the action to trigger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct InputActionTrigger {
     reason: InputActionTriggerReason,
-    modifiers: ModifiersState
+    #[serde(default)]
+    modifiers: ModifiersState,
+    /// Keys that need to be held together with the key of the reason, see with_chord
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    chord: Vec<KeyCode>
 }
 impl InputActionTrigger {
     /// The longest form of creating an InputActionTrigger
     pub fn new(reason: InputActionTriggerReason, modifiers: ModifiersState) -> Self {
         Self {
             reason,
-            modifiers
+            modifiers,
+            chord: vec![]
         }
     }
 
@@ -40,6 +112,16 @@ impl InputActionTrigger {
         Self::new(InputActionTriggerReason::Key(key), modifiers)
     }
 
+    /// Shorthand for creating a new key InputActionTrigger without any modifiers
+    pub fn key(key: KeyCode) -> Self {
+        Self::new_key(key, ModifiersState::empty())
+    }
+
+    /// Same as new_mouse, for symmetry with `key`
+    pub fn mouse(mouse_button: MouseButton) -> Self {
+        Self::new_mouse(mouse_button)
+    }
+
 
     /// Shorthand for creating a new InputActionTrigger, for including a modifier, see new_mouse_mod
     pub fn new_mouse(mouse_button: MouseButton) -> Self {
@@ -50,6 +132,69 @@ impl InputActionTrigger {
     pub fn new_mouse_mod(mouse_button: MouseButton, modifiers: ModifiersState) -> Self {
         Self::new(InputActionTriggerReason::Mouse(mouse_button), modifiers)
     }
+
+
+    /// Creates a gamepad button InputActionTrigger, gamepad triggers ignore the keyboard modifiers
+    pub fn gamepad_button(button: Button) -> Self {
+        Self::new(InputActionTriggerReason::GamepadButton(button), ModifiersState::empty())
+    }
+
+    /// Creates a gamepad axis InputActionTrigger, see `InputActionTriggerReason::GamepadAxis` for the threshold
+    pub fn gamepad_axis(axis: Axis, threshold: f32) -> Self {
+        Self::new(InputActionTriggerReason::GamepadAxis { axis, threshold }, ModifiersState::empty())
+    }
+
+
+    /// Adds modifiers that need to be held, for example `InputActionTrigger::key(KeyCode::KeyS).with_ctrl().with_shift()`
+    pub fn with_modifiers(mut self, modifiers: ModifiersState) -> Self {
+        self.modifiers |= modifiers;
+        self
+    }
+
+    pub fn with_ctrl(self) -> Self {
+        self.with_modifiers(ModifiersState::CONTROL)
+    }
+
+    pub fn with_shift(self) -> Self {
+        self.with_modifiers(ModifiersState::SHIFT)
+    }
+
+    pub fn with_alt(self) -> Self {
+        self.with_modifiers(ModifiersState::ALT)
+    }
+
+    /// The Windows/Command key
+    pub fn with_super(self) -> Self {
+        self.with_modifiers(ModifiersState::SUPER)
+    }
+
+
+    /// Makes a key trigger a chord: the action is pressed once its key and all of these keys are held
+    /// (in any order, for example `InputActionTrigger::key(KeyCode::KeyA).with_chord(&[KeyCode::KeyS])` for A + S)
+    /// and released as soon as any of them is released, unless another trigger of the action is still held.
+    /// The keys have to be held at the same time, sequences (G, then B) are not supported.
+    pub fn with_chord(mut self, keys: &[KeyCode]) -> Self {
+        if !matches!(self.reason, InputActionTriggerReason::Key(_)) {
+            warn!("with_chord: Only key triggers can be chords");
+            return self;
+        };
+        self.chord.extend_from_slice(keys);
+        self
+    }
+
+
+    pub fn reason(&self) -> &InputActionTriggerReason {
+        &self.reason
+    }
+
+    /// The keys that need to be held together with the key of the reason, empty for a single key
+    pub fn chord(&self) -> &[KeyCode] {
+        &self.chord
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
 }
 
 
@@ -57,9 +202,16 @@ impl InputActionTrigger {
 pub struct InputAction {
     name: String,
     triggers: Vec<InputActionTrigger>,
+    /// The action set this action belongs to, None means it is always active
+    set: Option<String>,
 
     pressed: bool,
-    just_pressed: bool
+    just_pressed: bool,
+    just_released: bool,
+    /// When the current press started, None while released
+    pressed_since: Option<Instant>,
+    /// How far the action is pressed (0.0 - 1.0), either 0.0 or 1.0 except for gamepad axes
+    strength: f32
 }
 impl InputAction {
     /// Creates a new input action with just a name
@@ -67,8 +219,12 @@ impl InputAction {
         Self {
             name: String::from(name),
             triggers: vec![],
+            set: None,
             pressed: false,
-            just_pressed: false
+            just_pressed: false,
+            just_released: false,
+            pressed_since: None,
+            strength: 0.0
         }
     }
 
@@ -78,8 +234,12 @@ impl InputAction {
         Self {
             name: String::from(name),
             triggers: vec![trigger],
+            set: None,
             pressed: false,
-            just_pressed: false
+            just_pressed: false,
+            just_released: false,
+            pressed_since: None,
+            strength: 0.0
         }
     }
 
@@ -90,6 +250,18 @@ impl InputAction {
     }
 
 
+    /// How far the action is pressed (0.0 - 1.0), keys and buttons are either 0.0 or 1.0
+    pub fn strength(&self) -> f32 {
+        self.strength
+    }
+
+
+    /// Returns the name of the action set this action belongs to (None if it is always active)
+    pub fn set(&self) -> Option<&str> {
+        self.set.as_deref()
+    }
+
+
     /// Adds a new trigger to the list of triggers
     pub fn add_trigger(&mut self, trigger: InputActionTrigger) {
         self.triggers.push(trigger);
@@ -107,9 +279,153 @@ impl InputAction {
     pub fn num_triggers(&self) -> usize {
         self.triggers.len()
     }
+
+
+    /// Updates the pressed state. just_pressed and just_released are only ever set here and cleared once per frame,
+    /// so a press and release within the same frame counts as both just pressed and just released.
+    fn set_pressed(&mut self, pressed: bool) {
+        if pressed && !self.pressed {
+            self.just_pressed = true;
+            self.pressed_since = Some(Instant::now());
+        };
+        if !pressed && self.pressed {
+            self.just_released = true;
+        };
+        if !pressed {
+            self.pressed_since = None;
+        };
+        self.pressed = pressed;
+        self.strength = pressed as u8 as f32;
+    }
+}
+
+
+
+
+
+/// How a single action is stored in a bindings file
+#[derive(Debug, Serialize, Deserialize)]
+struct ActionBinding {
+    #[serde(deserialize_with = "deserialize_triggers")]
+    triggers: Vec<InputActionTrigger>,
+    #[serde(default)]
+    set: Option<String>
+}
+
+
+/// The triggers of an ActionBinding, the ones with keys that winit does not know are skipped
+fn deserialize_triggers<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<InputActionTrigger>, D::Error> {
+    let triggers = Vec::<BindingTrigger>::deserialize(deserializer)?;
+    Ok(triggers.into_iter().filter_map(|trigger| {
+        let reason = match trigger.reason {
+            BindingReason::Key(BindingKey(Ok(key))) => InputActionTriggerReason::Key(key),
+            BindingReason::Key(BindingKey(Err(name))) => {
+                warn!("Skipping an input binding with the unknown key '{}'", name);
+                return None;
+            },
+            BindingReason::Mouse(button) => InputActionTriggerReason::Mouse(button),
+            BindingReason::GamepadButton(button) => InputActionTriggerReason::GamepadButton(button),
+            BindingReason::GamepadAxis { axis, threshold } => InputActionTriggerReason::GamepadAxis { axis, threshold }
+        };
+        let mut chord = Vec::with_capacity(trigger.chord.len());
+        for key in trigger.chord {
+            match key {
+                BindingKey(Ok(key)) => chord.push(key),
+                BindingKey(Err(name)) => {
+                    warn!("Skipping an input binding with the unknown key '{}'", name);
+                    return None;
+                }
+            };
+        }
+        let trigger = InputActionTrigger::new(reason, trigger.modifiers);
+        match chord.is_empty() {
+            true => Some(trigger),
+            false => Some(trigger.with_chord(&chord))
+        }
+    }).collect())
+}
+
+
+/// Mirrors InputActionTrigger when reading a bindings file
+#[derive(Deserialize)]
+struct BindingTrigger {
+    reason: BindingReason,
+    #[serde(default)]
+    modifiers: ModifiersState,
+    #[serde(default)]
+    chord: Vec<BindingKey>
+}
+
+
+/// Mirrors InputActionTriggerReason when reading a bindings file
+#[derive(Deserialize)]
+enum BindingReason {
+    Key(BindingKey),
+    Mouse(MouseButton),
+    GamepadButton(Button),
+    GamepadAxis {
+        axis: Axis,
+        threshold: f32
+    }
+}
+
+
+/// A key in a bindings file, the name is kept if winit does not know the key
+struct BindingKey(Result<KeyCode, String>);
+impl<'de> Deserialize<'de> for BindingKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeyVisitor;
+        impl<'de> serde::de::Visitor<'de> for KeyVisitor {
+            type Value = BindingKey;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("the name of a key")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<Self::Value, E> {
+                let key = KeyCode::deserialize(serde::de::value::StrDeserializer::<E>::new(name));
+                Ok(BindingKey(key.map_err(|_| String::from(name))))
+            }
+
+            fn visit_enum<A: serde::de::EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+                let (name, variant): (String, _) = data.variant()?;
+                serde::de::VariantAccess::unit_variant(variant)?;
+                self.visit_str(&name)
+            }
+        }
+        // RON writes keys as enum variants (Space), JSON as strings ("Space")
+        deserializer.deserialize_enum("KeyCode", &[], KeyVisitor)
+    }
 }
 
 
+/// The contents of a bindings file (RON, or JSON for files ending in .json), the actions by name. For example:
+/// ```ron
+/// {
+///     "jump": (triggers: [(reason: Key(Space))]),
+///     "fire": (triggers: [(reason: Mouse(Left)), (reason: Key(KeyF), modifiers: (shift_key: true))], set: Some("game")),
+///     "left": (triggers: [(reason: Key(KeyA)), (reason: GamepadAxis(axis: LeftStickX, threshold: -0.5))]),
+///     "dash": (triggers: [(reason: Key(KeyA), chord: [KeyS])]),
+/// }
+/// ```
+type Bindings = HashMap<String, ActionBinding>;
+
+
+
+/// The input state at one point in time (for example one frame of a replay), see `InputSystem::snapshot`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputSnapshot {
+    /// Whether each action is pressed and whether it was just pressed, by name
+    pub actions: BTreeMap<String, (bool, bool)>,
+    pub pressed_keys: Vec<KeyCode>,
+    pub modifiers: ModifiersState,
+    pub mouse_position: [i32; 2],
+    pub last_mouse_position: [i32; 2]
+}
+
+
+/// How many pixels of a `MouseScrollDelta::PixelDelta` (touchpads) count as one line of scrolling
+pub const SCROLL_PIXELS_PER_LINE: f32 = 20.0;
 
 
 
@@ -117,8 +433,35 @@ pub struct InputSystem {
     ctx: GeeseContextHandle<Self>,
     actions: HashMap<String, InputAction>,
     current_modifiers: ModifiersState,
+    /// All keys that are currently held down, regardless of any actions
+    pressed_keys: HashSet<KeyCode>,
+    /// All mouse buttons that are currently held down, regardless of any actions
+    pressed_mouse_buttons: HashSet<MouseButton>,
+    /// Stack of action sets, only actions of the topmost set (and those without a set) respond
+    action_sets: Vec<String>,
     mouse_position: IVec2,
     last_mouse_position: IVec2,
+    /// Multiplier applied in get_camera_look_delta
+    mouse_sensitivity: Vec2,
+    /// Mouse deltas shorter than this (in pixels) are ignored by get_camera_look_delta
+    mouse_deadzone: i32,
+    /// The scrolling of the current frame in lines, see get_scroll_delta
+    scroll_delta: Vec2,
+    /// None if no gamepad backend is available on this platform
+    gilrs: Option<Gilrs>,
+    /// The gamepad buttons that are held down (on any gamepad)
+    gamepad_buttons: HashSet<Button>,
+    /// The last value of every gamepad axis that has moved (on any gamepad)
+    gamepad_axes: HashMap<Axis, f32>,
+    /// When the current and the previous frame started, used for the timing of action_repeat
+    frame_start: Instant,
+    last_frame_start: Instant,
+    /// The bindings file that gets reloaded when it changes, see watch_bindings
+    bindings_path: Option<PathBuf>,
+    /// While true, input events from the windows are ignored and the state only changes through restore
+    replay_mode: bool,
+    /// The action whose first trigger gets replaced by the next input, see start_rebind
+    rebinding: Option<String>
 }
 impl InputSystem {
     /// Registers a new InputAction
@@ -131,11 +474,79 @@ impl InputSystem {
     }
 
 
+    /// Registers a new InputAction that only responds while its action set is on top of the
+    /// action set stack (see push_action_set)
+    pub fn add_action_to_set(&mut self, set: &str, name: &str, trigger: InputActionTrigger) {
+        if !self.actions.contains_key(name) {
+            let mut action = InputAction::new(name, trigger);
+            action.set = Some(String::from(set));
+            self.actions.insert(String::from(name), action);
+        } else {
+            warn!("add_action_to_set: An action with that name already exists!");
+        };
+    }
+
+
+    /// Makes the action set the active one, for example "menu" while a menu is open.
+    /// Actions of the previously active set stop responding until this set is popped again.
+    pub fn push_action_set(&mut self, set: &str) {
+        self.action_sets.push(String::from(set));
+    }
+
+
+    /// Removes the topmost action set and returns its name, the set below becomes active again
+    pub fn pop_action_set(&mut self) -> Option<String> {
+        let popped = self.action_sets.pop();
+        if popped.is_none() {
+            warn!("pop_action_set: The action set stack is already empty");
+        };
+        popped
+    }
+
+
+    /// The action set that is currently active, None if the stack is empty
+    pub fn active_action_set(&self) -> Option<&str> {
+        self.action_sets.last().map(|set| set.as_str())
+    }
+
+
+    /// Actions without a set are always active, the others only when their set is on top of the stack
+    fn is_action_active(&self, action: &InputAction) -> bool {
+        match &action.set {
+            None => true,
+            Some(set) => self.action_sets.last() == Some(set)
+        }
+    }
+
+
+    /// Registers multiple InputActions at once, see add_action
+    pub fn add_actions<'a>(&mut self, actions: impl IntoIterator<Item = (&'a str, InputActionTrigger)>) {
+        actions.into_iter().for_each(|(name, trigger)| self.add_action(name, trigger));
+    }
+
+
+    /// Adds a trigger to an existing InputAction, or registers a new one if it doesn't exist yet
+    pub fn add_trigger_to_action(&mut self, name: &str, trigger: InputActionTrigger) {
+        match self.actions.get_mut(name) {
+            Some(action) => action.add_trigger(trigger),
+            None => {
+                self.actions.insert(String::from(name), InputAction::new(name, trigger));
+            }
+        };
+    }
+
+
+    /// Returns true if an InputAction with that name has been registered
+    pub fn contains_action(&self, name: &str) -> bool {
+        self.actions.contains_key(name)
+    }
+
+
     /// Returns true when at least one of the triggers of an InputAction
     /// are pressed down
     pub fn is_action_pressed(&self, name: &str) -> bool {
         match self.actions.get(name) {
-            Some(action) => action.pressed,
+            Some(action) => action.pressed && self.is_action_active(action),
             None => {
                 warn!("is_action_pressed: Action '{}' does not exist. Create it by calling add_action.", name);
                 false
@@ -148,7 +559,7 @@ impl InputSystem {
     /// have been pressed down **this frame**
     pub fn is_action_just_pressed(&self, name: &str) -> bool {
         match self.actions.get(name) {
-            Some(action) => action.just_pressed,
+            Some(action) => action.just_pressed && self.is_action_active(action),
             None => {
                 warn!("is_action_just_pressed: Action '{}' does not exist. Create it by calling add_action.", name);
                 false
@@ -157,6 +568,69 @@ impl InputSystem {
     }
 
 
+    /// Returns true when at least one of the triggers of an InputAction
+    /// have been released **this frame**, for example to fire a charged attack
+    pub fn is_action_just_released(&self, name: &str) -> bool {
+        match self.actions.get(name) {
+            Some(action) => action.just_released && self.is_action_active(action),
+            None => {
+                warn!("is_action_just_released: Action '{}' does not exist. Create it by calling add_action.", name);
+                false
+            }
+        }
+    }
+
+
+    /// Returns true on the frame the action gets pressed, and then repeatedly while it is held: first after
+    /// initial_delay, then every interval (like the key repeat of the OS, for example for navigating menus).
+    /// The timing is based on the start of the frames, so calling this multiple times per frame gives the same result.
+    pub fn action_repeat(&self, name: &str, initial_delay: Duration, interval: Duration) -> bool {
+        let action = match self.actions.get(name) {
+            Some(action) => action,
+            None => {
+                warn!("action_repeat: Action '{}' does not exist. Create it by calling add_action.", name);
+                return false;
+            }
+        };
+        if !self.is_action_active(action) {
+            return false;
+        };
+        if action.just_pressed {
+            return true;
+        };
+        let Some(pressed_since) = action.pressed_since else {
+            return false;
+        };
+        let held = self.frame_start.saturating_duration_since(pressed_since);
+        let held_last_frame = self.last_frame_start.saturating_duration_since(pressed_since);
+        Self::num_repeats(held, initial_delay, interval) > Self::num_repeats(held_last_frame, initial_delay, interval)
+    }
+
+
+    /// How many repeats have happened after holding an action for that long (not counting the initial press)
+    fn num_repeats(held: Duration, initial_delay: Duration, interval: Duration) -> u128 {
+        if held < initial_delay {
+            0
+        } else if interval.is_zero() {
+            1
+        } else {
+            1 + (held - initial_delay).as_nanos() / interval.as_nanos()
+        }
+    }
+
+
+    /// Returns true if the key is currently held down, without needing an InputAction
+    pub fn is_key_pressed(&self, key: KeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+
+    /// Returns the modifier keys (Shift, Ctrl, Alt, Super) that are currently held down
+    pub fn current_modifiers(&self) -> ModifiersState {
+        self.current_modifiers
+    }
+
+
     pub fn get_mouse_position(&self) -> IVec2 {
         self.mouse_position
     }
@@ -168,6 +642,42 @@ impl InputSystem {
     }
 
 
+    /// Returns the mouse delta (see get_mouse_delta) scaled by the mouse sensitivity.
+    /// Deltas shorter than the dead zone return zero.
+    pub fn get_camera_look_delta(&self) -> Vec2 {
+        let delta = self.get_mouse_delta().as_vec2();
+        if delta.length() < self.mouse_deadzone as f32 {
+            return Vec2::ZERO;
+        };
+        delta * self.mouse_sensitivity
+    }
+
+
+    /// Sets the multiplier that is applied in get_camera_look_delta (default is 1.0, 1.0)
+    pub fn set_mouse_sensitivity(&mut self, sensitivity: Vec2) {
+        self.mouse_sensitivity = sensitivity;
+    }
+    pub fn mouse_sensitivity(&self) -> Vec2 {
+        self.mouse_sensitivity
+    }
+
+
+    /// Sets the dead zone (in pixels) that is applied in get_camera_look_delta (default is 0)
+    pub fn set_mouse_deadzone(&mut self, deadzone: i32) {
+        self.mouse_deadzone = deadzone;
+    }
+    pub fn mouse_deadzone(&self) -> i32 {
+        self.mouse_deadzone
+    }
+
+
+    /// Returns how far the mouse wheel (or touchpad) has been scrolled this frame, in lines. Positive y scrolls up
+    /// (away from the user), positive x to the right. Touchpads report pixels, which are converted with SCROLL_PIXELS_PER_LINE.
+    pub fn get_scroll_delta(&self) -> Vec2 {
+        self.scroll_delta
+    }
+
+
     pub fn get_input_vector(&self, action_left: &str, action_right: &str, action_up: &str, action_down: &str) -> IVec2 {
         let actions = [
             (action_left, self.actions.get(action_left)),
@@ -181,45 +691,268 @@ impl InputSystem {
                 return IVec2::ZERO;
             };
         };
+        let pressed = |index: usize| {
+            let action = actions[index].1.unwrap();
+            (action.pressed && self.is_action_active(action)) as i32
+        };
         IVec2::new(
-            actions[1].1.unwrap().pressed as i32 - actions[0].1.unwrap().pressed as i32,
-            actions[2].1.unwrap().pressed as i32 - actions[3].1.unwrap().pressed as i32
+            pressed(1) - pressed(0),
+            pressed(2) - pressed(3)
         )
     }
 
 
+    /// Returns how far the action is pressed (0.0 - 1.0), see `InputAction::strength`
+    pub fn get_action_strength(&self, name: &str) -> f32 {
+        match self.actions.get(name) {
+            Some(action) if self.is_action_active(action) => action.strength,
+            Some(_) => 0.0,
+            None => {
+                warn!("get_action_strength: Action '{}' does not exist. Create it by calling add_action.", name);
+                0.0
+            }
+        }
+    }
+
+
+    /// Returns a value between -1.0 and 1.0 from two actions, for example "left" and "right". Keys give
+    /// -1.0, 0.0 or 1.0, gamepad axes anything in between.
+    pub fn get_input_axis(&self, negative: &str, positive: &str) -> f32 {
+        for name in [negative, positive] {
+            if !self.actions.contains_key(name) {
+                warn!("get_input_axis: Action '{}' does not exist, create it using add_action.", name);
+                return 0.0;
+            };
+        }
+        (self.get_action_strength(positive) - self.get_action_strength(negative)).clamp(-1.0, 1.0)
+    }
+
+
+    /// Like get_input_vector, but with analog values (see get_input_axis). The length is at most 1.0,
+    /// so moving diagonally is not faster than moving straight.
+    pub fn get_input_vector_f32(&self, action_left: &str, action_right: &str, action_up: &str, action_down: &str) -> Vec2 {
+        for name in [action_left, action_right, action_up, action_down] {
+            if !self.actions.contains_key(name) {
+                warn!("get_input_vector_f32: Action '{}' does not exist, create it using add_action.", name);
+                return Vec2::ZERO;
+            };
+        }
+        Vec2::new(
+            self.get_input_axis(action_left, action_right),
+            self.get_input_axis(action_down, action_up)
+        ).clamp_length_max(1.0)
+    }
+
+
+    /// Captures the state of all actions, held keys, modifiers and the mouse
+    pub fn snapshot(&self) -> InputSnapshot {
+        let mut pressed_keys: Vec<KeyCode> = self.pressed_keys.iter().copied().collect();
+        // Sorted, so equal states always give equal snapshots
+        pressed_keys.sort_by_key(|key| format!("{:?}", key));
+        InputSnapshot {
+            actions: self.actions.iter().map(|(name, action)| (name.clone(), (action.pressed, action.just_pressed))).collect(),
+            pressed_keys,
+            modifiers: self.current_modifiers,
+            mouse_position: self.mouse_position.to_array(),
+            last_mouse_position: self.last_mouse_position.to_array()
+        }
+    }
+
+
+    /// Replaces the current input state with a snapshot. Actions that are not part of the snapshot are released,
+    /// names in the snapshot without a registered action are ignored. Use together with set_replay_mode,
+    /// otherwise the next real input event changes the state again.
+    pub fn restore(&mut self, snapshot: &InputSnapshot) {
+        let frame_start = self.frame_start;
+        self.actions.iter_mut().for_each(|(name, action)| {
+            let (pressed, just_pressed) = snapshot.actions.get(name).copied().unwrap_or((false, false));
+            action.pressed_since = match pressed {
+                true => action.pressed_since.or(Some(frame_start)),
+                false => None
+            };
+            action.pressed = pressed;
+            action.just_pressed = just_pressed;
+            action.just_released = false;
+            action.strength = pressed as u8 as f32;
+        });
+        self.pressed_keys = snapshot.pressed_keys.iter().copied().collect();
+        self.current_modifiers = snapshot.modifiers;
+        self.mouse_position = IVec2::from_array(snapshot.mouse_position);
+        self.last_mouse_position = IVec2::from_array(snapshot.last_mouse_position);
+    }
+
+
+    /// While enabled, keyboard, mouse and modifier events are ignored, so states restored from
+    /// snapshots (for example when playing back a replay) are not overwritten by real input
+    pub fn set_replay_mode(&mut self, replay_mode: bool) {
+        self.replay_mode = replay_mode;
+    }
+
+    pub fn replay_mode(&self) -> bool {
+        self.replay_mode
+    }
+
+
+    /// Waits for the next key press, mouse button or gamepad button and makes it the first trigger of the action
+    /// (together with the modifiers held at that moment), then raises events::Rebound. Modifier keys alone are
+    /// not captured, so combinations like Ctrl + S can be bound. Until then, no action responds to input.
+    pub fn start_rebind(&mut self, action_name: &str) {
+        if !self.actions.contains_key(action_name) {
+            warn!("start_rebind: Action '{}' does not exist. Create it by calling add_action.", action_name);
+            return;
+        };
+        // Actions would not see the releases while capturing, so they would stay pressed. This is not a real
+        // release, so just_released is not set
+        self.actions.values_mut().for_each(|action| {
+            action.pressed = false;
+            action.pressed_since = None;
+            action.strength = 0.0;
+        });
+        self.rebinding = Some(String::from(action_name));
+    }
+
+
+    /// Stops waiting for input started with start_rebind, the action keeps its triggers
+    pub fn cancel_rebind(&mut self) {
+        self.rebinding = None;
+    }
+
+
+    /// The action that is waiting for its new trigger, None when not rebinding
+    pub fn rebinding_action(&self) -> Option<&str> {
+        self.rebinding.as_deref()
+    }
+
+
+    /// Makes the reason the first trigger of the action that is being rebound
+    fn finish_rebind(&mut self, reason: InputActionTriggerReason) {
+        let Some(name) = self.rebinding.take() else {
+            return;
+        };
+        let modifiers = match reason {
+            InputActionTriggerReason::Key(_) | InputActionTriggerReason::Mouse(_) => self.current_modifiers,
+            _ => ModifiersState::empty()
+        };
+        let trigger = InputActionTrigger::new(reason, modifiers);
+        match self.actions.get_mut(&name) {
+            Some(action) => {
+                match action.triggers.first_mut() {
+                    Some(first) => *first = trigger,
+                    None => action.triggers.push(trigger)
+                };
+                self.ctx.raise_event(events::Rebound { action: name });
+            },
+            None => warn!("The action '{}' was removed while rebinding it", name)
+        };
+    }
+
+
     /// Updates keyboard input for all InputAction's
     pub(crate) fn handle_keyevent(&mut self, event: &KeyEvent) {
-        self.actions.iter_mut().for_each(|(key, action)| {
+        self.handle_key(event.physical_key, event.state, event.repeat);
+    }
+
+
+    /// Like handle_keyevent, but without needing a KeyEvent (which cannot be created outside of winit)
+    pub(crate) fn handle_key(&mut self, physical_key: PhysicalKey, state: ElementState, repeat: bool) {
+        if self.replay_mode {
+            return;
+        };
+        if let PhysicalKey::Code(key) = physical_key {
+            match state {
+                ElementState::Pressed => self.pressed_keys.insert(key),
+                ElementState::Released => self.pressed_keys.remove(&key)
+            };
+        };
+        if self.rebinding.is_some() {
+            let is_modifier = matches!(physical_key, PhysicalKey::Code(
+                KeyCode::ShiftLeft | KeyCode::ShiftRight | KeyCode::ControlLeft | KeyCode::ControlRight |
+                KeyCode::AltLeft | KeyCode::AltRight | KeyCode::SuperLeft | KeyCode::SuperRight));
+            if let (PhysicalKey::Code(key), ElementState::Pressed, false, false) = (physical_key, state, repeat, is_modifier) {
+                self.finish_rebind(InputActionTriggerReason::Key(key));
+            };
+            return;
+        };
+        if repeat {
+            return;
+        };
+        let held = HeldInput {
+            keys: &self.pressed_keys,
+            mouse_buttons: &self.pressed_mouse_buttons,
+            gamepad_buttons: &self.gamepad_buttons,
+            gamepad_axes: &self.gamepad_axes
+        };
+        self.actions.values_mut().for_each(|action| {
+            let (mut pressed, mut released) = (false, false);
             action.triggers.iter().for_each(|trigger| {
-                if let InputActionTriggerReason::Key(trigger_key) = trigger.reason {
-                    if event.physical_key == trigger_key && self.current_modifiers == trigger.modifiers {
-                        action.just_pressed = event.state == ElementState::Pressed && event.repeat == false;
-                        action.pressed = event.state == ElementState::Pressed;
-                    };
+                let InputActionTriggerReason::Key(trigger_key) = trigger.reason else {
+                    return;
+                };
+                let mut trigger_keys = std::iter::once(trigger_key).chain(trigger.chord.iter().copied());
+                if !trigger_keys.clone().any(|chord_key| physical_key == chord_key) {
+                    return;
+                };
+                match state {
+                    ElementState::Pressed => {
+                        pressed |= self.current_modifiers == trigger.modifiers && trigger_keys.all(|chord_key| held.keys.contains(&chord_key));
+                    },
+                    // Releasing any key of a chord releases it
+                    ElementState::Released => released = true
                 };
             });
+            if pressed {
+                action.set_pressed(true);
+            };
+            if released && !action.triggers.iter().any(|trigger| held.is_held(trigger)) {
+                action.set_pressed(false);
+            };
         });
     }
 
 
     /// Updates mouse input for all InputAction's
     pub(crate) fn handle_mouse_input(&mut self, button: MouseButton, state: ElementState) {
+        if self.replay_mode {
+            return;
+        };
+        match state {
+            ElementState::Pressed => self.pressed_mouse_buttons.insert(button),
+            ElementState::Released => self.pressed_mouse_buttons.remove(&button)
+        };
+        if self.rebinding.is_some() {
+            if state == ElementState::Pressed {
+                self.finish_rebind(InputActionTriggerReason::Mouse(button));
+            };
+            return;
+        };
+        let held = HeldInput {
+            keys: &self.pressed_keys,
+            mouse_buttons: &self.pressed_mouse_buttons,
+            gamepad_buttons: &self.gamepad_buttons,
+            gamepad_axes: &self.gamepad_axes
+        };
         self.actions.values_mut().for_each(|action| {
-            action.triggers.iter().for_each(|trigger| {
-                if let InputActionTriggerReason::Mouse(trigger_button) = trigger.reason {
-                    if button == trigger_button && self.current_modifiers == trigger.modifiers {
-                        action.just_pressed = state == ElementState::Pressed;
-                        action.pressed = state == ElementState::Pressed;
-                    };
-                };
+            let triggered = action.triggers.iter().any(|trigger| {
+                trigger.reason == InputActionTriggerReason::Mouse(button) && (state == ElementState::Released || self.current_modifiers == trigger.modifiers)
             });
+            if !triggered {
+                return;
+            };
+            match state {
+                ElementState::Pressed => action.set_pressed(true),
+                ElementState::Released => if !action.triggers.iter().any(|trigger| held.is_held(trigger)) {
+                    action.set_pressed(false);
+                }
+            };
         });
     }
 
 
     /// Sets the current mouse position and updates the last mouse position
     pub(crate) fn handle_cursor_movement(&mut self, new_position: PhysicalPosition<f64>) {
+        if self.replay_mode {
+            return;
+        };
         let tmp = self.mouse_position;
         // new_position always ends in .0 so we can safely cast here without loosing precision
         self.mouse_position = IVec2::new(new_position.x as i32, new_position.y as i32);
@@ -227,26 +960,721 @@ impl InputSystem {
     }
 
 
+    /// Adds the scrolling to the scroll delta of the current frame
+    pub(crate) fn handle_mouse_wheel(&mut self, delta: MouseScrollDelta) {
+        if self.replay_mode {
+            return;
+        };
+        self.scroll_delta += match delta {
+            MouseScrollDelta::LineDelta(x, y) => Vec2::new(x, y),
+            MouseScrollDelta::PixelDelta(position) => Vec2::new(position.x as f32, position.y as f32) / SCROLL_PIXELS_PER_LINE
+        };
+    }
+
+
+    /// Handles the events of all gamepads since the last poll, called at the start of every frame before `PreUpdate`
+    pub(crate) fn poll_gamepads(&mut self) {
+        let mut events = vec![];
+        if let Some(gilrs) = self.gilrs.as_mut() {
+            while let Some(event) = gilrs.next_event() {
+                events.push(event);
+            }
+        };
+        for gilrs::Event { id, event, .. } in events {
+            match event {
+                gilrs::EventType::ButtonPressed(button, _) => self.handle_gamepad_button(button, ElementState::Pressed),
+                gilrs::EventType::ButtonReleased(button, _) => self.handle_gamepad_button(button, ElementState::Released),
+                gilrs::EventType::AxisChanged(axis, value, _) => self.handle_gamepad_axis(axis, value),
+                gilrs::EventType::Connected => info!("Gamepad {} connected", id),
+                gilrs::EventType::Disconnected => {
+                    info!("Gamepad {} disconnected", id);
+                    self.release_gamepad_input();
+                },
+                _ => ()
+            };
+        }
+    }
+
+
+    /// Updates gamepad button input for all InputAction's
+    pub(crate) fn handle_gamepad_button(&mut self, button: Button, state: ElementState) {
+        if self.replay_mode {
+            return;
+        };
+        match state {
+            ElementState::Pressed => self.gamepad_buttons.insert(button),
+            ElementState::Released => self.gamepad_buttons.remove(&button)
+        };
+        if self.rebinding.is_some() {
+            if state == ElementState::Pressed {
+                self.finish_rebind(InputActionTriggerReason::GamepadButton(button));
+            };
+            return;
+        };
+        let held = HeldInput {
+            keys: &self.pressed_keys,
+            mouse_buttons: &self.pressed_mouse_buttons,
+            gamepad_buttons: &self.gamepad_buttons,
+            gamepad_axes: &self.gamepad_axes
+        };
+        self.actions.values_mut().for_each(|action| {
+            let triggered = action.triggers.iter().any(|trigger| trigger.reason == InputActionTriggerReason::GamepadButton(button));
+            if !triggered {
+                return;
+            };
+            match state {
+                ElementState::Pressed => action.set_pressed(true),
+                ElementState::Released => if !action.triggers.iter().any(|trigger| held.is_held(trigger)) {
+                    action.set_pressed(false);
+                }
+            };
+        });
+    }
+
+
+    /// Updates gamepad axis input for all InputAction's, actions are only pressed or released when the value crosses
+    /// their threshold, their strength follows the value while pressed
+    pub(crate) fn handle_gamepad_axis(&mut self, axis: Axis, value: f32) {
+        if self.replay_mode {
+            return;
+        };
+        let old_value = self.gamepad_axes.insert(axis, value).unwrap_or(0.0);
+        if self.rebinding.is_some() {
+            return;
+        };
+        let held = HeldInput {
+            keys: &self.pressed_keys,
+            mouse_buttons: &self.pressed_mouse_buttons,
+            gamepad_buttons: &self.gamepad_buttons,
+            gamepad_axes: &self.gamepad_axes
+        };
+        self.actions.values_mut().for_each(|action| {
+            let any_trigger_held = action.triggers.iter().any(|trigger| held.is_held(trigger));
+            let (mut changed, mut strength) = (None, None);
+            action.triggers.iter().for_each(|trigger| {
+                if let InputActionTriggerReason::GamepadAxis { axis: trigger_axis, threshold } = trigger.reason {
+                    if trigger_axis != axis {
+                        return;
+                    };
+                    let pressed = InputActionTriggerReason::axis_pressed(threshold, value);
+                    if pressed != InputActionTriggerReason::axis_pressed(threshold, old_value) && (pressed || !any_trigger_held) {
+                        changed = Some(pressed);
+                    };
+                    if pressed {
+                        strength = Some(InputActionTriggerReason::axis_strength(threshold, value));
+                    };
+                };
+            });
+            if let Some(pressed) = changed {
+                action.set_pressed(pressed);
+            };
+            if let Some(strength) = strength {
+                action.strength = strength;
+            };
+        });
+    }
+
+
+    /// Releases all gamepad buttons and centers all axes, so nothing stays pressed after a gamepad got disconnected
+    fn release_gamepad_input(&mut self) {
+        let buttons: Vec<Button> = self.gamepad_buttons.iter().copied().collect();
+        buttons.into_iter().for_each(|button| self.handle_gamepad_button(button, ElementState::Released));
+        let axes: Vec<Axis> = self.gamepad_axes.keys().copied().collect();
+        axes.into_iter().for_each(|axis| self.handle_gamepad_axis(axis, 0.0));
+    }
+
+
     pub(crate) fn update_modifiers(&mut self, modifiers: &Modifiers) {
+        if self.replay_mode {
+            return;
+        };
         self.current_modifiers = modifiers.state();
     }
 
 
-    /// Sets the `just_pressed` property of all InputAction's to `false`
-    pub(crate) fn reset_just_pressed(&mut self) {
+    /// Loads the actions from a bindings file (see `Bindings`) and reloads them whenever the file changes.
+    /// When reloading, actions keep their pressed state. If the file cannot be read or parsed,
+    /// the current bindings are kept and an error is logged.
+    pub fn watch_bindings(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        // The file watcher reports absolute paths
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.reload_bindings_from(&path);
+        self.ctx.get_mut::<FileWatcher>().watch(&path, false);
+        self.bindings_path = Some(path);
+    }
+
+
+    fn on_files_changed(&mut self, event: &crate::filewatcher::events::FilesChanged) {
+        let Some(bindings_path) = self.bindings_path.clone() else {
+            return;
+        };
+        let changed = event.paths.iter().any(|path| {
+            path.canonicalize().unwrap_or_else(|_| path.clone()) == bindings_path
+        });
+        if changed {
+            self.reload_bindings_from(&bindings_path);
+        };
+    }
+
+
+    fn reload_bindings_from(&mut self, path: &Path) {
+        if let Err(e) = self.load_bindings(path) {
+            error!("Keeping the current input bindings: {:#}", e);
+        };
+    }
+
+
+    /// Replaces all actions with the ones from a bindings file (see `Bindings`), for example the controls
+    /// a player has remapped. Triggers with unknown keys are skipped, if the file cannot be read or parsed,
+    /// the current actions are kept.
+    pub fn load_bindings(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let bindings = Self::read_bindings(path)?;
+        self.apply_bindings(bindings);
+        info!("Loaded input bindings from '{}'", path.display());
+        Ok(())
+    }
+
+
+    /// Writes all actions with their triggers and sets to a bindings file, which can be loaded again with
+    /// load_bindings or watch_bindings. The file is written as JSON if the path ends in .json, as RON otherwise.
+    pub fn save_bindings(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        // Sorted by name, so saving the same bindings always gives the same file
+        let bindings: BTreeMap<&str, ActionBinding> = self.actions.iter()
+            .map(|(name, action)| (name.as_str(), ActionBinding {
+                triggers: action.triggers.clone(),
+                set: action.set.clone()
+            }))
+            .collect();
+        let source = match Self::is_json(path) {
+            true => serde_json::to_string_pretty(&bindings)
+                .with_context(|| format!("Could not serialize input bindings '{}'", path.display()))?,
+            false => ron::ser::to_string_pretty(&bindings, ron::ser::PrettyConfig::default())
+                .with_context(|| format!("Could not serialize input bindings '{}'", path.display()))?
+        };
+        std::fs::write(path, source)
+            .with_context(|| format!("Could not write input bindings '{}'", path.display()))
+    }
+
+
+    fn read_bindings(path: &Path) -> anyhow::Result<Bindings> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read input bindings '{}'", path.display()))?;
+        match Self::is_json(path) {
+            true => serde_json::from_str(&source)
+                .with_context(|| format!("Could not parse input bindings '{}'", path.display())),
+            false => ron::from_str(&source)
+                .with_context(|| format!("Could not parse input bindings '{}'", path.display()))
+        }
+    }
+
+
+    fn is_json(path: &Path) -> bool {
+        path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"))
+    }
+
+
+    /// Replaces all actions with the bindings, actions that existed before keep their pressed state
+    fn apply_bindings(&mut self, bindings: Bindings) {
+        let mut old_actions = std::mem::take(&mut self.actions);
+        for (name, binding) in bindings {
+            let mut action = InputAction::empty(&name);
+            action.triggers = binding.triggers;
+            action.set = binding.set;
+            if let Some(old_action) = old_actions.remove(&name) {
+                action.pressed = old_action.pressed;
+                action.just_pressed = old_action.just_pressed;
+                action.just_released = old_action.just_released;
+                action.pressed_since = old_action.pressed_since;
+                action.strength = old_action.strength;
+            };
+            self.actions.insert(name, action);
+        }
+    }
+
+
+    /// Sets the `just_pressed` and `just_released` properties of all InputAction's to `false`, resets the scroll delta
+    /// and marks the start of a new frame
+    pub(crate) fn reset_frame_state(&mut self) {
+        self.scroll_delta = Vec2::ZERO;
+        self.last_frame_start = self.frame_start;
+        self.frame_start = Instant::now();
         self.actions.values_mut().for_each(|action| {
             action.just_pressed = false;
+            action.just_released = false;
         });
     }
 }
 impl GeeseSystem for InputSystem {
+    const DEPENDENCIES: geese::Dependencies = dependencies()
+        .with::<Mut<FileWatcher>>();
+    const EVENT_HANDLERS: geese::EventHandlers<Self> = event_handlers()
+        .with(Self::on_files_changed);
+
+
     fn new(ctx: geese::GeeseContextHandle<Self>) -> Self {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => Some(gilrs),
+            Err(gilrs::Error::NotImplemented(gilrs)) => {
+                warn!("Gamepads are not supported on this platform");
+                Some(gilrs)
+            },
+            Err(e) => {
+                error!("Could not initialize gamepad support: {}", e);
+                None
+            }
+        };
         Self {
             ctx,
+            gilrs,
+            gamepad_buttons: HashSet::default(),
+            gamepad_axes: HashMap::default(),
             actions: HashMap::default(),
+            action_sets: vec![],
             mouse_position: IVec2::ZERO,
             last_mouse_position: IVec2::ZERO,
-            current_modifiers: ModifiersState::empty()
+            mouse_sensitivity: Vec2::ONE,
+            mouse_deadzone: 0,
+            scroll_delta: Vec2::ZERO,
+            frame_start: Instant::now(),
+            last_frame_start: Instant::now(),
+            bindings_path: None,
+            replay_mode: false,
+            rebinding: None,
+            current_modifiers: ModifiersState::empty(),
+            pressed_keys: HashSet::default(),
+            pressed_mouse_buttons: HashSet::default()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+
+    use geese::{GeeseContextHandle, GeeseSystem};
+    use gilrs::Axis;
+    use glam::{IVec2, Vec2};
+    use winit::{dpi::PhysicalPosition, event::{MouseButton, MouseScrollDelta}, keyboard::{KeyCode, ModifiersState}};
+
+    use crate::{input_system::{events, SCROLL_PIXELS_PER_LINE}, InputActionTrigger, InputActionTriggerReason, InputSystem, TestHarness};
+
+    fn harness_with_action(name: &str, trigger: InputActionTrigger) -> TestHarness {
+        let mut harness = TestHarness::new();
+        harness.get_ctx().get_mut::<InputSystem>().add_action(name, trigger);
+        harness
+    }
+
+
+    #[test]
+    fn press_and_release_in_the_same_frame() {
+        let mut harness = harness_with_action("jump", InputActionTrigger::key(KeyCode::Space));
+        harness.press_key(KeyCode::Space);
+        harness.release_key(KeyCode::Space);
+        {
+            let input = harness.get_ctx().get::<InputSystem>();
+            assert!(input.is_action_just_pressed("jump"));
+            assert!(input.is_action_just_released("jump"));
+            assert!(!input.is_action_pressed("jump"));
+        }
+
+        harness.step();
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(!input.is_action_just_pressed("jump"));
+        assert!(!input.is_action_just_released("jump"));
+    }
+
+    #[test]
+    fn start_rebind_does_not_count_as_release() {
+        let mut harness = harness_with_action("jump", InputActionTrigger::key(KeyCode::Space));
+        harness.press_key(KeyCode::Space);
+        harness.step();
+
+        harness.get_ctx().get_mut::<InputSystem>().start_rebind("jump");
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(!input.is_action_pressed("jump"));
+        assert!(!input.is_action_just_released("jump"));
+    }
+
+    #[test]
+    fn chord_needs_all_keys_held() {
+        let mut harness = harness_with_action("build", InputActionTrigger::key(KeyCode::KeyG).with_chord(&[KeyCode::KeyB]));
+        harness.press_key(KeyCode::KeyB);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("build"));
+        harness.press_key(KeyCode::KeyG);
+        assert!(harness.get_ctx().get::<InputSystem>().is_action_pressed("build"));
+        harness.release_key(KeyCode::KeyB);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("build"));
+    }
+
+    #[test]
+    fn chord_is_not_a_sequence() {
+        let mut harness = harness_with_action("build", InputActionTrigger::key(KeyCode::KeyG).with_chord(&[KeyCode::KeyB]));
+        harness.press_key(KeyCode::KeyG);
+        harness.release_key(KeyCode::KeyG);
+        harness.step();
+        harness.press_key(KeyCode::KeyB);
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(!input.is_action_pressed("build"));
+        assert!(!input.is_action_just_pressed("build"));
+    }
+
+    #[test]
+    fn release_keeps_action_pressed_while_another_trigger_is_held() {
+        let mut harness = harness_with_action("build", InputActionTrigger::key(KeyCode::KeyG).with_chord(&[KeyCode::KeyB]));
+        harness.get_ctx().get_mut::<InputSystem>().add_trigger_to_action("build", InputActionTrigger::key(KeyCode::Enter));
+        harness.press_key(KeyCode::Enter);
+        harness.press_key(KeyCode::KeyG);
+        harness.press_key(KeyCode::KeyB);
+        harness.step();
+
+        // The chord is broken, but Enter is still held
+        harness.release_key(KeyCode::KeyB);
+        {
+            let input = harness.get_ctx().get::<InputSystem>();
+            assert!(input.is_action_pressed("build"));
+            assert!(!input.is_action_just_released("build"));
         }
+
+        harness.release_key(KeyCode::Enter);
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(!input.is_action_pressed("build"));
+        assert!(input.is_action_just_released("build"));
+    }
+
+    #[test]
+    fn release_keeps_action_pressed_while_another_key_is_held() {
+        let mut harness = harness_with_action("left", InputActionTrigger::key(KeyCode::KeyA));
+        harness.get_ctx().get_mut::<InputSystem>().add_trigger_to_action("left", InputActionTrigger::key(KeyCode::ArrowLeft));
+        harness.press_key(KeyCode::KeyA);
+        harness.press_key(KeyCode::ArrowLeft);
+        harness.release_key(KeyCode::KeyA);
+        assert!(harness.get_ctx().get::<InputSystem>().is_action_pressed("left"));
+        harness.release_key(KeyCode::ArrowLeft);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("left"));
+    }
+
+    #[test]
+    fn camera_look_delta_applies_deadzone_and_sensitivity() {
+        let mut harness = TestHarness::new();
+        {
+            let mut input = harness.get_ctx().get_mut::<InputSystem>();
+            input.set_mouse_deadzone(2);
+            input.set_mouse_sensitivity(Vec2::splat(0.5));
+        }
+        harness.move_mouse(IVec2::new(1, 0));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_camera_look_delta(), Vec2::ZERO);
+
+        harness.move_mouse(IVec2::new(11, 0));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_camera_look_delta(), Vec2::new(5.0, 0.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn num_repeats_counts_delay_and_interval() {
+        let ms = Duration::from_millis;
+        assert_eq!(InputSystem::num_repeats(ms(299), ms(300), ms(100)), 0);
+        assert_eq!(InputSystem::num_repeats(ms(300), ms(300), ms(100)), 1);
+        assert_eq!(InputSystem::num_repeats(ms(399), ms(300), ms(100)), 1);
+        assert_eq!(InputSystem::num_repeats(ms(550), ms(300), ms(100)), 3);
+        // Without an interval, the action only repeats once after the delay
+        assert_eq!(InputSystem::num_repeats(ms(300), ms(300), Duration::ZERO), 1);
+        assert_eq!(InputSystem::num_repeats(ms(10_000), ms(300), Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn action_repeat_across_frames() {
+        let mut harness = harness_with_action("down", InputActionTrigger::key(KeyCode::ArrowDown));
+        harness.press_key(KeyCode::ArrowDown);
+        let (delay, interval) = (Duration::from_millis(300), Duration::from_millis(100));
+        assert!(harness.get_ctx().get::<InputSystem>().action_repeat("down", delay, interval));
+        harness.step();
+
+        // Frames use real time, so their starts are set relative to the press
+        let mut repeats_in_frame = |last_ms: u64, now_ms: u64, interval: Duration| {
+            let mut input = harness.get_ctx().get_mut::<InputSystem>();
+            let pressed_since = input.actions["down"].pressed_since.unwrap();
+            input.last_frame_start = pressed_since + Duration::from_millis(last_ms);
+            input.frame_start = pressed_since + Duration::from_millis(now_ms);
+            input.action_repeat("down", delay, interval)
+        };
+        assert!(!repeats_in_frame(0, 100, interval));
+        assert!(!repeats_in_frame(100, 250, interval));
+        assert!(repeats_in_frame(250, 300, interval));
+        assert!(!repeats_in_frame(300, 350, interval));
+        assert!(repeats_in_frame(350, 400, interval));
+        // A long frame that skips several intervals still repeats only once
+        assert!(repeats_in_frame(400, 650, interval));
+
+        assert!(repeats_in_frame(250, 300, Duration::ZERO));
+        assert!(!repeats_in_frame(300, 1000, Duration::ZERO));
+    }
+
+    #[test]
+    fn action_sets_with_overlapping_bindings() {
+        let mut harness = TestHarness::new();
+        {
+            let mut input = harness.get_ctx().get_mut::<InputSystem>();
+            input.add_action_to_set("game", "jump", InputActionTrigger::key(KeyCode::Space));
+            input.add_action_to_set("menu", "confirm", InputActionTrigger::key(KeyCode::Space));
+            input.push_action_set("game");
+        }
+        harness.press_key(KeyCode::Space);
+        {
+            let input = harness.get_ctx().get::<InputSystem>();
+            assert!(input.is_action_just_pressed("jump"));
+            assert!(!input.is_action_pressed("confirm"));
+        }
+        harness.release_key(KeyCode::Space);
+        harness.step();
+
+        harness.get_ctx().get_mut::<InputSystem>().push_action_set("menu");
+        harness.press_key(KeyCode::Space);
+        {
+            let input = harness.get_ctx().get::<InputSystem>();
+            assert!(input.is_action_just_pressed("confirm"));
+            assert!(!input.is_action_pressed("jump"));
+        }
+        harness.release_key(KeyCode::Space);
+        harness.step();
+
+        assert_eq!(harness.get_ctx().get_mut::<InputSystem>().pop_action_set().as_deref(), Some("menu"));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().active_action_set(), Some("game"));
+        harness.press_key(KeyCode::Space);
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(input.is_action_just_pressed("jump"));
+        assert!(!input.is_action_pressed("confirm"));
+    }
+
+    #[test]
+    fn add_actions_registers_every_action() {
+        let mut harness = TestHarness::new();
+        {
+            let mut input = harness.get_ctx().get_mut::<InputSystem>();
+            assert!(!input.contains_action("left"));
+            input.add_actions([
+                ("left", InputActionTrigger::key(KeyCode::KeyA)),
+                ("right", InputActionTrigger::key(KeyCode::KeyD))
+            ]);
+            // Actions that already exist keep their trigger
+            input.add_actions([("left", InputActionTrigger::key(KeyCode::ArrowLeft))]);
+            assert!(input.contains_action("left"));
+            assert!(input.contains_action("right"));
+            assert!(!input.contains_action("up"));
+        }
+        harness.press_key(KeyCode::ArrowLeft);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("left"));
+        harness.press_key(KeyCode::KeyA);
+        harness.press_key(KeyCode::KeyD);
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert!(input.is_action_pressed("left"));
+        assert!(input.is_action_pressed("right"));
+    }
+
+    #[test]
+    fn key_stays_pressed_across_frames() {
+        let mut harness = TestHarness::new();
+        assert!(!harness.get_ctx().get::<InputSystem>().is_key_pressed(KeyCode::KeyW));
+        harness.press_key(KeyCode::KeyW);
+        harness.step_frames(3);
+        assert!(harness.get_ctx().get::<InputSystem>().is_key_pressed(KeyCode::KeyW));
+        assert!(!harness.get_ctx().get::<InputSystem>().is_key_pressed(KeyCode::KeyS));
+
+        harness.release_key(KeyCode::KeyW);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_key_pressed(KeyCode::KeyW));
+        harness.step();
+        assert!(!harness.get_ctx().get::<InputSystem>().is_key_pressed(KeyCode::KeyW));
+    }
+
+    #[test]
+    fn modifier_builder_matches_new_key() {
+        let built = InputActionTrigger::key(KeyCode::KeyS).with_ctrl().with_shift();
+        assert_eq!(built, InputActionTrigger::new_key(KeyCode::KeyS, ModifiersState::CONTROL | ModifiersState::SHIFT));
+
+        let mut harness = harness_with_action("save_as", built);
+        harness.set_modifiers(ModifiersState::CONTROL);
+        harness.press_key(KeyCode::KeyS);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("save_as"));
+        harness.release_key(KeyCode::KeyS);
+
+        harness.set_modifiers(ModifiersState::CONTROL | ModifiersState::SHIFT);
+        harness.press_key(KeyCode::KeyS);
+        assert!(harness.get_ctx().get::<InputSystem>().is_action_just_pressed("save_as"));
+    }
+
+    #[test]
+    fn scroll_delta_resets_every_frame() {
+        let mut harness = TestHarness::new();
+        harness.scroll(Vec2::new(0.0, 1.0));
+        harness.scroll(Vec2::new(-1.0, 2.0));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_scroll_delta(), Vec2::new(-1.0, 3.0));
+        harness.step();
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_scroll_delta(), Vec2::ZERO);
+
+        // Touchpads report pixels
+        let pixels = PhysicalPosition::new(0.0, 2.0 * SCROLL_PIXELS_PER_LINE as f64);
+        harness.get_ctx().get_mut::<InputSystem>().handle_mouse_wheel(MouseScrollDelta::PixelDelta(pixels));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_scroll_delta(), Vec2::new(0.0, 2.0));
+    }
+
+    #[test]
+    fn axis_strength_scales_past_the_threshold() {
+        assert_eq!(InputActionTriggerReason::axis_strength(0.5, 0.25), 0.0);
+        assert_eq!(InputActionTriggerReason::axis_strength(0.5, 0.5), 0.0);
+        assert_eq!(InputActionTriggerReason::axis_strength(0.5, 0.75), 0.5);
+        assert_eq!(InputActionTriggerReason::axis_strength(0.5, 1.0), 1.0);
+        assert_eq!(InputActionTriggerReason::axis_strength(-0.5, -0.75), 0.5);
+        assert_eq!(InputActionTriggerReason::axis_strength(-0.5, 0.75), 0.0);
+
+        let mut harness = harness_with_action("right", InputActionTrigger::gamepad_axis(Axis::LeftStickX, 0.5));
+        harness.set_gamepad_axis(Axis::LeftStickX, 0.25);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("right"));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_action_strength("right"), 0.0);
+        harness.set_gamepad_axis(Axis::LeftStickX, 0.75);
+        assert!(harness.get_ctx().get::<InputSystem>().is_action_pressed("right"));
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_action_strength("right"), 0.5);
+        harness.set_gamepad_axis(Axis::LeftStickX, 1.0);
+        assert_eq!(harness.get_ctx().get::<InputSystem>().get_action_strength("right"), 1.0);
+    }
+
+    #[test]
+    fn rebind_captures_the_next_key() {
+        struct RebindListener {
+            rebound: Vec<String>
+        }
+        impl RebindListener {
+            fn on_rebound(&mut self, event: &events::Rebound) {
+                self.rebound.push(event.action.clone());
+            }
+        }
+        impl GeeseSystem for RebindListener {
+            const EVENT_HANDLERS: geese::EventHandlers<Self> = geese::event_handlers()
+                .with(Self::on_rebound);
+
+            fn new(_ctx: GeeseContextHandle<Self>) -> Self {
+                Self {
+                    rebound: vec![]
+                }
+            }
+        }
+
+        let mut harness = harness_with_action("jump", InputActionTrigger::key(KeyCode::Space));
+        harness.add_system::<RebindListener>();
+        harness.get_ctx().get_mut::<InputSystem>().start_rebind("jump");
+        assert_eq!(harness.get_ctx().get::<InputSystem>().rebinding_action(), Some("jump"));
+
+        // Modifiers alone are not captured, but are part of the new trigger
+        harness.set_modifiers(ModifiersState::CONTROL);
+        harness.press_key(KeyCode::ControlLeft);
+        assert_eq!(harness.get_ctx().get::<InputSystem>().rebinding_action(), Some("jump"));
+        harness.press_key(KeyCode::KeyJ);
+        harness.get_ctx().flush();
+        {
+            let input = harness.get_ctx().get::<InputSystem>();
+            assert_eq!(input.rebinding_action(), None);
+            assert_eq!(input.actions["jump"].triggers[0], InputActionTrigger::key(KeyCode::KeyJ).with_ctrl());
+            // The key that got captured does not press the action
+            assert!(!input.is_action_pressed("jump"));
+        }
+        assert_eq!(harness.get_ctx().get::<RebindListener>().rebound, vec![String::from("jump")]);
+
+        harness.release_key(KeyCode::KeyJ);
+        harness.release_key(KeyCode::ControlLeft);
+        harness.set_modifiers(ModifiersState::empty());
+        harness.press_key(KeyCode::Space);
+        assert!(!harness.get_ctx().get::<InputSystem>().is_action_pressed("jump"));
+    }
+
+    #[test]
+    fn restore_brings_back_a_snapshot() {
+        let mut harness = harness_with_action("jump", InputActionTrigger::key(KeyCode::Space));
+        harness.get_ctx().get_mut::<InputSystem>().add_action("left", InputActionTrigger::key(KeyCode::KeyA));
+        harness.move_mouse(IVec2::new(3, 4));
+        harness.set_modifiers(ModifiersState::SHIFT);
+        harness.press_key(KeyCode::Space);
+        let snapshot = harness.get_ctx().get::<InputSystem>().snapshot();
+
+        harness.step();
+        harness.release_key(KeyCode::Space);
+        harness.press_key(KeyCode::KeyA);
+        harness.move_mouse(IVec2::new(10, 10));
+        harness.set_modifiers(ModifiersState::empty());
+        assert_ne!(harness.get_ctx().get::<InputSystem>().snapshot(), snapshot);
+
+        harness.get_ctx().get_mut::<InputSystem>().restore(&snapshot);
+        let input = harness.get_ctx().get::<InputSystem>();
+        assert_eq!(input.snapshot(), snapshot);
+        assert!(input.is_action_just_pressed("jump"));
+        assert!(!input.is_action_pressed("left"));
+        assert!(input.is_key_pressed(KeyCode::Space));
+        assert_eq!(input.get_mouse_delta(), IVec2::new(3, -4));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serde_json::from_str::<crate::InputSnapshot>(&json).unwrap(), snapshot);
+    }
+
+
+    /// A path in the temp directory that is unique to this test process
+    fn temp_bindings_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("granular_bindings_{}_{}", std::process::id(), name))
+    }
+
+    fn bindings_of(harness: &mut TestHarness) -> BTreeMap<String, (Vec<InputActionTrigger>, Option<String>)> {
+        let input = harness.get_ctx().get::<InputSystem>();
+        input.actions.iter().map(|(name, action)| (name.clone(), (action.triggers.clone(), action.set.clone()))).collect()
+    }
+
+    fn assert_round_trip(file_name: &str) {
+        let mut harness = harness_with_action("jump", InputActionTrigger::key(KeyCode::Space));
+        {
+            let mut input = harness.get_ctx().get_mut::<InputSystem>();
+            input.add_trigger_to_action("jump", InputActionTrigger::gamepad_axis(Axis::LeftStickY, 0.5));
+            input.add_action_to_set("game", "fire", InputActionTrigger::mouse(MouseButton::Left));
+            input.add_trigger_to_action("fire", InputActionTrigger::key(KeyCode::KeyF).with_shift());
+            input.add_action("dash", InputActionTrigger::key(KeyCode::KeyA).with_chord(&[KeyCode::KeyS]));
+        }
+        let path = temp_bindings_path(file_name);
+        harness.get_ctx().get::<InputSystem>().save_bindings(&path).unwrap();
+
+        let mut loaded = TestHarness::new();
+        loaded.get_ctx().get_mut::<InputSystem>().load_bindings(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bindings_of(&mut loaded), bindings_of(&mut harness));
+    }
+
+
+    #[test]
+    fn bindings_round_trip_ron() {
+        assert_round_trip("round_trip.ron");
+    }
+
+    #[test]
+    fn bindings_round_trip_json() {
+        assert_round_trip("round_trip.json");
+    }
+
+    #[test]
+    fn bindings_skip_unknown_keys() {
+        let ron_path = temp_bindings_path("unknown.ron");
+        std::fs::write(&ron_path, r#"{
+            "jump": (triggers: [(reason: Key(Space)), (reason: Key(NotAKey))]),
+            "dash": (triggers: [(reason: Key(KeyA), chord: [NotAKey])]),
+        }"#).unwrap();
+        let json_path = temp_bindings_path("unknown.json");
+        std::fs::write(&json_path, r#"{
+            "jump": { "triggers": [{ "reason": { "Key": "Space" } }, { "reason": { "Key": "NotAKey" } }] },
+            "dash": { "triggers": [{ "reason": { "Key": "KeyA" }, "chord": ["NotAKey"] }] }
+        }"#).unwrap();
+
+        for path in [ron_path, json_path] {
+            let mut harness = TestHarness::new();
+            harness.get_ctx().get_mut::<InputSystem>().load_bindings(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            let bindings = bindings_of(&mut harness);
+            assert_eq!(bindings["jump"].0, vec![InputActionTrigger::key(KeyCode::Space)]);
+            assert!(bindings["dash"].0.is_empty());
+        }
+    }
+}
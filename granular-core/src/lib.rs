@@ -11,20 +11,35 @@ pub use assets::AssetSystem;
 //mod tick;
 pub mod graphics;
 pub use graphics::{BatchRenderer, Camera};
-use graphics::{Renderer, WindowSystem};
+use graphics::{GraphicsSettings, GraphicsSystem, Renderer, WindowSystem};
 
 mod eventloop_system;
-pub use eventloop_system::EventLoopSystem;
+pub use eventloop_system::{EventLoopSystem, EventProxy};
+use eventloop_system::UserEvent;
 
 mod filewatcher;
 use filewatcher::FileWatcher;
 
 pub mod input_system;
-pub use input_system::{InputSystem, InputActionTrigger, InputAction};
+pub use input_system::{InputSystem, InputActionTrigger, InputActionTriggerReason, InputAction, InputSnapshot};
 
 pub mod simulation;
 pub use simulation::*;
 
+pub mod time_system;
+pub use time_system::{Interpolated, TimeSystem};
+
+mod frame_info;
+pub use frame_info::FrameInfo;
+
+pub mod tilemap;
+
+pub mod tween;
+pub use tween::Lerp;
+
+pub mod test_harness;
+pub use test_harness::TestHarness;
+
 
 pub mod events {
     pub struct Initialized {
@@ -40,19 +55,93 @@ pub mod events {
         pub const FIXED_TICKS: [u64; 3] = [5000, 2500, 1000];
     }
 
+    /// Raised right before the frame gets rendered, after all update phases
     pub struct Draw;
+
+    /// Can be raised by any system (a quit button, a fatal error handler, ...) to shut the engine down.
+    /// The current frame still finishes, then the event loop exits and `Exiting` is raised.
+    pub struct RequestExit;
+
+    /// Raised once when the event loop exits (the main window was closed or `RequestExit` was raised),
+    /// while all systems are still alive, use this for cleaning up or saving
+    pub struct Exiting;
+
+    /// Raised after the GPU device has been lost and recreated. All assets have been loaded again and the
+    /// Camera, the renderers and every system depending on them have been recreated (so their settings are back to default).
+    pub struct DeviceReset;
+
+    /// Raised after a window requested with `WindowSystem::request_window` has been created
+    pub struct WindowCreated {
+        pub window_id: winit::window::WindowId
+    }
+
+    /// Raised when an additional (not the main) window needs to be drawn. The frame of that
+    /// window has already been started, so its content can be rendered using `GraphicsSystem::frame_data_mut`
+    pub struct DrawWindow {
+        pub window_id: winit::window::WindowId
+    }
+
+    /// Events which are raised every frame in a guaranteed order:
+    /// 
+    /// `PreUpdate` -> `Update` -> `PostUpdate` -> (`timing` events) -> ... -> `PreRender` -> `Draw`
+    /// 
+    /// Each phase is flushed on its own, so all events raised while handling
+    /// one phase are handled before the next phase starts.
+    pub mod phase {
+        /// All input received since the last frame has been processed, use this for reacting to input
+        pub struct PreUpdate;
+        /// Main game logic, like physics
+        pub struct Update;
+        /// Runs after all game logic, for example for moving the camera
+        pub struct PostUpdate;
+        /// Raised right before `Draw`, for preparing render data
+        pub struct PreRender;
+    }
 }
 
 
 
 
+/// Decides when input gets sampled and the update phases run within a frame, see `GranularEngine::set_latency_mode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LatencyMode {
+    /// The update phases run at the start of every event loop iteration with the input received since the
+    /// last one, which is reset right after them. The frame is rendered later once the window requests a redraw
+    #[default]
+    Throughput,
+    /// The update phases run right before the main window gets rendered, using the input received
+    /// in the same iteration, and input is reset after rendering. This cuts the input-to-photon latency
+    /// by roughly one frame, but the CPU work can no longer overlap with waiting for the next redraw,
+    /// which can reduce throughput. Nothing gets updated while the main window does not redraw (for example when minimized).
+    LowLatency
+}
+
+
+
+/// The engine, which owns the GeeseContext and drives the event loop.
+///
+/// Lifecycle:
+/// 1. `new` adds the systems that do not need a window (`WindowSystem`, `EventLoopSystem`, `FileWatcher`, `InputSystem`, `TimeSystem`, `FrameInfo`)
+///    and `GraphicsSettings`, which can be changed before the graphics get initialized
+/// 2. `run` starts the event loop, once the window is created, the `Renderer`, `AssetSystem` and
+///    all systems registered with `register_startup_system` are added
+/// 3. The closures registered with `on_ready` run
+/// 4. `AppSystem` is added and `events::Initialized` is raised
 pub struct GranularEngine<AppSystem: GeeseSystem> {
     ctx: GeeseContext,
+    /// Systems that get added once graphics are initialized
+    startup_systems: geese::EventBuffer,
+    /// Closures that run once the graphics are initialized, see `on_ready`
+    ready_callbacks: Vec<Box<dyn FnOnce(&mut GeeseContext)>>,
+    /// Whether the window and graphics systems have been created (in `resumed`)
+    graphics_initialized: bool,
+    /// Whether the main window was closed or an exit was requested (see events::RequestExit)
     close_requested: bool,
     /// Current frame
     frame: u64,
     /// When each tick (in ms) last occured
     last_ticks: HashMap<Duration, Instant>,
+    latency_mode: LatencyMode,
     application: PhantomData<AppSystem>
 }
 
@@ -63,7 +152,10 @@ impl<AppSystem: GeeseSystem> GranularEngine<AppSystem> {
             .with(geese::notify::add_system::<WindowSystem>())
             .with(geese::notify::add_system::<EventLoopSystem>())
             .with(geese::notify::add_system::<FileWatcher>())
-            .with(geese::notify::add_system::<InputSystem>());
+            .with(geese::notify::add_system::<InputSystem>())
+            .with(geese::notify::add_system::<TimeSystem>())
+            .with(geese::notify::add_system::<FrameInfo>())
+            .with(geese::notify::add_system::<GraphicsSettings>());
 
         let now = Instant::now();
         let mut last_ticks = HashMap::default();
@@ -73,9 +165,13 @@ impl<AppSystem: GeeseSystem> GranularEngine<AppSystem> {
 
         Self {
             ctx,
+            startup_systems: geese::EventBuffer::default(),
+            ready_callbacks: vec![],
+            graphics_initialized: false,
             close_requested: false,
             frame: 0,
             last_ticks,
+            latency_mode: LatencyMode::default(),
             application: PhantomData
         }
     }
@@ -86,6 +182,30 @@ impl<AppSystem: GeeseSystem> GranularEngine<AppSystem> {
     }
 
 
+    /// Returns an EventProxy which can raise events from other threads while the engine is running
+    pub fn event_proxy(&self) -> EventProxy {
+        self.ctx.get::<EventLoopSystem>().event_proxy()
+    }
+
+
+    /// Registers a system that gets added after the window and graphics have been initialized
+    /// (right before `AppSystem`), so it may depend on the `GraphicsSystem`, `Renderer` etc.
+    ///
+    /// Systems that do not depend on graphics can be added right away via `get_ctx`.
+    pub fn register_startup_system<S: GeeseSystem>(&mut self) {
+        self.startup_systems = std::mem::take(&mut self.startup_systems)
+            .with(geese::notify::add_system::<S>());
+    }
+
+
+    /// Registers a closure that runs exactly once, right after the `GraphicsSystem`, `Renderer`, `AssetSystem`
+    /// and the startup systems have been created and before `AppSystem` is added. This is the place to add
+    /// systems and load the initial assets with everything available. Closures run in the order they were registered.
+    pub fn on_ready(&mut self, callback: impl FnOnce(&mut GeeseContext) + 'static) {
+        self.ready_callbacks.push(Box::new(callback));
+    }
+
+
     pub fn run(&mut self) {
         info!("GranularEngine run");
         let mut event_loop_sys = self.ctx.get_mut::<EventLoopSystem>();
@@ -96,44 +216,106 @@ impl<AppSystem: GeeseSystem> GranularEngine<AppSystem> {
     }
 
 
-    pub fn update(&mut self) {
+    /// Sets when input gets sampled and the update phases run (Throughput by default)
+    pub fn set_latency_mode(&mut self, latency_mode: LatencyMode) {
+        self.latency_mode = latency_mode;
+    }
+
+    pub fn latency_mode(&self) -> LatencyMode {
+        self.latency_mode
+    }
+
+
+    /// Starts a new frame: polls the gamepads, measures the time, runs the update phases and the scheduled ticks
+    fn run_frame_update(&mut self) {
+        self.ctx.get_mut::<InputSystem>().poll_gamepads();
+        self.ctx.get_mut::<TimeSystem>().begin_frame();
+        self.ctx.get_mut::<FrameInfo>().begin_frame(self.frame);
+        self.update();
+        self.handle_scheduling();
+        self.frame += 1;
+    }
+
 
+    /// Raises the update phase events (see `events::phase`), one flush per phase
+    pub fn update(&mut self) {
+        self.ctx.flush().with(events::phase::PreUpdate);
+        self.ctx.flush().with(events::phase::Update);
+        self.ctx.flush().with(events::phase::PostUpdate);
     }
 
 
     pub fn handle_scheduling(&mut self) {
-        let mut buffer = geese::EventBuffer::default()
-            .with(events::timing::Tick::<1>);
-        
         let now = Instant::now();
         self.last_ticks.iter_mut().for_each(|(tickrate, last)| {
             if *last + *tickrate < now {
                 *last = now;
-                let tickrate_millis = tickrate.as_millis() as u64;
-                match tickrate_millis {
-                    1000 => {self.ctx.flush().with(events::timing::FixedTick::<1000>);},
-                    2500 => {self.ctx.flush().with(events::timing::FixedTick::<2500>);},
-                    5000 => {self.ctx.flush().with(events::timing::FixedTick::<5000>);},
-                    _ => ()
-                };
+                raise_fixed_tick(&mut self.ctx, tickrate.as_millis() as u64);
             }
         });
+        raise_frame_ticks(&mut self.ctx, self.frame);
+    }
 
-        if self.frame % 60 == 0 {
-            buffer = buffer.with(events::timing::Tick::<60>);
-        };
-        if self.frame % 30 == 0 {
-            buffer = buffer.with(events::timing::Tick::<30>);
-        };
-        if self.frame % 2 == 0 {
-            buffer = buffer.with(events::timing::Tick::<2>);
-        };
-        // 1 Frame tick is already handled at the very top
-        
-        self.ctx.flush().with_buffer(buffer);
+
+    /// Creates the windows requested with `WindowSystem::request_window` together with their surfaces
+    /// (and applies a pending custom cursor)
+    fn create_pending_windows(&mut self, event_loop: &ActiveEventLoop) {
+        self.ctx.get_mut::<WindowSystem>().create_pending_cursor(event_loop);
+        let new_windows = self.ctx.get_mut::<WindowSystem>().create_pending_windows(event_loop);
+        for window in new_windows {
+            self.ctx.get_mut::<GraphicsSystem>().add_surface(window.clone());
+            self.ctx.flush().with(events::WindowCreated { window_id: window.id() });
+        }
+    }
+
+
+    /// Recreates the GPU device and everything that was created with the old one
+    fn reset_graphics_device(&mut self) {
+        self.ctx.get_mut::<GraphicsSystem>().reset_device();
+        self.ctx.get_mut::<AssetSystem>().reload_all();
+        // Resetting the camera also recreates all systems depending on it (the renderers)
+        self.ctx.flush().with(geese::notify::reset_system::<Camera>());
+        self.ctx.flush().with(events::DeviceReset);
     }
 }
-impl<AppSystem: GeeseSystem> ApplicationHandler for GranularEngine<AppSystem> {
+
+
+/// Raises `events::timing::FixedTick<millis>` (one of `FIXED_TICKS`)
+pub(crate) fn raise_fixed_tick(ctx: &mut GeeseContext, millis: u64) {
+    ctx.get_mut::<FrameInfo>().count_fixed_tick(millis);
+    match millis {
+        1000 => {ctx.flush().with(events::timing::FixedTick::<1000>);},
+        2500 => {ctx.flush().with(events::timing::FixedTick::<2500>);},
+        5000 => {ctx.flush().with(events::timing::FixedTick::<5000>);},
+        _ => ()
+    };
+}
+
+
+/// Raises the `events::timing::Tick<N>` that are due in this frame
+pub(crate) fn raise_frame_ticks(ctx: &mut GeeseContext, frame: u64) {
+    let mut buffer = geese::EventBuffer::default()
+        .with(events::timing::Tick::<1>);
+
+    let mut frame_info = ctx.get_mut::<FrameInfo>();
+    frame_info.count_tick(1);
+    if frame % 60 == 0 {
+        frame_info.count_tick(60);
+        buffer = buffer.with(events::timing::Tick::<60>);
+    };
+    if frame % 30 == 0 {
+        frame_info.count_tick(30);
+        buffer = buffer.with(events::timing::Tick::<30>);
+    };
+    if frame % 2 == 0 {
+        frame_info.count_tick(2);
+        buffer = buffer.with(events::timing::Tick::<2>);
+    };
+    drop(frame_info);
+
+    ctx.flush().with_buffer(buffer);
+}
+impl<AppSystem: GeeseSystem> ApplicationHandler<UserEvent> for GranularEngine<AppSystem> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         info!("Resumed!");
         {
@@ -143,41 +325,74 @@ impl<AppSystem: GeeseSystem> ApplicationHandler for GranularEngine<AppSystem> {
         self.ctx.flush()
             .with(geese::notify::add_system::<Renderer>())
             .with(geese::notify::add_system::<AssetSystem>())
+            .with_buffer(std::mem::take(&mut self.startup_systems));
+        for callback in std::mem::take(&mut self.ready_callbacks) {
+            callback(&mut self.ctx);
+        }
+        self.ctx.flush()
             .with(geese::notify::add_system::<AppSystem>())
             .with(events::Initialized{});
-        
+        self.graphics_initialized = true;
     }
 
 
     fn exiting(&mut self, event_loop: &ActiveEventLoop) {
         info!("Exiting...");
+        self.ctx.flush().with(events::Exiting);
+    }
+
+
+    /// The end of an event loop iteration (after the frame was rendered), the safe point to exit at
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        if !self.close_requested && self.ctx.get::<EventLoopSystem>().exit_requested() {
+            info!("Exit requested");
+            self.close_requested = true;
+            event_loop.exit();
+        };
     }
 
 
     fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: winit::event::StartCause) {
-        {
-            let mut input = self.ctx.get_mut::<InputSystem>();
-            input.reset_just_pressed();
-        }
-        self.update();
-        self.handle_scheduling();
-        self.frame += 1;
+        if self.graphics_initialized {
+            self.create_pending_windows(event_loop);
+            if self.ctx.get::<GraphicsSystem>().needs_reset() {
+                self.reset_graphics_device();
+            };
+        };
+        if self.latency_mode == LatencyMode::Throughput {
+            // The input received since the last update is reset only after this update has seen it
+            self.run_frame_update();
+            self.ctx.get_mut::<InputSystem>().reset_frame_state();
+        };
+    }
+
+
+    /// Raises events that were sent using an EventProxy
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: UserEvent) {
+        event.raise(&mut self.ctx);
     }
 
 
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
-        _window_id: WindowId,
+        window_id: WindowId,
         event: WindowEvent,
     ) {
         match event {
             WindowEvent::CloseRequested => {
-                event_loop.exit();
+                let is_main_window = window_id == self.ctx.get::<WindowSystem>().main_window_id();
+                if is_main_window {
+                    self.close_requested = true;
+                    event_loop.exit();
+                } else {
+                    self.ctx.get_mut::<GraphicsSystem>().remove_surface(window_id);
+                    self.ctx.get_mut::<WindowSystem>().remove_window(window_id);
+                };
             },
             WindowEvent::Resized(new_size) => {
                 let mut renderer = self.ctx.get_mut::<Renderer>();
-                renderer.resize(new_size);
+                renderer.resize(window_id, new_size);
                 #[cfg(target_os="macos")]
                 graphics.request_redraw();
             },
@@ -186,12 +401,27 @@ impl<AppSystem: GeeseSystem> ApplicationHandler for GranularEngine<AppSystem> {
                 input.update_modifiers(&modifiers);
             },
             WindowEvent::RedrawRequested => {
-                self.ctx.flush().with(events::Draw);
-                let mut renderer = self.ctx.get_mut::<Renderer>();
-                renderer.start_frame();
-                renderer.render();
-                renderer.end_frame();
-                renderer.request_redraw();
+                let is_main_window = window_id == self.ctx.get::<WindowSystem>().main_window_id();
+                if is_main_window {
+                    if self.latency_mode == LatencyMode::LowLatency {
+                        self.run_frame_update();
+                    };
+                    self.ctx.flush().with(events::phase::PreRender);
+                    self.ctx.flush().with(events::Draw);
+                    let mut renderer = self.ctx.get_mut::<Renderer>();
+                    renderer.start_frame(window_id);
+                    renderer.render();
+                    renderer.end_frame(window_id);
+                    renderer.request_redraw();
+                    drop(renderer);
+                    if self.latency_mode == LatencyMode::LowLatency {
+                        self.ctx.get_mut::<InputSystem>().reset_frame_state();
+                    };
+                } else {
+                    self.ctx.get_mut::<Renderer>().start_frame(window_id);
+                    self.ctx.flush().with(events::DrawWindow { window_id });
+                    self.ctx.get_mut::<Renderer>().end_frame(window_id);
+                };
             },
             WindowEvent::KeyboardInput{event, is_synthetic: false, ..} => {
                 let mut input = self.ctx.get_mut::<InputSystem>();
@@ -205,8 +435,9 @@ impl<AppSystem: GeeseSystem> ApplicationHandler for GranularEngine<AppSystem> {
                 let mut input = self.ctx.get_mut::<InputSystem>();
                 input.handle_mouse_input(button, state);
             },
-            WindowEvent::MouseWheel { device_id, delta, phase } => {
-
+            WindowEvent::MouseWheel { delta, .. } => {
+                let mut input = self.ctx.get_mut::<InputSystem>();
+                input.handle_mouse_wheel(delta);
             },
             
             
@@ -0,0 +1,210 @@
+use std::hash::Hasher;
+
+use glam::IVec2;
+use rustc_hash::FxHasher;
+
+use crate::{GRID_HEIGHT, GRID_WIDTH};
+
+use super::{grid::{BoundaryMode, CellGrid, GridPos}, material::{Material, MaterialId}};
+
+
+/// Offsets to the neighbouring cells, y points down like in GridPos
+pub const UP: IVec2 = IVec2::new(0, -1);
+pub const DOWN: IVec2 = IVec2::new(0, 1);
+pub const LEFT: IVec2 = IVec2::new(-1, 0);
+pub const RIGHT: IVec2 = IVec2::new(1, 0);
+
+
+/// The movement and reactions of a material, see `Material::with_behavior`
+pub trait CellBehavior: Send + Sync {
+    /// Called once per step for every cell of the material, cells are visited from the bottom row up
+    fn update(&self, cell: &mut CellUpdate);
+}
+
+
+/// A cell that is being updated by its CellBehavior, together with read access to its surroundings
+pub struct CellUpdate<'a> {
+    grid: &'a mut CellGrid,
+    materials: &'a [Material],
+    boundary: BoundaryMode,
+    pos: GridPos,
+    tick: u64,
+    removed: bool
+}
+impl<'a> CellUpdate<'a> {
+    pub(super) fn new(grid: &'a mut CellGrid, materials: &'a [Material], boundary: BoundaryMode, pos: GridPos, tick: u64) -> Self {
+        Self {
+            grid,
+            materials,
+            boundary,
+            pos,
+            tick,
+            removed: false
+        }
+    }
+
+
+    /// The current position of the cell, it changes when the cell moves
+    pub fn pos(&self) -> GridPos {
+        self.pos
+    }
+
+    pub fn material(&self) -> Option<MaterialId> {
+        self.grid.cell_at_pos(self.pos).and_then(|cell| cell.material())
+    }
+
+    pub fn temperature(&self) -> i16 {
+        self.grid.cell_at_pos(self.pos).map(|cell| cell.temperature()).unwrap_or_default()
+    }
+
+    /// Returns true once the cell has been removed, it cannot do anything else afterwards
+    pub fn is_removed(&self) -> bool {
+        self.removed
+    }
+
+
+    /// A pseudo random number, which is the same for the same position and tick, so the simulation stays deterministic
+    pub fn random(&self) -> u32 {
+        let mut hasher = FxHasher::default();
+        hasher.write_usize(self.pos.0);
+        hasher.write_usize(self.pos.1);
+        hasher.write_u64(self.tick);
+        (hasher.finish() >> 32) as u32
+    }
+
+
+    /// Returns true if the position at offset is free. Outside of the grid, only the Void boundary counts as free.
+    pub fn is_empty(&self, offset: IVec2) -> bool {
+        match self.neighbour(offset) {
+            Some(pos) => self.grid.cell_at_pos(pos).is_none(),
+            None => self.boundary == BoundaryMode::Void
+        }
+    }
+
+    /// Returns the material of the cell at offset, None if there is no cell or it has no material
+    pub fn material_at(&self, offset: IVec2) -> Option<MaterialId> {
+        self.neighbour(offset)
+            .and_then(|pos| self.grid.cell_at_pos(pos))
+            .and_then(|cell| cell.material())
+    }
+
+
+    /// Moves the cell by offset if the position is free. A cell moving down can also sink through lighter
+    /// cells and a cell moving up can rise through heavier ones (see `Material::density`), which then swap places.
+    /// Returns true if the cell moved (or fell out of a Void boundary and got removed).
+    pub fn try_move(&mut self, offset: IVec2) -> bool {
+        if self.removed || offset == IVec2::ZERO {
+            return false;
+        };
+        let Some(target) = self.neighbour(offset) else {
+            if self.boundary == BoundaryMode::Void {
+                self.remove();
+                return true;
+            };
+            return false;
+        };
+        if target == self.pos {
+            return false;
+        };
+        match self.grid.cell_at_pos(target) {
+            None => self.grid.move_cell(self.pos, target),
+            Some(other) => {
+                let displaces = match (self.density(self.material()), self.density(other.material())) {
+                    (Some(density), Some(other_density)) => (offset.y > 0 && density > other_density) || (offset.y < 0 && density < other_density),
+                    _ => false
+                };
+                if !displaces {
+                    return false;
+                };
+                self.grid.swap_cells(self.pos, target);
+            }
+        };
+        self.pos = target;
+        true
+    }
+
+
+    /// Turns the cell into another material, keeping its temperature
+    pub fn set_material(&mut self, material: MaterialId) {
+        if self.removed {
+            return;
+        };
+        match self.materials.get(material as usize) {
+            Some(properties) => self.grid.change_material(self.pos, material, properties.color),
+            None => log::warn!("CellUpdate::set_material: Unknown material {}", material)
+        };
+    }
+
+
+    pub fn remove(&mut self) {
+        if !self.removed {
+            self.grid.remove_cell_at_pos(self.pos);
+            self.removed = true;
+        };
+    }
+
+
+    /// The position at offset, wrapped around with the Wrap boundary, None if it lies outside of the grid
+    fn neighbour(&self, offset: IVec2) -> Option<GridPos> {
+        let (x, y) = (self.pos.0 as i64 + offset.x as i64, self.pos.1 as i64 + offset.y as i64);
+        if x >= 0 && x < GRID_WIDTH as i64 && y >= 0 && y < GRID_HEIGHT as i64 {
+            return Some((x as usize, y as usize));
+        };
+        match self.boundary {
+            BoundaryMode::Wrap => Some((x.rem_euclid(GRID_WIDTH as i64) as usize, y.rem_euclid(GRID_HEIGHT as i64) as usize)),
+            BoundaryMode::Solid | BoundaryMode::Void => None
+        }
+    }
+
+    /// Cells without a material cannot be displaced and cannot displace others
+    fn density(&self, material: Option<MaterialId>) -> Option<f32> {
+        material.and_then(|id| self.materials.get(id as usize)).map(|material| material.density)
+    }
+}
+
+
+
+/// Falls straight down or diagonally, piling up like sand
+pub struct PowderBehavior;
+impl CellBehavior for PowderBehavior {
+    fn update(&self, cell: &mut CellUpdate) {
+        let (first, second) = match cell.random() % 2 {
+            0 => (LEFT, RIGHT),
+            _ => (RIGHT, LEFT)
+        };
+        let _ = cell.try_move(DOWN) || cell.try_move(DOWN + first) || cell.try_move(DOWN + second);
+    }
+}
+
+
+/// Falls like a powder, but also flows sideways, so it spreads out flat
+pub struct LiquidBehavior;
+impl CellBehavior for LiquidBehavior {
+    fn update(&self, cell: &mut CellUpdate) {
+        let (first, second) = match cell.random() % 2 {
+            0 => (LEFT, RIGHT),
+            _ => (RIGHT, LEFT)
+        };
+        let _ = cell.try_move(DOWN) || cell.try_move(DOWN + first) || cell.try_move(DOWN + second)
+            || cell.try_move(first) || cell.try_move(second);
+    }
+}
+
+
+/// Rises and flickers sideways, each step it burns out with a chance of 1 in burnout_chance
+pub struct FireBehavior {
+    pub burnout_chance: u32
+}
+impl CellBehavior for FireBehavior {
+    fn update(&self, cell: &mut CellUpdate) {
+        if cell.random() % self.burnout_chance.max(1) == 0 {
+            cell.remove();
+            return;
+        };
+        let side = match (cell.random() >> 8) % 2 {
+            0 => LEFT,
+            _ => RIGHT
+        };
+        let _ = cell.try_move(UP + side) || cell.try_move(UP) || cell.try_move(side);
+    }
+}
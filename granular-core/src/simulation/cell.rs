@@ -1,6 +1,6 @@
 use palette::Srgba;
 
-use super::grid::GridPos;
+use super::{grid::GridPos, material::MaterialId};
 
 
 pub type CellColor = Srgba<u8>;
@@ -9,13 +9,28 @@ pub type CellColor = Srgba<u8>;
 #[derive(Debug, PartialEq)]
 pub struct Cell {
     pos: GridPos,
-    color: CellColor // Sand: CellColor::new(221, 193, 48, 255)
+    color: CellColor, // Sand: CellColor::new(221, 193, 48, 255)
+    /// None for plain colored cells, which take no part in the heat simulation
+    material: Option<MaterialId>,
+    temperature: i16
 }
 impl Cell {
     pub fn new(pos: GridPos, color: CellColor) -> Self {
         Self {
             pos,
-            color
+            color,
+            material: None,
+            temperature: 0
+        }
+    }
+
+    /// Creates a cell of a material, see `Simulation::set_material_cell`
+    pub fn with_material(pos: GridPos, color: CellColor, material: MaterialId, temperature: i16) -> Self {
+        Self {
+            pos,
+            color,
+            material: Some(material),
+            temperature
         }
     }
 
@@ -23,8 +38,30 @@ impl Cell {
         self.pos
     }
 
+    pub(super) fn set_pos(&mut self, pos: GridPos) {
+        self.pos = pos;
+    }
+
     pub fn color(&self) -> &CellColor {
         &self.color
     }
+
+    pub fn material(&self) -> Option<MaterialId> {
+        self.material
+    }
+
+    pub fn temperature(&self) -> i16 {
+        self.temperature
+    }
+
+    pub(super) fn set_temperature(&mut self, temperature: i16) {
+        self.temperature = temperature;
+    }
+
+    /// Changes the material (and with it the color) of the cell, keeping its temperature
+    pub(super) fn set_material(&mut self, material: MaterialId, color: CellColor) {
+        self.material = Some(material);
+        self.color = color;
+    }
 }
 impl Eq for Cell {}
\ No newline at end of file
@@ -1,6 +1,80 @@
 use glam::IVec2;
 
+use super::{GridPos, CHUNK_HEIGHT, CHUNK_WIDTH};
+
+
+const CHUNK_SIZE: IVec2 = IVec2::new(CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
+
+
 #[derive(Debug)]
 pub struct Chunk {
     pub position: IVec2
-}
\ No newline at end of file
+}
+impl Chunk {
+    /// The world position of the chunks corner with the lowest coordinates
+    pub fn world_position(&self) -> IVec2 {
+        chunk_to_world(self.position)
+    }
+}
+
+
+/// Returns the position of the chunk containing the world position. Negative positions are rounded
+/// towards negative infinity, so (-1, -1) is inside of chunk (-1, -1) and not (0, 0).
+pub fn world_to_chunk(pos: IVec2) -> IVec2 {
+    pos.div_euclid(CHUNK_SIZE)
+}
+
+
+/// Returns the world position of the chunks corner with the lowest coordinates
+pub fn chunk_to_world(chunk_pos: IVec2) -> IVec2 {
+    chunk_pos * CHUNK_SIZE
+}
+
+
+/// Splits the world position into the position of its chunk and the offset inside of that chunk
+pub fn world_to_local(pos: IVec2) -> (IVec2, GridPos) {
+    let offset = pos.rem_euclid(CHUNK_SIZE);
+    (world_to_chunk(pos), (offset.x as usize, offset.y as usize))
+}
+
+
+/// Index of the slot a chunk is stored in when num_chunks * num_chunks chunks are loaded.
+/// Chunks that are num_chunks apart share the same slot, which lets the loaded area move without shifting.
+pub(super) fn chunk_slot(chunk_pos: IVec2, num_chunks: i32) -> usize {
+    let halfsize = num_chunks / 2;
+    let slot = (chunk_pos + IVec2::splat(halfsize)).rem_euclid(IVec2::splat(num_chunks));
+    (slot.y * num_chunks + slot.x) as usize
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_positions_round_down() {
+        assert_eq!(world_to_chunk(IVec2::new(0, 0)), IVec2::new(0, 0));
+        assert_eq!(world_to_chunk(IVec2::new(-1, -1)), IVec2::new(-1, -1));
+        assert_eq!(world_to_chunk(CHUNK_SIZE - IVec2::ONE), IVec2::new(0, 0));
+        assert_eq!(world_to_chunk(CHUNK_SIZE), IVec2::new(1, 1));
+        assert_eq!(world_to_chunk(-CHUNK_SIZE), IVec2::new(-1, -1));
+        assert_eq!(world_to_chunk(-CHUNK_SIZE - IVec2::ONE), IVec2::new(-2, -2));
+
+        assert_eq!(world_to_local(IVec2::new(-1, -1)), (IVec2::new(-1, -1), (CHUNK_WIDTH - 1, CHUNK_HEIGHT - 1)));
+        assert_eq!(world_to_local(IVec2::new(CHUNK_WIDTH as i32 + 3, -2)), (IVec2::new(1, -1), (3, CHUNK_HEIGHT - 2)));
+    }
+
+    #[test]
+    fn round_trips_across_chunk_boundaries() {
+        for x in [-2 * CHUNK_SIZE.x - 1, -CHUNK_SIZE.x, -1, 0, 1, CHUNK_SIZE.x - 1, CHUNK_SIZE.x, 3 * CHUNK_SIZE.x + 5] {
+            for y in [-CHUNK_SIZE.y - 1, -1, 0, CHUNK_SIZE.y, 2 * CHUNK_SIZE.y - 1] {
+                let pos = IVec2::new(x, y);
+                let (chunk, local) = world_to_local(pos);
+                assert!(local.0 < CHUNK_WIDTH && local.1 < CHUNK_HEIGHT);
+                assert_eq!(chunk_to_world(chunk) + IVec2::new(local.0 as i32, local.1 as i32), pos);
+                assert_eq!(world_to_chunk(chunk_to_world(chunk)), chunk);
+            }
+        }
+    }
+}
@@ -1,21 +1,57 @@
-use log::debug;
+use log::{debug, warn};
+use rustc_hash::FxHashMap as HashMap;
 
 use crate::{GRID_HEIGHT, GRID_WIDTH};
 
-use super::cell::{Cell, CellColor};
+use super::{behavior::CellUpdate, cell::{Cell, CellColor}, material::{Material, MaterialId}};
 
 pub type GridPos = (usize, usize);
 pub const EMPTY_CELL_IDX: usize = 0;
+/// Index into the grids color palette, stored once per cell in the texture data
+pub type PaletteIndex = u8;
+pub const EMPTY_PALETTE_IDX: PaletteIndex = 0;
+pub const MAX_PALETTE_SIZE: usize = 256;
 
 const TOTAL_NUM_CELLS: usize = GRID_WIDTH * GRID_HEIGHT;
 
+
+/// What happens to cells that would move past the edge of the grid, see `Simulation::set_boundary`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryMode {
+    /// The edges act like walls, cells pile up on the bottom row
+    #[default]
+    Solid,
+    /// Cells that move past an edge disappear. This includes cells at the left or right edge that slide
+    /// diagonally past it because the cell below them is occupied, not only cells falling out of the bottom.
+    Void,
+    /// Cells that move past an edge come back in on the opposite side
+    Wrap
+}
+
 // https://github.com/ARez2/FallingRust/blob/main/src/matrix.rs
 
 pub struct CellGrid {
     cells: Vec<Cell>,
     grid: Vec<usize>,
     
-    texture_data: Vec<u8>
+    /// All colors that are currently in use, the first entry is always the empty cell color
+    palette: Vec<CellColor>,
+    /// The palette index of each color (by its channels), so writing a color does not search the palette
+    palette_lookup: HashMap<[u8; 4], PaletteIndex>,
+    /// How many cells use each palette entry, entries no cell uses get reused once the palette is full
+    palette_refs: Vec<u32>,
+    /// Increased whenever an entry is added to the palette or replaced
+    palette_version: u64,
+    /// Whether the palette overflowed since an entry could last be added, so the warning is only logged once
+    palette_overflowed: bool,
+    /// One palette index per cell
+    texture_data: Vec<PaletteIndex>,
+    /// Bounding box (min, max, both inclusive) of all cells whose color changed since the last upload
+    dirty_region: Option<(GridPos, GridPos)>,
+    /// Grid indices of all cells that changed since the last take_changed_cells, None while nobody needs them
+    changed_cells: Option<Vec<usize>>,
+    /// One entry per grid position, true if a cell moved there during the current step (so it is not updated twice)
+    updated: Vec<bool>
 }
 impl CellGrid {
     // Creates a new empty CellGrid. You can also use `CellGrid::empty()`
@@ -25,21 +61,132 @@ impl CellGrid {
 
     // Creates a new empty CellGrid.
     pub fn empty() -> Self {
-        let mut texture_data = Vec::with_capacity(4 * TOTAL_NUM_CELLS);
-        texture_data.resize_with(4 * TOTAL_NUM_CELLS, || 0);
+        let mut texture_data = Vec::with_capacity(TOTAL_NUM_CELLS);
+        texture_data.resize_with(TOTAL_NUM_CELLS, || EMPTY_PALETTE_IDX);
         let mut grid = Vec::with_capacity(TOTAL_NUM_CELLS);
         grid.resize_with(TOTAL_NUM_CELLS, || 0);
+        let empty_color = CellColor::new(0, 0, 0, 0);
         Self {
-            cells: vec![Cell::new((0, 0), CellColor::new(0, 0, 0, 0))],
+            cells: vec![Cell::new((0, 0), empty_color)],
             grid,
-            texture_data
+            palette: vec![empty_color],
+            palette_lookup: HashMap::from_iter([(Self::palette_key(&empty_color), EMPTY_PALETTE_IDX)]),
+            palette_refs: vec![0],
+            palette_version: 0,
+            palette_overflowed: false,
+            texture_data,
+            dirty_region: None,
+            changed_cells: None,
+            updated: vec![false; TOTAL_NUM_CELLS]
         }
     }
 
-    pub(super) fn get_texture_data(&self) -> &[u8] {
+    /// Returns one palette index per cell, see `get_palette` for the colors
+    pub(super) fn get_texture_data(&self) -> &[PaletteIndex] {
         &self.texture_data
     }
 
+    /// Returns all colors referenced by the texture data. Entries can be replaced once the palette
+    /// is full, use `get_palette_version` to know if it has changed.
+    pub(super) fn get_palette(&self) -> &[CellColor] {
+        &self.palette
+    }
+
+    /// Changes whenever an entry of the palette is added or replaced
+    pub(super) fn get_palette_version(&self) -> u64 {
+        self.palette_version
+    }
+
+
+    /// Returns the region of the texture data that changed since the last call and resets it
+    pub(super) fn take_dirty_region(&mut self) -> Option<(GridPos, GridPos)> {
+        self.dirty_region.take()
+    }
+
+
+    /// Starts or stops recording the individual cells that changed, see take_changed_cells
+    pub(super) fn set_track_changes(&mut self, track: bool) {
+        match (track, &self.changed_cells) {
+            (true, None) => self.changed_cells = Some(vec![]),
+            (false, Some(_)) => self.changed_cells = None,
+            _ => ()
+        };
+    }
+
+
+    /// Returns the positions of all cells that changed since the last call (can contain duplicates), empty if not tracking
+    pub(super) fn take_changed_cells(&mut self) -> Vec<GridPos> {
+        match &mut self.changed_cells {
+            Some(changed) => changed.drain(..).map(|grid_idx| (grid_idx % GRID_WIDTH, grid_idx / GRID_WIDTH)).collect(),
+            None => vec![]
+        }
+    }
+
+
+    /// Expands the dirty region so it includes the cell at grid_idx
+    fn mark_dirty(&mut self, grid_idx: usize) {
+        if let Some(changed) = &mut self.changed_cells {
+            changed.push(grid_idx);
+        };
+        let pos = (grid_idx % GRID_WIDTH, grid_idx / GRID_WIDTH);
+        self.dirty_region = match self.dirty_region {
+            None => Some((pos, pos)),
+            Some((min, max)) => Some((
+                (min.0.min(pos.0), min.1.min(pos.1)),
+                (max.0.max(pos.0), max.1.max(pos.1))
+            ))
+        };
+    }
+
+
+    fn palette_key(color: &CellColor) -> [u8; 4] {
+        [color.red, color.green, color.blue, color.alpha]
+    }
+
+
+    /// Returns the palette index of that color, adding it to the palette if it is new. Once the palette
+    /// is full, an entry that no cell uses anymore gets replaced. If every entry is in use, the cell
+    /// is drawn with the empty color.
+    fn palette_index(&mut self, color: &CellColor) -> PaletteIndex {
+        let key = Self::palette_key(color);
+        if let Some(idx) = self.palette_lookup.get(&key) {
+            return *idx;
+        };
+        let idx = if self.palette.len() < MAX_PALETTE_SIZE {
+            self.palette.push(*color);
+            self.palette_refs.push(0);
+            self.palette.len() - 1
+        } else {
+            let Some(unused) = (1..self.palette.len()).find(|idx| self.palette_refs[*idx] == 0) else {
+                if !self.palette_overflowed {
+                    warn!("Cell palette is full ({} colors in use), new colors are drawn as empty cells, starting with {:?}", MAX_PALETTE_SIZE, color);
+                    self.palette_overflowed = true;
+                };
+                return EMPTY_PALETTE_IDX;
+            };
+            self.palette_lookup.remove(&Self::palette_key(&self.palette[unused]));
+            self.palette[unused] = *color;
+            unused
+        };
+        self.palette_overflowed = false;
+        self.palette_lookup.insert(key, idx as PaletteIndex);
+        self.palette_version += 1;
+        idx as PaletteIndex
+    }
+
+
+    /// Points the cell at grid_idx to a palette entry, keeping track of how many cells use each entry
+    fn set_palette_index(&mut self, grid_idx: usize, idx: PaletteIndex) {
+        let old_idx = self.texture_data[grid_idx];
+        if old_idx != EMPTY_PALETTE_IDX {
+            self.palette_refs[old_idx as usize] -= 1;
+        };
+        if idx != EMPTY_PALETTE_IDX {
+            self.palette_refs[idx as usize] += 1;
+        };
+        self.texture_data[grid_idx] = idx;
+    }
+
 
     /// Converts the position into an index to be used in self.data
     #[inline]
@@ -70,18 +217,16 @@ impl CellGrid {
 
 
     fn set_color_at_grididx(&mut self, grid_idx: usize, color: &CellColor) {
-        self.texture_data[grid_idx * 4 + 0] = color.red;
-        self.texture_data[grid_idx * 4 + 1] = color.green;
-        self.texture_data[grid_idx * 4 + 2] = color.blue;
-        self.texture_data[grid_idx * 4 + 3] = color.alpha;
+        // Release the old entry first, so a full palette can reuse it for the new color
+        self.set_palette_index(grid_idx, EMPTY_PALETTE_IDX);
+        let idx = self.palette_index(color);
+        self.set_palette_index(grid_idx, idx);
+        self.mark_dirty(grid_idx);
     }
 
     fn set_color_at_grididx_empty(&mut self, grid_idx: usize) {
-        let empty_col = self.cells[0].color();
-        self.texture_data[grid_idx * 4 + 0] = empty_col.red;
-        self.texture_data[grid_idx * 4 + 1] = empty_col.green;
-        self.texture_data[grid_idx * 4 + 2] = empty_col.blue;
-        self.texture_data[grid_idx * 4 + 3] = empty_col.alpha;
+        self.set_palette_index(grid_idx, EMPTY_PALETTE_IDX);
+        self.mark_dirty(grid_idx);
     }
 
 
@@ -119,8 +264,8 @@ impl CellGrid {
         self.set_color_at_grididx_empty(grid_idx);
         //self.set_color_at_grididx(grid_idx, self.cells[0].color());
         // If our cell is at the back of the cells, then we can remove it normally
-        if cell_index == self.cells.len() {
-            self.cells.remove(cell_index);
+        if cell_index == self.cells.len() - 1 {
+            self.cells.pop();
             return;
         };
         // If not, we need to do a swap remove
@@ -131,4 +276,261 @@ impl CellGrid {
             self.cells.swap_remove(cell_index);
         };
     }
-}
\ No newline at end of file
+
+
+    /// Returns the color of the cell at that position, None if the position is empty
+    pub fn color_at_pos(&self, pos: GridPos) -> Option<&CellColor> {
+        let cell_index = self.grid[self.grid_idx(pos)];
+        if cell_index == EMPTY_CELL_IDX {
+            None
+        } else {
+            Some(self.get_cell_from_cellidx(cell_index).color())
+        }
+    }
+
+
+    /// Returns the cell at that position, None if the position is empty
+    pub fn cell_at_pos(&self, pos: GridPos) -> Option<&Cell> {
+        match self.grid[self.grid_idx(pos)] {
+            EMPTY_CELL_IDX => None,
+            cell_index => Some(self.get_cell_from_cellidx(cell_index))
+        }
+    }
+
+    pub(super) fn cell_at_pos_mut(&mut self, pos: GridPos) -> Option<&mut Cell> {
+        match self.grid[self.grid_idx(pos)] {
+            EMPTY_CELL_IDX => None,
+            cell_index => Some(self.get_cell_from_cellidx_mut(cell_index))
+        }
+    }
+
+
+    /// Moves the cell at `from` to the (empty) position `to`
+    pub(super) fn move_cell(&mut self, from: GridPos, to: GridPos) {
+        let from_idx = self.grid_idx(from);
+        let to_idx = self.grid_idx(to);
+        let cell_index = self.grid[from_idx];
+        self.grid[to_idx] = cell_index;
+        self.grid[from_idx] = EMPTY_CELL_IDX;
+        self.updated[to_idx] = true;
+        let cell = self.get_cell_from_cellidx_mut(cell_index);
+        cell.set_pos(to);
+        let color = *cell.color();
+        self.set_color_at_grididx(to_idx, &color);
+        self.set_color_at_grididx_empty(from_idx);
+    }
+
+
+    /// Swaps the (non-empty) cells at a and b
+    pub(super) fn swap_cells(&mut self, a: GridPos, b: GridPos) {
+        let (a_idx, b_idx) = (self.grid_idx(a), self.grid_idx(b));
+        self.grid.swap(a_idx, b_idx);
+        for (grid_idx, pos) in [(a_idx, a), (b_idx, b)] {
+            self.updated[grid_idx] = true;
+            let cell_index = self.grid[grid_idx];
+            let cell = self.get_cell_from_cellidx_mut(cell_index);
+            cell.set_pos(pos);
+            let color = *cell.color();
+            self.set_color_at_grididx(grid_idx, &color);
+        }
+    }
+
+
+    /// Changes the material and color of the cell at pos, keeping its temperature
+    pub(super) fn change_material(&mut self, pos: GridPos, material: MaterialId, color: CellColor) {
+        let grid_idx = self.grid_idx(pos);
+        let Some(cell) = self.cell_at_pos_mut(pos) else {
+            return;
+        };
+        cell.set_material(material, color);
+        self.set_color_at_grididx(grid_idx, &color);
+    }
+
+
+    /// Advances the grid by one tick. Cells whose material has a behavior (see `Material::with_behavior`) are
+    /// updated by it, all other cells fall down by one, or slide diagonally if the cell below is occupied.
+    /// What happens at the edges of the grid depends on boundary.
+    pub(super) fn step(&mut self, boundary: BoundaryMode, materials: &[Material], tick: u64) {
+        self.updated.fill(false);
+        // Cells that wrap from the bottom to the top row, they are moved after all other cells,
+        // since the top row is handled last
+        let mut wrapped = vec![];
+        // Going from the bottom up ensures that no falling cell gets moved twice in the same step,
+        // cells that move up or sideways are kept from moving again by self.updated
+        for y in (0..GRID_HEIGHT).rev() {
+            for x in 0..GRID_WIDTH {
+                let grid_idx = self.grid_idx((x, y));
+                let cell_index = self.grid[grid_idx];
+                if cell_index == EMPTY_CELL_IDX || self.updated[grid_idx] {
+                    continue;
+                };
+                let behavior = self.cells[cell_index].material()
+                    .and_then(|id| materials.get(id as usize))
+                    .and_then(|material| material.behavior.as_deref());
+                match behavior {
+                    Some(behavior) => behavior.update(&mut CellUpdate::new(self, materials, boundary, (x, y), tick)),
+                    None => self.fall((x, y), boundary, &mut wrapped)
+                };
+            }
+        }
+        for (from, to) in wrapped {
+            if self.grid[self.grid_idx(to)] == EMPTY_CELL_IDX {
+                self.move_cell(from, to);
+            };
+        }
+    }
+
+
+    /// The default movement of cells: down, down left or down right, whichever is free first
+    fn fall(&mut self, (x, y): GridPos, boundary: BoundaryMode, wrapped: &mut Vec<(GridPos, GridPos)>) {
+        let (ix, below) = (x as i64, y as i64 + 1);
+        for (target_x, target_y) in [(ix, below), (ix - 1, below), (ix + 1, below)] {
+            let inside = target_x >= 0 && target_x < GRID_WIDTH as i64 && target_y < GRID_HEIGHT as i64;
+            let target = match (inside, boundary) {
+                (true, _) => (target_x as usize, target_y as usize),
+                (false, BoundaryMode::Solid) => continue,
+                (false, BoundaryMode::Void) => {
+                    self.remove_cell_at_pos((x, y));
+                    break;
+                },
+                (false, BoundaryMode::Wrap) => {
+                    let target = (target_x.rem_euclid(GRID_WIDTH as i64) as usize, target_y.rem_euclid(GRID_HEIGHT as i64) as usize);
+                    if target_y >= GRID_HEIGHT as i64 {
+                        wrapped.push(((x, y), target));
+                        break;
+                    };
+                    target
+                }
+            };
+            if self.grid[self.grid_idx(target)] == EMPTY_CELL_IDX {
+                self.move_cell((x, y), target);
+                break;
+            };
+        }
+    }
+
+
+    /// Spreads heat between neighbouring cells that have a material, then applies the phase changes
+    /// of all cells whose temperature crossed a threshold. Only the cells themselves are visited,
+    /// so empty parts of the grid cost nothing.
+    pub(super) fn update_temperatures(&mut self, materials: &[Material]) {
+        // The heat flowing into each cell, collected first so the order of the cells does not matter
+        let mut deltas = vec![0i32; self.cells.len()];
+        for cell_index in 1..self.cells.len() {
+            let cell = &self.cells[cell_index];
+            let Some(material) = cell.material().and_then(|id| materials.get(id as usize)) else {
+                continue;
+            };
+            // Only look right and down, so every pair of neighbours is handled once
+            let (x, y) = cell.pos();
+            for neighbour in [(x + 1, y), (x, y + 1)] {
+                if neighbour.0 >= GRID_WIDTH || neighbour.1 >= GRID_HEIGHT {
+                    continue;
+                };
+                let neighbour_index = self.grid[self.grid_idx(neighbour)];
+                if neighbour_index == EMPTY_CELL_IDX {
+                    continue;
+                };
+                let other = &self.cells[neighbour_index];
+                let Some(other_material) = other.material().and_then(|id| materials.get(id as usize)) else {
+                    continue;
+                };
+                let conductivity = material.conductivity.min(other_material.conductivity);
+                // A cell has at most 4 neighbours, so giving each at most a quarter of the difference never overshoots
+                let flow = ((other.temperature() as i32 - cell.temperature() as i32) as f32 * conductivity * 0.25) as i32;
+                deltas[cell_index] += flow;
+                deltas[neighbour_index] -= flow;
+            }
+        }
+
+        for cell_index in 1..self.cells.len() {
+            let cell = &mut self.cells[cell_index];
+            let Some(material) = cell.material().and_then(|id| materials.get(id as usize)) else {
+                continue;
+            };
+            let temperature = (cell.temperature() as i32 + deltas[cell_index]).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            cell.set_temperature(temperature);
+            let Some(into) = material.phase_change(temperature) else {
+                continue;
+            };
+            let Some(new_material) = materials.get(into as usize) else {
+                warn!("Material '{}' turns into the unknown material {}", material.name, into);
+                continue;
+            };
+            let pos = cell.pos();
+            self.change_material(pos, into, new_material.color);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(i: usize) -> CellColor {
+        CellColor::new(i as u8, (i >> 8) as u8, 100, 255)
+    }
+
+    fn palette_index_at(grid: &CellGrid, pos: GridPos) -> PaletteIndex {
+        grid.get_texture_data()[grid.grid_idx(pos)]
+    }
+
+
+    #[test]
+    fn same_color_shares_palette_entry() {
+        let mut grid = CellGrid::empty();
+        grid.place_cell(Cell::new((0, 0), color(1)));
+        grid.place_cell(Cell::new((1, 0), color(1)));
+        grid.place_cell(Cell::new((2, 0), color(2)));
+        assert_eq!(grid.get_palette().len(), 3);
+        assert_eq!(palette_index_at(&grid, (0, 0)), palette_index_at(&grid, (1, 0)));
+        assert_ne!(palette_index_at(&grid, (0, 0)), palette_index_at(&grid, (2, 0)));
+
+        grid.move_cell((2, 0), (2, 1));
+        assert_eq!(palette_index_at(&grid, (2, 0)), EMPTY_PALETTE_IDX);
+        assert_eq!(grid.get_palette()[palette_index_at(&grid, (2, 1)) as usize], color(2));
+    }
+
+    #[test]
+    fn dirty_region_covers_changed_cells() {
+        let mut grid = CellGrid::empty();
+        assert_eq!(grid.take_dirty_region(), None);
+
+        grid.place_cell(Cell::new((7, 3), color(1)));
+        assert_eq!(grid.take_dirty_region(), Some(((7, 3), (7, 3))));
+        assert_eq!(grid.take_dirty_region(), None);
+
+        grid.place_cell(Cell::new((9, 8), color(1)));
+        grid.place_cell(Cell::new((2, 5), color(2)));
+        assert_eq!(grid.take_dirty_region(), Some(((2, 5), (9, 8))));
+
+        // Moving a cell touches both positions
+        grid.move_cell((2, 5), (2, 6));
+        assert_eq!(grid.take_dirty_region(), Some(((2, 5), (2, 6))));
+    }
+
+    #[test]
+    fn full_palette_reuses_unused_entries() {
+        let mut grid = CellGrid::empty();
+        // The empty color takes the first entry
+        for i in 1..MAX_PALETTE_SIZE {
+            grid.place_cell(Cell::new((i, 0), color(i)));
+        }
+        assert_eq!(grid.get_palette().len(), MAX_PALETTE_SIZE);
+
+        // Every entry is in use, so the new color cannot be shown
+        grid.place_cell(Cell::new((0, 1), color(MAX_PALETTE_SIZE)));
+        assert_eq!(palette_index_at(&grid, (0, 1)), EMPTY_PALETTE_IDX);
+
+        // Once no cell uses a color anymore, its entry gets replaced
+        let freed = palette_index_at(&grid, (5, 0));
+        grid.remove_cell_at_pos((5, 0));
+        let version = grid.get_palette_version();
+        grid.place_cell(Cell::new((1, 1), color(MAX_PALETTE_SIZE + 1)));
+        assert_eq!(palette_index_at(&grid, (1, 1)), freed);
+        assert_eq!(grid.get_palette()[freed as usize], color(MAX_PALETTE_SIZE + 1));
+        assert_ne!(grid.get_palette_version(), version);
+        assert_eq!(grid.get_palette().len(), MAX_PALETTE_SIZE);
+    }
+}
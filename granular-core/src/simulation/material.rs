@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use super::{behavior::{CellBehavior, FireBehavior, LiquidBehavior, PowderBehavior}, cell::CellColor};
+
+
+/// Index of a material inside of the simulations material table, see `Simulation::register_material`
+pub type MaterialId = u16;
+
+/// The built-in materials, which every Simulation registers first
+pub const MATERIAL_SAND: MaterialId = 0;
+pub const MATERIAL_WATER: MaterialId = 1;
+pub const MATERIAL_FIRE: MaterialId = 2;
+
+
+/// Turns a cell into another material once its temperature crosses the threshold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseChange {
+    pub temperature: i16,
+    pub into: MaterialId
+}
+
+
+/// The properties shared by all cells of a material. Cells without a material (placed with `Simulation::set_cell`)
+/// keep their temperature, are skipped by the heat simulation and fall like sand.
+#[derive(Clone)]
+pub struct Material {
+    pub name: String,
+    pub color: CellColor,
+    /// The temperature new cells of this material start with
+    pub temperature: i16,
+    /// How much heat is exchanged with neighbouring cells each step, from 0.0 (insulator) to 1.0.
+    /// Two cells exchange heat at the lower conductivity of both.
+    pub conductivity: f32,
+    /// Applied when the temperature reaches or exceeds the threshold (for example ice melting into water)
+    pub heated_into: Option<PhaseChange>,
+    /// Applied when the temperature reaches or falls below the threshold (for example water freezing into ice)
+    pub cooled_into: Option<PhaseChange>,
+    /// Heavier cells sink through lighter ones and lighter ones rise through heavier ones, see `CellUpdate::try_move`
+    pub density: f32,
+    /// Moves the cells of this material each step, without one they fall like sand
+    pub behavior: Option<Arc<dyn CellBehavior>>
+}
+impl Material {
+    /// A material without phase changes that does not conduct any heat
+    pub fn new(name: &str, color: CellColor) -> Self {
+        Self {
+            name: name.to_string(),
+            color,
+            temperature: 0,
+            conductivity: 0.0,
+            heated_into: None,
+            cooled_into: None,
+            density: 1.0,
+            behavior: None
+        }
+    }
+
+
+    /// The materials every Simulation starts with, in the order of MATERIAL_SAND, MATERIAL_WATER and MATERIAL_FIRE
+    pub(super) fn built_in() -> Vec<Material> {
+        vec![
+            Material::new("sand", CellColor::new(221, 193, 48, 255))
+                .with_density(1.6)
+                .with_behavior(PowderBehavior),
+            Material::new("water", CellColor::new(40, 110, 220, 255))
+                .with_density(1.0)
+                .with_behavior(LiquidBehavior),
+            Material::new("fire", CellColor::new(240, 100, 20, 255))
+                .with_temperature(800)
+                .with_density(0.1)
+                .with_behavior(FireBehavior { burnout_chance: 20 })
+        ]
+    }
+
+
+    pub fn with_temperature(mut self, temperature: i16) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn with_conductivity(mut self, conductivity: f32) -> Self {
+        self.conductivity = conductivity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_density(mut self, density: f32) -> Self {
+        self.density = density;
+        self
+    }
+
+    pub fn with_behavior(mut self, behavior: impl CellBehavior + 'static) -> Self {
+        self.behavior = Some(Arc::new(behavior));
+        self
+    }
+
+    /// The material turns into `into` at or above that temperature
+    pub fn heated_into(mut self, temperature: i16, into: MaterialId) -> Self {
+        self.heated_into = Some(PhaseChange { temperature, into });
+        self
+    }
+
+    /// The material turns into `into` at or below that temperature
+    pub fn cooled_into(mut self, temperature: i16, into: MaterialId) -> Self {
+        self.cooled_into = Some(PhaseChange { temperature, into });
+        self
+    }
+
+
+    /// Whether cells of this material take part in the heat simulation in any way
+    pub(super) fn uses_heat(&self) -> bool {
+        self.conductivity > 0.0 || self.heated_into.is_some() || self.cooled_into.is_some()
+    }
+
+
+    /// Returns the material a cell of this material turns into at that temperature, None if it stays the same
+    pub(super) fn phase_change(&self, temperature: i16) -> Option<MaterialId> {
+        match (self.heated_into, self.cooled_into) {
+            (Some(heated), _) if temperature >= heated.temperature => Some(heated.into),
+            (_, Some(cooled)) if temperature <= cooled.temperature => Some(cooled.into),
+            _ => None
+        }
+    }
+}
+impl std::fmt::Debug for Material {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Material")
+            .field("name", &self.name)
+            .field("color", &self.color)
+            .field("temperature", &self.temperature)
+            .field("conductivity", &self.conductivity)
+            .field("heated_into", &self.heated_into)
+            .field("cooled_into", &self.cooled_into)
+            .field("density", &self.density)
+            .field("behavior", &self.behavior.is_some())
+            .finish()
+    }
+}
@@ -1,97 +1,699 @@
+use std::time::Duration;
+
 use geese::{GeeseContextHandle, GeeseSystem};
 
 
 mod grid;
 use glam::IVec2;
-use grid::CellGrid;
-use log::info;
+use grid::{CellGrid, PaletteIndex};
+pub use grid::{BoundaryMode, GridPos, MAX_PALETTE_SIZE};
+use log::{info, warn};
 use palette::Srgba;
 
+use crate::TimeSystem;
+
 pub(self) mod cell;
+use cell::Cell;
+pub use cell::CellColor;
+pub(self) mod material;
+pub use material::{Material, MaterialId, PhaseChange, MATERIAL_FIRE, MATERIAL_SAND, MATERIAL_WATER};
+pub(self) mod behavior;
+pub use behavior::{CellBehavior, CellUpdate, FireBehavior, LiquidBehavior, PowderBehavior};
 pub(self) mod chunk;
-use chunk::Chunk;
+use chunk::chunk_slot;
+pub use chunk::{chunk_to_world, world_to_chunk, world_to_local, Chunk};
+
+pub mod events {
+    /// Raised by `Simulation::step` when a cell inside of a region watched with `Simulation::watch_region`
+    /// changed during that step (or was changed with set_cell/remove_cell since the last step)
+    pub struct RegionChanged {
+        pub id: u32
+    }
+}
 
 pub const GRID_WIDTH: usize = 600;
 pub const GRID_HEIGHT: usize = 400;
 pub const CHUNK_WIDTH: usize = 50;
 pub const CHUNK_HEIGHT: usize = 50;
+/// Default number of chunks per axis that are kept loaded around the center, see `Simulation::set_num_active_chunks`
 pub const NUM_CHUNKS: i32 = 8;
+/// Default time between two steps when driven by `Simulation::advance`
+pub const DEFAULT_STEP_INTERVAL: Duration = Duration::from_micros(16_667);
+/// Default for `Simulation::set_max_steps_per_frame`
+pub const DEFAULT_MAX_STEPS_PER_FRAME: u32 = 5;
 
+/// The cellular automaton. It does not depend on any graphics system, so it can be created
+/// inside of a GeeseContext on its own and stepped headless (for example in CI).
 pub struct Simulation {
     ctx: GeeseContextHandle<Self>,
     grid: CellGrid,
     chunks: Vec<Chunk>,
     center_position: IVec2,
-    center_chunk_pos: IVec2
+    center_chunk_pos: IVec2,
+    /// Number of chunks per axis that are kept loaded around the center
+    num_chunks: i32,
+    /// How many steps have been simulated in total
+    tick: u64,
+    boundary: BoundaryMode,
+    /// Regions (min and max, both inclusive) that raise events::RegionChanged, by their id
+    watched_regions: Vec<(u32, GridPos, GridPos)>,
+    /// Indexed by MaterialId, starts with the built-in materials (see MATERIAL_SAND)
+    materials: Vec<Material>,
+    /// Fixed timestep used by advance
+    step_interval: Duration,
+    /// Time passed to advance that has not been simulated yet
+    accumulated_time: Duration,
+    max_steps_per_frame: u32,
+    /// How many steps advance and run_fixed_steps skipped in total because of max_steps_per_frame
+    dropped_steps: u64
 }
 impl Simulation {
-    pub(crate) fn get_grid_texture_data(&self) -> &[u8] {
+    /// Sets how many chunks per axis are kept loaded around the center (NUM_CHUNKS by default).
+    /// More chunks use more memory, but need to be streamed in less often. Odd numbers get rounded up.
+    /// All chunks get recreated around the current center.
+    pub fn set_num_active_chunks(&mut self, num_chunks: i32) {
+        let mut num_chunks = num_chunks.max(2);
+        if num_chunks % 2 != 0 {
+            warn!("set_num_active_chunks: {} is odd, using {} instead", num_chunks, num_chunks + 1);
+            num_chunks += 1;
+        };
+        self.num_chunks = num_chunks;
+        self.chunks = Self::create_chunks(self.center_chunk_pos, num_chunks);
+    }
+
+    /// Number of chunks per axis that are kept loaded
+    pub fn num_active_chunks(&self) -> i32 {
+        self.num_chunks
+    }
+
+    /// How many chunks are loaded in each direction from the center chunk
+    pub fn active_chunk_radius(&self) -> i32 {
+        self.num_chunks / 2
+    }
+
+
+    /// Iterates over all loaded chunks (num_active_chunks² of them) together with the world position
+    /// of their corner with the lowest coordinates. The order is not related to the chunk positions.
+    pub fn active_chunks(&self) -> impl Iterator<Item = (IVec2, &Chunk)> {
+        self.chunks.iter().map(|chunk| (chunk.world_position(), chunk))
+    }
+
+
+    /// Creates num_chunks * num_chunks chunks around the center, stored at the same indices that add_chunk uses
+    fn create_chunks(center_chunk_pos: IVec2, num_chunks: i32) -> Vec<Chunk> {
+        let halfsize = num_chunks / 2;
+        let mut chunks: Vec<Chunk> = (0..num_chunks * num_chunks).map(|_| Chunk { position: IVec2::ZERO }).collect();
+        for y in -halfsize..halfsize {
+            for x in -halfsize..halfsize {
+                let position = center_chunk_pos + IVec2::new(x, y);
+                chunks[chunk_slot(position, num_chunks)] = Chunk { position };
+            }
+        }
+        chunks
+    }
+
+
+    /// Sets what happens to cells that move past the edges of the grid (Solid by default)
+    pub fn set_boundary(&mut self, boundary: BoundaryMode) {
+        self.boundary = boundary;
+    }
+
+    pub fn boundary(&self) -> BoundaryMode {
+        self.boundary
+    }
+
+
+    /// Advances the simulation by one tick
+    pub fn step(&mut self) {
+        self.grid.step(self.boundary, &self.materials, self.tick);
+        // The heat simulation visits every cell, so it is skipped while no material uses it
+        if self.materials.iter().any(Material::uses_heat) {
+            self.grid.update_temperatures(&self.materials);
+        };
+        self.tick += 1;
+        self.notify_changed_regions();
+    }
+
+
+    /// Raises events::RegionChanged (at most once per step) with that id whenever a cell between min and max
+    /// (both inclusive) changes. Replaces an existing region with the same id.
+    pub fn watch_region(&mut self, id: u32, min: GridPos, max: GridPos) {
+        self.unwatch_region(id);
+        self.watched_regions.push((id, min, max));
+        self.grid.set_track_changes(true);
+    }
+
+
+    pub fn unwatch_region(&mut self, id: u32) {
+        self.watched_regions.retain(|(region_id, _, _)| *region_id != id);
+        self.grid.set_track_changes(!self.watched_regions.is_empty());
+    }
+
+
+    /// Raises events::RegionChanged for every watched region that contains a changed cell
+    fn notify_changed_regions(&mut self) {
+        let changed = self.grid.take_changed_cells();
+        if changed.is_empty() {
+            return;
+        };
+        for (id, min, max) in self.watched_regions.iter() {
+            if changed.iter().any(|pos| pos.0 >= min.0 && pos.0 <= max.0 && pos.1 >= min.1 && pos.1 <= max.1) {
+                self.ctx.raise_event(events::RegionChanged { id: *id });
+            };
+        }
+    }
+
+
+    /// Advances the simulation by that many ticks
+    pub fn step_n(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+
+    /// Drives the simulation with its own fixed timestep, for when there is no `TimeSystem` (for example headless):
+    /// adds dt to the accumulated time and runs one step per step_interval of it. At most max_steps_per_frame
+    /// steps run per call, the remaining time is dropped, so a slow frame does not lead to even more steps
+    /// in the next one. Returns the number of steps that ran. Inside of the engine use `run_fixed_steps` instead.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulated_time += dt;
+        let due_steps = (self.accumulated_time.as_nanos() / self.step_interval.as_nanos().max(1)) as u64;
+        let steps = due_steps.min(self.max_steps_per_frame as u64) as u32;
+        if due_steps > steps as u64 {
+            self.dropped_steps += due_steps - steps as u64;
+            self.accumulated_time = Duration::ZERO;
+        } else {
+            self.accumulated_time -= self.step_interval * steps;
+        };
+        self.step_n(steps);
+        steps
+    }
+
+
+    /// Runs one step per fixed step of the TimeSystem that is due in this frame (see `TimeSystem::fixed_steps`),
+    /// at most max_steps_per_frame of them. The simulation then shares the fixed timestep of the TimeSystem,
+    /// so things that move with it can be rendered in between two steps using `TimeSystem::interpolation_alpha`.
+    /// Returns the number of steps that ran.
+    pub fn run_fixed_steps(&mut self, time: &TimeSystem) -> u32 {
+        let due_steps = time.fixed_steps();
+        let steps = due_steps.min(self.max_steps_per_frame);
+        self.dropped_steps += (due_steps - steps) as u64;
+        self.step_n(steps);
+        steps
+    }
+
+
+    /// Sets the time between two steps when driven by advance (DEFAULT_STEP_INTERVAL by default)
+    pub fn set_step_interval(&mut self, step_interval: Duration) {
+        if step_interval.is_zero() {
+            warn!("set_step_interval: The interval has to be greater than zero");
+            return;
+        };
+        self.step_interval = step_interval;
+    }
+
+    pub fn step_interval(&self) -> Duration {
+        self.step_interval
+    }
+
+
+    /// Sets how many steps a single call to advance or run_fixed_steps may run (DEFAULT_MAX_STEPS_PER_FRAME by default)
+    pub fn set_max_steps_per_frame(&mut self, max_steps: u32) {
+        self.max_steps_per_frame = max_steps.max(1);
+    }
+
+    pub fn max_steps_per_frame(&self) -> u32 {
+        self.max_steps_per_frame
+    }
+
+
+    /// How many steps advance and run_fixed_steps have skipped so far because the simulation fell behind
+    pub fn dropped_steps(&self) -> u64 {
+        self.dropped_steps
+    }
+
+
+    /// How many ticks have been simulated so far
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+
+    /// Places a cell with that color at the grid position, replacing any cell that was there
+    pub fn set_cell(&mut self, pos: GridPos, color: CellColor) {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            warn!("set_cell: Position {:?} is outside of the grid", pos);
+            return;
+        };
+        self.grid.place_cell(Cell::new(pos, color));
+    }
+
+
+    /// Removes the cell at the grid position (if there is one)
+    pub fn remove_cell(&mut self, pos: GridPos) {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            warn!("remove_cell: Position {:?} is outside of the grid", pos);
+            return;
+        };
+        self.grid.remove_cell_at_pos(pos);
+    }
+
+
+    /// Adds a material to the material table and returns its id. Phase changes can point to materials
+    /// that are registered later (for example ice and water referencing each other), see material_mut.
+    /// Its behavior decides how its cells move, so new kinds of cells do not need changes to the simulation.
+    /// Returns None if the material table is full (it holds MaterialId::MAX + 1 materials).
+    pub fn register_material(&mut self, material: Material) -> Option<MaterialId> {
+        if self.materials.len() > MaterialId::MAX as usize {
+            warn!("register_material: The material table is full, '{}' is not registered", material.name);
+            return None;
+        };
+        self.materials.push(material);
+        Some((self.materials.len() - 1) as MaterialId)
+    }
+
+    pub fn material(&self, id: MaterialId) -> Option<&Material> {
+        self.materials.get(id as usize)
+    }
+
+    pub fn material_mut(&mut self, id: MaterialId) -> Option<&mut Material> {
+        self.materials.get_mut(id as usize)
+    }
+
+
+    /// Places a cell of that material (with its color and starting temperature), replacing any cell that was there
+    pub fn set_material_cell(&mut self, pos: GridPos, material: MaterialId) {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            warn!("set_material_cell: Position {:?} is outside of the grid", pos);
+            return;
+        };
+        let Some(properties) = self.materials.get(material as usize) else {
+            warn!("set_material_cell: Unknown material {}", material);
+            return;
+        };
+        self.grid.place_cell(Cell::with_material(pos, properties.color, material, properties.temperature));
+    }
+
+
+    /// Returns the material of the cell at that position, None if it is empty or has no material
+    pub fn material_at(&self, pos: GridPos) -> Option<MaterialId> {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            return None;
+        };
+        self.grid.cell_at_pos(pos).and_then(|cell| cell.material())
+    }
+
+
+    /// Returns the temperature of the cell at that position, None if it is empty
+    pub fn temperature_at(&self, pos: GridPos) -> Option<i16> {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            return None;
+        };
+        self.grid.cell_at_pos(pos).map(|cell| cell.temperature())
+    }
+
+
+    /// Sets the temperature of the cell at that position (for example to heat it up), phase changes happen on the next step
+    pub fn set_temperature(&mut self, pos: GridPos, temperature: i16) {
+        if pos.0 >= GRID_WIDTH || pos.1 >= GRID_HEIGHT {
+            warn!("set_temperature: Position {:?} is outside of the grid", pos);
+            return;
+        };
+        match self.grid.cell_at_pos_mut(pos) {
+            Some(cell) => cell.set_temperature(temperature),
+            None => warn!("set_temperature: There is no cell at {:?}", pos)
+        };
+    }
+
+
+    /// Returns the colors of all cells between min and max (both inclusive, clamped to the grid)
+    /// row by row, None for empty cells
+    pub fn export_region(&self, min: GridPos, max: GridPos) -> Vec<Option<CellColor>> {
+        let max = (max.0.min(GRID_WIDTH - 1), max.1.min(GRID_HEIGHT - 1));
+        let mut region = vec![];
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                region.push(self.grid.color_at_pos((x, y)).copied());
+            }
+        }
+        region
+    }
+
+
+    pub(crate) fn get_grid_texture_data(&self) -> &[PaletteIndex] {
         self.grid.get_texture_data()
     }
 
+    pub(crate) fn get_grid_palette(&self) -> &[CellColor] {
+        self.grid.get_palette()
+    }
+
+    pub(crate) fn get_grid_palette_version(&self) -> u64 {
+        self.grid.get_palette_version()
+    }
+
+    /// Returns the (inclusive) min and max position of all cells that changed since the last call
+    pub(crate) fn take_dirty_region(&mut self) -> Option<(GridPos, GridPos)> {
+        self.grid.take_dirty_region()
+    }
+
+    /// Puts the chunk into its slot, replacing the chunk that was there if it has another position
     fn add_chunk(&mut self, chunk: Chunk) {
-    //fn add_chunk(&mut self, chunk: Chunk) {
-        let halfsize = NUM_CHUNKS/2;
-        let arr_x = (chunk.position.x + halfsize).rem_euclid(NUM_CHUNKS);
-        let arr_y = (chunk.position.y + halfsize).rem_euclid(NUM_CHUNKS);
-        info!("  Chunk pos: {:?} at 2D index {},{}", chunk.position, arr_x, arr_y);
-        let arr_idx = arr_y as usize * NUM_CHUNKS as usize + arr_x as usize;
-        info!("    Currently there: {:?}", self.chunks[arr_idx].position);
+        let arr_idx = chunk_slot(chunk.position, self.num_chunks);
         let prev_chunk = &self.chunks[arr_idx];
         if chunk.position != prev_chunk.position {
+            info!("Chunk {:?} replaces {:?} at index {}", chunk.position, prev_chunk.position, arr_idx);
             self.chunks[arr_idx] = chunk;
             // TODO: Storing/ Loading of new/old chunk
         }
     }
 
+    /// Moves the window of loaded chunks so it is centered around the chunk containing pos (a world position).
+    /// Chunks that stay inside of the window are kept, the ones that leave it get replaced by the newly
+    /// visible ones. This works for moves in any direction and over any distance.
     pub fn set_center_position(&mut self, pos: IVec2) {
-        if pos != self.center_position {
-            let new_chunk_pos = pos / IVec2::new(CHUNK_WIDTH as i32, CHUNK_HEIGHT as i32);
-            let new_max_chunk_pos = new_chunk_pos + IVec2::new(NUM_CHUNKS - 1, NUM_CHUNKS - 1);
-            let chunk_pos_diff = new_max_chunk_pos - (self.center_chunk_pos + IVec2::new(NUM_CHUNKS-1, NUM_CHUNKS-1));
-            
-            if chunk_pos_diff == IVec2::ZERO {
-                return;
-            }
-            info!("Pos: {}     Diff: {}", new_chunk_pos, chunk_pos_diff);
-            let old = self.center_chunk_pos;
-            let hchunks = NUM_CHUNKS / 2;
-            // [1, 0] -> Right Edge   -> IVec2(old.x + NUM_CHUNKS-1, old.y + NUM_CHUNKS-1) to IVec2(old.x + NUM_CHUNKS-1, old.y - NUM_CHUNKS)
-            // [0, -1] -> Bottom Edge -> IVec2(old.x - NUM_CHUNKS, old.y - NUM_CHUNKS)     to IVec2(old.x + NUM_CHUNKS-1, old.y - NUM_CHUNKS)
-            for new_y in (old.y - hchunks)..(old.y + hchunks) {
-                if chunk_pos_diff.x == 1 {
-                    self.add_chunk(Chunk { position: IVec2::new(old.x + hchunks, new_y) });
-                } else if chunk_pos_diff.x == -1 {
-                    self.add_chunk(Chunk { position: IVec2::new(old.x - hchunks - 1, new_y) });
-                }
+        if pos == self.center_position {
+            return;
+        };
+        self.center_position = pos;
+        let new_chunk_pos = world_to_chunk(pos);
+        if new_chunk_pos == self.center_chunk_pos {
+            return;
+        };
+        self.center_chunk_pos = new_chunk_pos;
+        let halfsize = self.num_chunks / 2;
+        for y in -halfsize..halfsize {
+            for x in -halfsize..halfsize {
+                self.add_chunk(Chunk { position: new_chunk_pos + IVec2::new(x, y) });
             }
-            for new_x in (old.x - hchunks)..(old.x + hchunks) {
-                if chunk_pos_diff.y == 1 {
-                    self.add_chunk(Chunk { position: IVec2::new(new_x, old.y + hchunks) });
-                } else if chunk_pos_diff.y == -1 {
-                    self.add_chunk(Chunk { position: IVec2::new(new_x, old.y - hchunks - 1) });
-                }
-            }
-            self.center_chunk_pos = new_chunk_pos;
-            self.center_position = pos;
         }
     }
 }
 impl GeeseSystem for Simulation {
     fn new(ctx: geese::GeeseContextHandle<Self>) -> Self {
         let mut grid = CellGrid::empty();
-        let mut chunks = vec![];
-        for y in -NUM_CHUNKS as i32/2..NUM_CHUNKS/2 {
-            for x in -NUM_CHUNKS as i32/2..NUM_CHUNKS/2 {
-                chunks.push(Chunk {position: IVec2::new(x, y)});
-            }
-        }
+        let chunks = Self::create_chunks(IVec2::ZERO, NUM_CHUNKS);
         Self {
             ctx,
             grid,
             chunks,
             center_position: IVec2::new(0, 0),
-            center_chunk_pos: IVec2::new(0, 0)
+            center_chunk_pos: IVec2::new(0, 0),
+            num_chunks: NUM_CHUNKS,
+            tick: 0,
+            boundary: BoundaryMode::default(),
+            watched_regions: vec![],
+            materials: Material::built_in(),
+            step_interval: DEFAULT_STEP_INTERVAL,
+            accumulated_time: Duration::ZERO,
+            max_steps_per_frame: DEFAULT_MAX_STEPS_PER_FRAME,
+            dropped_steps: 0
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use geese::GeeseContext;
+
+    use super::*;
+
+    fn simulation() -> GeeseContext {
+        let mut ctx = GeeseContext::default();
+        ctx.flush().with(geese::notify::add_system::<Simulation>());
+        ctx
+    }
+
+    fn has_cell(sim: &Simulation, pos: GridPos) -> bool {
+        sim.export_region(pos, pos)[0].is_some()
+    }
+
+    /// Never moves, used to hold other cells in place
+    struct Wall;
+    impl CellBehavior for Wall {
+        fn update(&self, _cell: &mut CellUpdate) {}
+    }
+
+    fn gray() -> CellColor {
+        CellColor::new(128, 128, 128, 255)
+    }
+
+
+    #[test]
+    fn bottom_boundary_modes() {
+        let bottom = (10, GRID_HEIGHT - 1);
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        sim.set_cell(bottom, gray());
+        sim.step();
+        assert!(has_cell(&sim, bottom));
+
+        sim.set_boundary(BoundaryMode::Void);
+        sim.step();
+        assert!(!has_cell(&sim, bottom));
+
+        // Wrapping cells are moved after the top row, so the cell that falls out of the top row makes room
+        sim.set_boundary(BoundaryMode::Wrap);
+        sim.set_cell(bottom, gray());
+        sim.set_cell((10, 0), gray());
+        sim.step();
+        assert!(!has_cell(&sim, bottom));
+        assert!(has_cell(&sim, (10, 0)));
+        assert!(has_cell(&sim, (10, 1)));
+    }
+
+    #[test]
+    fn side_boundary_modes() {
+        for boundary in [BoundaryMode::Solid, BoundaryMode::Void, BoundaryMode::Wrap] {
+            let mut ctx = simulation();
+            let mut sim = ctx.get_mut::<Simulation>();
+            let wall = sim.register_material(Material::new("wall", gray()).with_behavior(Wall)).unwrap();
+            sim.set_boundary(boundary);
+            // The cell on the left edge cannot fall straight down, so it tries down left first
+            sim.set_material_cell((0, 101), wall);
+            sim.set_cell((0, 100), gray());
+            sim.step();
+            assert!(!has_cell(&sim, (0, 100)));
+            match boundary {
+                BoundaryMode::Solid => assert!(has_cell(&sim, (1, 101))),
+                BoundaryMode::Void => assert!(!has_cell(&sim, (1, 101)) && !has_cell(&sim, (GRID_WIDTH - 1, 101))),
+                BoundaryMode::Wrap => assert!(has_cell(&sim, (GRID_WIDTH - 1, 101)))
+            };
+        }
+    }
+
+
+    /// The chunk positions of num_chunks * num_chunks chunks around center
+    fn chunk_window(center: IVec2, num_chunks: i32) -> Vec<IVec2> {
+        let halfsize = num_chunks / 2;
+        let mut window: Vec<IVec2> = (-halfsize..halfsize)
+            .flat_map(|y| (-halfsize..halfsize).map(move |x| center + IVec2::new(x, y)))
+            .collect();
+        window.sort_by_key(|pos| (pos.y, pos.x));
+        window
+    }
+
+    fn active_chunk_positions(sim: &Simulation) -> Vec<IVec2> {
+        let mut positions: Vec<IVec2> = sim.active_chunks().map(|(_, chunk)| chunk.position).collect();
+        positions.sort_by_key(|pos| (pos.y, pos.x));
+        positions
+    }
+
+
+    #[test]
+    fn active_chunks_are_unique() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        for num_chunks in [NUM_CHUNKS, 2, 6] {
+            sim.set_num_active_chunks(num_chunks);
+            assert_eq!(sim.active_chunks().count(), (num_chunks * num_chunks) as usize);
+            assert_eq!(active_chunk_positions(&sim), chunk_window(IVec2::ZERO, num_chunks));
+            assert!(sim.active_chunks().all(|(world_pos, chunk)| world_pos == chunk_to_world(chunk.position)));
+        }
+    }
+
+    #[test]
+    fn scrolling_moves_the_chunk_window() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        for num_chunks in [4, 16] {
+            sim.set_center_position(IVec2::ZERO);
+            sim.set_num_active_chunks(num_chunks);
+            assert_eq!(active_chunk_positions(&sim), chunk_window(IVec2::ZERO, num_chunks));
+            // Right, diagonal, far away (including negative chunks) and back
+            for center in [IVec2::new(1, 0), IVec2::new(2, 1), IVec2::new(1, 0), IVec2::new(-7, 23), IVec2::new(-8, 22), IVec2::ZERO] {
+                sim.set_center_position(chunk_to_world(center) + IVec2::new(3, 4));
+                assert_eq!(active_chunk_positions(&sim), chunk_window(center, num_chunks), "{}x{} window around {}", num_chunks, num_chunks, center);
+                assert!(sim.chunks.iter().all(|chunk| sim.chunks[chunk_slot(chunk.position, num_chunks)].position == chunk.position));
+            }
+        }
+    }
+
+    #[test]
+    fn custom_behavior_moves_cells() {
+        struct Rises;
+        impl CellBehavior for Rises {
+            fn update(&self, cell: &mut CellUpdate) {
+                cell.try_move(IVec2::new(0, -1));
+            }
+        }
+
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        let balloon = sim.register_material(Material::new("balloon", gray()).with_behavior(Rises)).unwrap();
+        sim.set_material_cell((30, 100), balloon);
+        sim.step();
+        // Cells that moved up are not updated again in the same step
+        assert_eq!(sim.material_at((30, 100)), None);
+        assert_eq!(sim.material_at((30, 99)), Some(balloon));
+        sim.step_n(99);
+        assert_eq!(sim.material_at((30, 0)), Some(balloon));
+        // The top edge is solid by default
+        sim.step();
+        assert_eq!(sim.material_at((30, 0)), Some(balloon));
+    }
+
+    #[test]
+    fn region_changed_once_per_step() {
+        struct RegionListener {
+            changed: Vec<u32>
+        }
+        impl RegionListener {
+            fn on_region_changed(&mut self, event: &events::RegionChanged) {
+                self.changed.push(event.id);
+            }
+        }
+        impl GeeseSystem for RegionListener {
+            const EVENT_HANDLERS: geese::EventHandlers<Self> = geese::event_handlers()
+                .with(Self::on_region_changed);
+
+            fn new(_ctx: GeeseContextHandle<Self>) -> Self {
+                Self {
+                    changed: vec![]
+                }
+            }
         }
+
+        let mut ctx = simulation();
+        ctx.flush().with(geese::notify::add_system::<RegionListener>());
+        let bottom = GRID_HEIGHT - 1;
+        let step = |ctx: &mut GeeseContext, place: &[GridPos]| {
+            let mut sim = ctx.get_mut::<Simulation>();
+            place.iter().for_each(|pos| sim.set_cell(*pos, gray()));
+            sim.step();
+            drop(sim);
+            ctx.flush();
+            std::mem::take(&mut ctx.get_mut::<RegionListener>().changed)
+        };
+        ctx.get_mut::<Simulation>().watch_region(1, (0, bottom - 9), (9, bottom));
+        ctx.get_mut::<Simulation>().watch_region(2, (100, bottom - 9), (109, bottom));
+
+        // Two cells changed inside of the region, but it is only reported once
+        assert_eq!(step(&mut ctx, &[(5, bottom), (6, bottom)]), [1]);
+        // Nothing moves on the bottom row
+        assert_eq!(step(&mut ctx, &[]), Vec::<u32>::new());
+        // Outside of both regions
+        assert_eq!(step(&mut ctx, &[(50, bottom)]), Vec::<u32>::new());
+        // A cell falling into the region from above changes it in every step
+        assert_eq!(step(&mut ctx, &[(105, bottom - 11)]), Vec::<u32>::new());
+        assert_eq!(step(&mut ctx, &[]), [2]);
+        assert_eq!(step(&mut ctx, &[]), [2]);
+
+        ctx.get_mut::<Simulation>().unwatch_region(2);
+        assert_eq!(step(&mut ctx, &[]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn sand_piles_up() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        let sand = Some(sim.material(MATERIAL_SAND).unwrap().color);
+        let bottom = GRID_HEIGHT - 1;
+
+        sim.set_material_cell((20, 0), MATERIAL_SAND);
+        sim.step_n(GRID_HEIGHT as u32);
+        assert_eq!(sim.export_region((19, bottom - 1), (21, bottom)), [None, None, None, None, sand, None]);
+
+        // The second grain lands on the first one and slides off to either side
+        sim.set_material_cell((20, 0), MATERIAL_SAND);
+        sim.step_n(GRID_HEIGHT as u32);
+        let region = sim.export_region((19, bottom - 1), (21, bottom));
+        assert_eq!(region[..3], [None, None, None]);
+        assert_eq!(region[4], sand);
+        assert_eq!(region.iter().filter(|color| color.is_some()).count(), 2);
+    }
+
+    #[test]
+    fn advance_caps_steps_per_call() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        sim.set_step_interval(Duration::from_millis(10));
+        sim.set_max_steps_per_frame(4);
+
+        assert_eq!(sim.advance(Duration::from_millis(25)), 2);
+        assert_eq!(sim.accumulated_time, Duration::from_millis(5));
+        assert_eq!(sim.dropped_steps(), 0);
+
+        // 1000 steps are due, only 4 run and the rest of the time is dropped instead of carried over
+        assert_eq!(sim.advance(Duration::from_secs(10)), 4);
+        assert_eq!(sim.dropped_steps(), 996);
+        assert_eq!(sim.accumulated_time, Duration::ZERO);
+        assert_eq!(sim.tick(), 6);
+
+        assert_eq!(sim.advance(Duration::from_millis(9)), 0);
+        assert_eq!(sim.advance(Duration::from_millis(1)), 1);
+        assert_eq!(sim.tick(), 7);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn ice_water_steam_phase_changes() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        let ice = sim.register_material(Material::new("ice", CellColor::new(200, 230, 255, 255)).with_temperature(-10)).unwrap();
+        let water = sim.register_material(Material::new("water", CellColor::new(40, 110, 220, 255)).with_temperature(20)).unwrap();
+        let steam = sim.register_material(Material::new("steam", CellColor::new(230, 230, 230, 255)).with_temperature(120)).unwrap();
+        sim.material_mut(ice).unwrap().heated_into = Some(PhaseChange { temperature: 1, into: water });
+        sim.material_mut(water).unwrap().cooled_into = Some(PhaseChange { temperature: 0, into: ice });
+        sim.material_mut(water).unwrap().heated_into = Some(PhaseChange { temperature: 100, into: steam });
+        sim.material_mut(steam).unwrap().cooled_into = Some(PhaseChange { temperature: 99, into: water });
+
+        // On the bottom row the cell cannot fall
+        let pos = (10, GRID_HEIGHT - 1);
+        sim.set_material_cell(pos, ice);
+        sim.step();
+        assert_eq!(sim.material_at(pos), Some(ice));
+
+        sim.set_temperature(pos, 5);
+        sim.step();
+        assert_eq!(sim.material_at(pos), Some(water));
+
+        sim.set_temperature(pos, 150);
+        sim.step();
+        assert_eq!(sim.material_at(pos), Some(steam));
+        assert_eq!(sim.temperature_at(pos), Some(150));
+
+        // Cooling steam far below freezing condenses it first, then it freezes on the next step
+        sim.set_temperature(pos, -20);
+        sim.step();
+        assert_eq!(sim.material_at(pos), Some(water));
+        sim.step();
+        assert_eq!(sim.material_at(pos), Some(ice));
+    }
+
+    #[test]
+    fn register_material_refuses_when_full() {
+        let mut ctx = simulation();
+        let mut sim = ctx.get_mut::<Simulation>();
+        let color = CellColor::new(0, 0, 0, 255);
+        let mut last = None;
+        while let Some(id) = sim.register_material(Material::new("filler", color)) {
+            last = Some(id);
+        }
+        assert_eq!(last, Some(MaterialId::MAX));
+        assert_eq!(sim.register_material(Material::new("overflow", color)), None);
+        assert_eq!(sim.material(MaterialId::MAX).unwrap().name, "filler");
+    }
+}
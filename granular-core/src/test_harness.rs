@@ -0,0 +1,247 @@
+use std::time::Duration;
+
+use geese::{GeeseContext, GeeseSystem};
+use gilrs::{Axis, Button};
+use glam::{IVec2, Vec2};
+use winit::{dpi::PhysicalPosition, event::{ElementState, MouseButton, MouseScrollDelta}, keyboard::{KeyCode, ModifiersState, PhysicalKey}};
+
+use crate::{events, filewatcher::FileWatcher, raise_fixed_tick, raise_frame_ticks, FrameInfo, InputSystem, TimeSystem};
+
+
+/// Runs the engine's systems without a window or event loop, for integration tests of gameplay systems.
+///
+/// Frames only advance when `step` is called, with a delta time that is set instead of measured, and input
+/// is injected through the same code paths the window events take.
+///
+/// The harness only covers the CPU side of the engine: there is no GPU, no offscreen render target and
+/// no frame capture, so systems that depend on the `GraphicsSystem` (renderers, assets) cannot be added,
+/// systems like the `Simulation` can. `PreRender` and `Draw` are still raised, but nothing is rendered.
+///
+/// ```ignore
+/// let mut harness = TestHarness::new();
+/// harness.add_system::<Player>();
+/// harness.get_ctx().get_mut::<InputSystem>().add_action("jump", InputActionTrigger::key(KeyCode::Space));
+/// harness.press_key(KeyCode::Space);
+/// harness.step();
+/// assert!(harness.get_ctx().get::<Player>().is_jumping());
+/// ```
+pub struct TestHarness {
+    ctx: GeeseContext,
+    frame: u64,
+    delta: Duration,
+    /// Simulated time since each fixed tick (in ms) was last raised, in a fixed order to stay deterministic
+    fixed_tick_time: Vec<(u64, Duration)>
+}
+impl TestHarness {
+    /// The delta time used by step until set_delta is called (60 frames per second)
+    pub const DEFAULT_DELTA: Duration = Duration::from_micros(16_667);
+
+
+    /// Creates a context with the systems that do not need a window (`InputSystem`, `TimeSystem`, `FrameInfo`)
+    pub fn new() -> Self {
+        let mut ctx = GeeseContext::default();
+        ctx.flush()
+            .with(geese::notify::add_system::<FileWatcher>())
+            .with(geese::notify::add_system::<InputSystem>())
+            .with(geese::notify::add_system::<TimeSystem>())
+            .with(geese::notify::add_system::<FrameInfo>());
+
+        Self {
+            ctx,
+            frame: 0,
+            delta: Self::DEFAULT_DELTA,
+            fixed_tick_time: events::timing::FIXED_TICKS.iter().map(|millis| (*millis, Duration::ZERO)).collect()
+        }
+    }
+
+
+    pub fn get_ctx(&mut self) -> &mut GeeseContext {
+        &mut self.ctx
+    }
+
+
+    /// Adds a system (and its dependencies) to the context
+    pub fn add_system<S: GeeseSystem>(&mut self) -> &mut Self {
+        self.ctx.flush().with(geese::notify::add_system::<S>());
+        self
+    }
+
+
+    /// Sets the delta time of the following frames, see `TimeSystem::delta`
+    pub fn set_delta(&mut self, delta: Duration) {
+        self.delta = delta;
+    }
+
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// The number of frames that have been stepped
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+
+    /// Runs one frame: the update phases, the ticks that are due (fixed ticks use the simulated time),
+    /// `PreRender` and `Draw`. Input injected before this call is visible during the frame, afterwards
+    /// just pressed and just released actions are reset like at the end of a real frame.
+    pub fn step(&mut self) {
+        self.ctx.get_mut::<InputSystem>().poll_gamepads();
+        self.ctx.get_mut::<TimeSystem>().begin_frame_with_delta(self.delta);
+        self.ctx.get_mut::<FrameInfo>().begin_frame(self.frame);
+        self.ctx.flush().with(events::phase::PreUpdate);
+        self.ctx.flush().with(events::phase::Update);
+        self.ctx.flush().with(events::phase::PostUpdate);
+
+        for (millis, time) in self.fixed_tick_time.iter_mut() {
+            *time += self.delta;
+            let tickrate = Duration::from_millis(*millis);
+            if *time >= tickrate {
+                *time -= tickrate;
+                raise_fixed_tick(&mut self.ctx, *millis);
+            };
+        }
+        raise_frame_ticks(&mut self.ctx, self.frame);
+
+        self.ctx.flush().with(events::phase::PreRender);
+        self.ctx.flush().with(events::Draw);
+        self.ctx.get_mut::<InputSystem>().reset_frame_state();
+        self.frame += 1;
+    }
+
+    /// Runs step `frames` times
+    pub fn step_frames(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
+
+    pub fn press_key(&mut self, key: KeyCode) {
+        self.ctx.get_mut::<InputSystem>().handle_key(PhysicalKey::Code(key), ElementState::Pressed, false);
+    }
+
+    pub fn release_key(&mut self, key: KeyCode) {
+        self.ctx.get_mut::<InputSystem>().handle_key(PhysicalKey::Code(key), ElementState::Released, false);
+    }
+
+
+    pub fn press_mouse(&mut self, button: MouseButton) {
+        self.ctx.get_mut::<InputSystem>().handle_mouse_input(button, ElementState::Pressed);
+    }
+
+    pub fn release_mouse(&mut self, button: MouseButton) {
+        self.ctx.get_mut::<InputSystem>().handle_mouse_input(button, ElementState::Released);
+    }
+
+
+    pub fn press_gamepad_button(&mut self, button: Button) {
+        self.ctx.get_mut::<InputSystem>().handle_gamepad_button(button, ElementState::Pressed);
+    }
+
+    pub fn release_gamepad_button(&mut self, button: Button) {
+        self.ctx.get_mut::<InputSystem>().handle_gamepad_button(button, ElementState::Released);
+    }
+
+    /// Moves a gamepad axis to value (-1.0 - 1.0)
+    pub fn set_gamepad_axis(&mut self, axis: Axis, value: f32) {
+        self.ctx.get_mut::<InputSystem>().handle_gamepad_axis(axis, value);
+    }
+
+
+    /// Moves the cursor to position (in window pixels)
+    pub fn move_mouse(&mut self, position: IVec2) {
+        self.ctx.get_mut::<InputSystem>().handle_cursor_movement(PhysicalPosition::new(position.x as f64, position.y as f64));
+    }
+
+    /// Scrolls the mouse wheel by delta lines, see `InputSystem::get_scroll_delta`
+    pub fn scroll(&mut self, delta: Vec2) {
+        self.ctx.get_mut::<InputSystem>().handle_mouse_wheel(MouseScrollDelta::LineDelta(delta.x, delta.y));
+    }
+
+    /// Sets the held modifiers, they apply to keys and mouse buttons pressed afterwards
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.ctx.get_mut::<InputSystem>().update_modifiers(&modifiers.into());
+    }
+}
+impl Default for TestHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use geese::{dependencies, event_handlers, GeeseContextHandle};
+
+    use crate::InputActionTrigger;
+
+    use super::*;
+
+    struct Player {
+        ctx: GeeseContextHandle<Self>,
+        jumps: u32
+    }
+    impl Player {
+        fn update(&mut self, _: &events::phase::Update) {
+            if self.ctx.get::<InputSystem>().is_action_just_pressed("jump") {
+                self.jumps += 1;
+            };
+        }
+    }
+    impl GeeseSystem for Player {
+        const DEPENDENCIES: geese::Dependencies = dependencies()
+            .with::<InputSystem>();
+        const EVENT_HANDLERS: geese::EventHandlers<Self> = event_handlers()
+            .with(Self::update);
+
+        fn new(ctx: GeeseContextHandle<Self>) -> Self {
+            Self {
+                ctx,
+                jumps: 0
+            }
+        }
+    }
+
+
+    #[test]
+    fn system_reacts_to_injected_key() {
+        let mut harness = TestHarness::new();
+        harness.add_system::<Player>();
+        harness.get_ctx().get_mut::<InputSystem>().add_action("jump", InputActionTrigger::key(KeyCode::Space));
+
+        harness.step();
+        assert_eq!(harness.get_ctx().get::<Player>().jumps, 0);
+
+        harness.press_key(KeyCode::Space);
+        harness.step();
+        assert_eq!(harness.get_ctx().get::<Player>().jumps, 1);
+
+        // Holding the key does not jump again
+        harness.step_frames(3);
+        assert_eq!(harness.get_ctx().get::<Player>().jumps, 1);
+        assert_eq!(harness.frame(), 5);
+    }
+
+    #[test]
+    fn frame_info_counts_frames_and_ticks() {
+        let mut harness = TestHarness::new();
+        harness.set_delta(Duration::from_millis(50));
+        harness.step_frames(61);
+        assert_eq!(harness.frame(), 61);
+
+        let frame_info = harness.get_ctx().get::<FrameInfo>();
+        // The index of the last frame that was stepped
+        assert_eq!(frame_info.frame(), 60);
+        assert_eq!(frame_info.tick_count(1), 61);
+        assert_eq!(frame_info.tick_count(2), 31);
+        assert_eq!(frame_info.tick_count(30), 3);
+        assert_eq!(frame_info.tick_count(60), 2);
+        // 61 frames of 50ms are 3.05s
+        assert_eq!(frame_info.fixed_tick_count(1000), 3);
+        assert_eq!(frame_info.fixed_tick_count(2500), 1);
+        assert_eq!(frame_info.fixed_tick_count(5000), 0);
+    }
+}
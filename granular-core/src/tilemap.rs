@@ -0,0 +1,513 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use glam::{IVec2, Vec2};
+use log::*;
+use palette::Srgba;
+use serde::Deserialize;
+
+use crate::assets::{AssetHandle, AssetSystem, TextureAsset};
+use crate::graphics::{BatchRenderer, Quad};
+
+
+/// Tiled stores the flip flags of a tile in the highest bits of its GID
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const ROTATED_HEXAGONAL_120: u32 = 0x1000_0000;
+const GID_MASK: u32 = !(FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL_120);
+
+
+
+/// The parts of a Tiled JSON map that are supported
+#[derive(Debug, Deserialize)]
+struct TiledMap {
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TiledLayer>,
+    tilesets: Vec<TiledTileset>
+}
+
+
+#[derive(Debug, Deserialize)]
+struct TiledLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    data: Option<TiledLayerData>,
+    #[serde(default = "default_true")]
+    visible: bool,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default)]
+    offsetx: f32,
+    #[serde(default)]
+    offsety: f32
+}
+
+
+/// Tile layer data is either a plain array of GIDs or an encoded (and possibly compressed) string
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum TiledLayerData {
+    Array(Vec<u32>),
+    Encoded(String)
+}
+
+
+/// A tileset, either embedded into the map or in its own file (then only firstgid and source are set)
+#[derive(Debug, Deserialize)]
+struct TiledTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32
+}
+impl TiledTileset {
+    /// Checks that the tiles can be cut out of the image and returns the path of the image
+    fn validate(&self, first_gid: u32) -> anyhow::Result<&str> {
+        let Some(image) = &self.image else {
+            bail!("Tileset with the first GID {} has no image, image collection tilesets are not supported", first_gid);
+        };
+        if self.columns == 0 || self.tilewidth == 0 || self.tileheight == 0 {
+            bail!("Tileset '{}' has no columns or tiles of size zero", image);
+        };
+        // The UVs are divided by the image size
+        if self.imagewidth == 0 || self.imageheight == 0 {
+            bail!("Tileset '{}' has an image size of zero", image);
+        };
+        Ok(image)
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+
+
+/// A tileset image that has been loaded as a texture
+struct Tileset {
+    first_gid: u32,
+    tile_count: u32,
+    columns: u32,
+    tile_size: IVec2,
+    image_size: Vec2,
+    margin: u32,
+    spacing: u32,
+    texture: AssetHandle<TextureAsset>
+}
+impl Tileset {
+    fn new(first_gid: u32, tileset: &TiledTileset, texture: AssetHandle<TextureAsset>) -> Self {
+        Self {
+            first_gid,
+            tile_count: tileset.tilecount,
+            columns: tileset.columns,
+            tile_size: IVec2::new(tileset.tilewidth as i32, tileset.tileheight as i32),
+            image_size: Vec2::new(tileset.imagewidth as f32, tileset.imageheight as f32),
+            margin: tileset.margin,
+            spacing: tileset.spacing,
+            texture
+        }
+    }
+
+
+    /// Returns the UV rectangle (min, max) of the tile with that index inside of this tileset
+    fn tile_uv_rect(&self, local_id: u32) -> (Vec2, Vec2) {
+        let col = local_id % self.columns;
+        let row = local_id / self.columns;
+        let min = IVec2::new(
+            (self.margin + col * (self.tile_size.x as u32 + self.spacing)) as i32,
+            (self.margin + row * (self.tile_size.y as u32 + self.spacing)) as i32);
+        let max = min + self.tile_size;
+        (min.as_vec2() / self.image_size, max.as_vec2() / self.image_size)
+    }
+}
+
+
+
+/// A single layer of tiles, the GIDs still contain the flip flags
+pub struct TileLayer {
+    name: String,
+    gids: Vec<u32>,
+    visible: bool,
+    opacity: f32,
+    /// Offset in pixels, with y pointing up
+    offset: IVec2
+}
+impl TileLayer {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn opacity(&self) -> f32 {
+        self.opacity
+    }
+}
+
+
+
+/// An orthogonal map made in Tiled (https://www.mapeditor.org), loaded from its JSON format.
+///
+/// The top left corner of the map is placed at the origin (x going right, y going up), so the map extends
+/// into negative y. Each tile layer gets drawn on its own layer of the BatchRenderer, starting at the base layer
+/// in the order of the layers in Tiled. Horizontally and vertically flipped tiles are supported, diagonal flips
+/// (used for rotated tiles) are ignored. Since quads use half sizes, tile sizes should be even.
+pub struct Tilemap {
+    width: u32,
+    height: u32,
+    tile_size: IVec2,
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    origin: IVec2,
+    base_layer: i32
+}
+impl Tilemap {
+    /// Loads a map saved as JSON (.tmj/ .json) relative to the base path of the AssetSystem,
+    /// together with the tileset textures it references. External tilesets (.tsj) are supported as well.
+    pub fn load(asset_sys: &mut AssetSystem, path: impl TryInto<PathBuf>) -> anyhow::Result<Self> {
+        let path = asset_sys.add_basepath(path);
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read tilemap '{}'", path.display()))?;
+        let map_dir = path.parent().unwrap_or(Path::new(""));
+        let tilemap = Self::from_json(&source, |tileset| Self::load_tileset(asset_sys, tileset, map_dir))
+            .with_context(|| format!("Could not load tilemap '{}'", path.display()))?;
+        info!("Loaded tilemap '{}' ({}x{} tiles, {} layers)", path.display(), tilemap.width, tilemap.height, tilemap.layers.len());
+        Ok(tilemap)
+    }
+
+
+    /// Parses a map from its JSON source, load_tileset turns each tileset of the map into a Tileset with a texture
+    fn from_json(source: &str, mut load_tileset: impl FnMut(TiledTileset) -> anyhow::Result<Tileset>) -> anyhow::Result<Self> {
+        let map: TiledMap = serde_json::from_str(source).context("Could not parse the map")?;
+        if !map.orientation.is_empty() && map.orientation != "orthogonal" {
+            bail!("The map has the orientation '{}', only orthogonal maps are supported", map.orientation);
+        };
+        if map.infinite {
+            bail!("Infinite maps are not supported");
+        };
+
+        let mut tilesets = vec![];
+        for tileset in map.tilesets {
+            tilesets.push(load_tileset(tileset)?);
+        }
+        tilesets.sort_by_key(|tileset| tileset.first_gid);
+
+        let mut layers = vec![];
+        for layer in map.layers {
+            if layer.kind != "tilelayer" {
+                debug!("Tilemap: Skipping layer '{}' of type '{}'", layer.name, layer.kind);
+                continue;
+            };
+            let gids = match layer.data {
+                Some(TiledLayerData::Array(gids)) => gids,
+                Some(TiledLayerData::Encoded(_)) => bail!("Layer '{}' uses an encoded layer format, save it as CSV instead", layer.name),
+                None => vec![]
+            };
+            if gids.len() != (map.width * map.height) as usize {
+                bail!("Layer '{}' has {} tiles, expected {}", layer.name, gids.len(), map.width * map.height);
+            };
+            layers.push(TileLayer {
+                name: layer.name,
+                gids,
+                visible: layer.visible,
+                opacity: layer.opacity,
+                offset: IVec2::new(layer.offsetx as i32, -layer.offsety as i32)
+            });
+        }
+
+        Ok(Self {
+            width: map.width,
+            height: map.height,
+            tile_size: IVec2::new(map.tilewidth as i32, map.tileheight as i32),
+            tilesets,
+            layers,
+            origin: IVec2::ZERO,
+            base_layer: 0
+        })
+    }
+
+
+    fn load_tileset(asset_sys: &mut AssetSystem, tileset: TiledTileset, map_dir: &Path) -> anyhow::Result<Tileset> {
+        let first_gid = tileset.firstgid;
+        // External tilesets are relative to the map, their images relative to the tileset file
+        let (tileset, tileset_dir) = match &tileset.source {
+            Some(source) => {
+                let tileset_path = map_dir.join(source);
+                let source = std::fs::read_to_string(&tileset_path)
+                    .with_context(|| format!("Could not read tileset '{}'", tileset_path.display()))?;
+                let external: TiledTileset = serde_json::from_str(&source)
+                    .with_context(|| format!("Could not parse tileset '{}'", tileset_path.display()))?;
+                (external, tileset_path.parent().unwrap_or(Path::new("")).to_path_buf())
+            },
+            None => (tileset, map_dir.to_path_buf())
+        };
+
+        let image = tileset.validate(first_gid)?;
+        // AssetSystem::load joins the path onto its base path, which keeps absolute paths as they are
+        let texture = asset_sys.load::<TextureAsset>(tileset_dir.join(image), false);
+        Ok(Tileset::new(first_gid, &tileset, texture))
+    }
+
+
+    /// Queues a quad for every visible tile that overlaps camera_rect (min and max in world space,
+    /// see `Camera::visible_world_rect`). Returns the number of quads that have been queued.
+    pub fn draw(&self, renderer: &mut BatchRenderer, camera_rect: (Vec2, Vec2)) -> usize {
+        let mut num_quads = 0;
+        self.for_each_visible_tile(camera_rect, |quad, layer| {
+            renderer.draw_quad(&quad, layer);
+            num_quads += 1;
+        });
+        num_quads
+    }
+
+
+    /// Calls f with the quad and the BatchRenderer layer of every tile that draw would queue
+    fn for_each_visible_tile(&self, camera_rect: (Vec2, Vec2), mut f: impl FnMut(Quad, i32)) {
+        // Tiles of tilesets that are larger than the map tiles stick out of their cell to the top and right
+        let max_tile_size = self.tilesets.iter().fold(self.tile_size, |size, tileset| size.max(tileset.tile_size));
+        let overhang = (max_tile_size - self.tile_size).as_vec2();
+        let tile_size = self.tile_size.as_vec2();
+
+        for (layer_idx, layer) in self.layers.iter().enumerate() {
+            if !layer.visible {
+                continue;
+            };
+            let top_left = (self.origin + layer.offset).as_vec2();
+            // Columns go right, rows go down from the top left corner
+            let min_col = ((camera_rect.0.x - top_left.x - overhang.x) / tile_size.x).floor().max(0.0) as u32;
+            let max_col = ((camera_rect.1.x - top_left.x) / tile_size.x).ceil().min(self.width as f32) as u32;
+            let min_row = ((top_left.y - camera_rect.1.y) / tile_size.y).floor().max(0.0) as u32;
+            let max_row = ((top_left.y - camera_rect.0.y + overhang.y) / tile_size.y).ceil().min(self.height as f32) as u32;
+
+            for row in min_row..max_row {
+                for col in min_col..max_col {
+                    let gid = layer.gids[(row * self.width + col) as usize];
+                    let Some(quad) = self.tile_quad(gid, layer, col, row) else {
+                        continue;
+                    };
+                    f(quad, self.base_layer + layer_idx as i32);
+                }
+            }
+        }
+    }
+
+
+    /// Creates the quad for the tile in that cell, None if the cell is empty or the GID has no tileset
+    fn tile_quad(&self, gid: u32, layer: &TileLayer, col: u32, row: u32) -> Option<Quad> {
+        let id = gid & GID_MASK;
+        if id == 0 {
+            return None;
+        };
+        let tileset = self.tileset_for(id)?;
+
+        // Tiles are aligned to the bottom left of their cell
+        let cell_bottom_left = self.origin + layer.offset + IVec2::new(col as i32 * self.tile_size.x, -((row as i32 + 1) * self.tile_size.y));
+        let (uv_min, uv_max) = tileset.tile_uv_rect(id - tileset.first_gid);
+        Some(Quad::builder()
+            .center(cell_bottom_left + tileset.tile_size / 2)
+            .size(tileset.tile_size / 2)
+            .texture(tileset.texture.clone())
+            .uv_rect(uv_min, uv_max)
+            .flip_x(gid & FLIPPED_HORIZONTALLY != 0)
+            .flip_y(gid & FLIPPED_VERTICALLY != 0)
+            .color(Srgba::new(1.0, 1.0, 1.0, layer.opacity))
+            .build())
+    }
+
+
+    /// Returns the tileset with the highest first GID that is still <= id
+    fn tileset_for(&self, id: u32) -> Option<&Tileset> {
+        let tileset = self.tilesets.iter().rev().find(|tileset| tileset.first_gid <= id)?;
+        if tileset.tile_count != 0 && id - tileset.first_gid >= tileset.tile_count {
+            return None;
+        };
+        Some(tileset)
+    }
+
+
+    /// Returns the GID (without flip flags) of the tile in that layer and cell, 0 means empty
+    pub fn tile_at(&self, layer: usize, col: u32, row: u32) -> Option<u32> {
+        if col >= self.width || row >= self.height {
+            return None;
+        };
+        let layer = self.layers.get(layer)?;
+        Some(layer.gids[(row * self.width + col) as usize] & GID_MASK)
+    }
+
+
+    /// Width and height in tiles
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn tile_size(&self) -> IVec2 {
+        self.tile_size
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layers_mut(&mut self) -> &mut [TileLayer] {
+        &mut self.layers
+    }
+
+
+    /// Sets where the top left corner of the map is in world space
+    pub fn set_origin(&mut self, origin: IVec2) {
+        self.origin = origin;
+    }
+
+    pub fn origin(&self) -> IVec2 {
+        self.origin
+    }
+
+
+    /// Sets the BatchRenderer layer of the first tile layer, the following tile layers use the layers above
+    pub fn set_base_layer(&mut self, base_layer: i32) {
+        self.base_layer = base_layer;
+    }
+
+    pub fn base_layer(&self) -> i32 {
+        self.base_layer
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// A 4x4 map of 16x16 tiles with two tilesets: 1 - 16 in a 4x4 image, 17 and 18 next to each other
+    /// in an image with a margin of 1 and a spacing of 2
+    const MAP: &str = r#"{
+        "width": 4, "height": 4, "tilewidth": 16, "tileheight": 16, "orientation": "orthogonal",
+        "tilesets": [
+            { "firstgid": 17, "image": "props.png", "imagewidth": 36, "imageheight": 18, "tilewidth": 16, "tileheight": 16,
+              "columns": 2, "tilecount": 2, "margin": 1, "spacing": 2 },
+            { "firstgid": 1, "image": "ground.png", "imagewidth": 64, "imageheight": 64, "tilewidth": 16, "tileheight": 16,
+              "columns": 4, "tilecount": 16 }
+        ],
+        "layers": [
+            { "type": "tilelayer", "name": "ground", "data": [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16] },
+            { "type": "objectgroup", "name": "spawns", "objects": [] },
+            { "type": "tilelayer", "name": "props", "data": [40, 0, 0, 0, 0, 2147483665, 0, 0, 0, 0, 0, 0, 0, 0, 18, 0] }
+        ]
+    }"#;
+
+    /// Parses a map without an AssetSystem, the texture ids are the first GIDs of the tilesets
+    fn parse(source: &str) -> anyhow::Result<Tilemap> {
+        Tilemap::from_json(source, |tileset| {
+            tileset.validate(tileset.firstgid)?;
+            Ok(Tileset::new(tileset.firstgid, &tileset, AssetHandle::new(Arc::new(tileset.firstgid as u64))))
+        })
+    }
+
+    fn visible_tiles(tilemap: &Tilemap, camera_rect: (Vec2, Vec2)) -> Vec<(Quad, i32)> {
+        let mut tiles = vec![];
+        tilemap.for_each_visible_tile(camera_rect, |quad, layer| tiles.push((quad, layer)));
+        tiles
+    }
+
+
+    #[test]
+    fn parses_a_four_by_four_map() {
+        let tilemap = parse(MAP).unwrap();
+        assert_eq!(tilemap.size(), (4, 4));
+        assert_eq!(tilemap.tile_size(), IVec2::new(16, 16));
+        let names: Vec<&str> = tilemap.layers().iter().map(|layer| layer.name()).collect();
+        assert_eq!(names, vec!["ground", "props"]);
+        // The flip flags are not part of the GID
+        assert_eq!(tilemap.tile_at(1, 1, 1), Some(17));
+        assert_eq!(tilemap.tile_at(0, 3, 3), Some(16));
+        assert_eq!(tilemap.tile_at(0, 4, 0), None);
+    }
+
+    #[test]
+    fn emits_one_quad_per_tile() {
+        let tilemap = parse(MAP).unwrap();
+        let tiles = visible_tiles(&tilemap, (Vec2::splat(-100.0), Vec2::splat(100.0)));
+        // 16 ground tiles and 2 props, GID 40 belongs to no tileset
+        assert_eq!(tiles.len(), 18);
+        assert_eq!(tiles.iter().filter(|(_, layer)| *layer == 0).count(), 16);
+
+        // Rows go down from the origin
+        let (first, _) = &tiles[0];
+        assert_eq!((first.center, first.size), (IVec2::new(8, -8), IVec2::new(8, 8)));
+        assert_eq!(first.uv_rect, (Vec2::ZERO, Vec2::splat(0.25)));
+        assert_eq!(tiles[15].0.center, IVec2::new(56, -56));
+        assert_eq!(tiles[15].0.uv_rect, (Vec2::splat(0.75), Vec2::ONE));
+
+        let (flipped, layer) = &tiles[16];
+        assert_eq!((flipped.center, *layer), (IVec2::new(24, -24), 1));
+        assert_eq!(flipped.texture.as_ref().map(|texture| **texture.id()), Some(17));
+        assert!(flipped.flip_x && !flipped.flip_y);
+        assert_eq!(flipped.uv_rect, (Vec2::new(1.0, 1.0) / Vec2::new(36.0, 18.0), Vec2::new(17.0, 17.0) / Vec2::new(36.0, 18.0)));
+        let (second, _) = &tiles[17];
+        assert_eq!(second.center, IVec2::new(40, -56));
+        assert!(!second.flip_x);
+        assert_eq!(second.uv_rect, (Vec2::new(19.0, 1.0) / Vec2::new(36.0, 18.0), Vec2::new(35.0, 17.0) / Vec2::new(36.0, 18.0)));
+    }
+
+    #[test]
+    fn culls_tiles_outside_of_the_camera() {
+        let mut tilemap = parse(MAP).unwrap();
+        let tiles = visible_tiles(&tilemap, (Vec2::new(0.0, -16.0), Vec2::new(16.0, 0.0)));
+        assert_eq!(tiles.len(), 1);
+        assert_eq!(tiles[0].0.center, IVec2::new(8, -8));
+
+        tilemap.set_origin(IVec2::new(1000, 0));
+        assert!(visible_tiles(&tilemap, (Vec2::new(0.0, -16.0), Vec2::new(16.0, 0.0))).is_empty());
+        tilemap.layers_mut()[0].set_visible(false);
+        assert_eq!(visible_tiles(&tilemap, (Vec2::new(900.0, -100.0), Vec2::new(1100.0, 100.0))).len(), 2);
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        let zero_image_size = MAP.replace(r#""imagewidth": 64"#, r#""imagewidth": 0"#);
+        let error = format!("{:#}", parse(&zero_image_size).unwrap_err());
+        assert!(error.contains("image size of zero"), "{}", error);
+        assert!(parse(&MAP.replace(r#""columns": 4"#, r#""columns": 0"#)).is_err());
+        assert!(parse(&MAP.replace(r#""orientation": "orthogonal""#, r#""orientation": "isometric""#)).is_err());
+        assert!(parse(&MAP.replace("15, 16]", "15]")).is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use std::time::{Duration, Instant};
+
+use geese::{GeeseContextHandle, GeeseSystem};
+use log::warn;
+
+use crate::tween::Lerp;
+
+
+/// How many frame durations are kept in `FrameTimes`
+pub const FRAME_TIME_HISTORY: usize = 240;
+
+/// The time between two fixed steps until `TimeSystem::set_fixed_timestep` is called (60 steps per second)
+pub const DEFAULT_FIXED_TIMESTEP: Duration = Duration::from_micros(16_667);
+/// At most this many fixed steps are due in a single frame, the rest of the time is dropped
+pub const MAX_FIXED_STEPS_PER_FRAME: u32 = 8;
+
+
+/// Fixed-size ring buffer of the most recent frame durations, it never allocates after construction
+#[derive(Debug, Clone)]
+pub struct FrameTimes {
+    times: [Duration; FRAME_TIME_HISTORY],
+    /// Where the next duration gets written to
+    next: usize,
+    len: usize
+}
+impl FrameTimes {
+    pub fn new() -> Self {
+        Self {
+            times: [Duration::ZERO; FRAME_TIME_HISTORY],
+            next: 0,
+            len: 0
+        }
+    }
+
+
+    /// Adds a duration, overwriting the oldest one once the buffer is full
+    pub fn push(&mut self, frame_time: Duration) {
+        self.times[self.next] = frame_time;
+        self.next = (self.next + 1) % FRAME_TIME_HISTORY;
+        self.len = (self.len + 1).min(FRAME_TIME_HISTORY);
+    }
+
+
+    /// Iterates over the stored durations, from the oldest to the newest
+    pub fn iter(&self) -> impl Iterator<Item = Duration> + '_ {
+        let start = (self.next + FRAME_TIME_HISTORY - self.len) % FRAME_TIME_HISTORY;
+        (0..self.len).map(move |i| self.times[(start + i) % FRAME_TIME_HISTORY])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The most recent frame duration
+    pub fn last(&self) -> Option<Duration> {
+        self.iter().last()
+    }
+
+
+    pub fn min(&self) -> Option<Duration> {
+        self.iter().min()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.iter().max()
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        };
+        Some(self.iter().sum::<Duration>() / self.len as u32)
+    }
+
+
+    /// Returns the duration below which `percentile` percent (0 - 100) of the frames are, for example 99.0 for the p99
+    pub fn percentile(&self, percentile: f32) -> Option<Duration> {
+        if self.is_empty() {
+            return None;
+        };
+        let mut sorted = [Duration::ZERO; FRAME_TIME_HISTORY];
+        self.iter().enumerate().for_each(|(i, time)| sorted[i] = time);
+        let sorted = &mut sorted[..self.len];
+        sorted.sort_unstable();
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * self.len as f32).ceil() as usize;
+        Some(sorted[rank.clamp(1, self.len) - 1])
+    }
+
+
+    /// Removes all durations
+    pub fn clear(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+}
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+/// The state of the last two fixed steps, for rendering in between them. Call `push` once per fixed step
+/// with the new state and draw `get(time.interpolation_alpha())`, which lags behind by at most one step
+/// but moves smoothly when there are more frames than fixed steps.
+#[derive(Debug, Clone, Copy)]
+pub struct Interpolated<T> {
+    previous: T,
+    current: T
+}
+impl<T: Lerp> Interpolated<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            previous: value.clone(),
+            current: value
+        }
+    }
+
+
+    /// Stores the state after a fixed step, the old one becomes the previous state
+    pub fn push(&mut self, value: T) {
+        self.previous = std::mem::replace(&mut self.current, value);
+    }
+
+    /// Sets both states, so the value jumps there without interpolating (for example after teleporting)
+    pub fn reset(&mut self, value: T) {
+        self.previous = value.clone();
+        self.current = value;
+    }
+
+
+    pub fn previous(&self) -> &T {
+        &self.previous
+    }
+
+    pub fn current(&self) -> &T {
+        &self.current
+    }
+
+    /// The state in between the previous and the current one
+    pub fn get(&self, alpha: f32) -> T {
+        self.previous.lerp(&self.current, alpha.clamp(0.0, 1.0))
+    }
+}
+
+
+
+/// Keeps track of the time between frames
+pub struct TimeSystem {
+    ctx: GeeseContextHandle<Self>,
+    startup: Instant,
+    last_frame: Instant,
+    delta: Duration,
+    frame_times: FrameTimes,
+    fixed_timestep: Duration,
+    /// Time that has passed but is not yet covered by fixed steps
+    fixed_accumulator: Duration,
+    fixed_steps: u32
+}
+impl TimeSystem {
+    /// The time between the start of the last and the current frame
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// The time since the engine was started
+    pub fn elapsed(&self) -> Duration {
+        self.startup.elapsed()
+    }
+
+    /// The durations of the last FRAME_TIME_HISTORY frames, for example for a frame time graph
+    pub fn frame_times(&self) -> &FrameTimes {
+        &self.frame_times
+    }
+
+
+    /// Sets the time between two fixed steps (DEFAULT_FIXED_TIMESTEP by default)
+    pub fn set_fixed_timestep(&mut self, timestep: Duration) {
+        if timestep.is_zero() {
+            warn!("set_fixed_timestep: The timestep has to be greater than zero");
+            return;
+        };
+        self.fixed_timestep = timestep;
+        self.fixed_accumulator = Duration::ZERO;
+    }
+
+    pub fn fixed_timestep(&self) -> Duration {
+        self.fixed_timestep
+    }
+
+
+    /// How many fixed steps are due in the current frame. Systems with fixed updates run them this many
+    /// times (each advancing by fixed_timestep) and then render with interpolation_alpha.
+    pub fn fixed_steps(&self) -> u32 {
+        self.fixed_steps
+    }
+
+    /// How far the current frame lies between the last fixed step and the next one (0.0 - 1.0),
+    /// used to interpolate between the previous and the current state, see `Interpolated`
+    pub fn interpolation_alpha(&self) -> f32 {
+        (self.fixed_accumulator.as_secs_f64() / self.fixed_timestep.as_secs_f64()).clamp(0.0, 1.0) as f32
+    }
+
+
+    /// Measures the time since the last frame, called by the engine at the start of every frame
+    pub(crate) fn begin_frame(&mut self) {
+        let now = Instant::now();
+        self.begin_frame_with_delta(now - self.last_frame);
+    }
+
+    /// Starts a frame with a given delta instead of measuring it, used by the TestHarness
+    pub(crate) fn begin_frame_with_delta(&mut self, delta: Duration) {
+        self.delta = delta;
+        self.last_frame = Instant::now();
+        self.frame_times.push(delta);
+
+        self.fixed_accumulator += delta;
+        let due_steps = (self.fixed_accumulator.as_nanos() / self.fixed_timestep.as_nanos()) as u64;
+        self.fixed_steps = due_steps.min(MAX_FIXED_STEPS_PER_FRAME as u64) as u32;
+        if due_steps > self.fixed_steps as u64 {
+            self.fixed_accumulator = Duration::ZERO;
+        } else {
+            self.fixed_accumulator -= self.fixed_timestep * self.fixed_steps;
+        };
+    }
+}
+impl GeeseSystem for TimeSystem {
+    fn new(ctx: GeeseContextHandle<Self>) -> Self {
+        let now = Instant::now();
+        Self {
+            ctx,
+            startup: now,
+            last_frame: now,
+            delta: Duration::ZERO,
+            frame_times: FrameTimes::new(),
+            fixed_timestep: DEFAULT_FIXED_TIMESTEP,
+            fixed_accumulator: Duration::ZERO,
+            fixed_steps: 0
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use geese::GeeseContext;
+
+    use super::*;
+
+    #[test]
+    fn frame_times_statistics() {
+        let mut frame_times = FrameTimes::new();
+        assert_eq!(frame_times.average(), None);
+        assert_eq!(frame_times.percentile(99.0), None);
+
+        for ms in 1..=10 {
+            frame_times.push(Duration::from_millis(ms));
+        }
+        assert_eq!(frame_times.len(), 10);
+        assert_eq!(frame_times.average(), Some(Duration::from_micros(5500)));
+        assert_eq!(frame_times.percentile(50.0), Some(Duration::from_millis(5)));
+        assert_eq!(frame_times.percentile(99.0), Some(Duration::from_millis(10)));
+        assert_eq!(frame_times.min(), Some(Duration::from_millis(1)));
+        assert_eq!(frame_times.last(), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn frame_times_wrap_around() {
+        let mut frame_times = FrameTimes::new();
+        for _ in 0..FRAME_TIME_HISTORY {
+            frame_times.push(Duration::from_millis(1));
+        }
+        for _ in 0..10 {
+            frame_times.push(Duration::from_millis(5));
+        }
+        // The 10 oldest durations have been overwritten
+        assert_eq!(frame_times.len(), FRAME_TIME_HISTORY);
+        let total = Duration::from_millis((FRAME_TIME_HISTORY as u64 - 10) + 10 * 5);
+        assert_eq!(frame_times.average(), Some(total / FRAME_TIME_HISTORY as u32));
+        assert_eq!(frame_times.percentile(99.0), Some(Duration::from_millis(5)));
+        assert_eq!(frame_times.percentile(50.0), Some(Duration::from_millis(1)));
+        assert_eq!(frame_times.iter().next(), Some(Duration::from_millis(1)));
+        assert_eq!(frame_times.last(), Some(Duration::from_millis(5)));
+
+        frame_times.clear();
+        assert!(frame_times.is_empty());
+    }
+
+
+    #[test]
+    fn interpolation_alpha() {
+        let mut ctx = GeeseContext::default();
+        ctx.flush().with(geese::notify::add_system::<TimeSystem>());
+        let mut time = ctx.get_mut::<TimeSystem>();
+        time.set_fixed_timestep(Duration::from_millis(10));
+        assert_eq!(time.interpolation_alpha(), 0.0);
+
+        time.begin_frame_with_delta(Duration::from_millis(25));
+        assert_eq!(time.fixed_steps(), 2);
+        assert!((time.interpolation_alpha() - 0.5).abs() < 1e-6);
+
+        time.begin_frame_with_delta(Duration::from_millis(2));
+        assert_eq!(time.fixed_steps(), 0);
+        assert!((time.interpolation_alpha() - 0.7).abs() < 1e-6);
+
+        // More steps are due than allowed per frame, the remaining time gets dropped
+        time.begin_frame_with_delta(Duration::from_millis(200));
+        assert_eq!(time.fixed_steps(), MAX_FIXED_STEPS_PER_FRAME);
+        assert_eq!(time.interpolation_alpha(), 0.0);
+    }
+
+    #[test]
+    fn interpolated_values() {
+        let mut position = Interpolated::new(0.0_f32);
+        position.push(10.0);
+        assert_eq!(position.get(0.0), 0.0);
+        assert_eq!(position.get(0.25), 2.5);
+        assert_eq!(position.get(2.0), 10.0);
+
+        position.reset(4.0);
+        assert_eq!(position.get(0.5), 4.0);
+    }
+}
@@ -0,0 +1,337 @@
+use std::{f32::consts::PI, time::Duration};
+
+use glam::{IVec2, Vec2, Vec3};
+use palette::Srgba;
+
+
+
+/// Types that can be linearly interpolated, `t` is in [0, 1] (but may overshoot for some easings)
+pub trait Lerp: Clone {
+    fn lerp(&self, to: &Self, t: f32) -> Self;
+}
+impl Lerp for f32 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+impl Lerp for Vec2 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        Vec2::lerp(*self, *to, t)
+    }
+}
+impl Lerp for Vec3 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        Vec3::lerp(*self, *to, t)
+    }
+}
+impl Lerp for IVec2 {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        self.as_vec2().lerp(to.as_vec2(), t).round().as_ivec2()
+    }
+}
+impl Lerp for Srgba {
+    fn lerp(&self, to: &Self, t: f32) -> Self {
+        Srgba::new(
+            Lerp::lerp(&self.red, &to.red, t),
+            Lerp::lerp(&self.green, &to.green, t),
+            Lerp::lerp(&self.blue, &to.blue, t),
+            Lerp::lerp(&self.alpha, &to.alpha, t)
+        )
+    }
+}
+
+
+
+/// The Robert Penner easing functions
+/// See: https://easings.net
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    QuadIn, QuadOut, QuadInOut,
+    CubicIn, CubicOut, CubicInOut,
+    QuartIn, QuartOut, QuartInOut,
+    QuintIn, QuintOut, QuintInOut,
+    SineIn, SineOut, SineInOut,
+    ExpoIn, ExpoOut, ExpoInOut,
+    CircIn, CircOut, CircInOut,
+    BackIn, BackOut, BackInOut,
+    ElasticIn, ElasticOut, ElasticInOut,
+    BounceIn, BounceOut, BounceInOut
+}
+impl Easing {
+    /// Maps the linear progress t (clamped to [0, 1]) to the eased progress
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        const BACK_C1: f32 = 1.70158;
+        const BACK_C2: f32 = BACK_C1 * 1.525;
+        const BACK_C3: f32 = BACK_C1 + 1.0;
+        const ELASTIC_C4: f32 = (2.0 * PI) / 3.0;
+        const ELASTIC_C5: f32 = (2.0 * PI) / 4.5;
+
+        match self {
+            Easing::Linear => t,
+
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t).powi(2),
+            Easing::QuadInOut => if t < 0.5 { 2.0 * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0 },
+
+            Easing::CubicIn => t.powi(3),
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut => if t < 0.5 { 4.0 * t.powi(3) } else { 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0 },
+
+            Easing::QuartIn => t.powi(4),
+            Easing::QuartOut => 1.0 - (1.0 - t).powi(4),
+            Easing::QuartInOut => if t < 0.5 { 8.0 * t.powi(4) } else { 1.0 - (-2.0 * t + 2.0).powi(4) / 2.0 },
+
+            Easing::QuintIn => t.powi(5),
+            Easing::QuintOut => 1.0 - (1.0 - t).powi(5),
+            Easing::QuintInOut => if t < 0.5 { 16.0 * t.powi(5) } else { 1.0 - (-2.0 * t + 2.0).powi(5) / 2.0 },
+
+            Easing::SineIn => 1.0 - ((t * PI) / 2.0).cos(),
+            Easing::SineOut => ((t * PI) / 2.0).sin(),
+            Easing::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+
+            Easing::ExpoIn => if t == 0.0 { 0.0 } else { 2f32.powf(10.0 * t - 10.0) },
+            Easing::ExpoOut => if t == 1.0 { 1.0 } else { 1.0 - 2f32.powf(-10.0 * t) },
+            Easing::ExpoInOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else if t < 0.5 {
+                    2f32.powf(20.0 * t - 10.0) / 2.0
+                } else {
+                    (2.0 - 2f32.powf(-20.0 * t + 10.0)) / 2.0
+                }
+            },
+
+            Easing::CircIn => 1.0 - (1.0 - t * t).sqrt(),
+            Easing::CircOut => (1.0 - (t - 1.0).powi(2)).sqrt(),
+            Easing::CircInOut => {
+                if t < 0.5 {
+                    (1.0 - (1.0 - (2.0 * t).powi(2)).sqrt()) / 2.0
+                } else {
+                    ((1.0 - (-2.0 * t + 2.0).powi(2)).sqrt() + 1.0) / 2.0
+                }
+            },
+
+            Easing::BackIn => BACK_C3 * t.powi(3) - BACK_C1 * t * t,
+            Easing::BackOut => 1.0 + BACK_C3 * (t - 1.0).powi(3) + BACK_C1 * (t - 1.0).powi(2),
+            Easing::BackInOut => {
+                if t < 0.5 {
+                    ((2.0 * t).powi(2) * ((BACK_C2 + 1.0) * 2.0 * t - BACK_C2)) / 2.0
+                } else {
+                    ((2.0 * t - 2.0).powi(2) * ((BACK_C2 + 1.0) * (t * 2.0 - 2.0) + BACK_C2) + 2.0) / 2.0
+                }
+            },
+
+            Easing::ElasticIn => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    -2f32.powf(10.0 * t - 10.0) * ((t * 10.0 - 10.75) * ELASTIC_C4).sin()
+                }
+            },
+            Easing::ElasticOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else {
+                    2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * ELASTIC_C4).sin() + 1.0
+                }
+            },
+            Easing::ElasticInOut => {
+                if t == 0.0 || t == 1.0 {
+                    t
+                } else if t < 0.5 {
+                    -(2f32.powf(20.0 * t - 10.0) * ((20.0 * t - 11.125) * ELASTIC_C5).sin()) / 2.0
+                } else {
+                    (2f32.powf(-20.0 * t + 10.0) * ((20.0 * t - 11.125) * ELASTIC_C5).sin()) / 2.0 + 1.0
+                }
+            },
+
+            Easing::BounceIn => 1.0 - Self::bounce_out(1.0 - t),
+            Easing::BounceOut => Self::bounce_out(t),
+            Easing::BounceInOut => {
+                if t < 0.5 {
+                    (1.0 - Self::bounce_out(1.0 - 2.0 * t)) / 2.0
+                } else {
+                    (1.0 + Self::bounce_out(2.0 * t - 1.0)) / 2.0
+                }
+            }
+        }
+    }
+
+
+    fn bounce_out(t: f32) -> f32 {
+        const N1: f32 = 7.5625;
+        const D1: f32 = 2.75;
+        if t < 1.0 / D1 {
+            N1 * t * t
+        } else if t < 2.0 / D1 {
+            let t = t - 1.5 / D1;
+            N1 * t * t + 0.75
+        } else if t < 2.5 / D1 {
+            let t = t - 2.25 / D1;
+            N1 * t * t + 0.9375
+        } else {
+            let t = t - 2.625 / D1;
+            N1 * t * t + 0.984375
+        }
+    }
+}
+
+
+
+/// Interpolates between two values over a duration, using an easing function
+#[derive(Debug, Clone)]
+pub struct Tween<T: Lerp> {
+    from: T,
+    to: T,
+    duration: Duration,
+    easing: Easing
+}
+impl<T: Lerp> Tween<T> {
+    pub fn new(from: T, to: T, duration: Duration, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            easing
+        }
+    }
+
+    /// Shorthand for a linear Tween
+    pub fn linear(from: T, to: T, duration: Duration) -> Self {
+        Self::new(from, to, duration, Easing::Linear)
+    }
+
+    /// Returns the value after `elapsed` time, values outside of the duration are clamped
+    pub fn sample(&self, elapsed: Duration) -> T {
+        let t = if self.duration.is_zero() {
+            1.0
+        } else {
+            elapsed.as_secs_f32() / self.duration.as_secs_f32()
+        };
+        self.from.lerp(&self.to, self.easing.apply(t))
+    }
+
+    pub fn is_finished(&self, elapsed: Duration) -> bool {
+        elapsed >= self.duration
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    pub fn from(&self) -> &T {
+        &self.from
+    }
+
+    pub fn to(&self) -> &T {
+        &self.to
+    }
+}
+
+
+
+/// Plays multiple Tweens one after another
+#[derive(Debug, Clone)]
+pub struct Timeline<T: Lerp> {
+    tweens: Vec<Tween<T>>
+}
+impl<T: Lerp> Timeline<T> {
+    pub fn new() -> Self {
+        Self {
+            tweens: vec![]
+        }
+    }
+
+    /// Appends a tween to the end of the timeline
+    pub fn then(mut self, tween: Tween<T>) -> Self {
+        self.tweens.push(tween);
+        self
+    }
+
+    /// The combined duration of all tweens
+    pub fn duration(&self) -> Duration {
+        self.tweens.iter().map(|tween| tween.duration()).sum()
+    }
+
+    pub fn is_finished(&self, elapsed: Duration) -> bool {
+        elapsed >= self.duration()
+    }
+
+    /// Samples the tween that is active at `elapsed`, after the end the last value is held.
+    /// Returns None if the timeline is empty.
+    pub fn sample(&self, elapsed: Duration) -> Option<T> {
+        let mut start = Duration::ZERO;
+        for tween in self.tweens.iter() {
+            if elapsed < start + tween.duration() {
+                return Some(tween.sample(elapsed - start));
+            };
+            start += tween.duration();
+        };
+        self.tweens.last().map(|tween| tween.to().clone())
+    }
+}
+impl<T: Lerp> Default for Timeline<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every easing with its value at t = 0.5 (see https://easings.net)
+    const HALFWAY: [(Easing, f32); 31] = [
+        (Easing::Linear, 0.5),
+        (Easing::QuadIn, 0.25), (Easing::QuadOut, 0.75), (Easing::QuadInOut, 0.5),
+        (Easing::CubicIn, 0.125), (Easing::CubicOut, 0.875), (Easing::CubicInOut, 0.5),
+        (Easing::QuartIn, 0.0625), (Easing::QuartOut, 0.9375), (Easing::QuartInOut, 0.5),
+        (Easing::QuintIn, 0.03125), (Easing::QuintOut, 0.96875), (Easing::QuintInOut, 0.5),
+        (Easing::SineIn, 0.292893), (Easing::SineOut, 0.707107), (Easing::SineInOut, 0.5),
+        (Easing::ExpoIn, 0.03125), (Easing::ExpoOut, 0.96875), (Easing::ExpoInOut, 0.5),
+        (Easing::CircIn, 0.133975), (Easing::CircOut, 0.866025), (Easing::CircInOut, 0.5),
+        (Easing::BackIn, -0.087698), (Easing::BackOut, 1.087698), (Easing::BackInOut, 0.5),
+        (Easing::ElasticIn, -0.015625), (Easing::ElasticOut, 1.015625), (Easing::ElasticInOut, 0.5),
+        (Easing::BounceIn, 0.234375), (Easing::BounceOut, 0.765625), (Easing::BounceInOut, 0.5)
+    ];
+
+    fn assert_close(easing: Easing, t: f32, expected: f32) {
+        let value = easing.apply(t);
+        assert!((value - expected).abs() < 1e-5, "{:?} at t = {}: expected {}, got {}", easing, t, expected, value);
+    }
+
+
+    #[test]
+    fn easings_start_at_zero_and_end_at_one() {
+        for (easing, _) in HALFWAY {
+            assert_close(easing, 0.0, 0.0);
+            assert_close(easing, 1.0, 1.0);
+        }
+    }
+
+    #[test]
+    fn easings_at_halfway() {
+        for (easing, expected) in HALFWAY {
+            assert_close(easing, 0.5, expected);
+        }
+    }
+
+    #[test]
+    fn progress_is_clamped() {
+        assert_close(Easing::QuadIn, -1.0, 0.0);
+        assert_close(Easing::QuadIn, 2.0, 1.0);
+    }
+
+    #[test]
+    fn tween_samples() {
+        let tween = Tween::new(10.0, 20.0, Duration::from_secs(2), Easing::QuadIn);
+        assert_eq!(tween.sample(Duration::ZERO), 10.0);
+        assert_eq!(tween.sample(Duration::from_secs(1)), 12.5);
+        assert_eq!(tween.sample(Duration::from_secs(3)), 20.0);
+        assert!(tween.is_finished(Duration::from_secs(2)));
+    }
+}
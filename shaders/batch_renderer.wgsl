@@ -3,6 +3,12 @@ struct VertexInput {
     @location(1) color: vec4<f32>,
     @location(2) tex_coords: vec2<f32>,
     @location(3) tex_index: i32,
+    @location(4) depth: f32,
+    @location(5) local_pos: vec2<f32>,
+    // Half size (xy), corner radius (z) and outline thickness (w)
+    @location(6) shape: vec4<f32>,
+    // Min (xy) and size (zw) of the uv rect the coordinates wrap around in, zero size for no wrapping
+    @location(7) uv_wrap: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -10,13 +16,16 @@ struct VertexOutput {
     @location(0) color: vec4<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) tex_index: i32,
+    @location(3) local_pos: vec2<f32>,
+    @location(4) shape: vec4<f32>,
+    @location(5) uv_wrap: vec4<f32>,
 }
 
 struct Globals {
     canvas_transform: mat4x4f,
 }
 
-@group(0) @binding(0)
+@group(1) @binding(0)
 var<uniform> globals: Globals;
 
 
@@ -25,13 +34,48 @@ fn vert_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
     // out.clip_position = globals.view_proj * globals.transform * vec4<f32>(in.position, 1.0);
     out.clip_position = globals.canvas_transform * vec4<f32>(vec2<f32>(in.position), 0.0, 1.0);
+    // The depth is already normalized to [0, 1] (see BatchRenderer::layer_to_depth)
+    out.clip_position.z = in.depth;
     out.color = in.color;
     out.tex_coords = in.tex_coords;
     out.tex_index = in.tex_index;
+    out.local_pos = in.local_pos;
+    out.shape = in.shape;
+    out.uv_wrap = in.uv_wrap;
     return out;
 }
 
 
+// Signed distance from p to a rectangle with rounded corners that is centered at the origin
+fn rounded_rect_sdf(p: vec2<f32>, half_size: vec2<f32>, radius: f32) -> f32 {
+    let q = abs(p) - half_size + vec2<f32>(radius);
+    return length(max(q, vec2<f32>(0.0))) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+
+// Wraps uv back into the rect (min xy, size zw), so an offset uv rect tiles instead of showing its neighbours in an atlas
+fn wrap_uv(uv: vec2<f32>, rect: vec4<f32>) -> vec2<f32> {
+    let wrapped = rect.xy + fract((uv - rect.xy) / rect.zw) * rect.zw;
+    return select(uv, wrapped, rect.z != 0.0 && rect.w != 0.0);
+}
+
+
+// Whether the edges of shapes fade out over one pixel, otherwise they are hard (set per pipeline)
+override shape_antialiasing: bool = true;
+
+
+// How much of the fragment lies inside of the (rounded and/ or outlined) quad, 1.0 for plain quads
+fn shape_coverage(local_pos: vec2<f32>, shape: vec4<f32>) -> f32 {
+    let radius = clamp(shape.z, 0.0, min(shape.x, shape.y));
+    let dist = rounded_rect_sdf(local_pos, shape.xy, radius);
+    // Antialias over one pixel, a tiny width gives a hard edge at the same position
+    let aa = select(0.0001, max(fwidth(dist), 0.0001), shape_antialiasing);
+    let outer = clamp(0.5 - dist / aa, 0.0, 1.0);
+    let inner = select(1.0, clamp(0.5 + (dist + shape.w) / aa, 0.0, 1.0), shape.w > 0.0);
+    return select(outer * inner, 1.0, shape.z <= 0.0 && shape.w <= 0.0);
+}
+
+
 
 @group(0) @binding(1)
 var textures: binding_array<texture_2d<f32>>;
@@ -39,8 +83,18 @@ var textures: binding_array<texture_2d<f32>>;
 var samplers: binding_array<sampler>;
 
 
+// Fragments with a lower alpha get discarded (set per pipeline, used by layers with an alpha cutoff)
+override alpha_threshold: f32 = 0.0;
+
+
 @fragment
 fn uniform_main(in: VertexOutput) -> @location(0) vec4<f32> {
     var index: i32 = in.tex_index;
-    return textureSample(textures[index], samplers[index], in.tex_coords) * in.color;
+    // Wrap the coordinates, so quads with an uv_offset tile inside of their uv rect
+    var color = textureSample(textures[index], samplers[index], wrap_uv(in.tex_coords, in.uv_wrap)) * in.color;
+    color.a *= shape_coverage(in.local_pos, in.shape);
+    if color.a < alpha_threshold {
+        discard;
+    }
+    return color;
 }
\ No newline at end of file
@@ -10,7 +10,8 @@ struct VertexOutput {
 }
 
 struct Globals {
-    canvas_transform: mat4x4f,
+    // Either identity (screen space) or the camera transform combined with the grid size
+    transform: mat4x4f,
 }
 
 @group(0) @binding(2)
@@ -20,19 +21,30 @@ var<uniform> globals: Globals;
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
-    out.position = vec4<f32>(in.position, 0.0, 1.0);
-    var p = vec2<f32>(out.position.x, out.position.y);
+    out.position = globals.transform * vec4<f32>(in.position, 0.0, 1.0);
     //out.tex_coords = in.tex_coords;
-    out.tex_coords = fma(p, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
+    out.tex_coords = fma(in.position, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
     return out;
 }
 
 // Fragment shader bindings
 
-@group(0) @binding(0) var r_tex_color: texture_2d<f32>;
-@group(0) @binding(1) var r_tex_sampler: sampler;
+struct Palette {
+    colors: array<vec4<f32>, 256>,
+}
+
+// One palette index per cell
+@group(0) @binding(0) var r_tex_indices: texture_2d<u32>;
+@group(0) @binding(1) var<uniform> palette: Palette;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(r_tex_color, r_tex_sampler, in.tex_coords);
+    // The triangle is bigger than the grid, which is visible when it is placed in world space
+    if any(in.tex_coords < vec2<f32>(0.0)) || any(in.tex_coords > vec2<f32>(1.0)) {
+        discard;
+    }
+    let size = vec2<f32>(textureDimensions(r_tex_indices));
+    let texel = clamp(vec2<i32>(in.tex_coords * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    let index = textureLoad(r_tex_indices, texel, 0).r;
+    return palette.colors[index];
 }
\ No newline at end of file
@@ -2,10 +2,14 @@
 pub mod prelude {
     pub use granular_core::{
         GranularEngine,
+        EventProxy,
         events,
         Simulation,
+        TimeSystem,
+        tween::{Tween, Timeline, Easing},
+        tilemap::Tilemap,
         input_system::*,
-        AssetSystem, assets::{AssetHandle, TextureAsset},
+        AssetSystem, assets::{AssetHandle, AnyAssetHandle, TextureAsset},
         Camera, BatchRenderer, graphics::{self, WindowSystem}
     };
 }
\ No newline at end of file